@@ -0,0 +1,135 @@
+//! Row building throughput of the generated row builders (default) and of the descriptor driven
+//! row builder of the `descriptor-builders` feature. The mode is chosen at compile time, compare
+//! the two with
+//!
+//! ```sh
+//! cargo bench --bench row_builders -- --save-baseline generated
+//! cargo bench --bench row_builders --features descriptor-builders -- --baseline generated
+//! ```
+//!
+//! Add `--no-default-features --features offline` to both to run without ROS.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use r2a::{ArrowSupport, RowBuilder};
+
+#[cfg(feature = "offline")]
+use r2a::cdr::CdrMessage;
+#[cfg(feature = "offline")]
+use r2a::msgs as r2r;
+#[cfg(not(feature = "offline"))]
+use r2r::WrappedTypesupport;
+
+use r2r::geometry_msgs::msg::{Transform, TransformStamped, Vector3};
+use r2r::sensor_msgs::msg::LaserScan;
+use r2r::std_msgs::msg::Header;
+use r2r::tf2_msgs::msg::TFMessage;
+
+const ROWS: usize = 1000;
+
+fn header(sec: i32) -> Header {
+    Header {
+        stamp: r2r::builtin_interfaces::msg::Time { sec, nanosec: 0 },
+        frame_id: "laser".to_string(),
+    }
+}
+
+fn laser_scan(sec: i32) -> LaserScan {
+    LaserScan {
+        header: header(sec),
+        angle_min: -1.57,
+        angle_max: 1.57,
+        angle_increment: 0.01,
+        range_max: 10.0,
+        ranges: (0..360).map(|i| i as f32 * 0.01).collect(),
+        intensities: (0..360).map(|i| i as f32).collect(),
+        ..Default::default()
+    }
+}
+
+fn tf_message(sec: i32) -> TFMessage {
+    TFMessage {
+        transforms: (0..10)
+            .map(|i| TransformStamped {
+                header: header(sec),
+                child_frame_id: format!("link_{}", i),
+                transform: Transform {
+                    translation: Vector3 {
+                        x: i as f64,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    ..Default::default()
+                },
+            })
+            .collect(),
+    }
+}
+
+fn mode() -> &'static str {
+    if cfg!(feature = "descriptor-builders") {
+        "descriptor"
+    } else {
+        "generated"
+    }
+}
+
+fn bench_row_builders(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("row_builders/{}", mode()));
+
+    let scans: Vec<LaserScan> = (0..ROWS as i32).map(laser_scan).collect();
+    let fields = LaserScan::arrow_fields(true);
+    group.bench_function("laser_scan", |b| {
+        b.iter(|| {
+            let mut row_builder = LaserScan::new_row_builder(fields.iter().collect());
+            for scan in &scans {
+                row_builder.add_row(scan).unwrap();
+            }
+            row_builder.to_arc_arrays()
+        })
+    });
+
+    let flat_fields = LaserScan::flat_arrow_fields(false);
+    group.bench_function("laser_scan_flat", |b| {
+        b.iter(|| {
+            let mut row_builder = LaserScan::new_flat_row_builder(flat_fields.iter().collect());
+            for scan in &scans {
+                row_builder.add_row(scan).unwrap();
+            }
+            row_builder.to_arc_arrays()
+        })
+    });
+
+    let raw_scans: Vec<Vec<u8>> = scans
+        .iter()
+        .map(|scan| scan.to_serialized_bytes().unwrap())
+        .collect();
+    group.bench_function("laser_scan_raw", |b| {
+        b.iter_batched(
+            || LaserScan::new_row_builder(fields.iter().collect()),
+            |mut row_builder| {
+                for raw_scan in &raw_scans {
+                    row_builder.add_raw_row(raw_scan).unwrap();
+                }
+                row_builder.to_arc_arrays()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let tf_messages: Vec<TFMessage> = (0..ROWS as i32).map(tf_message).collect();
+    let tf_fields = TFMessage::flat_arrow_fields(true);
+    group.bench_function("tf_message_flat", |b| {
+        b.iter(|| {
+            let mut row_builder = TFMessage::new_flat_row_builder(tf_fields.iter().collect());
+            for tf in &tf_messages {
+                row_builder.add_row(tf).unwrap();
+            }
+            row_builder.to_arc_arrays()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_row_builders);
+criterion_main!(benches);
@@ -1,8 +1,13 @@
+// Without the default features only the interface definitions are read, the code that scrapes
+// the r2r output is unused.
+#![cfg_attr(not(feature = "default"), allow(dead_code, unused_imports))]
+
 use proc_macro2::TokenStream;
 use quote::quote;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashSet;
 use std::env;
 use std::fmt::Display;
@@ -11,6 +16,7 @@ use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 use syn::parse_str;
 use syn::Ident;
@@ -19,6 +25,15 @@ use syn::ItemMod;
 use syn::{visit::Visit, ItemStruct, Type};
 use walkdir::WalkDir;
 
+use r2a_codegen::*;
+
+#[path = "codegen/ros_idl.rs"]
+mod ros_idl;
+use ros_idl::{InterfaceSource, ROSConstant, ROSType};
+
+#[path = "codegen/message_types.rs"]
+mod message_types;
+
 enum SourceCode {
     TokenStream(TokenStream),
     #[allow(dead_code)]
@@ -35,7 +50,6 @@ impl Display for SourceCode {
 }
 
 struct StructVisitor<'a> {
-    schema_name_format: String,
     structs_by_schema: &'a mut BTreeMap<String, ROSStruct>,
     structs_by_type: &'a mut BTreeMap<String, ROSStruct>,
     module_stack: Vec<String>,
@@ -53,6 +67,8 @@ struct ROSStruct {
     packaged_name: String,
     schema_name: String,
     fields: Vec<ROSField>,
+    /// Only known when the struct is read from the interface definitions.
+    constants: Vec<ROSConstant>,
 }
 
 impl ROSStruct {
@@ -61,6 +77,7 @@ impl ROSStruct {
             packaged_name,
             schema_name,
             fields: Vec::new(),
+            constants: Vec::new(),
         }
     }
 
@@ -73,11 +90,22 @@ impl ROSStruct {
 struct ROSField {
     name: String,
     native_type: String,
+    /// Only known when the struct is read from the interface definitions.
+    ros_type: Option<ROSType>,
 }
 
 impl ROSField {
     pub fn new(name: String, native_type: String) -> Self {
-        ROSField { name, native_type }
+        ROSField {
+            name,
+            native_type,
+            ros_type: None,
+        }
+    }
+
+    pub fn with_ros_type(mut self, ros_type: ROSType) -> Self {
+        self.ros_type = Some(ros_type);
+        self
     }
 }
 
@@ -138,7 +166,7 @@ impl<'a> Visit<'a> for StructVisitor<'a> {
         package_name.push_str("::");
         package_name.push_str(&i.ident.to_string());
         if self.valid_structs.contains(&package_name) {
-            let schema_name = self.schema_name_format.replace("{}", &i.ident.to_string());
+            let schema_name = ros_schema_name(&self.module_stack, &i.ident.to_string());
 
             let mut my_struct = ROSStruct::new(package_name.clone(), schema_name.clone());
 
@@ -158,6 +186,29 @@ impl<'a> Visit<'a> for StructVisitor<'a> {
     }
 }
 
+/// Returns the ROS schema name of a struct based on the module it was found in. r2r generates
+/// messages into `r2r::<pkg>::msg`, the request and response of a service into
+/// `r2r::<pkg>::srv::<Service>`, and the goal, result, feedback and the wrapping service and
+/// feedback types of an action into `r2r::<pkg>::action::<Action>`, with the services in
+/// `SendGoal` and `GetResult` submodules.
+fn ros_schema_name(module_stack: &[String], struct_name: &str) -> String {
+    match module_stack {
+        [_, package, interface_kind, interface]
+            if interface_kind == "srv" || interface_kind == "action" =>
+        {
+            format!(
+                "{}/{}/{}_{}",
+                package, interface_kind, interface, struct_name
+            )
+        }
+        [_, package, interface_kind, action, service] if interface_kind == "action" => {
+            format!("{}/action/{}_{}_{}", package, action, service, struct_name)
+        }
+        [_, package, ..] => format!("{}/msg/{}", package, struct_name),
+        _ => struct_name.to_string(),
+    }
+}
+
 fn create_name(original_name: &str, suffix: &str) -> String {
     let name = format!("{}{}", original_name, suffix);
     name.replace("::", "_").replace('/', "_")
@@ -170,108 +221,123 @@ fn create_name_identity(original_name: &str, suffix: &str) -> Ident {
     )
 }
 
+/// The segments of the module the r2a items of `packaged_name` are generated in. They mirror the
+/// r2r modules, for example `["sensor_msgs", "msg"]` for `r2r::sensor_msgs::msg::LaserScan`.
+fn generated_module_path(packaged_name: &str) -> Vec<String> {
+    let mut segments: Vec<String> = packaged_name
+        .split("::")
+        .skip(1)
+        .map(|segment| segment.to_string())
+        .collect();
+    segments.pop();
+    segments
+}
+
+/// The identifier of an item generated for `packaged_name`, as defined in its own module, for
+/// example `LaserScan_Schema`.
+fn create_item_identity(packaged_name: &str, suffix: &str) -> Ident {
+    let struct_name = packaged_name.rsplit("::").next().unwrap();
+    create_name_identity(struct_name, suffix)
+}
+
+/// The name of an item generated for `packaged_name` as seen from the users of r2a, for example
+/// `r2a::sensor_msgs::msg::LaserScan_RowBuilder`. Used in log messages.
+fn generated_item_name(packaged_name: &str, suffix: &str) -> String {
+    let struct_path = packaged_name.strip_prefix("r2r::").unwrap_or(packaged_name);
+    format!("r2a::{}{}", struct_path, suffix)
+}
+
+/// The path of an item generated for `packaged_name`, for example
+/// `crate::sensor_msgs::msg::LaserScan_Schema`, to refer to it from other modules.
+fn create_item_path(packaged_name: &str, suffix: &str) -> TokenStream {
+    let modules = generated_module_path(packaged_name)
+        .into_iter()
+        .map(|module| Ident::new(&module, proc_macro2::Span::call_site()));
+    let item = create_item_identity(packaged_name, suffix);
+    quote!(crate::#(#modules::)*#item)
+}
+
 fn is_desired_trait(path: &syn::Path, trait_name: &str) -> bool {
     path.segments
         .last()
         .map_or(false, |segment| segment.ident == trait_name)
 }
 
-fn type_to_string(ty: &Type) -> String {
-    match ty {
-        Type::Path(type_path) => {
-            type_path
-                .path
-                .segments
-                .iter()
-                .map(|segment| {
-                    let ident = segment.ident.to_string();
-                    if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
-                        let generics = args
-                            .args
-                            .iter()
-                            .map(|arg| {
-                                match arg {
-                                    syn::GenericArgument::Type(ty) => type_to_string(ty),
-                                    // Handle other types of generic arguments...
-                                    _ => "".to_string(),
-                                }
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ");
-                        format!("{}<{}>", ident, generics)
-                    } else {
-                        ident
-                    }
-                })
-                .collect::<Vec<_>>()
-                .join("::")
-        }
-        _ => format!("{:?}", "x"),
+fn generate_imports() -> TokenStream {
+    quote! {
+        //use crate::{ROSField, ROSStruct};
+        #[allow(unused_imports)]
+        use crate::introspection::{ArraySize, FieldEntry, TypeEntry};
     }
 }
 
-fn rust_type_to_arrow_type_token_stream(
-    typ: &str,
-    field_name: &str,
-    nullable: bool,
-) -> TokenStream {
-    if typ == "Vec<u8>" {
-        quote!(Field::new(#field_name, DataType::LargeBinary, #nullable))
-    } else if typ.starts_with("Vec") {
-        let type_token = match typ {
-            "Vec<bool>" => quote!(DataType::Boolean),
-            "Vec<str>" | "Vec<std::string::String>" => quote!(DataType::Utf8),
-            "Vec<char>" => quote!(DataType::Utf8),
-            "Vec<i8>" => quote!(DataType::Int8),
-            "Vec<i16>" => quote!(DataType::Int16),
-            "Vec<i32>" => quote!(DataType::Int32),
-            "Vec<i64>" => quote!(DataType::Int64),
-            "Vec<i128>" => quote!(DataType::Int64),
-            "Vec<isize>" => quote!(DataType::Int64),
-            "Vec<u16>" => quote!(DataType::UInt16),
-            "Vec<u32>" => quote!(DataType::UInt32),
-            "Vec<u64>" => quote!(DataType::UInt64),
-            "Vec<u128>" => quote!(DataType::UInt64),
-            "Vec<usize>" => quote!(DataType::UInt64),
-            "Vec<f32>" => quote!(DataType::Float32),
-            "Vec<f64>" => quote!(DataType::Float64),
-            typ => panic!("Unupported type: {}", typ), // I guess in this case we just can't build?
-        };
-        quote!(Field::new(#field_name, DataType::LargeList(Arc::new(Field::new("item", #type_token, #nullable))), #nullable))
+fn generate_arrow_imports() -> TokenStream {
+    let r2r_imports = if cfg!(feature = "offline") {
+        quote! {
+            #[allow(unused_imports)]
+            use crate::cdr::CdrMessage;
+            #[allow(unused_imports)]
+            use crate::msgs as r2r;
+        }
     } else {
-        let type_token = match typ {
-            "bool" => quote!(DataType::Boolean),
-            "str" | "std::string::String" | "char" => quote!(DataType::Utf8),
-            "()" => quote!(DataType::Null),
-            "i8" => quote!(DataType::Int8),
-            "i16" => quote!(DataType::Int16),
-            "i32" => quote!(DataType::Int32),
-            "i64" => quote!(DataType::Int64),
-            "i128" => quote!(DataType::Int64), // Not exactly sure how to support this, but I haven't seen any ROS messages with this length
-            "isize" => quote!(DataType::Int64),
-            "u8" => quote!(DataType::UInt8),
-            "u16" => quote!(DataType::UInt16),
-            "u32" => quote!(DataType::UInt32),
-            "u64" => quote!(DataType::UInt64),
-            "u128" | "usize" => quote!(DataType::UInt64), // Arrow doesn't have u128
-            "f32" => quote!(DataType::Float32),
-            "f64" => quote!(DataType::Float64),
-            typ => panic!("Unupported type: {}", typ), // I guess in this case we just can't build?
-        };
-        quote!(Field::new(#field_name, #type_token, #nullable))
-    }
-}
-
-fn generate_imports() -> TokenStream {
+        quote! {
+            #[allow(unused_imports)]
+            use r2r::WrappedTypesupport;
+        }
+    };
     quote! {
-        //use crate::{ROSField, ROSStruct};
+        use arrow_schema::{DataType, Field};
+        #r2r_imports
     }
 }
 
-fn generate_arrow_imports() -> TokenStream {
+fn generate_module_imports() -> TokenStream {
+    // With the `offline` feature the message types are generated into `r2a::msgs`, which
+    // mirrors the root of r2r, and they are serialized with `r2a::cdr`.
+    let r2r_imports = if cfg!(feature = "offline") {
+        quote! {
+            #[allow(unused_imports)]
+            use crate::cdr::CdrMessage;
+            #[allow(unused_imports)]
+            use crate::msgs as r2r;
+        }
+    } else {
+        quote! {
+            #[allow(unused_imports)]
+            use r2r::WrappedTypesupport;
+        }
+    };
+    let descriptor_imports = if cfg!(feature = "descriptor-builders") {
+        quote! {
+            #[allow(unused_imports)]
+            use crate::descriptor::{FieldDescriptor, FieldKind, FieldValue, PrimitiveKind, Reflect, TypeDescriptor};
+            #[allow(unused_imports)]
+            use crate::StructRowBuilder;
+        }
+    } else {
+        quote!()
+    };
     quote! {
+        #[allow(unused_imports)]
+        use crate::message_struct::{message_struct_encoding, message_struct_to_json, MessageStructEncoding, MESSAGE_STRUCT_ENCODING_KEY};
+        #[allow(unused_imports)]
+        use crate::row_context::{is_row_context_field, RowContext, RowContextBuilder};
+        #[allow(unused_imports)]
+        use crate::row_filter::{RowFilter, RowFilterStats};
+        #[allow(unused_imports)]
+        use crate::{ArrowSupport, RowBuilder};
+        #[allow(unused_imports)]
+        use anyhow::Result;
+        #[allow(unused_imports)]
+        use arrow_array::builder::ArrayBuilder;
+        #[allow(unused_imports)]
+        use arrow_array::Array;
+        #[allow(unused_imports)]
         use arrow_schema::{DataType, Field, Fields, Schema};
-        use r2r::{WrappedTypesupport};
+        #[allow(unused_imports)]
+        use std::sync::Arc;
+        #r2r_imports
+        #descriptor_imports
     }
 }
 
@@ -279,15 +345,89 @@ fn generate_supported_schema_list(structs_by_schema: &BTreeMap<String, ROSStruct
     let schema_names = structs_by_schema
         .values()
         .map(|ros_struct| &ros_struct.schema_name);
+    let keywords = RUST_KEYWORDS.iter();
 
     let gen_function = quote! {
 
         pub static SUPPORTED_SCHEMAS: &'static [&'static str] = &[#(#schema_names),*];
+
+        /// The Rust keywords r2r appends an underscore to in field names.
+        pub(crate) static RUST_KEYWORDS: &[&str] = &[#(#keywords),*];
     };
 
     gen_function
 }
 
+/// The table `r2a::type_descriptor` builds the descriptors from, sorted by schema name.
+fn generate_type_table(
+    structs_by_schema: &BTreeMap<String, ROSStruct>,
+    structs_by_type: &BTreeMap<String, ROSStruct>,
+) -> TokenStream {
+    let type_entries = structs_by_schema.values().map(|ros_struct| {
+        let schema_name = &ros_struct.schema_name;
+        let schema_fn = create_item_path(&ros_struct.packaged_name, "_Schema");
+        let field_entries = ros_struct.fields.iter().map(|field| {
+            let name = &field.name;
+            let (ros_type, array, string_bound) = match &field.ros_type {
+                Some(ros_type) => {
+                    let array = match ros_type.array {
+                        Some(ros_idl::ArraySize::Unbounded) => quote!(Some(ArraySize::Unbounded)),
+                        Some(ros_idl::ArraySize::Fixed(size)) => {
+                            quote!(Some(ArraySize::Fixed(#size)))
+                        }
+                        Some(ros_idl::ArraySize::Bounded(bound)) => {
+                            quote!(Some(ArraySize::Bounded(#bound)))
+                        }
+                        None => quote!(None),
+                    };
+                    let string_bound = match ros_type.string_bound {
+                        Some(bound) => quote!(Some(#bound)),
+                        None => quote!(None),
+                    };
+                    (ros_type.base_type.clone(), array, string_bound)
+                }
+                None => {
+                    let array = if field.native_type.starts_with("Vec<") {
+                        quote!(Some(ArraySize::Unbounded))
+                    } else {
+                        quote!(None)
+                    };
+                    (
+                        ros_idl::ros_type_of_native_type(&field.native_type),
+                        array,
+                        quote!(None),
+                    )
+                }
+            };
+            let nested_type = match referenced_struct_type(&field.native_type)
+                .and_then(|referenced| structs_by_type.get(&referenced))
+            {
+                Some(nested) => {
+                    let nested_schema_name = &nested.schema_name;
+                    quote!(Some(#nested_schema_name))
+                }
+                None => quote!(None),
+            };
+            quote!(FieldEntry {
+                name: #name,
+                ros_type: #ros_type,
+                array: #array,
+                string_bound: #string_bound,
+                nested_type: #nested_type,
+            })
+        });
+        quote!(TypeEntry {
+            schema_name: #schema_name,
+            arrow_fields: #schema_fn,
+            fields: &[#(#field_entries),*],
+        })
+    });
+
+    quote! {
+        pub(crate) static TYPE_TABLE: &[TypeEntry] = &[#(#type_entries),*];
+    }
+}
+
 fn generate_arrow_schema_fields(
     schema: &str,
     structs_by_schema: &BTreeMap<String, ROSStruct>,
@@ -312,44 +452,7 @@ fn generate_arrow_schema_fields(
         };
 
         let mut typ: Vec<TokenStream> = match field.native_type.as_str() {
-            "bool"
-            | "str"
-            | "char"
-            | "()"
-            | "i8"
-            | "i16"
-            | "i32"
-            | "i64"
-            | "i128"
-            | "isize"
-            | "u8"
-            | "u16"
-            | "u32"
-            | "u64"
-            | "u128"
-            | "usize"
-            | "f32"
-            | "f64"
-            | "std::string::String"
-            | "Vec<bool>"
-            | "Vec<str>"
-            | "Vec<char>"
-            | "Vec<()>"
-            | "Vec<i8>"
-            | "Vec<i16>"
-            | "Vec<i32>"
-            | "Vec<i64>"
-            | "Vec<i128>"
-            | "Vec<isize>"
-            | "Vec<u8>"
-            | "Vec<u16>"
-            | "Vec<u32>"
-            | "Vec<u64>"
-            | "Vec<u128>"
-            | "Vec<usize>"
-            | "Vec<f32>"
-            | "Vec<f64>"
-            | "Vec<std::string::String>" => {
+            typ if is_primitive_type(typ) || is_primitive_vector_type(typ) => {
                 vec![rust_type_to_arrow_type_token_stream(
                     &field.native_type,
                     &field_name,
@@ -360,7 +463,7 @@ fn generate_arrow_schema_fields(
                 let typ = format!("r2r::{}", typ);
                 let field_struct = structs_by_type.get(&typ).unwrap();
 
-                let schema_fn = create_name_identity(&field_struct.packaged_name, "_Schema");
+                let schema_fn = create_item_path(&field_struct.packaged_name, "_Schema");
 
                 let nullable = true;
                 vec![quote!(
@@ -389,7 +492,7 @@ fn generate_arrow_schema_fields(
 
                 let suffix = if flat { "_FlatSchema" } else { "_Schema" };
 
-                let schema_fn = create_name_identity(&field_struct.packaged_name, suffix);
+                let schema_fn = create_item_path(&field_struct.packaged_name, suffix);
 
                 let nullable = true;
 
@@ -412,13 +515,14 @@ fn generate_arrow_schema_fields(
 fn generate_flat_arrow_schema(
     structs_by_schema: &BTreeMap<String, ROSStruct>,
     structs_by_type: &BTreeMap<String, ROSStruct>,
-) -> TokenStream {
-    let (schema_fn_call, schema_fn): (Vec<TokenStream>, Vec<TokenStream>) = structs_by_schema
+) -> (TokenStream, BTreeMap<String, TokenStream>) {
+    let (schema_fn_call, schema_fn): (Vec<TokenStream>, BTreeMap<String, TokenStream>) = structs_by_schema
         .values()
         .map(|ros_struct| {
             let schema_name = &ros_struct.schema_name;
-            let type_underscore_name_schema = create_name_identity(&ros_struct.packaged_name, "_FlatSchema");
-            let type_underscore_name_schema_struct = create_name_identity(&ros_struct.packaged_name, "_Schema");
+            let type_underscore_name_schema = create_item_identity(&ros_struct.packaged_name, "_FlatSchema");
+            let type_underscore_name_schema_path = create_item_path(&ros_struct.packaged_name, "_FlatSchema");
+            let type_underscore_name_schema_struct = create_item_identity(&ros_struct.packaged_name, "_Schema");
 
             let fields = generate_arrow_schema_fields(
                 schema_name,
@@ -430,7 +534,7 @@ fn generate_flat_arrow_schema(
             );
 
             let fn_call = quote!(
-                #schema_name => #type_underscore_name_schema(include_self_struct),
+                #schema_name => #type_underscore_name_schema_path(include_self_struct),
             );
 
             let schema_fn = quote!(
@@ -444,7 +548,7 @@ fn generate_flat_arrow_schema(
                 }
             );
 
-            (fn_call, schema_fn)
+            (fn_call, (ros_struct.packaged_name.clone(), schema_fn))
         })
         .unzip();
 
@@ -461,23 +565,123 @@ fn generate_flat_arrow_schema(
             }
         }
 
-        #(#schema_fn)*
-
     };
 
-    gen_function
+    (gen_function, schema_fn)
+}
+
+/// The schema dispatch of `r2a::new_raw_row_builder`.
+fn generate_raw_row_builder_dispatch(
+    structs_by_schema: &BTreeMap<String, ROSStruct>,
+) -> TokenStream {
+    let arms = structs_by_schema.values().map(|ros_struct| {
+        let schema_name = &ros_struct.schema_name;
+        let type_name: syn::Path = parse_str::<syn::Path>(&ros_struct.packaged_name).unwrap();
+        quote!(
+            #schema_name => Some(crate::raw_row_builder::boxed_raw_row_builder(
+                arrow_fields,
+                flat,
+                |bytes: &[u8]| Ok(#type_name::from_serialized_bytes(bytes)?),
+            )),
+        )
+    });
+
+    quote! {
+
+        #[allow(dead_code)]
+        pub(crate) fn new_raw_row_builder<'a>(ros_schema: &str, arrow_fields: Vec<&'a Field>, flat: bool) -> Option<Box<dyn crate::raw_row_builder::RawRowBuilder<'a> + 'a>> {
+            match ros_schema {
+                #(#arms)*
+                _ => None,
+            }
+        }
+
+    }
+}
+
+/// Returns true if `FromArrowValue` can read every field of the struct back from Arrow.
+fn supports_from_arrow(ros_struct: &ROSStruct) -> bool {
+    ros_struct.fields.iter().all(|field| {
+        let typ = field
+            .native_type
+            .strip_prefix("Vec<")
+            .and_then(|typ| typ.strip_suffix('>'))
+            .unwrap_or(&field.native_type);
+        !matches!(
+            typ,
+            "str" | "char" | "()" | "i128" | "isize" | "u128" | "usize"
+        )
+    })
+}
+
+fn generate_from_arrow_impls(structs_by_schema: &BTreeMap<String, ROSStruct>) -> TokenStream {
+    let impls = structs_by_schema
+        .values()
+        .filter(|ros_struct| supports_from_arrow(ros_struct))
+        .map(|ros_struct| {
+            let type_name: syn::Path = parse_str::<syn::Path>(&ros_struct.packaged_name).unwrap();
+            let fields = ros_struct.fields.iter().map(|field| {
+                let name = &field.name;
+                let ident = Ident::new(name, proc_macro2::Span::call_site());
+                quote!(#ident: crate::from_arrow::field_value(array, #name, row)?,)
+            });
+            // Messages without fields, like std_msgs/Empty, only check the row.
+            let array = if ros_struct.fields.is_empty() {
+                quote!(_)
+            } else {
+                quote!(array)
+            };
+            quote!(
+                impl crate::from_arrow::FromArrowValue for #type_name {
+                    fn from_arrow(array: &dyn arrow_array::Array, row: usize) -> anyhow::Result<Self> {
+                        let #array = crate::from_arrow::struct_value(array, row)?;
+                        Ok(#type_name {
+                            #(#fields)*
+                        })
+                    }
+                }
+            )
+        });
+
+    let arms = structs_by_schema
+        .values()
+        .filter(|ros_struct| supports_from_arrow(ros_struct))
+        .map(|ros_struct| {
+            let schema_name = &ros_struct.schema_name;
+            let type_name: syn::Path = parse_str::<syn::Path>(&ros_struct.packaged_name).unwrap();
+            quote!(
+                #schema_name => Some(|array: &dyn arrow_array::Array, row: usize| {
+                    let msg = <#type_name as crate::from_arrow::FromArrowValue>::from_arrow(array, row)?;
+                    Ok(msg.to_serialized_bytes()?)
+                }),
+            )
+        });
+
+    quote! {
+        #(#impls)*
+
+        #[allow(dead_code)]
+        pub(crate) fn message_serializer(ros_schema: &str) -> Option<crate::from_arrow::MessageSerializer> {
+            match ros_schema {
+                #(#arms)*
+                _ => None,
+            }
+        }
+    }
 }
 
 fn generate_arrow_schema(
     structs_by_schema: &BTreeMap<String, ROSStruct>,
     structs_by_type: &BTreeMap<String, ROSStruct>,
-) -> TokenStream {
-    let (schema_fn_call, schema_fn): (Vec<TokenStream>, Vec<TokenStream>) = structs_by_schema
+) -> (TokenStream, BTreeMap<String, TokenStream>) {
+    let (schema_fn_call, schema_fn): (Vec<TokenStream>, BTreeMap<String, TokenStream>) = structs_by_schema
         .values()
         .map(|ros_struct| {
             let schema_name = &ros_struct.schema_name;
             let type_underscore_name_schema =
-                create_name_identity(&ros_struct.packaged_name, "_Schema");
+                create_item_identity(&ros_struct.packaged_name, "_Schema");
+            let type_underscore_name_schema_path =
+                create_item_path(&ros_struct.packaged_name, "_Schema");
 
             let fields = generate_arrow_schema_fields(
                 schema_name,
@@ -489,7 +693,7 @@ fn generate_arrow_schema(
             );
 
             let fn_call = quote!(
-                #schema_name => #type_underscore_name_schema(include_self_struct),
+                #schema_name => #type_underscore_name_schema_path(include_self_struct),
             );
 
             let schema_fn = quote!(
@@ -503,7 +707,7 @@ fn generate_arrow_schema(
                 }
             );
 
-            (fn_call, schema_fn)
+            (fn_call, (ros_struct.packaged_name.clone(), schema_fn))
         })
         .unzip();
 
@@ -520,11 +724,9 @@ fn generate_arrow_schema(
             }
         }
 
-        #(#schema_fn)*
-
     };
 
-    gen_function
+    (gen_function, schema_fn)
 }
 
 enum FieldType {
@@ -553,11 +755,11 @@ fn rust_field_to_arrow_type_safe_token_stream(
 
     let (builder_type, builder_instantiation, builder_append, struct_builder_append) =
         match field_type {
-            FieldType::Struct(underlying_type_name_str) => {
+            FieldType::Struct(underlying_packaged_name) => {
                 let type_schema_fn_ident =
-                    create_name_identity(underlying_type_name_str.as_str(), schema_suffix);
+                    create_item_path(underlying_packaged_name.as_str(), schema_suffix);
                 let type_struct_builder_fn_ident =
-                    create_name_identity(underlying_type_name_str.as_str(), struct_builder_suffix);
+                    create_item_path(underlying_packaged_name.as_str(), struct_builder_suffix);
 
                 let builder_type = quote!(arrow_array::builder::StructBuilder);
                 let builder_instantiation = quote!(arrow_array::builder::StructBuilder::from_fields(#type_schema_fn_ident(false), 0));
@@ -581,13 +783,11 @@ fn rust_field_to_arrow_type_safe_token_stream(
                     struct_builder_append,
                 )
             }
-            FieldType::StructArray(object_array_underscore_name) => {
+            FieldType::StructArray(object_array_packaged_name) => {
                 let type_schema_fn_ident =
-                    create_name_identity(object_array_underscore_name.as_str(), schema_suffix);
-                let type_struct_builder_fn_ident = create_name_identity(
-                    object_array_underscore_name.as_str(),
-                    struct_builder_suffix,
-                );
+                    create_item_path(object_array_packaged_name.as_str(), schema_suffix);
+                let type_struct_builder_fn_ident =
+                    create_item_path(object_array_packaged_name.as_str(), struct_builder_suffix);
 
                 let builder_type = quote!(
                     arrow_array::builder::LargeListBuilder<arrow_array::builder::StructBuilder>
@@ -663,244 +863,6 @@ fn rust_field_to_arrow_type_safe_token_stream(
     }
 }
 
-fn primitive_vector_builder_components(
-    typ: &str,
-    path_field_name: &syn::Expr,
-    _flat: bool,
-    builder_field_name: &Ident,
-    index: &mut usize,
-) -> (TokenStream, TokenStream, TokenStream, TokenStream) {
-    let (builder_item_type, builder_item_instantiation, builder_append) = match typ {
-        "Vec<bool>" => (
-            quote!(arrow_array::builder::BooleanBuilder),
-            quote!(arrow_array::builder::BooleanBuilder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<str>" | "Vec<std::string::String>" => (
-            quote!(arrow_array::builder::StringBuilder),
-            quote!(arrow_array::builder::StringBuilder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(val.as_str()))),
-        ),
-        "Vec<char>" => (
-            quote!(arrow_array::builder::StringBuilder),
-            quote!(arrow_array::builder::StringBuilder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(val.to_string().as_str()))),
-        ),
-        "Vec<i8>" => (
-            quote!(arrow_array::builder::Int8Builder),
-            quote!(arrow_array::builder::Int8Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<i16>" => (
-            quote!(arrow_array::builder::Int16Builder),
-            quote!(arrow_array::builder::Int16Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<i32>" => (
-            quote!(arrow_array::builder::Int32Builder),
-            quote!(arrow_array::builder::Int32Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<i64>" => (
-            quote!(arrow_array::builder::Int64Builder),
-            quote!(arrow_array::builder::Int64Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<i128>" | "Vec<isize>" => (
-            quote!(arrow_array::builder::Int64Builder),
-            quote!(arrow_array::builder::Int64Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val as i64))), // Note: potential loss of data
-        ),
-        "Vec<u128>" | "Vec<usize>" => (
-            quote!(arrow_array::builder::UInt64Builder),
-            quote!(arrow_array::builder::UInt64Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val as u64))), // Note: potential loss of data
-        ),
-        "Vec<u8>" => (
-            quote!(arrow_array::builder::LargeBinaryBuilder),
-            quote!(arrow_array::builder::LargeBinaryBuilder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "Vec<u16>" => (
-            quote!(arrow_array::builder::UInt16Builder),
-            quote!(arrow_array::builder::UInt16Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<u32>" => (
-            quote!(arrow_array::builder::UInt32Builder),
-            quote!(arrow_array::builder::UInt32Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<u64>" => (
-            quote!(arrow_array::builder::UInt64Builder),
-            quote!(arrow_array::builder::UInt64Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<f32>" => (
-            quote!(arrow_array::builder::Float32Builder),
-            quote!(arrow_array::builder::Float32Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        "Vec<f64>" => (
-            quote!(arrow_array::builder::Float64Builder),
-            quote!(arrow_array::builder::Float64Builder::new()),
-            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
-        ),
-        _ => panic!("Unsupported type: {}", typ),
-    };
-    if typ == "Vec<u8>" {
-        (
-            quote!(#builder_item_type),
-            builder_item_instantiation,
-            quote!(self.#builder_field_name.as_mut().unwrap().append_value(&#builder_append)),
-            quote!(builder
-                    .field_builder::<#builder_item_type>(#index)
-                    .as_mut()
-                    .unwrap()
-                    .append_value(&#builder_append);
-            ),
-        )
-    } else {
-        wrap_primitive_vector_builder_components(
-            builder_item_type,
-            builder_item_instantiation,
-            builder_field_name,
-            builder_append,
-            index,
-            path_field_name,
-        )
-    }
-}
-
-fn wrap_primitive_vector_builder_components(
-    builder_item_type: TokenStream,
-    builder_item_instantiation: TokenStream,
-    builder_field_name: &Ident,
-    builder_append: TokenStream,
-    index: &mut usize,
-    path_field_name: &syn::Expr,
-) -> (TokenStream, TokenStream, TokenStream, TokenStream) {
-    (
-        quote!(arrow_array::builder::LargeListBuilder<#builder_item_type>),
-        quote!(arrow_array::builder::LargeListBuilder::new(#builder_item_instantiation)),
-        quote!(self.#builder_field_name.as_mut().unwrap().append_value(#builder_append)),
-        quote!({
-            let mut list_builder_option = builder.field_builder::<arrow_array::builder::LargeListBuilder<Box<dyn arrow_array::builder::ArrayBuilder>>>(#index);
-            let mut list_builder = list_builder_option.as_mut().unwrap();
-            let value_builder = list_builder.values().as_any_mut().downcast_mut::<#builder_item_type>().unwrap();
-            for value in msg.#path_field_name.iter() {
-              value_builder.append_value(value.clone());
-            }
-            list_builder.append(true);
-          }
-        ),
-    )
-}
-
-fn primitive_builder_components(
-    typ: &str,
-    path_field_name: syn::Expr,
-    builder_field_name: &Ident,
-    index: &mut usize,
-) -> (TokenStream, TokenStream, TokenStream, TokenStream) {
-    let (builder_item_type, builder_item_instantiation, builder_append) = match typ {
-        "bool" => (
-            quote!(arrow_array::builder::BooleanBuilder),
-            quote!(arrow_array::builder::BooleanBuilder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "str" | "std::string::String" => (
-            quote!(arrow_array::builder::StringBuilder),
-            quote!(arrow_array::builder::StringBuilder::new()),
-            quote!(msg.#path_field_name.as_str()),
-        ),
-        "char" => (
-            quote!(arrow_array::builder::StringBuilder),
-            quote!(arrow_array::builder::StringBuilder::new()),
-            quote!(msg.#path_field_name.to_string().as_str()),
-        ),
-        "()" => (
-            quote!(arrow_array::builder::NullBuilder),
-            quote!(arrow_array::builder::NullBuilder::new()),
-            quote!(None::<()>),
-        ),
-        "i8" => (
-            quote!(arrow_array::builder::Int8Builder),
-            quote!(arrow_array::builder::Int8Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "i16" => (
-            quote!(arrow_array::builder::Int16Builder),
-            quote!(arrow_array::builder::Int16Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "i32" => (
-            quote!(arrow_array::builder::Int32Builder),
-            quote!(arrow_array::builder::Int32Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "i64" => (
-            quote!(arrow_array::builder::Int64Builder),
-            quote!(arrow_array::builder::Int64Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        // Note: i128 and isize are mapped to Int64Builder with potential data loss
-        "i128" | "isize" => (
-            quote!(arrow_array::builder::Int64Builder),
-            quote!(arrow_array::builder::Int64Builder::new()),
-            quote!(msg.#path_field_name as i64),
-        ),
-        "u8" => (
-            quote!(arrow_array::builder::UInt8Builder),
-            quote!(arrow_array::builder::UInt8Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "u16" => (
-            quote!(arrow_array::builder::UInt16Builder),
-            quote!(arrow_array::builder::UInt16Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "u32" => (
-            quote!(arrow_array::builder::UInt32Builder),
-            quote!(arrow_array::builder::UInt32Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "u64" => (
-            quote!(arrow_array::builder::UInt64Builder),
-            quote!(arrow_array::builder::UInt64Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        // Note: u128 and usize are mapped to UInt64Builder with potential data loss
-        "u128" | "usize" => (
-            quote!(arrow_array::builder::UInt64Builder),
-            quote!(arrow_array::builder::UInt64Builder::new()),
-            quote!(msg.#path_field_name as u64),
-        ),
-        "f32" => (
-            quote!(arrow_array::builder::Float32Builder),
-            quote!(arrow_array::builder::Float32Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        "f64" => (
-            quote!(arrow_array::builder::Float64Builder),
-            quote!(arrow_array::builder::Float64Builder::new()),
-            quote!(msg.#path_field_name),
-        ),
-        _ => panic!("Unsupported type: {}", typ),
-    };
-
-    (
-        quote!(#builder_item_type),
-        builder_item_instantiation,
-        quote!(self.#builder_field_name.as_mut().unwrap().append_value(#builder_append)),
-        quote!(builder
-            .field_builder::<#builder_item_type>(#index)
-            .unwrap()
-            .append_value(#builder_append);
-        ),
-    )
-}
-
 struct ArrowSchemaField {
     builder_field_name: TokenStream,
     builder_type: TokenStream,
@@ -935,25 +897,7 @@ fn generate_arrow_schema_typesafe_parser_components(
         };
 
         let mut typ: Vec<ArrowSchemaField> = match field.native_type.as_str() {
-            "bool"
-            | "str"
-            | "char"
-            | "()"
-            | "i8"
-            | "i16"
-            | "i32"
-            | "i64"
-            | "i128"
-            | "isize"
-            | "u8"
-            | "u16"
-            | "u32"
-            | "u64"
-            | "u128"
-            | "usize"
-            | "f32"
-            | "f64"
-            | "std::string::String" => {
+            typ if is_primitive_type(typ) => {
                 vec![rust_field_to_arrow_type_safe_token_stream(
                     &field_name,
                     &dotted_path,
@@ -963,25 +907,7 @@ fn generate_arrow_schema_typesafe_parser_components(
                     index,
                 )]
             }
-            "Vec<bool>"
-            | "Vec<str>"
-            | "Vec<char>"
-            | "Vec<()>"
-            | "Vec<i8>"
-            | "Vec<i16>"
-            | "Vec<i32>"
-            | "Vec<i64>"
-            | "Vec<i128>"
-            | "Vec<isize>"
-            | "Vec<u8>"
-            | "Vec<u16>"
-            | "Vec<u32>"
-            | "Vec<u64>"
-            | "Vec<u128>"
-            | "Vec<usize>"
-            | "Vec<f32>"
-            | "Vec<f64>"
-            | "Vec<std::string::String>" => {
+            typ if is_primitive_vector_type(typ) => {
                 vec![rust_field_to_arrow_type_safe_token_stream(
                     &field_name,
                     &dotted_path,
@@ -1009,14 +935,11 @@ fn generate_arrow_schema_typesafe_parser_components(
                 let typ = format!("r2r::{}", typ);
                 let field_struct = structs_by_type.get(&typ).unwrap();
 
-                let type_underscore_name_str =
-                    field_struct.packaged_name.replace("::", "_").to_string();
-
                 vec![rust_field_to_arrow_type_safe_token_stream(
                     &field_name,
                     &dotted_path,
                     typ.as_str(),
-                    FieldType::Struct(type_underscore_name_str),
+                    FieldType::Struct(field_struct.packaged_name.clone()),
                     flat,
                     index,
                 )]
@@ -1026,14 +949,11 @@ fn generate_arrow_schema_typesafe_parser_components(
                 let typ = format!("r2r::{}", typ);
                 let field_struct = structs_by_type.get(&typ).unwrap();
 
-                let type_underscore_name_str =
-                    field_struct.packaged_name.replace("::", "_").to_string();
-
                 vec![rust_field_to_arrow_type_safe_token_stream(
                     &field_name,
                     &dotted_path,
                     typ.as_str(),
-                    FieldType::StructArray(type_underscore_name_str),
+                    FieldType::StructArray(field_struct.packaged_name.clone()),
                     flat,
                     index,
                 )]
@@ -1047,33 +967,29 @@ fn generate_arrow_schema_typesafe_parser_components(
 fn generate_arrow_rowbuilders(
     structs_by_schema: &BTreeMap<String, ROSStruct>,
     structs_by_type: &BTreeMap<String, ROSStruct>,
-) -> TokenStream {
-    let instantiation_and_row_appender: Vec<(TokenStream, TokenStream)> = structs_by_schema
+) -> BTreeMap<String, TokenStream> {
+    structs_by_schema
         .values()
         .map(|ros_struct| {
             let schema_name = &ros_struct.schema_name;
             let type_name_str = &ros_struct.packaged_name;
             let type_name: syn::Path = parse_str::<syn::Path>(type_name_str).unwrap();
             let rowbuilder_trait = create_name_identity("RowBuilder", "");
-            let type_underscore_name_str = create_name(&ros_struct.packaged_name, "_RowBuilder");
-            let type_underscore_name = create_name_identity(&type_underscore_name_str, "");
+            let type_underscore_name_str =
+                generated_item_name(&ros_struct.packaged_name, "_RowBuilder");
+            let type_underscore_name =
+                create_item_identity(&ros_struct.packaged_name, "_RowBuilder");
             let struct_builder_fn_ident =
-                create_name_identity(&ros_struct.packaged_name, "_StructBuilder");
-            let struct_schema_fn_ident = create_name_identity(&ros_struct.packaged_name, "_Schema");
+                create_item_identity(&ros_struct.packaged_name, "_StructBuilder");
+            let struct_schema_fn_ident = create_item_identity(&ros_struct.packaged_name, "_Schema");
 
             let rowbuilder_trait_flat = create_name_identity("RowBuilder", "");
             let type_underscore_name_flat_str =
-                create_name(&ros_struct.packaged_name, "_FlatRowBuilder");
+                generated_item_name(&ros_struct.packaged_name, "_FlatRowBuilder");
             let type_underscore_name_flat =
-                create_name_identity(&type_underscore_name_flat_str, "");
+                create_item_identity(&ros_struct.packaged_name, "_FlatRowBuilder");
             let struct_builder_fn_flat_ident =
-                create_name_identity(&ros_struct.packaged_name, "_FlatStructBuilder");
-            let schema_fn_flat_ident =
-                create_name_identity(&ros_struct.packaged_name, "_FlatSchema");
-
-            let instantion = quote!(
-                #schema_name => Box::new(#type_underscore_name::new(fields)),
-            );
+                create_item_identity(&ros_struct.packaged_name, "_FlatStructBuilder");
 
             let rowbuilder_tokens = generate_rowbuilder_tokens(
                 false,
@@ -1105,82 +1021,260 @@ fn generate_arrow_rowbuilders(
                 &struct_builder_fn_ident,
             );
 
-            let arrow_support = quote! (
-                impl<'a> ArrowSupport<'a> for #type_name {
-                    type RowBuilderType = #type_underscore_name<'a>;
-                    type FlatRowBuilderType = #type_underscore_name_flat<'a>;
+            let arrow_support = generate_arrow_support(
+                ros_struct,
+                quote!(#type_underscore_name<'a>),
+                quote!(#type_underscore_name_flat<'a>),
+                quote!(Self::RowBuilderType::new(arrow_fields)),
+                quote!(Self::FlatRowBuilderType::new(arrow_fields)),
+                quote!(#struct_builder_fn_ident(self, builder)),
+            );
 
-                    fn schema_name() -> &'static str{
-                        #schema_name
-                    }
+            (
+                ros_struct.packaged_name.clone(),
+                quote!(
 
-                    fn new_row_builder(arrow_fields: Vec<&'a Field>) -> Self::RowBuilderType {
-                        Self::RowBuilderType::new(arrow_fields)
-                    }
+                    #arrow_support
 
-                    fn new_flat_row_builder(arrow_fields: Vec<&'a Field>) -> Self::FlatRowBuilderType {
-                        Self::FlatRowBuilderType::new(arrow_fields)
-                    }
+                    #rowbuilder_tokens
 
-                    fn arrow_fields(include_self: bool) -> Vec<Field> {
-                        #struct_schema_fn_ident(include_self)
-                    }
+                    #flat_rowbuilder_tokens
 
-                    fn arrow_schema(include_self: bool) -> Schema {
-                        Schema::new(Self::arrow_fields(include_self))
-                    }
+                ),
+            )
+        })
+        .collect()
+}
 
-                    fn flat_arrow_fields(include_self: bool) -> Vec<Field> {
-                        #schema_fn_flat_ident(include_self)
-                    }
+/// The variant of `r2a::descriptor::PrimitiveKind` of a scalar field type, as its name.
+fn primitive_kind_name(typ: &str) -> &'static str {
+    match typ {
+        "bool" => "Bool",
+        "i8" => "I8",
+        "i16" => "I16",
+        "i32" => "I32",
+        "i64" => "I64",
+        "u8" => "U8",
+        "u16" => "U16",
+        "u32" => "U32",
+        "u64" => "U64",
+        "f32" => "F32",
+        "f64" => "F64",
+        "std::string::String" => "String",
+        typ => panic!("Unsupported type: {}", typ),
+    }
+}
 
-                    fn flat_arrow_schema(include_self: bool) -> Schema {
-                        Schema::new(Self::flat_arrow_fields(include_self))
-                    }
-                }
+/// The `FieldKind` of a field of a `TypeDescriptor`, and the `FieldValue` that `Reflect::field`
+/// hands out for it.
+fn generate_field_descriptor(
+    field: &ROSField,
+    structs_by_type: &BTreeMap<String, ROSStruct>,
+) -> (TokenStream, TokenStream) {
+    let field_ident = create_name_identity(&field.name, "");
+    let typ = field.native_type.as_str();
+    if is_primitive_type(typ) {
+        let kind = create_name_identity(primitive_kind_name(typ), "");
+        let value = if typ == "std::string::String" {
+            quote!(FieldValue::#kind(&self.#field_ident))
+        } else {
+            quote!(FieldValue::#kind(self.#field_ident))
+        };
+        (quote!(FieldKind::Primitive(PrimitiveKind::#kind)), value)
+    } else if is_primitive_vector_type(typ) {
+        let item_kind = primitive_kind_name(&typ[4..typ.len() - 1]);
+        let kind = create_name_identity(item_kind, "");
+        let value = if item_kind == "U8" {
+            create_name_identity("Bytes", "")
+        } else {
+            create_name_identity(item_kind, "List")
+        };
+        (
+            quote!(FieldKind::PrimitiveList(PrimitiveKind::#kind)),
+            quote!(FieldValue::#value(&self.#field_ident)),
+        )
+    } else {
+        let referenced = referenced_struct_type(typ).unwrap();
+        let field_struct = structs_by_type.get(&referenced).unwrap();
+        let descriptor = create_item_path(&field_struct.packaged_name, "_Descriptor");
+        if typ.starts_with("Vec") {
+            (
+                quote!(FieldKind::StructList(&#descriptor)),
+                quote!(FieldValue::StructList(&self.#field_ident)),
+            )
+        } else {
+            (
+                quote!(FieldKind::Struct(&#descriptor)),
+                quote!(FieldValue::Struct(&self.#field_ident)),
+            )
+        }
+    }
+}
+
+/// The code of the `descriptor-builders` feature: instead of the row builders and struct builder
+/// functions of `generate_arrow_rowbuilders`, every type gets a static `TypeDescriptor`, a
+/// `Reflect` implementation and an `ArrowSupport` implementation that uses the generic
+/// `StructRowBuilder`.
+fn generate_descriptor_rowbuilders(
+    structs_by_schema: &BTreeMap<String, ROSStruct>,
+    structs_by_type: &BTreeMap<String, ROSStruct>,
+) -> BTreeMap<String, TokenStream> {
+    structs_by_schema
+        .values()
+        .map(|ros_struct| {
+            let schema_name = &ros_struct.schema_name;
+            let type_name: syn::Path = parse_str::<syn::Path>(&ros_struct.packaged_name).unwrap();
+            let descriptor_ident = create_item_identity(&ros_struct.packaged_name, "_Descriptor");
+            let stamp_nanos = if has_header(ros_struct) {
+                let stamp_nanos = generate_stamp_nanos(ros_struct);
+                quote!(|msg: &#type_name| #stamp_nanos)
+            } else {
+                quote!(|_: &#type_name| None)
+            };
+
+            let (field_descriptors, field_values): (Vec<TokenStream>, Vec<TokenStream>) = ros_struct
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(index, field)| {
+                    let field_name = &field.name;
+                    let (kind, value) = generate_field_descriptor(field, structs_by_type);
+                    (
+                        quote!(FieldDescriptor { name: #field_name, kind: #kind }),
+                        quote!(#index => Some(#value),),
+                    )
+                })
+                .unzip();
+
+            let new_row_builder = |flat: bool| {
+                quote!(
+                    StructRowBuilder::new(arrow_fields, #flat, #stamp_nanos)
+                        .with_deserializer(|bytes: &[u8]| Ok(#type_name::from_serialized_bytes(bytes)?))
+                )
+            };
+            let arrow_support = generate_arrow_support(
+                ros_struct,
+                quote!(StructRowBuilder<'a, #type_name>),
+                quote!(StructRowBuilder<'a, #type_name>),
+                new_row_builder(false),
+                new_row_builder(true),
+                quote!(crate::descriptor::append_to_struct_builder(self, builder)),
             );
 
             (
-                instantion,
+                ros_struct.packaged_name.clone(),
                 quote!(
+                    #[allow(non_upper_case_globals)]
+                    pub static #descriptor_ident: TypeDescriptor = TypeDescriptor {
+                        schema_name: #schema_name,
+                        fields: &[#(#field_descriptors),*],
+                    };
+
+                    impl Reflect for #type_name {
+                        fn descriptor(&self) -> &'static TypeDescriptor {
+                            &#descriptor_ident
+                        }
+
+                        fn field(&self, index: usize) -> Option<FieldValue<'_>> {
+                            match index {
+                                #(#field_values)*
+                                _ => None,
+                            }
+                        }
+                    }
 
                     #arrow_support
+                ),
+            )
+        })
+        .collect()
+}
 
-                    // #[allow(non_snake_case,unused)]
-                    // pub fn #flat_struct_builder_fn_ident(msg : &#type_name, builder: &mut arrow_array::builder::StructBuilder) {
-                    //     #(#flat_struct_builder_appends)*
-                    //     builder.append(true);
-                    // }
+/// The `ArrowSupport` implementation of a ROS type. The row builders and the way messages are
+/// appended to a StructBuilder differ between the generated and the descriptor driven builders,
+/// the rest is the same.
+fn generate_arrow_support(
+    ros_struct: &ROSStruct,
+    row_builder_type: TokenStream,
+    flat_row_builder_type: TokenStream,
+    new_row_builder: TokenStream,
+    new_flat_row_builder: TokenStream,
+    append_to_struct_builder: TokenStream,
+) -> TokenStream {
+    let schema_name = &ros_struct.schema_name;
+    let type_name: syn::Path = parse_str::<syn::Path>(&ros_struct.packaged_name).unwrap();
+    let struct_schema_fn_ident = create_item_identity(&ros_struct.packaged_name, "_Schema");
+    let schema_fn_flat_ident = create_item_identity(&ros_struct.packaged_name, "_FlatSchema");
+
+    quote! (
+        impl<'a> ArrowSupport<'a> for #type_name {
+            type RowBuilderType = #row_builder_type;
+            type FlatRowBuilderType = #flat_row_builder_type;
+
+            fn schema_name() -> &'static str{
+                #schema_name
+            }
 
-                    #rowbuilder_tokens
+            fn new_row_builder(arrow_fields: Vec<&'a Field>) -> Self::RowBuilderType {
+                #new_row_builder
+            }
 
-                    #flat_rowbuilder_tokens
+            fn new_flat_row_builder(arrow_fields: Vec<&'a Field>) -> Self::FlatRowBuilderType {
+                #new_flat_row_builder
+            }
 
-                ),
-            )
-        })
-        .collect();
+            fn arrow_fields(include_self: bool) -> Vec<Field> {
+                #struct_schema_fn_ident(include_self)
+            }
 
-    let (_, row_appenders): (Vec<TokenStream>, Vec<TokenStream>) =
-        instantiation_and_row_appender.into_iter().unzip();
+            fn arrow_schema(include_self: bool) -> Schema {
+                Schema::new(Self::arrow_fields(include_self))
+            }
 
-    let gen_function = quote! {
+            fn flat_arrow_fields(include_self: bool) -> Vec<Field> {
+                #schema_fn_flat_ident(include_self)
+            }
 
-        // pub(crate) fn new_row_builder_for_schema<'a>(ros_schema : &str, fields: Vec<&'a Field>) -> Box<dyn RowBuilder<'a, T> + 'a> {
-        //     match ros_schema {
-        //         #(#instantiations)*
-        //         unsupported_schema => {
-        //             log::warn!("Unsupported schema: {}", unsupported_schema);
-        //             panic!("Unsupported schema: {}", unsupported_schema);
-        //             //Box::new(RawMessageRowBuilder::new(fields))
-        //         },
-        //     }
-        // }
-
-       #(#row_appenders)*
-    };
+            fn flat_arrow_schema(include_self: bool) -> Schema {
+                Schema::new(Self::flat_arrow_fields(include_self))
+            }
 
-    gen_function
+            fn message_struct_field(name: &str, encoding: MessageStructEncoding) -> Field {
+                let field = match encoding {
+                    MessageStructEncoding::Struct => Field::new_struct(name, #struct_schema_fn_ident(false), true),
+                    MessageStructEncoding::FlatStruct => Field::new_struct(name, #schema_fn_flat_ident(false), true),
+                    MessageStructEncoding::Json => Field::new(name, DataType::Utf8, true),
+                };
+                field.with_metadata(std::collections::HashMap::from([(
+                    MESSAGE_STRUCT_ENCODING_KEY.to_string(),
+                    encoding.as_str().to_string(),
+                )]))
+            }
+
+            fn append_to_struct_builder(&self, builder: &mut arrow_array::builder::StructBuilder) {
+                #append_to_struct_builder
+            }
+        }
+    )
+}
+
+/// Messages with a standard header are rate limited by their header stamp.
+fn has_header(ros_struct: &ROSStruct) -> bool {
+    ros_struct
+        .fields
+        .iter()
+        .any(|field| field.name == "header" && field.native_type == "std_msgs::msg::Header")
+}
+
+/// The time of a message for the rate limit of a `RowFilter`, as an expression of `msg`.
+fn generate_stamp_nanos(ros_struct: &ROSStruct) -> TokenStream {
+    if has_header(ros_struct) {
+        quote!(Some(
+            msg.header.stamp.sec as i64 * 1_000_000_000 + msg.header.stamp.nanosec as i64
+        ))
+    } else {
+        quote!(None::<i64>)
+    }
 }
 
 fn generate_rowbuilder_tokens(
@@ -1256,18 +1350,33 @@ fn generate_rowbuilder_tokens(
     let builder_finish: Vec<&TokenStream> =
         fields.iter().map(|field| &field.builder_finish).collect();
 
+    let flat_schema_fn_ident = create_item_identity(type_name_str, "_FlatSchema");
+    let flat_struct_builder_fn_ident = create_item_identity(type_name_str, "_FlatStructBuilder");
+
+    let deserialize_result = if cfg!(feature = "offline") {
+        quote!(anyhow::Result)
+    } else {
+        quote!(r2r::Result)
+    };
+
+    let stamp_nanos = generate_stamp_nanos(structs_by_schema.get(schema_name).unwrap());
+
     quote!(
         #[allow(non_camel_case_types)]
         pub struct #type_underscore_name<'a> {
             _arrow_fields: Vec<&'a Field>,
             #(#builder_field_definitions)*
             message_struct: Option<arrow_array::builder::StructBuilder>,
+            message_struct_name: Option<String>,
+            message_struct_encoding: MessageStructEncoding,
+            _context: RowContextBuilder,
+            _filter: Option<RowFilter<#type_name>>,
             _phantom: std::marker::PhantomData<&'a ()>,
         }
 
         impl<'a> #type_underscore_name<'a> {
 
-            pub fn deserialize(ser_msg : &[u8]) -> r2r::Result<#type_name> {
+            pub fn deserialize(ser_msg : &[u8]) -> #deserialize_result<#type_name> {
                 log::trace!("Deserializing bytes to {} in {}", #type_name_str, #type_underscore_name_str);
                 #type_name::from_serialized_bytes(ser_msg)
             }
@@ -1278,40 +1387,79 @@ fn generate_rowbuilder_tokens(
                 let mut this = Self {
                     _arrow_fields,
                     message_struct: None,
+                    message_struct_name: None,
+                    message_struct_encoding: MessageStructEncoding::Struct,
+                    _context: RowContextBuilder::default(),
+                    _filter: None,
                     #(#builder_field_init)*
                     _phantom: std::marker::PhantomData,
                 };
 
                 #[allow(unused)]
                 for field in &this._arrow_fields {
+                    if let Some(encoding) = message_struct_encoding(field) {
+                        let struct_fields = match encoding {
+                            MessageStructEncoding::FlatStruct => #flat_schema_fn_ident(false),
+                            _ => #struct_schema_fn_ident(false),
+                        };
+                        this.message_struct = Some(arrow_array::builder::StructBuilder::from_fields(struct_fields, 0));
+                        this.message_struct_name = Some(field.name().clone());
+                        this.message_struct_encoding = encoding;
+                        continue;
+                    }
                     match field.name().as_str() {
                         #(#builder_instantiation)*
-                        "message_struct" => {
-                            this.message_struct = Some(arrow_array::builder::StructBuilder::from_fields(#struct_schema_fn_ident(false), 0))
-                        },
+                        name if is_row_context_field(name) => this._context.add_field(field),
                         other => log::error!("Invalid field name: {}", other)
                     }
                 }
                 this
             }
 
-        }
-
-        impl<'a> #rowbuilder_trait<'a, #type_name> for #type_underscore_name<'a> {
-
+            fn append_row(&mut self, msg : &#type_name, ctx : Option<&RowContext>) -> Result<()> {
+                if let Some(filter) = self._filter.as_mut() {
+                    let stamp = #stamp_nanos.or_else(|| ctx.map(|ctx| ctx.recv_time_nanos()));
+                    if !filter.accept(msg, stamp) {
+                        return Ok(());
+                    }
+                }
 
-            fn add_row(&mut self, msg : &#type_name) -> Result<()> {
                 #[allow(unused)]
                 for field in &self._arrow_fields {
                     match field.name().as_str() {
+                        name if Some(name) == self.message_struct_name.as_deref() => match self.message_struct_encoding {
+                            MessageStructEncoding::FlatStruct => #flat_struct_builder_fn_ident(&msg, &mut self.message_struct.as_mut().unwrap()),
+                            _ => #regular_struct_builder_fn_ident(&msg, &mut self.message_struct.as_mut().unwrap()),
+                        },
                         #(#builder_append),*
-                        "message_struct" => #regular_struct_builder_fn_ident(&msg, &mut self.message_struct.as_mut().unwrap()),
+                        name if is_row_context_field(name) => self._context.append(name, ctx),
                         other => log::error!("Invalid field name: {}", other)
                     }
                 }
                 Ok(())
             }
 
+        }
+
+        impl<'a> #rowbuilder_trait<'a, #type_name> for #type_underscore_name<'a> {
+
+
+            fn add_row(&mut self, msg : &#type_name) -> Result<()> {
+                self.append_row(msg, None)
+            }
+
+            fn add_row_with_context(&mut self, msg : &#type_name, ctx : &RowContext) -> Result<()> {
+                self.append_row(msg, Some(ctx))
+            }
+
+            fn set_filter(&mut self, filter : RowFilter<#type_name>) {
+                self._filter = Some(filter);
+            }
+
+            fn filter_stats(&self) -> RowFilterStats {
+                self._filter.as_ref().map(|filter| filter.stats()).unwrap_or_default()
+            }
+
             fn add_raw_row(&mut self, msg : &[u8]) -> Result<()> {
                 log::debug!("Adding row in {}", #type_underscore_name_str);
                 #[allow(unused)]
@@ -1328,8 +1476,15 @@ fn generate_rowbuilder_tokens(
                 #[allow(unused)]
                 for field in &self._arrow_fields {
                     match field.name().as_str() {
+                        name if Some(name) == self.message_struct_name.as_deref() => {
+                            let message_struct = self.message_struct.as_mut().unwrap().finish();
+                            match self.message_struct_encoding {
+                                MessageStructEncoding::Json => res.push(Arc::new(message_struct_to_json(&message_struct))),
+                                _ => res.push(Arc::new(message_struct)),
+                            }
+                        },
                         #(#builder_finish)*
-                        "message_struct" => res.push(Arc::new(self.message_struct.as_mut().unwrap().finish())),
+                        name if is_row_context_field(name) => res.push(self._context.finish(name)),
                         other => log::error!("Invalid field name: {}", other)
                     }
                 }
@@ -1345,15 +1500,13 @@ fn generate_rowbuilder_tokens(
     )
 }
 
-#[cfg(feature = "doc-only")]
+#[cfg(not(any(feature = "default", feature = "offline")))]
 fn main() -> Result<()> {
     Ok(())
 }
 
-#[cfg(not(feature = "doc-only"))]
+#[cfg(any(feature = "default", feature = "offline"))]
 fn main() -> Result<()> {
-    // Parse the source code as a syn file
-    use r2r_common::get_env_hash;
     let out_dir = env::var("OUT_DIR").unwrap();
     let out_dir_path = Path::new(&out_dir);
 
@@ -1363,26 +1516,36 @@ fn main() -> Result<()> {
     let mut log_file =
         File::create(deps_dir.join("build_debug.log")).expect("Failed to create log file");
 
-    let env_hash = get_env_hash();
-
     writeln!(log_file, "This is a debug message from build.rs")
         .expect("Failed to write to log file");
 
-    writeln!(log_file, "Env hash: {}", r2r_common::get_env_hash())
+    let interface_source = InterfaceSource::from_env();
+    writeln!(log_file, "Interface source: {:?}", interface_source)
         .expect("Failed to write to log file");
-    let desired_trait = "WrappedTypesupport";
 
-    let implementing_structs =
-        find_implementing_structs(deps_dir, env_hash.as_str(), desired_trait);
+    let (structs_by_schema, structs_by_type) = match interface_source {
+        InterfaceSource::R2r => read_r2r_structs(deps_dir, &mut log_file)?,
+        InterfaceSource::Directories(directories) => {
+            let (structs_by_schema, structs_by_type) = ros_idl::read_interfaces(&directories)?;
+            for ros_struct in structs_by_schema.values() {
+                log_interface_definition(&mut log_file, ros_struct);
+            }
+            (structs_by_schema, structs_by_type)
+        }
+    };
 
+    let package_filter = PackageFilter::from_env();
+    writeln!(log_file, "Package filter: {:?}", package_filter)
+        .expect("Failed to write to log file");
     let (structs_by_schema, structs_by_type) =
-        find_structs_by_schema_and_type(deps_dir, env_hash.as_str(), &implementing_structs);
+        filter_packages(structs_by_schema, structs_by_type, &package_filter);
+    check_referenced_types(&structs_by_type)?;
 
     //let map_function = generate_map_function(&structs_by_schema);s
     generate_schema(
         out_dir_path,
         &structs_by_schema,
-        // &structs_by_type,
+        &structs_by_type,
         &mut log_file,
     )?;
 
@@ -1390,6 +1553,241 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Reads the structs from the Rust code r2r generated.
+#[cfg(feature = "default")]
+fn read_r2r_structs(
+    deps_dir: &Path,
+    log_file: &mut File,
+) -> Result<(BTreeMap<String, ROSStruct>, BTreeMap<String, ROSStruct>)> {
+    let env_hash = r2r_common::get_env_hash();
+    writeln!(log_file, "Env hash: {}", env_hash).expect("Failed to write to log file");
+
+    let desired_trait = "WrappedTypesupport";
+
+    let package_files = find_r2r_package_files(deps_dir, env_hash.as_str(), log_file)?;
+    let implementing_structs = find_implementing_structs(&package_files, desired_trait)?;
+
+    find_structs_by_schema_and_type(&package_files, &implementing_structs)
+}
+
+#[cfg(not(feature = "default"))]
+fn read_r2r_structs(
+    _deps_dir: &Path,
+    _log_file: &mut File,
+) -> Result<(BTreeMap<String, ROSStruct>, BTreeMap<String, ROSStruct>)> {
+    Err(anyhow!(
+        "Reading the code generated by r2r requires the default features"
+    ))
+}
+
+/// Writes the information that only the interface definitions have to the debug log.
+fn log_interface_definition(log_file: &mut File, ros_struct: &ROSStruct) {
+    writeln!(log_file, "Read {}", ros_struct.schema_name).expect("Failed to write to log file");
+    for field in &ros_struct.fields {
+        if let Some(ros_type) = &field.ros_type {
+            writeln!(log_file, "  {}: {}", field.name, ros_type)
+                .expect("Failed to write to log file");
+        }
+    }
+    for constant in &ros_struct.constants {
+        writeln!(
+            log_file,
+            "  {} {}={}",
+            constant.ros_type, constant.name, constant.value
+        )
+        .expect("Failed to write to log file");
+    }
+}
+
+/// The ROS packages to generate mappers for. By default every package r2r generated code for is
+/// kept. The set can be narrowed with environment variables, each holding a list of package
+/// names separated by `;`, `,` or whitespace:
+///
+/// - `IDL_PACKAGE_FILTER`: r2r's own filter, only these packages are kept.
+/// - `R2A_PACKAGES`: only these packages are kept.
+/// - `R2A_EXCLUDE_PACKAGES`: these packages are dropped.
+///
+/// Packages that a kept type depends on are always kept, see `filter_packages`.
+#[derive(Debug, Default)]
+struct PackageFilter {
+    include: Option<HashSet<String>>,
+    exclude: HashSet<String>,
+}
+
+impl PackageFilter {
+    fn from_env() -> Self {
+        for var in ["IDL_PACKAGE_FILTER", "R2A_PACKAGES", "R2A_EXCLUDE_PACKAGES"] {
+            println!("cargo:rerun-if-env-changed={}", var);
+        }
+        let include = [
+            env::var("IDL_PACKAGE_FILTER").ok(),
+            env::var("R2A_PACKAGES").ok(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|value| parse_package_list(&value))
+        .reduce(|a, b| a.intersection(&b).cloned().collect());
+        let exclude = env::var("R2A_EXCLUDE_PACKAGES")
+            .map(|value| parse_package_list(&value))
+            .unwrap_or_default();
+
+        PackageFilter { include, exclude }
+    }
+
+    fn keeps(&self, package: &str) -> bool {
+        let included = match &self.include {
+            Some(include) => include.contains(package),
+            None => true,
+        };
+        included && !self.exclude.contains(package)
+    }
+}
+
+fn parse_package_list(value: &str) -> HashSet<String> {
+    value
+        .split(|c: char| c == ';' || c == ',' || c.is_whitespace())
+        .filter(|package| !package.is_empty())
+        .map(|package| package.to_string())
+        .collect()
+}
+
+/// Returns the package of a struct from its packaged name, e.g. `std_msgs` for
+/// `r2r::std_msgs::msg::Header`.
+fn package_of(packaged_name: &str) -> &str {
+    packaged_name.split("::").nth(1).unwrap_or(packaged_name)
+}
+
+/// Returns the packaged name of the struct a field refers to, e.g. `r2r::std_msgs::msg::Header`
+/// for both `std_msgs::msg::Header` and `Vec<std_msgs::msg::Header>`, or `None` for primitive
+/// fields.
+fn referenced_struct_type(native_type: &str) -> Option<String> {
+    let typ = native_type
+        .strip_prefix("Vec<")
+        .and_then(|typ| typ.strip_suffix('>'))
+        .unwrap_or(native_type);
+    match typ {
+        "bool"
+        | "str"
+        | "char"
+        | "()"
+        | "i8"
+        | "i16"
+        | "i32"
+        | "i64"
+        | "i128"
+        | "isize"
+        | "u8"
+        | "u16"
+        | "u32"
+        | "u64"
+        | "u128"
+        | "usize"
+        | "f32"
+        | "f64"
+        | "std::string::String" => None,
+        typ => Some(format!("r2r::{}", typ)),
+    }
+}
+
+/// Keeps the structs of the packages selected by `package_filter`, plus every struct they refer
+/// to, directly or transitively, so that the referenced schemas and builders are always
+/// generated.
+fn filter_packages(
+    structs_by_schema: BTreeMap<String, ROSStruct>,
+    structs_by_type: BTreeMap<String, ROSStruct>,
+    package_filter: &PackageFilter,
+) -> (BTreeMap<String, ROSStruct>, BTreeMap<String, ROSStruct>) {
+    let mut kept_types: HashSet<String> = HashSet::new();
+    let mut pending: Vec<String> = structs_by_type
+        .keys()
+        .filter(|packaged_name| package_filter.keeps(package_of(packaged_name)))
+        .cloned()
+        .collect();
+
+    while let Some(packaged_name) = pending.pop() {
+        if kept_types.contains(&packaged_name) {
+            continue;
+        }
+        if let Some(ros_struct) = structs_by_type.get(&packaged_name) {
+            if !package_filter.keeps(package_of(&packaged_name)) {
+                println!(
+                    "Keeping {} from a filtered package, it is a dependency",
+                    packaged_name
+                );
+            }
+            pending.extend(
+                ros_struct
+                    .fields
+                    .iter()
+                    .filter_map(|field| referenced_struct_type(&field.native_type)),
+            );
+            kept_types.insert(packaged_name);
+        }
+    }
+
+    let structs_by_schema = structs_by_schema
+        .into_iter()
+        .filter(|(_, ros_struct)| kept_types.contains(&ros_struct.packaged_name))
+        .collect();
+    let structs_by_type = structs_by_type
+        .into_iter()
+        .filter(|(packaged_name, _)| kept_types.contains(packaged_name))
+        .collect();
+    (structs_by_schema, structs_by_type)
+}
+
+/// The items generated into one module, and its submodules.
+#[derive(Default)]
+struct GeneratedModule {
+    items: Vec<TokenStream>,
+    submodules: BTreeMap<String, GeneratedModule>,
+}
+
+impl GeneratedModule {
+    fn insert(&mut self, module_path: &[String], item: TokenStream) {
+        match module_path.split_first() {
+            Some((submodule, rest)) => self
+                .submodules
+                .entry(submodule.clone())
+                .or_default()
+                .insert(rest, item),
+            None => self.items.push(item),
+        }
+    }
+
+    fn to_token_stream(&self, imports: fn() -> TokenStream) -> TokenStream {
+        let module_imports = if self.items.is_empty() {
+            quote!()
+        } else {
+            imports()
+        };
+        let items = &self.items;
+        let submodules = self.submodules.iter().map(|(name, submodule)| {
+            let name = Ident::new(name, proc_macro2::Span::call_site());
+            let content = submodule.to_token_stream(imports);
+            quote!(
+                #[allow(non_snake_case)]
+                pub mod #name {
+                    #content
+                }
+            )
+        });
+        quote!(
+            #module_imports
+            #(#items)*
+            #(#submodules)*
+        )
+    }
+}
+
+/// Writes the generated code. The schema dispatch functions go to `generated_arrow_mappers.rs`,
+/// included in `ros_mapper`. Everything generated for a ROS type goes to the file of its
+/// package, `generated_packages/<package>.rs`, in the module that mirrors the r2r module of the
+/// type, for example `r2a::sensor_msgs::msg::LaserScan_RowBuilder`. `generated_packages.rs`
+/// declares one public module per package. With the `offline` feature the message types are
+/// generated the same way, to `generated_msgs/<package>.rs` and `generated_msgs.rs`. Files whose
+/// content did not change are not touched, so that cargo doesn't recompile r2a when nothing
+/// changed.
 fn generate_arrow_mappers(
     out_dir: String,
     structs_by_schema: BTreeMap<String, ROSStruct>,
@@ -1398,9 +1796,17 @@ fn generate_arrow_mappers(
 ) -> Result<(), anyhow::Error> {
     let output_path = Path::new(&out_dir).join("generated_arrow_mappers.rs");
     let arrow_imports = generate_arrow_imports();
-    let flat_arrow_schema_gen = generate_flat_arrow_schema(&structs_by_schema, &structs_by_type);
-    let arrow_schema_gen = generate_arrow_schema(&structs_by_schema, &structs_by_type);
-    let typesafe_parsers = generate_arrow_rowbuilders(&structs_by_schema, &structs_by_type);
+    let (flat_arrow_schema_gen, flat_schema_fns) =
+        generate_flat_arrow_schema(&structs_by_schema, &structs_by_type);
+    let (arrow_schema_gen, schema_fns) =
+        generate_arrow_schema(&structs_by_schema, &structs_by_type);
+    let raw_row_builder_dispatch = generate_raw_row_builder_dispatch(&structs_by_schema);
+    let from_arrow_impls = generate_from_arrow_impls(&structs_by_schema);
+    let typesafe_parsers = if cfg!(feature = "descriptor-builders") {
+        generate_descriptor_rowbuilders(&structs_by_schema, &structs_by_type)
+    } else {
+        generate_arrow_rowbuilders(&structs_by_schema, &structs_by_type)
+    };
     writeln!(log_file, "Writing to {:?}", output_path.clone())
         .expect("Failed to write to log file");
 
@@ -1410,20 +1816,103 @@ fn generate_arrow_mappers(
             SourceCode::TokenStream(arrow_imports),
             SourceCode::TokenStream(flat_arrow_schema_gen),
             SourceCode::TokenStream(arrow_schema_gen),
-            SourceCode::TokenStream(typesafe_parsers),
+            SourceCode::TokenStream(raw_row_builder_dispatch),
+            SourceCode::TokenStream(from_arrow_impls),
         ],
     )?;
+
+    let mut packages: BTreeMap<String, GeneratedModule> = BTreeMap::new();
+    for generated in [flat_schema_fns, schema_fns, typesafe_parsers] {
+        for (packaged_name, item) in generated {
+            let module_path = generated_module_path(&packaged_name);
+            let (package, module_path) = module_path.split_first().unwrap();
+            packages
+                .entry(package.clone())
+                .or_default()
+                .insert(module_path, item);
+        }
+    }
+
+    write_package_modules(
+        &out_dir,
+        "generated_packages",
+        &packages,
+        generate_module_imports,
+        "Arrow schemas and row builders of the `{}` ROS package.",
+        log_file,
+    )?;
+
+    if cfg!(feature = "offline") {
+        let mut message_packages: BTreeMap<String, GeneratedModule> = BTreeMap::new();
+        for (packaged_name, ros_struct) in &structs_by_type {
+            let module_path = generated_module_path(packaged_name);
+            let (package, module_path) = module_path.split_first().unwrap();
+            message_packages.entry(package.clone()).or_default().insert(
+                module_path,
+                message_types::generate_message_type(ros_struct),
+            );
+        }
+        write_package_modules(
+            &out_dir,
+            "generated_msgs",
+            &message_packages,
+            message_types::generate_message_imports,
+            "The message types of the `{}` ROS package.",
+            log_file,
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes one file per package to the `<name>` directory, and `<name>.rs`, which declares a
+/// public module per package that includes it. `doc` is the documentation of the package
+/// modules, with a `{}` for the package name.
+fn write_package_modules(
+    out_dir: &str,
+    name: &str,
+    packages: &BTreeMap<String, GeneratedModule>,
+    imports: fn() -> TokenStream,
+    doc: &str,
+    log_file: &mut File,
+) -> Result<()> {
+    let packages_dir = Path::new(out_dir).join(name);
+    fs::create_dir_all(&packages_dir)?;
+    let mut package_modules: Vec<TokenStream> = vec![];
+    for (package, module) in packages {
+        let package_path = packages_dir.join(format!("{}.rs", package));
+        writeln!(log_file, "Writing to {:?}", package_path).expect("Failed to write to log file");
+        write_token_streams_to_file(
+            &package_path,
+            vec![SourceCode::TokenStream(module.to_token_stream(imports))],
+        )?;
+
+        let package_ident = Ident::new(package, proc_macro2::Span::call_site());
+        let include_path = format!("/{}/{}.rs", name, package);
+        let doc = doc.replace("{}", package);
+        package_modules.push(quote!(
+            #[doc = #doc]
+            pub mod #package_ident {
+                include!(concat!(env!("OUT_DIR"), #include_path));
+            }
+        ));
+    }
+
+    write_token_streams_to_file(
+        &Path::new(out_dir).join(format!("{}.rs", name)),
+        vec![SourceCode::TokenStream(quote!(#(#package_modules)*))],
+    )?;
     Ok(())
 }
 
 fn generate_schema(
     out_dir_path: &Path,
     structs_by_schema: &BTreeMap<String, ROSStruct>,
-    //structs_by_type: &BTreeMap<String, ROSStruct>,
+    structs_by_type: &BTreeMap<String, ROSStruct>,
     log_file: &mut File,
 ) -> Result<(), anyhow::Error> {
     let output_path = out_dir_path.join("generated_schema.rs");
     let supported_schema_list = generate_supported_schema_list(structs_by_schema);
+    let type_table = generate_type_table(structs_by_schema, structs_by_type);
     let imports = generate_imports();
     writeln!(log_file, "Writing to {:?}", output_path.clone())
         .expect("Failed to write to log file");
@@ -1432,6 +1921,7 @@ fn generate_schema(
         vec![
             SourceCode::TokenStream(imports),
             SourceCode::TokenStream(supported_schema_list),
+            SourceCode::TokenStream(type_table),
         ],
     )?;
     Ok(())
@@ -1445,88 +1935,151 @@ fn write_token_streams_to_file(file_path: &Path, token_streams: Vec<SourceCode>)
         content.push_str(&format!("{}\n", token_stream)); // Accumulate the content
     }
 
-    fs::write(file_path, content)?;
+    // Format a temporary copy and only replace the file if the result differs, an unchanged
+    // modification time keeps cargo from recompiling the including module.
+    let tmp_path = file_path.with_extension("tmp.rs");
+    fs::write(&tmp_path, content)?;
 
     Command::new("rustfmt")
-        .arg(file_path.to_str().unwrap()) // Convert the Path to a &str
+        .arg(tmp_path.to_str().unwrap()) // Convert the Path to a &str
         .output() // Execute the command and capture the output
         .expect("Failed to execute rustfmt");
 
+    let formatted = fs::read(&tmp_path)?;
+    if fs::read(file_path).ok().as_ref() == Some(&formatted) {
+        fs::remove_file(&tmp_path)?;
+    } else {
+        fs::rename(&tmp_path, file_path)?;
+    }
+
     Ok(())
 }
 
 fn find_structs_by_schema_and_type(
-    deps_dir: &Path,
-    env_hash: &str,
+    package_files: &[(String, PathBuf)],
     implementing_structs: &HashSet<String>,
-) -> (BTreeMap<String, ROSStruct>, BTreeMap<String, ROSStruct>) {
+) -> Result<(BTreeMap<String, ROSStruct>, BTreeMap<String, ROSStruct>)> {
     let mut structs_by_schema: BTreeMap<String, ROSStruct> = BTreeMap::new();
     let mut structs_by_type: BTreeMap<String, ROSStruct> = BTreeMap::new();
-    for entry in WalkDir::new(deps_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e: &walkdir::DirEntry| {
-            let path = e.path().to_str().unwrap();
-            path.contains("r2r-")
-                && path.contains(env_hash)
-                && (path.ends_with("msgs.rs") || path.ends_with("interfaces.rs"))
-        })
-    {
-        println!("Reading {:?}", entry.path());
-        let file_content = fs::read_to_string(entry.path()).expect("Unable to read file");
-        let syntax_tree = syn::parse_file(&file_content).expect("Unable to parse code");
-        let file_name_no_ext = entry.file_name().to_string_lossy().replace(".rs", "");
-
-        let mut schema_name_format = format!("{}/msg/", &file_name_no_ext);
-        schema_name_format.push_str("{}");
+    for (package, path) in package_files {
+        println!("Reading {:?}", path);
+        let file_content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        let syntax_tree = syn::parse_file(&file_content)
+            .with_context(|| format!("Unable to parse {}", path.display()))?;
 
         // Create a new StructVisitor
         let mut visitor = StructVisitor {
-            schema_name_format,
             structs_by_schema: &mut structs_by_schema,
             structs_by_type: &mut structs_by_type,
-            module_stack: vec!["r2r".to_string(), file_name_no_ext],
+            module_stack: vec!["r2r".to_string(), package.clone()],
             valid_structs: implementing_structs,
         };
 
         visitor.visit_file(&syntax_tree);
     }
-    (structs_by_schema, structs_by_type)
+    Ok((structs_by_schema, structs_by_type))
 }
 
 fn find_implementing_structs(
-    deps_dir: &Path,
-    env_hash: &str,
+    package_files: &[(String, PathBuf)],
     desired_trait: &str,
-) -> HashSet<String> {
+) -> Result<HashSet<String>> {
     let mut implementing_structs: HashSet<String> = HashSet::new();
 
-    for entry in WalkDir::new(deps_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e: &walkdir::DirEntry| {
-            let path = e.path().to_str().unwrap();
-            path.contains("r2r-")
-                && path.contains(env_hash)
-                && (path.ends_with("msgs.rs") || path.ends_with("interfaces.rs"))
-        })
-    {
-        println!("Reading {:?}", entry.path());
-        let file_content = fs::read_to_string(entry.path()).expect("Unable to read file");
-        let syntax_tree = syn::parse_file(&file_content).expect("Unable to parse code");
-        let file_name_no_ext = entry.file_name().to_string_lossy().replace(".rs", "");
-
-        let mut schema_name_format = format!("{}/msg/", &file_name_no_ext);
-        schema_name_format.push_str("{}");
+    for (package, path) in package_files {
+        println!("Reading {:?}", path);
+        let file_content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        let syntax_tree = syn::parse_file(&file_content)
+            .with_context(|| format!("Unable to parse {}", path.display()))?;
 
         // Create a new StructVisitor
         let mut visitor = TraitImplVisitor {
             desired_trait,
             implementing_structs: &mut implementing_structs,
-            module_stack: vec!["r2r".to_string(), file_name_no_ext],
+            module_stack: vec!["r2r".to_string(), package.clone()],
         };
 
         visitor.visit_file(&syntax_tree);
     }
-    implementing_structs
+    Ok(implementing_structs)
+}
+
+/// Finds the file r2r generated for each of its packages, `<package>.rs` in the directory named
+/// after the environment hash in the build output of r2r. The packages are the ones r2r
+/// generates code for, from the ament index of the sourced ROS environment, so packages are
+/// found whatever their name is.
+#[cfg(feature = "default")]
+fn find_r2r_package_files(
+    deps_dir: &Path,
+    env_hash: &str,
+    log_file: &mut File,
+) -> Result<Vec<(String, PathBuf)>> {
+    let packages: BTreeSet<String> = r2r_common::get_wanted_messages()
+        .into_iter()
+        .map(|msg| msg.module)
+        .collect();
+    writeln!(log_file, "r2r packages: {:?}", packages).expect("Failed to write to log file");
+
+    let mut package_files: Vec<(String, PathBuf)> = vec![];
+    for entry in WalkDir::new(deps_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e: &walkdir::DirEntry| {
+            let path = e.path();
+            let in_hash_dir = path
+                .parent()
+                .and_then(|dir| dir.file_name())
+                .is_some_and(|dir| dir == env_hash);
+            in_hash_dir
+                && path.to_string_lossy().contains("r2r-")
+                && path.extension().is_some_and(|ext| ext == "rs")
+        })
+    {
+        let package = entry.path().file_stem().unwrap().to_string_lossy();
+        if packages.contains(package.as_ref()) {
+            package_files.push((package.to_string(), entry.path().to_path_buf()));
+        }
+    }
+
+    for package in &packages {
+        if !package_files.iter().any(|(found, _)| found == package) {
+            println!(
+                "cargo:warning=r2a: no code generated by r2r was found for package {}",
+                package
+            );
+        }
+    }
+    Ok(package_files)
+}
+
+/// Fails with a diagnostic listing every field whose type was not found, instead of panicking
+/// while generating the code for it. This happens when the interfaces of a package are missing
+/// from the sourced ROS environment.
+fn check_referenced_types(structs_by_type: &BTreeMap<String, ROSStruct>) -> Result<()> {
+    let missing: Vec<String> = structs_by_type
+        .values()
+        .flat_map(|ros_struct| {
+            ros_struct.fields.iter().filter_map(move |field| {
+                let referenced = referenced_struct_type(&field.native_type)?;
+                if structs_by_type.contains_key(&referenced) {
+                    None
+                } else {
+                    Some(format!(
+                        "  field `{}` of {} has type {}, which was not found",
+                        field.name, ros_struct.schema_name, referenced
+                    ))
+                }
+            })
+        })
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Missing ROS types, make sure the packages that define them are built and sourced:\n{}",
+            missing.join("\n")
+        ))
+    }
 }
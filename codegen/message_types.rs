@@ -0,0 +1,215 @@
+// Generates the message types of the `offline` feature from the interface definitions: a plain
+// Rust struct per message, laid out like the r2r type, with its constants, its default values
+// and its CDR serialization. The types go to `r2a::msgs`, which mirrors the root of r2r, so the
+// generated mappers refer to them through a `use crate::msgs as r2r` alias.
+
+use crate::ros_idl::{primitive_native_type, ArraySize};
+use crate::{is_primitive_type, ROSField, ROSStruct};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse_str, Ident};
+
+/// The imports of every generated message module.
+pub fn generate_message_imports() -> TokenStream {
+    quote! {
+        #[allow(unused_imports)]
+        use crate::cdr::{CdrMessage, CdrReader, CdrValue, CdrWriter};
+        #[allow(unused_imports)]
+        use crate::msgs as r2r;
+    }
+}
+
+/// Generates the struct of `ros_struct` with its `Default`, constants and CDR implementations.
+pub fn generate_message_type(ros_struct: &ROSStruct) -> TokenStream {
+    let struct_name = ros_struct.packaged_name.rsplit("::").next().unwrap();
+    let ident = Ident::new(struct_name, proc_macro2::Span::call_site());
+    let doc = format!("The `{}` message.", ros_struct.schema_name);
+
+    let field_idents: Vec<Ident> = ros_struct
+        .fields
+        .iter()
+        .map(|field| Ident::new(&field.name, proc_macro2::Span::call_site()))
+        .collect();
+    let field_types = ros_struct
+        .fields
+        .iter()
+        .map(|field| native_type_tokens(&field.native_type));
+    let defaults: Vec<Option<TokenStream>> = ros_struct.fields.iter().map(default_value).collect();
+    let reads = ros_struct.fields.iter().map(read_field);
+    let writes = ros_struct
+        .fields
+        .iter()
+        .zip(field_idents.iter())
+        .map(|(field, ident)| write_field(field, ident));
+    let constants: Vec<TokenStream> = ros_struct
+        .constants
+        .iter()
+        .filter_map(|constant| {
+            let name = Ident::new(&constant.name, proc_macro2::Span::call_site());
+            match primitive_native_type(&constant.ros_type)? {
+                "std::string::String" => {
+                    let value = unquote(&constant.value);
+                    Some(quote!(pub const #name: &'static str = #value;))
+                }
+                native_type => {
+                    let typ: syn::Type = parse_str(native_type).unwrap();
+                    let value = primitive_literal(&constant.value, native_type)?;
+                    Some(quote!(pub const #name: #typ = #value;))
+                }
+            }
+        })
+        .collect();
+    let constants = if constants.is_empty() {
+        quote!()
+    } else {
+        quote!(impl #ident { #(#constants)* })
+    };
+
+    // Only the fields with a default value or a fixed size need a `Default` implementation.
+    let (derive_default, default_impl) = if defaults.iter().all(Option::is_none) {
+        (quote!(Default,), quote!())
+    } else {
+        let defaults = defaults
+            .into_iter()
+            .map(|default| default.unwrap_or_else(|| quote!(Default::default())));
+        (
+            quote!(),
+            quote! {
+                impl Default for #ident {
+                    fn default() -> Self {
+                        Self {
+                            #(#field_idents: #defaults,)*
+                        }
+                    }
+                }
+            },
+        )
+    };
+
+    // Messages without fields are serialized as a single byte, like rosidl does.
+    let (read_body, write_body) = if ros_struct.fields.is_empty() {
+        (
+            quote!(u8::read_cdr(reader)?; Ok(Self {})),
+            quote!(0u8.write_cdr(writer)),
+        )
+    } else {
+        (
+            quote!(Ok(Self { #(#field_idents: #reads,)* })),
+            quote!(#(#writes)* Ok(())),
+        )
+    };
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Clone, Debug, #derive_default PartialEq)]
+        pub struct #ident {
+            #(pub #field_idents: #field_types,)*
+        }
+
+        #constants
+
+        #default_impl
+
+        impl CdrValue for #ident {
+            fn read_cdr(reader: &mut CdrReader) -> anyhow::Result<Self> {
+                #read_body
+            }
+
+            fn write_cdr(&self, writer: &mut CdrWriter) -> anyhow::Result<()> {
+                #write_body
+            }
+        }
+
+        impl CdrMessage for #ident {}
+    }
+}
+
+fn native_type_tokens(native_type: &str) -> TokenStream {
+    if let Some(element) = native_type
+        .strip_prefix("Vec<")
+        .and_then(|typ| typ.strip_suffix('>'))
+    {
+        let element = native_type_tokens(element);
+        quote!(Vec<#element>)
+    } else if is_primitive_type(native_type) {
+        let typ: syn::Type = parse_str(native_type).unwrap();
+        quote!(#typ)
+    } else {
+        let typ: syn::Path = parse_str(&format!("r2r::{}", native_type)).unwrap();
+        quote!(#typ)
+    }
+}
+
+/// The size of a fixed size array field. The synthesized action fields have no definition,
+/// and none of them is a fixed size array.
+fn fixed_size(field: &ROSField) -> Option<usize> {
+    match field.ros_type.as_ref()?.array {
+        Some(ArraySize::Fixed(size)) => Some(size),
+        _ => None,
+    }
+}
+
+fn read_field(field: &ROSField) -> TokenStream {
+    match (field.native_type.as_str(), fixed_size(field)) {
+        ("Vec<u8>", Some(size)) => quote!(reader.read_byte_array(#size)?),
+        ("Vec<u8>", None) => quote!(reader.read_byte_sequence()?),
+        (_, Some(size)) => quote!(reader.read_array(#size)?),
+        _ => quote!(CdrValue::read_cdr(reader)?),
+    }
+}
+
+fn write_field(field: &ROSField, ident: &Ident) -> TokenStream {
+    match (field.native_type.as_str(), fixed_size(field)) {
+        ("Vec<u8>", Some(size)) => quote!(writer.write_byte_array(&self.#ident, #size)?;),
+        ("Vec<u8>", None) => quote!(writer.write_byte_sequence(&self.#ident)?;),
+        (_, Some(size)) => quote!(writer.write_array(&self.#ident, #size)?;),
+        _ => quote!(self.#ident.write_cdr(writer)?;),
+    }
+}
+
+/// The default value of a field, or `None` for the default of its type. Fixed size arrays get
+/// their size, like in r2r, and scalar fields their default value from the definition. Array
+/// default values are not supported and fall back to an empty array.
+fn default_value(field: &ROSField) -> Option<TokenStream> {
+    if let Some(size) = fixed_size(field) {
+        return Some(quote!(vec![Default::default(); #size]));
+    }
+    let value = field.ros_type.as_ref()?.default_value.as_ref()?;
+    match field.native_type.as_str() {
+        "std::string::String" => {
+            let value = unquote(value);
+            Some(quote!(#value.to_string()))
+        }
+        native_type => primitive_literal(value, native_type),
+    }
+}
+
+/// A literal of a primitive type, for example `1f64` for a `float64` of value `1`.
+fn primitive_literal(value: &str, native_type: &str) -> Option<TokenStream> {
+    let value = value.trim();
+    match native_type {
+        "bool" => {
+            let value = value.eq_ignore_ascii_case("true") || value == "1";
+            Some(quote!(#value))
+        }
+        native_type if is_primitive_type(native_type) => {
+            let literal: syn::Expr = parse_str(&format!("{}{}", value, native_type)).ok()?;
+            Some(quote!(#literal))
+        }
+        _ => None,
+    }
+}
+
+/// Strips the quotes of a string constant or default value, which may also be unquoted.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(unquoted) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return unquoted.to_string();
+        }
+    }
+    value.to_string()
+}
@@ -0,0 +1,428 @@
+// Reads ROS 2 interface definitions (`.msg`, `.srv` and `.action` files) and builds the same
+// `ROSStruct` model the build script otherwise scrapes from the Rust code r2r generated. The
+// names and Rust types follow the r2r conventions, so the generated mappers refer to the r2r
+// types either way, but the model also keeps what only the definitions have: array bounds,
+// string bounds, default values and constants.
+
+use crate::{ros_schema_name, ROSField, ROSStruct};
+use anyhow::{anyhow, Context, Result};
+use r2a_codegen::RUST_KEYWORDS;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// The size of an array field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArraySize {
+    /// `type[]`
+    Unbounded,
+    /// `type[N]`
+    Fixed(usize),
+    /// `type[<=N]`
+    Bounded(usize),
+}
+
+/// The type of a field as written in the interface definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ROSType {
+    /// The element type, for example `float32`, `string` or `geometry_msgs/Point`.
+    pub base_type: String,
+    /// The bound of a `string<=N` or `wstring<=N`.
+    pub string_bound: Option<usize>,
+    /// Set if the field is an array.
+    pub array: Option<ArraySize>,
+    /// The default value, as written in the definition.
+    pub default_value: Option<String>,
+}
+
+impl Display for ROSType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.base_type)?;
+        if let Some(bound) = self.string_bound {
+            write!(f, "<={}", bound)?;
+        }
+        match self.array {
+            Some(ArraySize::Unbounded) => write!(f, "[]")?,
+            Some(ArraySize::Fixed(size)) => write!(f, "[{}]", size)?,
+            Some(ArraySize::Bounded(bound)) => write!(f, "[<={}]", bound)?,
+            None => {}
+        }
+        if let Some(default_value) = &self.default_value {
+            write!(f, " (default {})", default_value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A constant declared in an interface definition, for example `uint8 DEBUG=10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ROSConstant {
+    pub name: String,
+    pub ros_type: String,
+    pub value: String,
+}
+
+/// Where the build script reads the interface types from, selected with the
+/// `R2A_INTERFACE_SOURCE` environment variable:
+///
+/// - unset or `r2r`: the Rust code generated by r2r in the target directory.
+/// - `ament`: the `share/<package>/{msg,srv,action}` directories of every prefix in
+///   `AMENT_PREFIX_PATH`.
+/// - anything else: a list of directories, separated like `PATH`, that are searched for
+///   `<package>/{msg,srv,action}` directories.
+///
+/// With the `offline` feature the definitions bundled in `interfaces` are always read, after
+/// the directories above, and `r2r` means no other directories.
+#[derive(Debug)]
+pub enum InterfaceSource {
+    R2r,
+    Directories(Vec<PathBuf>),
+}
+
+impl InterfaceSource {
+    pub fn from_env() -> Self {
+        println!("cargo:rerun-if-env-changed=R2A_INTERFACE_SOURCE");
+        let source = Self::from_env_var();
+        if !cfg!(feature = "offline") {
+            return source;
+        }
+        let mut directories = match source {
+            InterfaceSource::R2r => vec![],
+            InterfaceSource::Directories(directories) => directories,
+        };
+        directories.push(Path::new(env!("CARGO_MANIFEST_DIR")).join("interfaces"));
+        InterfaceSource::Directories(directories)
+    }
+
+    fn from_env_var() -> Self {
+        match std::env::var("R2A_INTERFACE_SOURCE").as_deref() {
+            Err(_) | Ok("") | Ok("r2r") => InterfaceSource::R2r,
+            Ok("ament") => {
+                println!("cargo:rerun-if-env-changed=AMENT_PREFIX_PATH");
+                let prefixes = std::env::var_os("AMENT_PREFIX_PATH").unwrap_or_default();
+                InterfaceSource::Directories(
+                    std::env::split_paths(&prefixes)
+                        .map(|prefix| prefix.join("share"))
+                        .filter(|share| share.is_dir())
+                        .collect(),
+                )
+            }
+            Ok(directories) => {
+                InterfaceSource::Directories(std::env::split_paths(directories).collect())
+            }
+        }
+    }
+}
+
+/// Reads every interface definition found in `directories` and returns the structs by schema
+/// name and by r2r type. If a package is found in several directories, the first one wins, the
+/// same way as with the ament prefixes.
+pub fn read_interfaces(
+    directories: &[PathBuf],
+) -> Result<(BTreeMap<String, ROSStruct>, BTreeMap<String, ROSStruct>)> {
+    let mut structs_by_schema: BTreeMap<String, ROSStruct> = BTreeMap::new();
+    let mut structs_by_type: BTreeMap<String, ROSStruct> = BTreeMap::new();
+
+    for directory in directories {
+        println!("cargo:rerun-if-changed={}", directory.display());
+        for entry in WalkDir::new(directory)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let Some((package, kind, name)) = interface_of(entry.path()) else {
+                continue;
+            };
+            let definition = fs::read_to_string(entry.path())
+                .with_context(|| format!("Unable to read {}", entry.path().display()))?;
+            let structs = parse_interface(&package, &kind, &name, &definition)
+                .with_context(|| format!("Unable to parse {}", entry.path().display()))?;
+            for ros_struct in structs {
+                if structs_by_type.contains_key(&ros_struct.packaged_name) {
+                    continue;
+                }
+                structs_by_schema.insert(ros_struct.schema_name.clone(), ros_struct.clone());
+                structs_by_type.insert(ros_struct.packaged_name.clone(), ros_struct);
+            }
+        }
+    }
+    Ok((structs_by_schema, structs_by_type))
+}
+
+/// Returns the package, kind and name of an interface file at `<package>/<kind>/<Name>.<kind>`.
+fn interface_of(path: &Path) -> Option<(String, String, String)> {
+    let kind = path.extension()?.to_str()?;
+    if !matches!(kind, "msg" | "srv" | "action") {
+        return None;
+    }
+    let kind_dir = path.parent()?;
+    if kind_dir.file_name()?.to_str()? != kind {
+        return None;
+    }
+    let package = kind_dir.parent()?.file_name()?.to_str()?;
+    let name = path.file_stem()?.to_str()?;
+    Some((package.to_string(), kind.to_string(), name.to_string()))
+}
+
+/// Parses one interface definition into the structs r2r generates for it.
+fn parse_interface(
+    package: &str,
+    kind: &str,
+    name: &str,
+    definition: &str,
+) -> Result<Vec<ROSStruct>> {
+    let sections = split_sections(definition);
+    let expected_sections = match kind {
+        "msg" => 1,
+        "srv" => 2,
+        _ => 3,
+    };
+    if sections.len() != expected_sections {
+        return Err(anyhow!(
+            "Expected {} sections separated by ---, found {}",
+            expected_sections,
+            sections.len()
+        ));
+    }
+
+    let r2r_struct = |modules: &[&str], struct_name: &str, section: Option<&str>| {
+        let mut module_stack: Vec<String> = vec!["r2r".to_string(), package.to_string()];
+        module_stack.extend(modules.iter().map(|module| module.to_string()));
+        let packaged_name = format!("{}::{}", module_stack.join("::"), struct_name);
+        let schema_name = ros_schema_name(&module_stack, struct_name);
+        let mut ros_struct = ROSStruct::new(packaged_name, schema_name);
+        if let Some(section) = section {
+            parse_section(package, section, &mut ros_struct)?;
+        }
+        Ok::<ROSStruct, anyhow::Error>(ros_struct)
+    };
+
+    match kind {
+        "msg" => Ok(vec![r2r_struct(&["msg"], name, Some(sections[0]))?]),
+        "srv" => Ok(vec![
+            r2r_struct(&["srv", name], "Request", Some(sections[0]))?,
+            r2r_struct(&["srv", name], "Response", Some(sections[1]))?,
+        ]),
+        _ => {
+            let action_type =
+                |struct_name: &str| format!("{}::action::{}::{}", package, name, struct_name);
+            let uuid = "unique_identifier_msgs::msg::UUID".to_string();
+
+            let mut feedback_message = r2r_struct(&["action", name], "FeedbackMessage", None)?;
+            feedback_message.add_field(ROSField::new("goal_id".to_string(), uuid.clone()));
+            feedback_message.add_field(ROSField::new(
+                "feedback".to_string(),
+                action_type("Feedback"),
+            ));
+
+            let mut send_goal_request = r2r_struct(&["action", name, "SendGoal"], "Request", None)?;
+            send_goal_request.add_field(ROSField::new("goal_id".to_string(), uuid.clone()));
+            send_goal_request.add_field(ROSField::new("goal".to_string(), action_type("Goal")));
+
+            let mut send_goal_response =
+                r2r_struct(&["action", name, "SendGoal"], "Response", None)?;
+            send_goal_response.add_field(ROSField::new("accepted".to_string(), "bool".to_string()));
+            send_goal_response.add_field(ROSField::new(
+                "stamp".to_string(),
+                "builtin_interfaces::msg::Time".to_string(),
+            ));
+
+            let mut get_result_request =
+                r2r_struct(&["action", name, "GetResult"], "Request", None)?;
+            get_result_request.add_field(ROSField::new("goal_id".to_string(), uuid));
+
+            let mut get_result_response =
+                r2r_struct(&["action", name, "GetResult"], "Response", None)?;
+            get_result_response.add_field(ROSField::new("status".to_string(), "i8".to_string()));
+            get_result_response
+                .add_field(ROSField::new("result".to_string(), action_type("Result")));
+
+            Ok(vec![
+                r2r_struct(&["action", name], "Goal", Some(sections[0]))?,
+                r2r_struct(&["action", name], "Result", Some(sections[1]))?,
+                r2r_struct(&["action", name], "Feedback", Some(sections[2]))?,
+                feedback_message,
+                send_goal_request,
+                send_goal_response,
+                get_result_request,
+                get_result_response,
+            ])
+        }
+    }
+}
+
+fn split_sections(definition: &str) -> Vec<&str> {
+    let mut sections = vec![];
+    let mut start = 0;
+    let mut offset = 0;
+    for line in definition.split_inclusive('\n') {
+        if line.trim() == "---" {
+            sections.push(&definition[start..offset]);
+            start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    sections.push(&definition[start..]);
+    sections
+}
+
+/// Parses the fields and constants of one section of a definition into `ros_struct`.
+fn parse_section(package: &str, section: &str, ros_struct: &mut ROSStruct) -> Result<()> {
+    for line in section.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (type_token, rest) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("Invalid line: {}", line))?;
+        let rest = rest.trim_start();
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let (name, remainder) = rest.split_at(name_end);
+        let remainder = remainder.trim_start();
+
+        if let Some(value) = remainder.strip_prefix('=') {
+            ros_struct.constants.push(ROSConstant {
+                name: name.to_string(),
+                ros_type: type_token.to_string(),
+                value: value.trim().to_string(),
+            });
+            continue;
+        }
+
+        let mut ros_type = parse_type(type_token)?;
+        if !remainder.is_empty() {
+            ros_type.default_value = Some(remainder.to_string());
+        }
+        let native_type = native_type(package, &ros_type);
+        ros_struct
+            .add_field(ROSField::new(r2r_field_name(name), native_type).with_ros_type(ros_type));
+    }
+    Ok(())
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_type(type_token: &str) -> Result<ROSType> {
+    let (element, array) = match type_token.split_once('[') {
+        Some((element, size)) => {
+            let size = size
+                .strip_suffix(']')
+                .ok_or_else(|| anyhow!("Invalid array type: {}", type_token))?;
+            let array = if size.is_empty() {
+                ArraySize::Unbounded
+            } else if let Some(bound) = size.strip_prefix("<=") {
+                ArraySize::Bounded(bound.parse()?)
+            } else {
+                ArraySize::Fixed(size.parse()?)
+            };
+            (element, Some(array))
+        }
+        None => (type_token, None),
+    };
+    let (base_type, string_bound) = match element.split_once("<=") {
+        Some((base_type, bound)) => (base_type, Some(bound.parse()?)),
+        None => (element, None),
+    };
+    Ok(ROSType {
+        base_type: base_type.to_string(),
+        string_bound,
+        array,
+        default_value: None,
+    })
+}
+
+/// The Rust type r2r generates for a field of `ros_type` in a definition of `package`.
+fn native_type(package: &str, ros_type: &ROSType) -> String {
+    let element = match primitive_native_type(&ros_type.base_type) {
+        Some(native_type) => native_type.to_string(),
+        // ROS 1 style reference to the standard header.
+        None if ros_type.base_type == "Header" => "std_msgs::msg::Header".to_string(),
+        None => match ros_type.base_type.split_once('/') {
+            Some((message_package, message_name)) => {
+                let message_name = message_name.rsplit('/').next().unwrap();
+                format!("{}::msg::{}", message_package, message_name)
+            }
+            None => format!("{}::msg::{}", package, ros_type.base_type),
+        },
+    };
+    match ros_type.array {
+        Some(_) => format!("Vec<{}>", element),
+        None => element,
+    }
+}
+
+/// The Rust type r2r generates for a primitive ROS type, or `None` for message types.
+pub fn primitive_native_type(base_type: &str) -> Option<&'static str> {
+    match base_type {
+        "bool" => Some("bool"),
+        "byte" | "char" | "uint8" => Some("u8"),
+        "int8" => Some("i8"),
+        "int16" => Some("i16"),
+        "uint16" => Some("u16"),
+        "int32" => Some("i32"),
+        "uint32" => Some("u32"),
+        "int64" => Some("i64"),
+        "uint64" => Some("u64"),
+        "float32" => Some("f32"),
+        "float64" => Some("f64"),
+        "string" | "wstring" => Some("std::string::String"),
+        _ => None,
+    }
+}
+
+/// The ROS type of a Rust type r2r generates, for the structs read from the r2r code, which
+/// don't have the interface definition: `float32` for `f32`, `geometry_msgs/Point` for
+/// `geometry_msgs::msg::Point`. The element type of `Vec`s.
+pub fn ros_type_of_native_type(native_type: &str) -> String {
+    let element = native_type
+        .strip_prefix("Vec<")
+        .and_then(|typ| typ.strip_suffix('>'))
+        .unwrap_or(native_type);
+    let primitive = match element {
+        "bool" => "bool",
+        "u8" => "uint8",
+        "i8" => "int8",
+        "i16" => "int16",
+        "u16" => "uint16",
+        "i32" => "int32",
+        "u32" => "uint32",
+        "i64" => "int64",
+        "u64" => "uint64",
+        "f32" => "float32",
+        "f64" => "float64",
+        "std::string::String" => "string",
+        _ => {
+            let mut segments = element.split("::");
+            let package = segments.next().unwrap_or_default();
+            let name = segments.last().unwrap_or_default();
+            return format!("{}/{}", package, name);
+        }
+    };
+    primitive.to_string()
+}
+
+/// r2r appends an underscore to field names that are Rust keywords.
+fn r2r_field_name(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
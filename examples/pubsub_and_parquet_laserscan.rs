@@ -1,14 +1,10 @@
 use anyhow::Result;
-use arrow_array::Array;
-use arrow_array::RecordBatch;
-use arrow_schema::Schema;
 use futures::StreamExt;
-use parquet::arrow::ArrowWriter;
+use r2a::parquet::ParquetSink;
 use r2a::ArrowSupport;
 use r2a::RowBuilder;
 use r2r::sensor_msgs::msg::LaserScan;
 use r2r::QosProfile;
-use std::fs::File;
 use std::sync::{Arc, Mutex};
 use tokio::task;
 
@@ -60,7 +56,7 @@ async fn main() -> Result<()> {
 }
 
 ///
-/// This subscriber will write the received LaserScan messages to parquet files in batches of 10.
+/// This subscriber will write the received LaserScan messages to parquet files of 10 rows each.
 ///
 async fn subscriber(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
     let sub = arc_node
@@ -74,10 +70,12 @@ async fn subscriber(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
     let flat_fields = LaserScan::flat_arrow_fields(true);
     let mut flat_row_builder = LaserScan::new_flat_row_builder(flat_fields.iter().collect());
 
-    let mut count = 0;
-    sub.for_each(|msg| {
-        count += 1;
+    // Every 10 messages go to a new file in the directory of the topic,
+    // target/laser_scan/laser_scan/laser_scan_00000.parquet and so on.
+    let mut sink = ParquetSink::new("target/laser_scan")?.with_max_file_rows(10);
+    let mut flat_sink = ParquetSink::new("target/laser_scan_flat")?.with_max_file_rows(10);
 
+    sub.for_each(|msg| {
         match row_builder.add_row(&msg) {
             Ok(_) => {}
             Err(e) => {
@@ -92,22 +90,11 @@ async fn subscriber(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
             }
         }
 
-        if count > 0 && count % 10 == 0 {
-            let arrays = row_builder.to_arc_arrays();
-            let schema = Schema::new(fields.clone());
-            let file_path = format!("target/laser_scan_{}.parquet", count / 10);
-            write_to_parquet(arrays, Arc::new(schema), &file_path).unwrap();
-
-            println!("Wrote data to parquet file {}", file_path);
-
-            let arrays = flat_row_builder.to_arc_arrays();
-
-            let schema = Schema::new(flat_fields.clone());
-            let file_path = format!("target/laser_scan_flat_{}.parquet", count / 10);
-            write_to_parquet(arrays, Arc::new(schema), &file_path).unwrap();
-
-            println!("Wrote flat data to parquet file {}", file_path);
-        }
+        sink.write_rows("/laser_scan", &fields, &mut row_builder)
+            .unwrap();
+        flat_sink
+            .write_rows("/laser_scan", &flat_fields, &mut flat_row_builder)
+            .unwrap();
 
         futures::future::ready(())
     })
@@ -150,16 +137,3 @@ async fn publisher(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
 
     Ok(())
 }
-
-fn write_to_parquet(
-    arrays: Vec<Arc<dyn Array>>,
-    schema: Arc<Schema>,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
-    let file = File::create(file_path)?;
-    let mut writer = ArrowWriter::try_new(file, schema, None)?;
-    writer.write(&batch)?;
-    writer.close()?;
-    Ok(())
-}
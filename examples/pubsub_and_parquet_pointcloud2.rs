@@ -1,15 +1,11 @@
 use anyhow::Result;
-use arrow_array::Array;
-use arrow_array::RecordBatch;
-use arrow_schema::Schema;
 use futures::StreamExt;
-use parquet::arrow::ArrowWriter;
+use r2a::parquet::ParquetSink;
 use r2a::ArrowSupport;
 use r2a::RowBuilder;
 use r2r::sensor_msgs::msg::PointCloud2;
 use r2r::sensor_msgs::msg::PointField;
 use r2r::QosProfile;
-use std::fs::File;
 use std::sync::{Arc, Mutex};
 use tokio::task;
 
@@ -53,7 +49,7 @@ async fn main() -> Result<()> {
 }
 
 ///
-/// This subscriber will write the received LaserScan messages to parquet files in batches of 10.
+/// This subscriber will write the received PointCloud2 messages to parquet files of 10 rows each.
 ///
 async fn subscriber(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
     let sub = arc_node
@@ -67,10 +63,12 @@ async fn subscriber(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
     let flat_fields = PointCloud2::flat_arrow_fields(true);
     let mut flat_row_builder = PointCloud2::new_flat_row_builder(flat_fields.iter().collect());
 
-    let mut count = 0;
-    sub.for_each(|msg| {
-        count += 1;
+    // Every 10 messages go to a new file in the directory of the topic,
+    // target/point_cloud2/point_cloud2/point_cloud2_00000.parquet and so on.
+    let mut sink = ParquetSink::new("target/point_cloud2")?.with_max_file_rows(10);
+    let mut flat_sink = ParquetSink::new("target/point_cloud2_flat")?.with_max_file_rows(10);
 
+    sub.for_each(|msg| {
         match row_builder.add_row(&msg) {
             Ok(_) => {}
             Err(e) => {
@@ -85,22 +83,11 @@ async fn subscriber(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
             }
         }
 
-        if count > 0 && count % 10 == 0 {
-            let arrays = row_builder.to_arc_arrays();
-            let schema = Schema::new(fields.clone());
-            let file_path = format!("target/point_cloud2_{}.parquet", count / 10);
-            write_to_parquet(arrays, Arc::new(schema), &file_path).unwrap();
-
-            println!("Wrote data to parquet file {}", file_path);
-
-            let arrays = flat_row_builder.to_arc_arrays();
-
-            let schema = Schema::new(flat_fields.clone());
-            let file_path = format!("target/point_cloud2_flat_{}.parquet", count / 10);
-            write_to_parquet(arrays, Arc::new(schema), &file_path).unwrap();
-
-            println!("Wrote flat data to parquet file {}", file_path);
-        }
+        sink.write_rows("/point_cloud2", &fields, &mut row_builder)
+            .unwrap();
+        flat_sink
+            .write_rows("/point_cloud2", &flat_fields, &mut flat_row_builder)
+            .unwrap();
 
         futures::future::ready(())
     })
@@ -176,16 +163,3 @@ async fn publisher(arc_node: Arc<Mutex<r2r::Node>>) -> Result<()> {
 
     Ok(())
 }
-
-fn write_to_parquet(
-    arrays: Vec<Arc<dyn Array>>,
-    schema: Arc<Schema>,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
-    let file = File::create(file_path)?;
-    let mut writer = ArrowWriter::try_new(file, schema, None)?;
-    writer.write(&batch)?;
-    writer.close()?;
-    Ok(())
-}
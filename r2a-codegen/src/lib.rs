@@ -0,0 +1,379 @@
+//! Mapping of Rust field types to Arrow types and Arrow array builders. Shared by the build
+//! script of `r2a`, which generates the mappers of the r2r message types, and by the
+//! `r2a-derive` crate, which generates them for user-defined structs. Not meant to be used
+//! directly.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Ident, Type};
+
+/// The scalar field types that map to a single Arrow column.
+const PRIMITIVE_TYPES: &[&str] = &[
+    "bool",
+    "str",
+    "char",
+    "()",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "f32",
+    "f64",
+    "std::string::String",
+];
+
+/// The Rust keywords. r2r appends an underscore to ROS field names that are keywords, and the
+/// build script writes this list to the generated schema, so `r2a` strips the same underscores
+/// when it writes message definitions. `r2a-derive` uses the field names as they are.
+pub const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Returns true for the scalar types in `PRIMITIVE_TYPES`.
+pub fn is_primitive_type(typ: &str) -> bool {
+    PRIMITIVE_TYPES.contains(&typ)
+}
+
+/// Returns true for `Vec`s of the scalar types in `PRIMITIVE_TYPES`.
+pub fn is_primitive_vector_type(typ: &str) -> bool {
+    typ.strip_prefix("Vec<")
+        .and_then(|typ| typ.strip_suffix('>'))
+        .map(is_primitive_type)
+        .unwrap_or(false)
+}
+
+pub fn type_to_string(ty: &Type) -> String {
+    match ty {
+        Type::Path(type_path) => {
+            type_path
+                .path
+                .segments
+                .iter()
+                .map(|segment| {
+                    let ident = segment.ident.to_string();
+                    if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                        let generics = args
+                            .args
+                            .iter()
+                            .map(|arg| {
+                                match arg {
+                                    syn::GenericArgument::Type(ty) => type_to_string(ty),
+                                    // Handle other types of generic arguments...
+                                    _ => "".to_string(),
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!("{}<{}>", ident, generics)
+                    } else {
+                        ident
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("::")
+        }
+        _ => format!("{:?}", "x"),
+    }
+}
+
+pub fn rust_type_to_arrow_type_token_stream(
+    typ: &str,
+    field_name: &str,
+    nullable: bool,
+) -> TokenStream {
+    if typ == "Vec<u8>" {
+        quote!(Field::new(#field_name, DataType::LargeBinary, #nullable))
+    } else if typ.starts_with("Vec") {
+        let type_token = match typ {
+            "Vec<bool>" => quote!(DataType::Boolean),
+            "Vec<str>" | "Vec<std::string::String>" => quote!(DataType::Utf8),
+            "Vec<char>" => quote!(DataType::Utf8),
+            "Vec<i8>" => quote!(DataType::Int8),
+            "Vec<i16>" => quote!(DataType::Int16),
+            "Vec<i32>" => quote!(DataType::Int32),
+            "Vec<i64>" => quote!(DataType::Int64),
+            "Vec<i128>" => quote!(DataType::Int64),
+            "Vec<isize>" => quote!(DataType::Int64),
+            "Vec<u16>" => quote!(DataType::UInt16),
+            "Vec<u32>" => quote!(DataType::UInt32),
+            "Vec<u64>" => quote!(DataType::UInt64),
+            "Vec<u128>" => quote!(DataType::UInt64),
+            "Vec<usize>" => quote!(DataType::UInt64),
+            "Vec<f32>" => quote!(DataType::Float32),
+            "Vec<f64>" => quote!(DataType::Float64),
+            typ => panic!("Unupported type: {}", typ), // I guess in this case we just can't build?
+        };
+        quote!(Field::new(#field_name, DataType::LargeList(Arc::new(Field::new("item", #type_token, #nullable))), #nullable))
+    } else {
+        let type_token = match typ {
+            "bool" => quote!(DataType::Boolean),
+            "str" | "std::string::String" | "char" => quote!(DataType::Utf8),
+            "()" => quote!(DataType::Null),
+            "i8" => quote!(DataType::Int8),
+            "i16" => quote!(DataType::Int16),
+            "i32" => quote!(DataType::Int32),
+            "i64" => quote!(DataType::Int64),
+            "i128" => quote!(DataType::Int64), // Not exactly sure how to support this, but I haven't seen any ROS messages with this length
+            "isize" => quote!(DataType::Int64),
+            "u8" => quote!(DataType::UInt8),
+            "u16" => quote!(DataType::UInt16),
+            "u32" => quote!(DataType::UInt32),
+            "u64" => quote!(DataType::UInt64),
+            "u128" | "usize" => quote!(DataType::UInt64), // Arrow doesn't have u128
+            "f32" => quote!(DataType::Float32),
+            "f64" => quote!(DataType::Float64),
+            typ => panic!("Unupported type: {}", typ), // I guess in this case we just can't build?
+        };
+        quote!(Field::new(#field_name, #type_token, #nullable))
+    }
+}
+
+pub fn primitive_vector_builder_components(
+    typ: &str,
+    path_field_name: &syn::Expr,
+    _flat: bool,
+    builder_field_name: &Ident,
+    index: &mut usize,
+) -> (TokenStream, TokenStream, TokenStream, TokenStream) {
+    let (builder_item_type, builder_item_instantiation, builder_append) = match typ {
+        "Vec<bool>" => (
+            quote!(arrow_array::builder::BooleanBuilder),
+            quote!(arrow_array::builder::BooleanBuilder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<str>" | "Vec<std::string::String>" => (
+            quote!(arrow_array::builder::StringBuilder),
+            quote!(arrow_array::builder::StringBuilder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(val.as_str()))),
+        ),
+        "Vec<char>" => (
+            quote!(arrow_array::builder::StringBuilder),
+            quote!(arrow_array::builder::StringBuilder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(val.to_string().as_str()))),
+        ),
+        "Vec<i8>" => (
+            quote!(arrow_array::builder::Int8Builder),
+            quote!(arrow_array::builder::Int8Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<i16>" => (
+            quote!(arrow_array::builder::Int16Builder),
+            quote!(arrow_array::builder::Int16Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<i32>" => (
+            quote!(arrow_array::builder::Int32Builder),
+            quote!(arrow_array::builder::Int32Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<i64>" => (
+            quote!(arrow_array::builder::Int64Builder),
+            quote!(arrow_array::builder::Int64Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<i128>" | "Vec<isize>" => (
+            quote!(arrow_array::builder::Int64Builder),
+            quote!(arrow_array::builder::Int64Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val as i64))), // Note: potential loss of data
+        ),
+        "Vec<u128>" | "Vec<usize>" => (
+            quote!(arrow_array::builder::UInt64Builder),
+            quote!(arrow_array::builder::UInt64Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val as u64))), // Note: potential loss of data
+        ),
+        "Vec<u8>" => (
+            quote!(arrow_array::builder::LargeBinaryBuilder),
+            quote!(arrow_array::builder::LargeBinaryBuilder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "Vec<u16>" => (
+            quote!(arrow_array::builder::UInt16Builder),
+            quote!(arrow_array::builder::UInt16Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<u32>" => (
+            quote!(arrow_array::builder::UInt32Builder),
+            quote!(arrow_array::builder::UInt32Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<u64>" => (
+            quote!(arrow_array::builder::UInt64Builder),
+            quote!(arrow_array::builder::UInt64Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<f32>" => (
+            quote!(arrow_array::builder::Float32Builder),
+            quote!(arrow_array::builder::Float32Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        "Vec<f64>" => (
+            quote!(arrow_array::builder::Float64Builder),
+            quote!(arrow_array::builder::Float64Builder::new()),
+            quote!(msg.#path_field_name.iter().map(|val| Some(*val))),
+        ),
+        _ => panic!("Unsupported type: {}", typ),
+    };
+    if typ == "Vec<u8>" {
+        (
+            quote!(#builder_item_type),
+            builder_item_instantiation,
+            quote!(self.#builder_field_name.as_mut().unwrap().append_value(&#builder_append)),
+            quote!(builder
+                    .field_builder::<#builder_item_type>(#index)
+                    .as_mut()
+                    .unwrap()
+                    .append_value(&#builder_append);
+            ),
+        )
+    } else {
+        wrap_primitive_vector_builder_components(
+            builder_item_type,
+            builder_item_instantiation,
+            builder_field_name,
+            builder_append,
+            index,
+            path_field_name,
+        )
+    }
+}
+
+pub fn wrap_primitive_vector_builder_components(
+    builder_item_type: TokenStream,
+    builder_item_instantiation: TokenStream,
+    builder_field_name: &Ident,
+    builder_append: TokenStream,
+    index: &mut usize,
+    path_field_name: &syn::Expr,
+) -> (TokenStream, TokenStream, TokenStream, TokenStream) {
+    (
+        quote!(arrow_array::builder::LargeListBuilder<#builder_item_type>),
+        quote!(arrow_array::builder::LargeListBuilder::new(#builder_item_instantiation)),
+        quote!(self.#builder_field_name.as_mut().unwrap().append_value(#builder_append)),
+        quote!({
+            let mut list_builder_option = builder.field_builder::<arrow_array::builder::LargeListBuilder<Box<dyn arrow_array::builder::ArrayBuilder>>>(#index);
+            let mut list_builder = list_builder_option.as_mut().unwrap();
+            let value_builder = list_builder.values().as_any_mut().downcast_mut::<#builder_item_type>().unwrap();
+            for value in msg.#path_field_name.iter() {
+              value_builder.append_value(value.clone());
+            }
+            list_builder.append(true);
+          }
+        ),
+    )
+}
+
+pub fn primitive_builder_components(
+    typ: &str,
+    path_field_name: syn::Expr,
+    builder_field_name: &Ident,
+    index: &mut usize,
+) -> (TokenStream, TokenStream, TokenStream, TokenStream) {
+    let (builder_item_type, builder_item_instantiation, builder_append) = match typ {
+        "bool" => (
+            quote!(arrow_array::builder::BooleanBuilder),
+            quote!(arrow_array::builder::BooleanBuilder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "str" | "std::string::String" => (
+            quote!(arrow_array::builder::StringBuilder),
+            quote!(arrow_array::builder::StringBuilder::new()),
+            quote!(msg.#path_field_name.as_str()),
+        ),
+        "char" => (
+            quote!(arrow_array::builder::StringBuilder),
+            quote!(arrow_array::builder::StringBuilder::new()),
+            quote!(msg.#path_field_name.to_string().as_str()),
+        ),
+        "()" => (
+            quote!(arrow_array::builder::NullBuilder),
+            quote!(arrow_array::builder::NullBuilder::new()),
+            quote!(None::<()>),
+        ),
+        "i8" => (
+            quote!(arrow_array::builder::Int8Builder),
+            quote!(arrow_array::builder::Int8Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "i16" => (
+            quote!(arrow_array::builder::Int16Builder),
+            quote!(arrow_array::builder::Int16Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "i32" => (
+            quote!(arrow_array::builder::Int32Builder),
+            quote!(arrow_array::builder::Int32Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "i64" => (
+            quote!(arrow_array::builder::Int64Builder),
+            quote!(arrow_array::builder::Int64Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        // Note: i128 and isize are mapped to Int64Builder with potential data loss
+        "i128" | "isize" => (
+            quote!(arrow_array::builder::Int64Builder),
+            quote!(arrow_array::builder::Int64Builder::new()),
+            quote!(msg.#path_field_name as i64),
+        ),
+        "u8" => (
+            quote!(arrow_array::builder::UInt8Builder),
+            quote!(arrow_array::builder::UInt8Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "u16" => (
+            quote!(arrow_array::builder::UInt16Builder),
+            quote!(arrow_array::builder::UInt16Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "u32" => (
+            quote!(arrow_array::builder::UInt32Builder),
+            quote!(arrow_array::builder::UInt32Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "u64" => (
+            quote!(arrow_array::builder::UInt64Builder),
+            quote!(arrow_array::builder::UInt64Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        // Note: u128 and usize are mapped to UInt64Builder with potential data loss
+        "u128" | "usize" => (
+            quote!(arrow_array::builder::UInt64Builder),
+            quote!(arrow_array::builder::UInt64Builder::new()),
+            quote!(msg.#path_field_name as u64),
+        ),
+        "f32" => (
+            quote!(arrow_array::builder::Float32Builder),
+            quote!(arrow_array::builder::Float32Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        "f64" => (
+            quote!(arrow_array::builder::Float64Builder),
+            quote!(arrow_array::builder::Float64Builder::new()),
+            quote!(msg.#path_field_name),
+        ),
+        _ => panic!("Unsupported type: {}", typ),
+    };
+
+    (
+        quote!(#builder_item_type),
+        builder_item_instantiation,
+        quote!(self.#builder_field_name.as_mut().unwrap().append_value(#builder_append)),
+        quote!(builder
+            .field_builder::<#builder_item_type>(#index)
+            .unwrap()
+            .append_value(#builder_append);
+        ),
+    )
+}
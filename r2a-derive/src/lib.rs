@@ -0,0 +1,335 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! # r2a-derive
+//!
+//! `#[derive(ArrowSupport)]` for user-defined structs, so that they can be converted to Arrow
+//! the same way as the ROS 2 messages supported by [r2a](https://docs.rs/r2a). Use it through
+//! the `derive` feature of `r2a`, which re-exports the macro as `r2a::ArrowSupport`.
+//!
+//! Fields can be of the scalar types and `Vec`s of scalar types that r2a maps for ROS
+//! messages, of any type that implements `r2a::ArrowSupport` (r2r messages and other derived
+//! structs), or `Vec`s of those. Both the regular and the flat layout are supported, as well as
+//! the `message_struct` column.
+//!
+//! ## Attributes
+//! - `#[r2a(schema_name = "my_pkg/msg/PlannerDiagnostics")]` on the struct sets the value of
+//!   `schema_name()`. Defaults to the name of the struct.
+//! - `#[r2a(skip)]` on a field leaves it out of the Arrow fields.
+//!
+//! As for ROS messages, a `header: std_msgs::msg::Header` field provides the time used by the
+//! rate limit of `r2a::RowFilter`.
+//!
+//! ## Example
+//! ```ignore
+//! use r2a::ArrowSupport;
+//!
+//! #[derive(ArrowSupport)]
+//! #[r2a(schema_name = "planner/msg/Diagnostics")]
+//! struct Diagnostics {
+//!     header: r2r::std_msgs::msg::Header,
+//!     planning_time: f64,
+//!     waypoints: Vec<r2r::geometry_msgs::msg::Point>,
+//!     #[r2a(skip)]
+//!     cache: std::collections::HashMap<String, f64>,
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, LitStr, Type};
+
+use r2a_codegen::*;
+
+/// Implements `r2a::ArrowSupport` for a struct with named fields. See the crate documentation
+/// for the supported field types and attributes.
+#[proc_macro_derive(ArrowSupport, attributes(r2a))]
+pub fn derive_arrow_support(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_arrow_support(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// How a field is mapped to Arrow.
+enum FieldKind {
+    /// A scalar or a `Vec` of scalars, mapped by the shared type mapping.
+    Primitive(String),
+    /// A `Vec` of a type implementing `ArrowSupport`.
+    StructArray(Type),
+    /// A type implementing `ArrowSupport`.
+    Struct,
+}
+
+fn expand_arrow_support(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "ArrowSupport can't be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            syn::Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "ArrowSupport can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "ArrowSupport can only be derived for structs",
+            ))
+        }
+    };
+
+    let struct_name = &input.ident;
+    let mut schema_name = struct_name.to_string();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("r2a"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("schema_name") {
+                schema_name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("unsupported r2a attribute"))
+            }
+        })?;
+    }
+
+    let mut field_schemas: Vec<TokenStream2> = vec![];
+    let mut struct_builder_appends: Vec<TokenStream2> = vec![];
+    let mut has_header = false;
+    let mut index: usize = 0;
+    for field in fields {
+        if is_skipped(field)? {
+            continue;
+        }
+        let ident = field.ident.as_ref().unwrap();
+        let field_name = ident.to_string();
+        let ty = &field.ty;
+        let typ = match ty {
+            Type::Path(_) => normalize_type(&type_to_string(ty)),
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "unsupported field type, use #[r2a(skip)] to leave it out",
+                ))
+            }
+        };
+        if field_name == "header" && (typ == "Header" || typ.ends_with("::Header")) {
+            has_header = true;
+        }
+
+        let (field_schema, struct_builder_append) = match field_kind(ty, &typ) {
+            FieldKind::Primitive(typ) => {
+                let path_field_name: syn::Expr = syn::parse_quote!(#ident);
+                let (_, _, _, struct_builder_append) = if is_primitive_type(&typ) {
+                    primitive_builder_components(&typ, path_field_name, ident, &mut index)
+                } else {
+                    primitive_vector_builder_components(
+                        &typ,
+                        &path_field_name,
+                        false,
+                        ident,
+                        &mut index,
+                    )
+                };
+                (
+                    rust_type_to_arrow_type_token_stream(&typ, &field_name, true),
+                    struct_builder_append,
+                )
+            }
+            FieldKind::Struct => (
+                quote!(Field::new(#field_name, DataType::Struct(Fields::from(<#ty as ::r2a::ArrowSupport>::arrow_fields(false))), true)),
+                quote!(
+                    { // #ident
+                        let struct_builder = builder.field_builder::<arrow_array::builder::StructBuilder>(#index).unwrap();
+                        ::r2a::ArrowSupport::append_to_struct_builder(&msg.#ident, struct_builder);
+                    }
+                ),
+            ),
+            FieldKind::StructArray(element_ty) => (
+                quote!(Field::new(#field_name, DataType::LargeList(Arc::new(Field::new("item", DataType::Struct(Fields::from(<#element_ty as ::r2a::ArrowSupport>::arrow_fields(false))), true))), true)),
+                quote!(
+                    { // #ident
+                        let mut list_builder_option = builder.field_builder::<arrow_array::builder::LargeListBuilder<Box<dyn arrow_array::builder::ArrayBuilder>>>(#index);
+                        let list_builder = list_builder_option.as_mut().unwrap();
+                        let struct_builder : &mut arrow_array::builder::StructBuilder = list_builder.values().as_any_mut().downcast_mut::<arrow_array::builder::StructBuilder>().unwrap();
+                        for element in msg.#ident.iter() {
+                            ::r2a::ArrowSupport::append_to_struct_builder(element, struct_builder);
+                        }
+                        list_builder.append(true);
+                    }
+                ),
+            ),
+        };
+        field_schemas.push(field_schema);
+        struct_builder_appends.push(struct_builder_append);
+        index += 1;
+    }
+
+    let stamp_nanos = if has_header {
+        quote!(|msg: &#struct_name| Some(
+            msg.header.stamp.sec as i64 * 1_000_000_000 + msg.header.stamp.nanosec as i64
+        ))
+    } else {
+        quote!(|_: &#struct_name| None)
+    };
+
+    Ok(quote!(
+        const _: () = {
+            #[allow(unused_imports)]
+            use ::r2a::__private::arrow_array;
+            #[allow(unused_imports)]
+            use ::r2a::__private::arrow_array::builder::ArrayBuilder;
+            #[allow(unused_imports)]
+            use ::r2a::__private::arrow_schema::{DataType, Field, Fields, Schema};
+            #[allow(unused_imports)]
+            use ::std::sync::Arc;
+
+            impl<'a> ::r2a::ArrowSupport<'a> for #struct_name {
+                type RowBuilderType = ::r2a::StructRowBuilder<'a, #struct_name>;
+                type FlatRowBuilderType = ::r2a::StructRowBuilder<'a, #struct_name>;
+
+                fn schema_name() -> &'static str {
+                    #schema_name
+                }
+
+                fn new_row_builder(arrow_fields: Vec<&'a Field>) -> Self::RowBuilderType {
+                    ::r2a::StructRowBuilder::new(arrow_fields, false, #stamp_nanos)
+                }
+
+                fn new_flat_row_builder(arrow_fields: Vec<&'a Field>) -> Self::FlatRowBuilderType {
+                    ::r2a::StructRowBuilder::new(arrow_fields, true, #stamp_nanos)
+                }
+
+                fn arrow_fields(include_self: bool) -> Vec<Field> {
+                    let mut schema = vec![#(#field_schemas),*];
+                    if include_self {
+                        schema.push(Field::new_struct(::r2a::MESSAGE_STRUCT_FIELD, Self::arrow_fields(false), true))
+                    }
+                    schema
+                }
+
+                fn arrow_schema(include_self: bool) -> Schema {
+                    Schema::new(Self::arrow_fields(include_self))
+                }
+
+                fn flat_arrow_fields(include_self: bool) -> Vec<Field> {
+                    let mut schema = ::r2a::__private::flatten_fields(&Self::arrow_fields(false));
+                    if include_self {
+                        schema.push(Field::new_struct(::r2a::MESSAGE_STRUCT_FIELD, Self::arrow_fields(false), true))
+                    }
+                    schema
+                }
+
+                fn flat_arrow_schema(include_self: bool) -> Schema {
+                    Schema::new(Self::flat_arrow_fields(include_self))
+                }
+
+                fn message_struct_field(name: &str, encoding: ::r2a::MessageStructEncoding) -> Field {
+                    let field = match encoding {
+                        ::r2a::MessageStructEncoding::Struct => Field::new_struct(name, Self::arrow_fields(false), true),
+                        ::r2a::MessageStructEncoding::FlatStruct => Field::new_struct(name, Self::flat_arrow_fields(false), true),
+                        ::r2a::MessageStructEncoding::Json => Field::new(name, DataType::Utf8, true),
+                    };
+                    field.with_metadata(::std::collections::HashMap::from([(
+                        ::r2a::MESSAGE_STRUCT_ENCODING_KEY.to_string(),
+                        encoding.as_str().to_string(),
+                    )]))
+                }
+
+                #[allow(unused_variables)]
+                fn append_to_struct_builder(&self, builder: &mut arrow_array::builder::StructBuilder) {
+                    let msg = self;
+                    #(#struct_builder_appends)*
+                    builder.append(true);
+                }
+            }
+        };
+    ))
+}
+
+fn is_skipped(field: &syn::Field) -> syn::Result<bool> {
+    let mut skip = false;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("r2a"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported r2a attribute"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
+
+/// Brings the spellings of the string and vector types to the ones r2r uses, which the shared
+/// type mapping expects.
+fn normalize_type(typ: &str) -> String {
+    let typ = typ
+        .strip_prefix("std::vec::")
+        .or_else(|| typ.strip_prefix("alloc::vec::"))
+        .unwrap_or(typ);
+    if let Some(element) = typ.strip_prefix("Vec<").and_then(|t| t.strip_suffix('>')) {
+        format!("Vec<{}>", normalize_type(element))
+    } else if typ == "String" || typ == "alloc::string::String" {
+        "std::string::String".to_string()
+    } else {
+        typ.to_string()
+    }
+}
+
+fn field_kind(ty: &Type, typ: &str) -> FieldKind {
+    if is_primitive_type(typ) || is_primitive_vector_type(typ) {
+        return FieldKind::Primitive(typ.to_string());
+    }
+    match vec_element_type(ty) {
+        Some(element_ty) => FieldKind::StructArray(element_ty.clone()),
+        None => FieldKind::Struct,
+    }
+}
+
+fn vec_element_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != "Vec" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first()? {
+            syn::GenericArgument::Type(element_ty) => Some(element_ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,63 @@
+//! Reads the times of the rows of a batch, from the context columns or the message headers.
+
+use anyhow::{bail, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Int32Type, TimestampNanosecondType, UInt32Type};
+use arrow_array::{Array, RecordBatch, StructArray};
+use arrow_schema::{DataType, TimeUnit};
+
+/// Returns the values of a timestamp column, or `None` if the batch doesn't have it. Null and
+/// negative values are `None`.
+pub(crate) fn timestamp_column(
+    batch: &RecordBatch,
+    name: &str,
+) -> Result<Option<Vec<Option<u64>>>> {
+    let Some(column) = batch.column_by_name(name) else {
+        return Ok(None);
+    };
+    let DataType::Timestamp(TimeUnit::Nanosecond, _) = column.data_type() else {
+        bail!("Column {} is not a nanosecond timestamp", name);
+    };
+    let column = column.as_primitive::<TimestampNanosecondType>();
+    Ok(Some(
+        (0..column.len())
+            .map(|row| {
+                column
+                    .is_valid(row)
+                    .then(|| u64::try_from(column.value(row)).ok())
+                    .flatten()
+            })
+            .collect(),
+    ))
+}
+
+/// The header stamps of the rows in nanoseconds, if the messages have a header. Null stamps and
+/// stamps before the UNIX epoch are `None`.
+pub(crate) fn header_stamps(batch: &RecordBatch, flat: bool) -> Option<Vec<Option<u64>>> {
+    let (sec, nanosec) = if flat {
+        (
+            batch.column_by_name("header_stamp_sec")?.clone(),
+            batch.column_by_name("header_stamp_nanosec")?.clone(),
+        )
+    } else {
+        let header: &StructArray = batch.column_by_name("header")?.as_struct_opt()?;
+        let stamp: &StructArray = header.column_by_name("stamp")?.as_struct_opt()?;
+        (
+            stamp.column_by_name("sec")?.clone(),
+            stamp.column_by_name("nanosec")?.clone(),
+        )
+    };
+    let sec = sec.as_primitive_opt::<Int32Type>()?;
+    let nanosec = nanosec.as_primitive_opt::<UInt32Type>()?;
+    Some(
+        (0..batch.num_rows())
+            .map(|row| {
+                if sec.is_null(row) || nanosec.is_null(row) {
+                    return None;
+                }
+                let stamp = sec.value(row) as i64 * 1_000_000_000 + nanosec.value(row) as i64;
+                u64::try_from(stamp).ok()
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,373 @@
+//! CDR serialization of the message types generated by the `offline` feature. The `rosbag1`
+//! feature uses [`CdrWriter`] to convert ROS 1 messages.
+//!
+//! ROS 2 messages are serialized with the OMG CDR encoding (XCDR version 1): a 4 byte
+//! encapsulation header that selects the byte order, followed by the fields in declaration
+//! order, each aligned to its own size relative to the end of the header. Strings and unbounded
+//! or bounded arrays are prefixed by their length as a `u32`, fixed size arrays are not.
+//!
+//! The generated types implement [`CdrMessage`], which has the same `from_serialized_bytes` and
+//! `to_serialized_bytes` methods as `r2r::WrappedTypesupport`, so code that reads raw messages
+//! works with both.
+
+use anyhow::{anyhow, Context, Result};
+
+const CDR_LE: [u8; 4] = [0x00, 0x01, 0x00, 0x00];
+
+/// Reads the fields of a CDR serialized message.
+pub struct CdrReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    little_endian: bool,
+}
+
+impl<'a> CdrReader<'a> {
+    /// Creates a reader over a serialized message, starting with the encapsulation header.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(anyhow!(
+                "A CDR message is at least 4 bytes long, got {}",
+                data.len()
+            ));
+        }
+        let little_endian = match data[1] {
+            0x00 => false,
+            0x01 => true,
+            representation => {
+                return Err(anyhow!(
+                    "Unsupported CDR representation {:#04x}{:02x}",
+                    data[0],
+                    representation
+                ))
+            }
+        };
+        Ok(CdrReader {
+            data: &data[4..],
+            position: 0,
+            little_endian,
+        })
+    }
+
+    /// The number of bytes that were not read yet.
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.position = self.position.next_multiple_of(alignment);
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(anyhow!(
+                "Unexpected end of the CDR message at byte {}, {} more bytes needed",
+                self.position + 4,
+                len
+            ));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn read_aligned<const N: usize>(&mut self) -> Result<[u8; N]> {
+        self.align(N);
+        let mut bytes: [u8; N] = self.take(N)?.try_into()?;
+        if self.little_endian != cfg!(target_endian = "little") {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    /// Reads the length of a string or a sequence.
+    pub fn read_length(&mut self) -> Result<usize> {
+        Ok(u32::from_ne_bytes(self.read_aligned()?) as usize)
+    }
+
+    /// Reads a string, which is serialized with its terminating zero.
+    pub fn read_string(&mut self) -> Result<String> {
+        let len = self.read_length()?;
+        let bytes = self.take(len)?;
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        String::from_utf8(bytes.to_vec()).context("Invalid UTF-8 in a CDR string")
+    }
+
+    /// Reads an unbounded or bounded sequence.
+    pub fn read_sequence<T: CdrValue>(&mut self) -> Result<Vec<T>> {
+        let len = self.read_length()?;
+        self.read_array(len)
+    }
+
+    /// Reads a fixed size array, which has no length prefix.
+    pub fn read_array<T: CdrValue>(&mut self, len: usize) -> Result<Vec<T>> {
+        // Every element takes at least a byte, don't trust the length of a corrupt message.
+        let mut values = Vec::with_capacity(len.min(self.remaining()));
+        for _ in 0..len {
+            values.push(T::read_cdr(self)?);
+        }
+        Ok(values)
+    }
+
+    /// Reads a `uint8[]`, `byte[]` or `char[]` sequence in one go.
+    pub fn read_byte_sequence(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_length()?;
+        self.read_byte_array(len)
+    }
+
+    /// Reads a `uint8[N]`, `byte[N]` or `char[N]` array in one go.
+    pub fn read_byte_array(&mut self, len: usize) -> Result<Vec<u8>> {
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+/// Writes the fields of a message in little endian CDR.
+pub struct CdrWriter {
+    buffer: Vec<u8>,
+}
+
+impl Default for CdrWriter {
+    fn default() -> Self {
+        CdrWriter {
+            buffer: CDR_LE.to_vec(),
+        }
+    }
+}
+
+impl CdrWriter {
+    /// Returns the serialized message, with its encapsulation header.
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    fn write_aligned(&mut self, bytes: &[u8]) {
+        let position = self.buffer.len() - CDR_LE.len();
+        let padding = position.next_multiple_of(bytes.len()) - position;
+        self.buffer.resize(self.buffer.len() + padding, 0);
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Writes the length of a string or a sequence.
+    pub fn write_length(&mut self, len: usize) -> Result<()> {
+        let len = u32::try_from(len).context("Sequence too long for CDR")?;
+        self.write_aligned(&len.to_le_bytes());
+        Ok(())
+    }
+
+    /// Writes a string with its terminating zero.
+    pub fn write_string(&mut self, value: &str) -> Result<()> {
+        self.write_length(value.len() + 1)?;
+        self.buffer.extend_from_slice(value.as_bytes());
+        self.buffer.push(0);
+        Ok(())
+    }
+
+    /// Writes an unbounded or bounded sequence.
+    pub fn write_sequence<T: CdrValue>(&mut self, values: &[T]) -> Result<()> {
+        self.write_length(values.len())?;
+        values.iter().try_for_each(|value| value.write_cdr(self))
+    }
+
+    /// Writes a fixed size array, which must have exactly `len` elements.
+    pub fn write_array<T: CdrValue>(&mut self, values: &[T], len: usize) -> Result<()> {
+        check_array_len(values.len(), len)?;
+        values.iter().try_for_each(|value| value.write_cdr(self))
+    }
+
+    /// Writes a `uint8[]`, `byte[]` or `char[]` sequence in one go.
+    pub fn write_byte_sequence(&mut self, values: &[u8]) -> Result<()> {
+        self.write_length(values.len())?;
+        self.buffer.extend_from_slice(values);
+        Ok(())
+    }
+
+    /// Writes a `uint8[N]`, `byte[N]` or `char[N]` array in one go.
+    pub fn write_byte_array(&mut self, values: &[u8], len: usize) -> Result<()> {
+        check_array_len(values.len(), len)?;
+        self.buffer.extend_from_slice(values);
+        Ok(())
+    }
+}
+
+fn check_array_len(actual: usize, expected: usize) -> Result<()> {
+    if actual != expected {
+        return Err(anyhow!(
+            "A fixed size array of {} elements has {} elements",
+            expected,
+            actual
+        ));
+    }
+    Ok(())
+}
+
+/// A value that can be read from and written to CDR: a primitive, a string, a sequence or a
+/// message.
+pub trait CdrValue: Sized {
+    fn read_cdr(reader: &mut CdrReader) -> Result<Self>;
+    fn write_cdr(&self, writer: &mut CdrWriter) -> Result<()>;
+}
+
+/// A message, serialized with its CDR encapsulation header.
+pub trait CdrMessage: CdrValue {
+    /// Deserializes a message, in either byte order.
+    fn from_serialized_bytes(data: &[u8]) -> Result<Self> {
+        let mut reader = CdrReader::new(data)?;
+        Self::read_cdr(&mut reader)
+    }
+
+    /// Serializes a message in little endian, the byte order ROS 2 uses on every common
+    /// platform.
+    fn to_serialized_bytes(&self) -> Result<Vec<u8>> {
+        let mut writer = CdrWriter::default();
+        self.write_cdr(&mut writer)?;
+        Ok(writer.finish())
+    }
+}
+
+macro_rules! impl_cdr_value_for_primitives {
+    ($($typ:ty),*) => {
+        $(
+            impl CdrValue for $typ {
+                fn read_cdr(reader: &mut CdrReader) -> Result<Self> {
+                    Ok(<$typ>::from_ne_bytes(reader.read_aligned()?))
+                }
+
+                fn write_cdr(&self, writer: &mut CdrWriter) -> Result<()> {
+                    writer.write_aligned(&self.to_le_bytes());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_cdr_value_for_primitives!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+impl CdrValue for bool {
+    fn read_cdr(reader: &mut CdrReader) -> Result<Self> {
+        Ok(u8::read_cdr(reader)? != 0)
+    }
+
+    fn write_cdr(&self, writer: &mut CdrWriter) -> Result<()> {
+        (*self as u8).write_cdr(writer)
+    }
+}
+
+impl CdrValue for String {
+    fn read_cdr(reader: &mut CdrReader) -> Result<Self> {
+        reader.read_string()
+    }
+
+    fn write_cdr(&self, writer: &mut CdrWriter) -> Result<()> {
+        writer.write_string(self)
+    }
+}
+
+impl<T: CdrValue> CdrValue for Vec<T> {
+    fn read_cdr(reader: &mut CdrReader) -> Result<Self> {
+        reader.read_sequence()
+    }
+
+    fn write_cdr(&self, writer: &mut CdrWriter) -> Result<()> {
+        writer.write_sequence(self)
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::geometry_msgs::msg::{Point, Pose, PoseWithCovariance, Quaternion};
+    use crate::msgs::sensor_msgs::msg::{LaserScan, PointField};
+    use crate::msgs::std_msgs::msg::{Empty, Header};
+    use crate::{ArrowSupport, RowBuilder};
+
+    fn header() -> Header {
+        Header {
+            stamp: crate::msgs::builtin_interfaces::msg::Time { sec: 1, nanosec: 2 },
+            frame_id: "map".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_known_encoding() {
+        let bytes = header().to_serialized_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [0, 1, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, b'm', b'a', b'p', 0]
+        );
+
+        let big_endian = [
+            0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 4, b'm', b'a', b'p', 0,
+        ];
+        assert_eq!(
+            Header::from_serialized_bytes(&big_endian).unwrap(),
+            header()
+        );
+        assert!(Header::from_serialized_bytes(&bytes[..15]).is_err());
+
+        // Empty messages still take one byte.
+        assert_eq!(Empty {}.to_serialized_bytes().unwrap(), [0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let scan = LaserScan {
+            header: header(),
+            angle_min: -1.5,
+            angle_max: 1.5,
+            ranges: vec![1.0, 2.0, 3.0],
+            intensities: vec![0.5],
+            ..Default::default()
+        };
+        let bytes = scan.to_serialized_bytes().unwrap();
+        assert_eq!(LaserScan::from_serialized_bytes(&bytes).unwrap(), scan);
+
+        // The header ends at byte 16 of the payload, the f64 after it is aligned to 24.
+        let pose = PoseWithCovariance {
+            pose: Pose {
+                position: Point {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 3.0,
+                },
+                orientation: Quaternion::default(),
+            },
+            covariance: (0..36).map(f64::from).collect(),
+        };
+        let bytes = pose.to_serialized_bytes().unwrap();
+        assert_eq!(bytes.len(), 4 + 7 * 8 + 36 * 8);
+        assert_eq!(
+            PoseWithCovariance::from_serialized_bytes(&bytes).unwrap(),
+            pose
+        );
+
+        let wrong_size = PoseWithCovariance {
+            covariance: vec![0.0; 35],
+            ..pose
+        };
+        assert!(wrong_size.to_serialized_bytes().is_err());
+    }
+
+    #[test]
+    fn test_defaults_and_constants() {
+        assert_eq!(Quaternion::default().w, 1.0);
+        assert_eq!(PoseWithCovariance::default().covariance.len(), 36);
+        assert_eq!(PointField::FLOAT32, 7);
+    }
+
+    #[test]
+    fn test_raw_rows() {
+        let scan = LaserScan {
+            header: header(),
+            ranges: vec![1.0, 2.0],
+            ..Default::default()
+        };
+        let fields = LaserScan::flat_arrow_fields(false);
+        let mut row_builder = LaserScan::new_flat_row_builder(fields.iter().collect());
+        row_builder
+            .add_raw_row(&scan.to_serialized_bytes().unwrap())
+            .unwrap();
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), fields.len());
+        assert_eq!(arrays[0].len(), 1);
+    }
+}
@@ -0,0 +1,593 @@
+//! Queries the topics of bags and MCAP files with SQL, through DataFusion. Requires the
+//! `datafusion` feature, and the `rosbag2`, `mcap` or `rosbag1` feature of the recordings.
+//!
+//! A [`TopicTable`] is a DataFusion `TableProvider` for one topic of a recording, with the
+//! regular or the flat schema of [`crate::rosbag2::Rosbag2Reader::topic_schema`] and its
+//! equivalents. [`register_topics`] registers every supported topic of a recording under its
+//! [`topic_table_name`], `scan` for `/scan`.
+//!
+//! The messages are decoded while the query runs, in batches of the session's batch size:
+//!
+//! - Only the columns the query uses are built, the projection of a scan is passed to
+//!   `with_projection`.
+//! - Comparisons of `_recv_time`, the bag timestamp, with timestamp literals are pushed down
+//!   to `topic_batches_in_range`, so only the chunks or rows in the range are read. Other
+//!   filters are applied by DataFusion.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use datafusion::prelude::SessionContext;
+//!
+//! # async fn query() -> anyhow::Result<()> {
+//! let ctx = SessionContext::new();
+//! r2a::datafusion::register_topics(&ctx, "recordings/run_42/run_42_0.mcap", true)?;
+//! let df = ctx
+//!     .sql(
+//!         "SELECT header_stamp_sec, ranges FROM scan \
+//!          WHERE _recv_time >= '2024-10-13T14:00:00Z' AND range_max > 10",
+//!     )
+//!     .await?;
+//! df.show().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::RECV_TIME_FIELD;
+use ::datafusion::catalog::{Session, TableProvider};
+use ::datafusion::common::{DataFusionError, ScalarValue};
+use ::datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use ::datafusion::logical_expr::{
+    Between, BinaryExpr, Expr, Operator, TableProviderFilterPushDown, TableType,
+};
+use ::datafusion::physical_plan::stream::RecordBatchReceiverStream;
+use ::datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use ::datafusion::physical_plan::ExecutionPlan;
+use ::datafusion::prelude::SessionContext;
+use anyhow::{anyhow, bail, Result};
+use arrow_array::RecordBatch;
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use std::any::Any;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The format of a recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BagFormat {
+    /// A rosbag2 bag directory with the SQLite3 storage.
+    #[cfg(feature = "rosbag2")]
+    Rosbag2,
+    /// An MCAP file.
+    #[cfg(feature = "mcap")]
+    Mcap,
+    /// A ROS 1 bag.
+    #[cfg(feature = "rosbag1")]
+    Rosbag1,
+}
+
+impl BagFormat {
+    /// Detects the format of the recording at `path`: a directory is a rosbag2 bag, a `.mcap`
+    /// file an MCAP file and a `.bag` file a ROS 1 bag.
+    pub fn detect(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        #[cfg(feature = "rosbag2")]
+        if path.is_dir() {
+            return Ok(BagFormat::Rosbag2);
+        }
+        #[cfg(feature = "mcap")]
+        if path.extension().is_some_and(|ext| ext == "mcap") {
+            return Ok(BagFormat::Mcap);
+        }
+        #[cfg(feature = "rosbag1")]
+        if path.extension().is_some_and(|ext| ext == "bag") {
+            return Ok(BagFormat::Rosbag1);
+        }
+        bail!("Unsupported recording {}", path.display())
+    }
+}
+
+/// The name a topic is registered under by [`register_topics`]: the topic without its leading
+/// `/`, and `_` instead of the characters that are not letters, digits or `_`, for example
+/// `camera_image_raw` for `/camera/image_raw`.
+pub fn topic_table_name(topic: &str) -> String {
+    topic
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Registers the topics of the recording at `path` with `ctx`, one [`TopicTable`] per topic in
+/// the regular or, if `flat` is true, the flat layout. Topics of unsupported types are skipped
+/// with a warning. Returns the names of the registered tables.
+pub fn register_topics(
+    ctx: &SessionContext,
+    path: impl AsRef<Path>,
+    flat: bool,
+) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let format = BagFormat::detect(path)?;
+    let mut names = vec![];
+    for topic in recording_topics(format, path)? {
+        let table = match TopicTable::open(format, path, &topic, flat) {
+            Ok(table) => table,
+            Err(e) => {
+                log::warn!("Skipping topic {}: {:#}", topic, e);
+                continue;
+            }
+        };
+        let name = topic_table_name(&topic);
+        ctx.register_table(name.as_str(), Arc::new(table))?;
+        names.push(name);
+    }
+    Ok(names)
+}
+
+fn recording_topics(format: BagFormat, path: &Path) -> Result<Vec<String>> {
+    Ok(match format {
+        #[cfg(feature = "rosbag2")]
+        BagFormat::Rosbag2 => crate::rosbag2::Rosbag2Reader::open(path)?
+            .topics()
+            .iter()
+            .map(|topic| topic.name.clone())
+            .collect(),
+        #[cfg(feature = "mcap")]
+        BagFormat::Mcap => crate::mcap::McapReader::open(path)?
+            .topics()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        #[cfg(feature = "rosbag1")]
+        BagFormat::Rosbag1 => crate::rosbag1::Rosbag1Reader::open(path)?
+            .topics()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// A topic of a recording as a DataFusion table.
+#[derive(Debug, Clone)]
+pub struct TopicTable {
+    format: BagFormat,
+    path: PathBuf,
+    topic: String,
+    flat: bool,
+    schema: SchemaRef,
+}
+
+impl TopicTable {
+    /// Creates the table of `topic` in the recording at `path`, in the regular or, if `flat` is
+    /// true, the flat layout. The recording is read again by every query.
+    pub fn open(
+        format: BagFormat,
+        path: impl AsRef<Path>,
+        topic: &str,
+        flat: bool,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let schema = match format {
+            #[cfg(feature = "rosbag2")]
+            BagFormat::Rosbag2 => {
+                crate::rosbag2::Rosbag2Reader::open(path)?.topic_schema(topic, flat)?
+            }
+            #[cfg(feature = "mcap")]
+            BagFormat::Mcap => crate::mcap::McapReader::open(path)?.topic_schema(topic, flat)?,
+            #[cfg(feature = "rosbag1")]
+            BagFormat::Rosbag1 => {
+                crate::rosbag1::Rosbag1Reader::open(path)?.topic_schema(topic, flat)?
+            }
+        };
+        Ok(TopicTable {
+            format,
+            path: path.to_path_buf(),
+            topic: topic.to_string(),
+            flat,
+            schema: Arc::new(schema),
+        })
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+#[async_trait]
+impl TableProvider for TopicTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> ::datafusion::common::Result<Arc<dyn ExecutionPlan>> {
+        let projection = projection
+            .cloned()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let mut range = TimeRange::default();
+        for filter in filters {
+            range.intersect(filter);
+        }
+        let partition = TopicPartition {
+            table: self.clone(),
+            schema: Arc::new(self.schema.project(&projection)?),
+            projection,
+            range,
+            limit,
+        };
+        Ok(Arc::new(StreamingTableExec::try_new(
+            partition.schema.clone(),
+            vec![Arc::new(partition)],
+            None,
+            vec![],
+            false,
+            limit,
+        )?))
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> ::datafusion::common::Result<Vec<TableProviderFilterPushDown>> {
+        // The readers filter on the bag timestamp exactly.
+        Ok(filters
+            .iter()
+            .map(|filter| match time_bounds(filter) {
+                Some(_) => TableProviderFilterPushDown::Exact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+}
+
+/// The bag timestamps a scan reads, in nanoseconds since the UNIX epoch, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeRange {
+    start: i128,
+    end: i128,
+}
+
+impl Default for TimeRange {
+    fn default() -> Self {
+        TimeRange {
+            start: 0,
+            end: u64::MAX.into(),
+        }
+    }
+}
+
+impl TimeRange {
+    /// Narrows the range to the times that pass `filter`, if it is a time filter.
+    fn intersect(&mut self, filter: &Expr) {
+        for (op, time) in time_bounds(filter).unwrap_or_default() {
+            match op {
+                Operator::Gt => self.start = self.start.max(time + 1),
+                Operator::GtEq => self.start = self.start.max(time),
+                Operator::Lt => self.end = self.end.min(time - 1),
+                Operator::LtEq => self.end = self.end.min(time),
+                _ => {
+                    self.start = self.start.max(time);
+                    self.end = self.end.min(time);
+                }
+            }
+        }
+    }
+
+    /// The range for `topic_batches_in_range`, or `None` if it is empty.
+    fn bounds(&self) -> Option<std::ops::RangeInclusive<u64>> {
+        let start = u64::try_from(self.start.max(0)).ok()?;
+        let end = u64::try_from(self.end).ok()?;
+        (start <= end).then_some(start..=end)
+    }
+}
+
+/// The comparisons of `_recv_time` in `filter`, as the operator with the column on the left and
+/// the time in nanoseconds, or `None` if `filter` is not a time filter.
+fn time_bounds(filter: &Expr) -> Option<Vec<(Operator, i128)>> {
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let op = match op {
+                Operator::Eq | Operator::Lt | Operator::LtEq | Operator::Gt | Operator::GtEq => *op,
+                _ => return None,
+            };
+            if is_recv_time(left) {
+                Some(vec![(op, timestamp_nanos(right)?)])
+            } else if is_recv_time(right) {
+                Some(vec![(op.swap()?, timestamp_nanos(left)?)])
+            } else {
+                None
+            }
+        }
+        Expr::Between(Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        }) if is_recv_time(expr) => Some(vec![
+            (Operator::GtEq, timestamp_nanos(low)?),
+            (Operator::LtEq, timestamp_nanos(high)?),
+        ]),
+        _ => None,
+    }
+}
+
+fn is_recv_time(expr: &Expr) -> bool {
+    matches!(expr, Expr::Column(column) if column.name == RECV_TIME_FIELD)
+}
+
+fn timestamp_nanos(expr: &Expr) -> Option<i128> {
+    let (value, nanos_per_unit) = match expr {
+        Expr::Literal(ScalarValue::TimestampSecond(Some(value), _)) => (value, 1_000_000_000),
+        Expr::Literal(ScalarValue::TimestampMillisecond(Some(value), _)) => (value, 1_000_000),
+        Expr::Literal(ScalarValue::TimestampMicrosecond(Some(value), _)) => (value, 1_000),
+        Expr::Literal(ScalarValue::TimestampNanosecond(Some(value), _)) => (value, 1),
+        _ => return None,
+    };
+    Some(i128::from(*value) * nanos_per_unit)
+}
+
+/// Reads the batches of a scan.
+#[derive(Debug)]
+struct TopicPartition {
+    table: TopicTable,
+    projection: Vec<usize>,
+    schema: SchemaRef,
+    range: TimeRange,
+    limit: Option<usize>,
+}
+
+impl TopicPartition {
+    /// Opens the recording and sends its batches until the limit is reached or the receiver is
+    /// dropped.
+    fn read(&self, batch_size: usize, send: &mut dyn FnMut(RecordBatch) -> bool) -> Result<()> {
+        let Some(range) = self.range.bounds() else {
+            return Ok(());
+        };
+        let table = &self.table;
+        let mut rows = 0;
+        let mut send = |batch: Result<RecordBatch>| -> Result<bool> {
+            let batch = batch?;
+            rows += batch.num_rows();
+            Ok(send(batch) && self.limit.is_none_or(|limit| rows < limit))
+        };
+        match table.format {
+            #[cfg(feature = "rosbag2")]
+            BagFormat::Rosbag2 => {
+                let reader =
+                    crate::rosbag2::Rosbag2Reader::open(&table.path)?.with_batch_size(batch_size);
+                for batch in reader
+                    .topic_batches_in_range(&table.topic, table.flat, range)?
+                    .with_projection(&self.projection)?
+                {
+                    if !send(batch)? {
+                        break;
+                    }
+                }
+            }
+            #[cfg(feature = "mcap")]
+            BagFormat::Mcap => {
+                let reader =
+                    crate::mcap::McapReader::open(&table.path)?.with_batch_size(batch_size);
+                for batch in reader
+                    .topic_batches_in_range(&table.topic, table.flat, range)?
+                    .with_projection(&self.projection)?
+                {
+                    if !send(batch)? {
+                        break;
+                    }
+                }
+            }
+            #[cfg(feature = "rosbag1")]
+            BagFormat::Rosbag1 => {
+                let reader =
+                    crate::rosbag1::Rosbag1Reader::open(&table.path)?.with_batch_size(batch_size);
+                for batch in reader
+                    .topic_batches_in_range(&table.topic, table.flat, range)?
+                    .with_projection(&self.projection)?
+                {
+                    if !send(batch)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PartitionStream for TopicPartition {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let mut builder = RecordBatchReceiverStream::builder(self.schema.clone(), 2);
+        let tx = builder.tx();
+        let batch_size = ctx.session_config().batch_size();
+        let partition = TopicPartition {
+            table: self.table.clone(),
+            projection: self.projection.clone(),
+            schema: self.schema.clone(),
+            range: self.range,
+            limit: self.limit,
+        };
+        // The readers are blocking, they run on a thread of the blocking pool.
+        builder.spawn_blocking(move || {
+            partition
+                .read(batch_size, &mut |batch| tx.blocking_send(Ok(batch)).is_ok())
+                .map_err(|e| {
+                    DataFusionError::External(
+                        anyhow!(e)
+                            .context(format!("Unable to read {}", partition.table.topic))
+                            .into(),
+                    )
+                })
+        });
+        builder.build()
+    }
+}
+
+#[cfg(all(test, feature = "offline", feature = "mcap"))]
+mod tests {
+    use super::*;
+    use crate::mcap::McapWriter;
+    use crate::msgs::builtin_interfaces::msg::Time;
+    use crate::msgs::sensor_msgs::msg::LaserScan;
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::ArrowSupport;
+    use crate::RowBuilder;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, Int32Type, Int64Type};
+    use arrow_schema::Schema;
+    use datafusion::prelude::col;
+
+    /// Writes /scan messages at 1..=6 s, with `range_max` of 10 * s.
+    fn write_mcap(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "r2a_datafusion_{}_{}.mcap",
+            name,
+            std::process::id()
+        ));
+        let fields = LaserScan::flat_arrow_fields(false);
+        let mut row_builder = LaserScan::new_flat_row_builder(fields.iter().collect());
+        for sec in 1..=6 {
+            let scan = LaserScan {
+                header: Header {
+                    stamp: Time { sec, nanosec: 0 },
+                    frame_id: "laser".to_string(),
+                },
+                range_max: 10.0 * sec as f32,
+                ranges: vec![sec as f32, 0.5],
+                ..Default::default()
+            };
+            row_builder.add_row(&scan).unwrap();
+        }
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(fields.clone())),
+            row_builder.to_arc_arrays(),
+        )
+        .unwrap();
+        let mut writer = McapWriter::create(&path).unwrap();
+        writer
+            .write_batch("/scan", "sensor_msgs/msg/LaserScan", &batch, true)
+            .unwrap();
+        writer.finish().unwrap();
+        path
+    }
+
+    async fn query(ctx: &SessionContext, sql: &str) -> Vec<RecordBatch> {
+        ctx.sql(sql).await.unwrap().collect().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sql() {
+        let path = write_mcap("sql");
+        let ctx = SessionContext::new();
+        assert_eq!(register_topics(&ctx, &path, true).unwrap(), ["scan"]);
+
+        let batches = query(
+            &ctx,
+            "SELECT header_stamp_sec, ranges FROM scan \
+             WHERE _recv_time >= '1970-01-01T00:00:02Z' AND _recv_time < '1970-01-01T00:00:05Z' \
+             AND range_max > 25 ORDER BY header_stamp_sec",
+        )
+        .await;
+        let schema = batches[0].schema();
+        let names: Vec<&String> = schema.fields().iter().map(|field| field.name()).collect();
+        assert_eq!(names, ["header_stamp_sec", "ranges"]);
+        let secs: Vec<i32> = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_primitive::<Int32Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(secs, [3, 4]);
+        let ranges = batches[0].column(1).as_list::<i64>().value(0);
+        assert_eq!(ranges.as_primitive::<Float32Type>().values(), &[3.0, 0.5]);
+
+        let batches = query(&ctx, "SELECT count(*) FROM scan").await;
+        assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 6);
+        let batches = query(
+            &ctx,
+            "SELECT count(*) FROM scan WHERE _recv_time > '1970-01-01T00:00:05Z'",
+        )
+        .await;
+        assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 1);
+
+        // The nested layout.
+        let table = TopicTable::open(BagFormat::Mcap, &path, "/scan", false).unwrap();
+        ctx.register_table("nested_scan", Arc::new(table)).unwrap();
+        let batches = query(&ctx, "SELECT header FROM nested_scan LIMIT 2").await;
+        assert_eq!(
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            2
+        );
+        let header = batches[0].column(0).as_struct();
+        let frame_id = header.column_by_name("frame_id").unwrap();
+        assert_eq!(frame_id.as_string::<i32>().value(0), "laser");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_filter_pushdown() {
+        let path = write_mcap("pushdown");
+        let table = TopicTable::open(BagFormat::Mcap, &path, "/scan", true).unwrap();
+        let time = |sec: i64| Expr::Literal(ScalarValue::TimestampSecond(Some(sec), None));
+        let filters = [
+            col(RECV_TIME_FIELD).gt_eq(time(2)),
+            time(5).gt(col(RECV_TIME_FIELD)),
+            col(RECV_TIME_FIELD).between(time(1), time(4)),
+            col("range_max").gt(time(2)),
+            col(RECV_TIME_FIELD).is_null(),
+        ];
+        let pushdown = table
+            .supports_filters_pushdown(&filters.iter().collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(
+            pushdown,
+            [
+                TableProviderFilterPushDown::Exact,
+                TableProviderFilterPushDown::Exact,
+                TableProviderFilterPushDown::Exact,
+                TableProviderFilterPushDown::Unsupported,
+                TableProviderFilterPushDown::Unsupported,
+            ]
+        );
+
+        let mut range = TimeRange::default();
+        for filter in &filters {
+            range.intersect(filter);
+        }
+        assert_eq!(range.bounds(), Some(2_000_000_000..=4_000_000_000));
+        range.intersect(&col(RECV_TIME_FIELD).lt(time(2)));
+        assert_eq!(range.bounds(), None);
+        let mut range = TimeRange::default();
+        range.intersect(&col(RECV_TIME_FIELD).gt(time(-10)));
+        assert_eq!(range.bounds(), Some(0..=u64::MAX));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_topic_table_name() {
+        assert_eq!(topic_table_name("/scan"), "scan");
+        assert_eq!(topic_table_name("/camera/image_raw"), "camera_image_raw");
+        assert_eq!(topic_table_name("/robot-1/odom"), "robot_1_odom");
+    }
+}
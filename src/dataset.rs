@@ -0,0 +1,734 @@
+//! Writes topics to a Hive-partitioned Parquet dataset. Requires the `dataset` feature.
+//!
+//! A [`DatasetWriter`] routes every row to the partition of its topic and of its time, by
+//! default `<root>/topic=<topic>/date=YYYY-MM-DD/hour=HH/part-<N>.parquet` in UTC. The time of a
+//! row is its header stamp or its receive time, see [`PartitionTime`], and the partition
+//! directories below the topic are configured with [`PartitionKey`]s.
+//!
+//! The writer keeps one open file per partition, up to a limit, and starts a new file when a
+//! file reaches its maximum size or row count. Like [`crate::parquet::ParquetSink`], files are
+//! written with a `.tmp` suffix that is removed when they are closed, and their footer holds
+//! the [`crate::file_metadata`] of the topic. The `_manifest.json` file at the root of the
+//! dataset describes the ROS schema, the Arrow fields and the partitioning of every topic.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::dataset::{DatasetWriter, PartitionKey, PartitionTime};
+//! use r2a::rosbag2::Rosbag2Reader;
+//!
+//! let reader = Rosbag2Reader::open("recordings/run_42").unwrap();
+//! let mut writer = DatasetWriter::new("lake/robots")
+//!     .unwrap()
+//!     .with_partition_time(PartitionTime::RecvTime)
+//!     .with_partition_keys(vec![PartitionKey::Date, PartitionKey::Hour])
+//!     .with_max_file_rows(1_000_000);
+//! for topic in reader.topics() {
+//!     for batch in reader.topic_batches(&topic.name, true).unwrap() {
+//!         writer
+//!             .write_batch(&topic.name, &topic.type_name, &batch.unwrap())
+//!             .unwrap();
+//!     }
+//! }
+//! writer.finish().unwrap();
+//! ```
+
+use crate::batch_times::{header_stamps, timestamp_column};
+use crate::parquet::{topic_file_name, Compression, DEFAULT_ROW_GROUP_SIZE};
+use crate::utc_time::UtcTime;
+use crate::{ArrowSupport, RowBuilder, RECV_TIME_FIELD};
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::file::metadata::KeyValue;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::RecordBatch;
+use arrow_schema::{Field, Schema, SchemaRef};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The name of the manifest file at the root of a dataset.
+pub const MANIFEST_FILE_NAME: &str = "_manifest.json";
+
+/// The maximum number of files open at the same time, unless set with
+/// [`DatasetWriter::with_max_open_files`].
+pub const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+/// The time a row is partitioned by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionTime {
+    /// The `stamp` of the `header` of the message, in the nested or the flat layout.
+    HeaderStamp,
+    /// The `_recv_time` context column, see [`crate::RowContext`].
+    RecvTime,
+}
+
+/// A partition directory level below the topic, computed from the UTC time of a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionKey {
+    /// `year=YYYY`
+    Year,
+    /// `month=MM`
+    Month,
+    /// `day=DD`
+    Day,
+    /// `date=YYYY-MM-DD`
+    Date,
+    /// `hour=HH`
+    Hour,
+    /// `minute=MM`
+    Minute,
+}
+
+impl PartitionKey {
+    /// The name of the key in the partition directories.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PartitionKey::Year => "year",
+            PartitionKey::Month => "month",
+            PartitionKey::Day => "day",
+            PartitionKey::Date => "date",
+            PartitionKey::Hour => "hour",
+            PartitionKey::Minute => "minute",
+        }
+    }
+
+    fn value(&self, time: &UtcTime) -> String {
+        match self {
+            PartitionKey::Year => format!("{:04}", time.year),
+            PartitionKey::Month => format!("{:02}", time.month),
+            PartitionKey::Day => format!("{:02}", time.day),
+            PartitionKey::Date => format!("{:04}-{:02}-{:02}", time.year, time.month, time.day),
+            PartitionKey::Hour => format!("{:02}", time.hour),
+            PartitionKey::Minute => format!("{:02}", time.minute),
+        }
+    }
+}
+
+/// A field of a topic in the manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestField {
+    pub name: String,
+    /// The Arrow data type, as displayed by Arrow, for example `List(Float32)`.
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+/// The description of a topic in the manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestTopic {
+    /// The directory of the topic, relative to the root of the dataset.
+    pub directory: String,
+    /// The ROS schema name, for example `sensor_msgs/msg/LaserScan`.
+    pub schema_name: String,
+    /// The ROS message definition in the `ros2msg` format, if the type is known to r2a.
+    pub message_definition: Option<String>,
+    pub partition_time: PartitionTime,
+    pub partition_keys: Vec<PartitionKey>,
+    pub fields: Vec<ManifestField>,
+}
+
+/// The `_manifest.json` file of a dataset, with the topics written by all runs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The version of r2a that wrote the manifest last.
+    pub r2a_version: String,
+    pub topics: BTreeMap<String, ManifestTopic>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the dataset at `root`, or returns an empty manifest if there is
+    /// none.
+    pub fn read(root: impl AsRef<Path>) -> Result<Self> {
+        let path = root.as_ref().join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let file =
+            File::open(&path).with_context(|| format!("Unable to open {}", path.display()))?;
+        serde_json::from_reader(file).with_context(|| format!("Unable to parse {}", path.display()))
+    }
+
+    fn write(&self, root: &Path) -> Result<()> {
+        let path = root.join(MANIFEST_FILE_NAME);
+        let tmp_path = root.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&tmp_path, json)
+            .with_context(|| format!("Unable to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Unable to rename {}", tmp_path.display()))
+    }
+}
+
+/// The file a partition is being written to.
+struct PartitionFile {
+    writer: ArrowWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    rows: usize,
+    /// When the file was last written to, to close the least recently used file first.
+    last_write: u64,
+}
+
+impl PartitionFile {
+    fn close(self) -> Result<PathBuf> {
+        self.writer
+            .close()
+            .with_context(|| format!("Unable to close {}", self.tmp_path.display()))?;
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Unable to rename {}", self.tmp_path.display()))?;
+        Ok(self.path)
+    }
+}
+
+struct TopicState {
+    schema_name: String,
+    schema: SchemaRef,
+    properties: WriterProperties,
+}
+
+/// Writes the batches of every topic to a Hive-partitioned Parquet dataset.
+pub struct DatasetWriter {
+    root: PathBuf,
+    partition_time: PartitionTime,
+    partition_keys: Vec<PartitionKey>,
+    max_file_size: Option<usize>,
+    max_file_rows: Option<usize>,
+    max_open_files: usize,
+    row_group_size: usize,
+    compression: Compression,
+    manifest: Manifest,
+    topics: BTreeMap<String, TopicState>,
+    /// The open files by partition directory.
+    files: HashMap<PathBuf, PartitionFile>,
+    /// The next file index to try, by partition directory.
+    next_index: HashMap<PathBuf, usize>,
+    writes: u64,
+    /// The files that were closed.
+    closed: Vec<PathBuf>,
+}
+
+impl DatasetWriter {
+    /// Creates a writer for the dataset at `root`, which is created if needed. The topics of
+    /// the manifest of an existing dataset are kept, and new files are added next to the
+    /// existing ones.
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        fs::create_dir_all(root).with_context(|| format!("Unable to create {}", root.display()))?;
+        Ok(DatasetWriter {
+            root: root.to_path_buf(),
+            partition_time: PartitionTime::HeaderStamp,
+            partition_keys: vec![PartitionKey::Date, PartitionKey::Hour],
+            max_file_size: None,
+            max_file_rows: None,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            compression: Compression::SNAPPY,
+            manifest: Manifest::read(root)?,
+            topics: BTreeMap::new(),
+            files: HashMap::new(),
+            next_index: HashMap::new(),
+            writes: 0,
+            closed: vec![],
+        })
+    }
+
+    /// Sets the time the rows are partitioned by, the header stamp by default.
+    pub fn with_partition_time(mut self, partition_time: PartitionTime) -> Self {
+        self.partition_time = partition_time;
+        self
+    }
+
+    /// Sets the partition directories below the topic, `date` and `hour` by default. Without
+    /// keys, all the files of a topic are in its directory.
+    pub fn with_partition_keys(mut self, partition_keys: Vec<PartitionKey>) -> Self {
+        self.partition_keys = partition_keys;
+        self
+    }
+
+    /// Starts a new file of a partition once a file reaches `bytes`, counting the rows it
+    /// buffers for the current row group.
+    pub fn with_max_file_size(mut self, bytes: usize) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Starts a new file of a partition after `rows` rows. Batches are split if needed.
+    pub fn with_max_file_rows(mut self, rows: usize) -> Self {
+        self.max_file_rows = Some(rows.max(1));
+        self
+    }
+
+    /// Sets the maximum number of open files. The least recently written file is closed to
+    /// open another one, and the partition continues in a new file if it gets more rows.
+    pub fn with_max_open_files(mut self, files: usize) -> Self {
+        self.max_open_files = files.max(1);
+        self
+    }
+
+    /// Sets the maximum number of rows of a row group.
+    pub fn with_row_group_size(mut self, rows: usize) -> Self {
+        self.row_group_size = rows.max(1);
+        self
+    }
+
+    /// Sets the compression of the columns, snappy by default.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Writes a batch of messages of `schema_name` on `topic` to the partitions of its rows.
+    /// All batches of a topic must have the same schema.
+    pub fn write_batch(
+        &mut self,
+        topic: &str,
+        schema_name: &str,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        self.add_topic(topic, schema_name, batch.schema())?;
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+
+        let times = self.row_times(batch)?;
+        let topic_directory = Path::new(&topic_directory_name(topic)).to_path_buf();
+        let partitions: Vec<PathBuf> = times
+            .iter()
+            .map(|&time| self.partition_directory(&topic_directory, time))
+            .collect();
+        // Rows are mostly in time order, so the batch is written in runs of rows that go to the
+        // same partition.
+        let mut start = 0;
+        while start < batch.num_rows() {
+            let mut end = start + 1;
+            while end < batch.num_rows() && partitions[end] == partitions[start] {
+                end += 1;
+            }
+            self.write_partition(topic, &partitions[start], &batch.slice(start, end - start))?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Writes the rows accumulated by the row builder of a topic, created with `fields`, and
+    /// clears the row builder. Does nothing if the row builder is empty.
+    pub fn write_rows<'a, T: ArrowSupport<'a>>(
+        &mut self,
+        topic: &str,
+        fields: &[Field],
+        row_builder: &mut impl RowBuilder<'a, T>,
+    ) -> Result<()> {
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(fields.to_vec())),
+            row_builder.to_arc_arrays(),
+        )?;
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        self.write_batch(topic, T::schema_name(), &batch)
+    }
+
+    /// Registers the topic on its first batch and updates the manifest.
+    fn add_topic(&mut self, topic: &str, schema_name: &str, schema: SchemaRef) -> Result<()> {
+        if let Some(state) = self.topics.get(topic) {
+            if state.schema_name != schema_name || state.schema != schema {
+                bail!(
+                    "Topic {} was written with a different schema than this batch of {}",
+                    topic,
+                    schema_name
+                );
+            }
+            return Ok(());
+        }
+
+        let manifest_topic = ManifestTopic {
+            directory: topic_directory_name(topic),
+            schema_name: schema_name.to_string(),
+            message_definition: crate::type_descriptor(schema_name)
+                .map(|descriptor| descriptor.message_definition()),
+            partition_time: self.partition_time,
+            partition_keys: self.partition_keys.clone(),
+            fields: schema
+                .fields()
+                .iter()
+                .map(|field| ManifestField {
+                    name: field.name().clone(),
+                    data_type: field.data_type().to_string(),
+                    nullable: field.is_nullable(),
+                })
+                .collect(),
+        };
+        if let Some(existing) = self.manifest.topics.get(topic) {
+            if existing.schema_name != manifest_topic.schema_name
+                || existing.fields != manifest_topic.fields
+            {
+                log::warn!(
+                    "The schema of {} changed from {} to {}, updating the manifest",
+                    topic,
+                    existing.schema_name,
+                    schema_name
+                );
+            }
+        }
+        self.manifest
+            .topics
+            .insert(topic.to_string(), manifest_topic);
+        self.manifest.r2a_version = env!("CARGO_PKG_VERSION").to_string();
+        self.manifest.write(&self.root)?;
+
+        let metadata = crate::file_metadata(topic, schema_name)
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value))
+            .collect();
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_size)
+            .set_compression(self.compression)
+            .set_key_value_metadata(Some(metadata))
+            .build();
+        self.topics.insert(
+            topic.to_string(),
+            TopicState {
+                schema_name: schema_name.to_string(),
+                schema,
+                properties,
+            },
+        );
+        Ok(())
+    }
+
+    /// The partition time of each row, in nanoseconds since the UNIX epoch.
+    fn row_times(&self, batch: &RecordBatch) -> Result<Vec<i64>> {
+        match self.partition_time {
+            PartitionTime::HeaderStamp => {
+                let stamps = header_stamps(batch, true)
+                    .or_else(|| header_stamps(batch, false))
+                    .ok_or_else(|| {
+                        anyhow!("The batch has no header stamp to partition the rows by")
+                    })?;
+                stamps
+                    .into_iter()
+                    .enumerate()
+                    .map(|(row, stamp)| {
+                        stamp.map(|stamp| stamp as i64).ok_or_else(|| {
+                            anyhow!("Row {} has a null or negative header stamp", row)
+                        })
+                    })
+                    .collect()
+            }
+            PartitionTime::RecvTime => timestamp_column(batch, RECV_TIME_FIELD)?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The batch has no {} column to partition by",
+                        RECV_TIME_FIELD
+                    )
+                })?
+                .into_iter()
+                .enumerate()
+                .map(|(row, time)| {
+                    time.map(|time| time as i64)
+                        .ok_or_else(|| anyhow!("Row {} has no {}", row, RECV_TIME_FIELD))
+                })
+                .collect(),
+        }
+    }
+
+    fn partition_directory(&self, topic_directory: &Path, time: i64) -> PathBuf {
+        let time = UtcTime::from_nanos(time);
+        let mut directory = topic_directory.to_path_buf();
+        for key in &self.partition_keys {
+            directory.push(format!("{}={}", key.name(), key.value(&time)));
+        }
+        directory
+    }
+
+    /// Writes rows that all belong to `partition`, relative to the root.
+    fn write_partition(
+        &mut self,
+        topic: &str,
+        partition: &Path,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            if !self.files.contains_key(partition) {
+                self.open_file(topic, partition)?;
+            }
+            self.writes += 1;
+            let file = self.files.get_mut(partition).unwrap();
+            file.last_write = self.writes;
+
+            let rows = match self.max_file_rows {
+                Some(max_rows) => (max_rows - file.rows).min(batch.num_rows() - offset),
+                None => batch.num_rows() - offset,
+            };
+            file.writer
+                .write(&batch.slice(offset, rows))
+                .with_context(|| format!("Unable to write to {}", file.tmp_path.display()))?;
+            file.rows += rows;
+            offset += rows;
+
+            let full = self
+                .max_file_rows
+                .is_some_and(|max_rows| file.rows >= max_rows)
+                || self.max_file_size.is_some_and(|max_size| {
+                    file.writer.bytes_written() + file.writer.in_progress_size() >= max_size
+                });
+            if full {
+                let file = self.files.remove(partition).unwrap();
+                self.closed.push(file.close()?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the next file of a partition, closing the least recently written file if too
+    /// many files are open.
+    fn open_file(&mut self, topic: &str, partition: &Path) -> Result<()> {
+        if self.files.len() >= self.max_open_files {
+            let oldest = self
+                .files
+                .iter()
+                .min_by_key(|(_, file)| file.last_write)
+                .map(|(partition, _)| partition.clone())
+                .unwrap();
+            let file = self.files.remove(&oldest).unwrap();
+            self.closed.push(file.close()?);
+        }
+
+        let directory = self.root.join(partition);
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("Unable to create {}", directory.display()))?;
+        let next_index = self.next_index.entry(partition.to_path_buf()).or_insert(0);
+        // Continue after the files of earlier runs and of closed files, complete or not.
+        let (path, tmp_path) = loop {
+            let file_name = format!("part-{}.parquet", next_index);
+            let path = directory.join(&file_name);
+            let tmp_path = directory.join(format!("{}.tmp", file_name));
+            *next_index += 1;
+            if !path.exists() && !tmp_path.exists() {
+                break (path, tmp_path);
+            }
+        };
+
+        let state = &self.topics[topic];
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("Unable to create {}", tmp_path.display()))?;
+        let writer =
+            ArrowWriter::try_new(file, state.schema.clone(), Some(state.properties.clone()))?;
+        self.files.insert(
+            partition.to_path_buf(),
+            PartitionFile {
+                writer,
+                path,
+                tmp_path,
+                rows: 0,
+                last_write: self.writes,
+            },
+        );
+        Ok(())
+    }
+
+    /// The number of open files.
+    pub fn open_files(&self) -> usize {
+        self.files.len()
+    }
+
+    fn close_files(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        let mut files: Vec<PartitionFile> = self.files.drain().map(|(_, file)| file).collect();
+        files.sort_by_key(|file| file.last_write);
+        for file in files {
+            match file.close() {
+                Ok(path) => self.closed.push(path),
+                Err(e) => result = Err(e),
+            }
+        }
+        result
+    }
+
+    /// Closes the open files and returns the files written by the writer, in the order they
+    /// were closed.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        self.close_files()?;
+        Ok(std::mem::take(&mut self.closed))
+    }
+}
+
+impl Drop for DatasetWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.close_files() {
+            log::error!("Unable to close the dataset files: {}", e);
+        }
+    }
+}
+
+/// The directory of a topic, `topic=<name>` with the name from [`topic_file_name`].
+fn topic_directory_name(topic: &str) -> String {
+    format!("topic={}", topic_file_name(topic))
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::builtin_interfaces::msg::Time;
+    use crate::msgs::geometry_msgs::msg::PointStamped;
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::{row_context_fields, RowContext, SCHEMA_NAME_METADATA_KEY};
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("r2a_dataset_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn point(sec: i32) -> PointStamped {
+        PointStamped {
+            header: Header {
+                stamp: Time { sec, nanosec: 0 },
+                frame_id: "base_link".to_string(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn rows(path: &Path) -> i64 {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        reader.metadata().file_metadata().num_rows()
+    }
+
+    #[test]
+    fn test_partition_by_header_stamp() {
+        let dir = temp_dir("stamp");
+        let fields = PointStamped::flat_arrow_fields(false);
+        let mut row_builder = PointStamped::new_flat_row_builder(fields.iter().collect());
+        let mut writer = DatasetWriter::new(&dir)
+            .unwrap()
+            .with_max_file_rows(3)
+            .with_max_open_files(1);
+        // 2024-10-13 13:59:58 to 14:00:01, then 15:00:00 to 15:00:03.
+        let start = 1_728_827_998;
+        for sec in [0, 1, 2, 3, 3602, 3603, 3604, 3605].map(|offset| start + offset) {
+            row_builder.add_row(&point(sec)).unwrap();
+        }
+        writer
+            .write_rows("/robot/point", &fields, &mut row_builder)
+            .unwrap();
+        // Opening the file of an hour closed the file of the previous hour.
+        assert_eq!(writer.open_files(), 1);
+        let files = writer.finish().unwrap();
+
+        let topic_dir = dir.join("topic=robot.point/date=2024-10-13");
+        assert_eq!(
+            files,
+            [
+                topic_dir.join("hour=13/part-0.parquet"),
+                topic_dir.join("hour=14/part-0.parquet"),
+                topic_dir.join("hour=15/part-0.parquet"),
+                topic_dir.join("hour=15/part-1.parquet"),
+            ]
+        );
+        assert_eq!(
+            files.iter().map(|file| rows(file)).collect::<Vec<_>>(),
+            [2, 2, 3, 1]
+        );
+        let reader = SerializedFileReader::new(File::open(&files[0]).unwrap()).unwrap();
+        let key_value = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .unwrap();
+        assert!(key_value.iter().any(|kv| kv.key == SCHEMA_NAME_METADATA_KEY
+            && kv.value.as_deref() == Some("geometry_msgs/msg/PointStamped")));
+
+        let manifest = Manifest::read(&dir).unwrap();
+        let topic = &manifest.topics["/robot/point"];
+        assert_eq!(topic.directory, "topic=robot.point");
+        assert_eq!(topic.schema_name, "geometry_msgs/msg/PointStamped");
+        assert_eq!(topic.partition_time, PartitionTime::HeaderStamp);
+        assert_eq!(
+            topic.partition_keys,
+            [PartitionKey::Date, PartitionKey::Hour]
+        );
+        assert!(topic
+            .message_definition
+            .as_ref()
+            .unwrap()
+            .contains("MSG: std_msgs/Header"));
+        assert_eq!(topic.fields.len(), fields.len());
+
+        // A new writer adds files next to the existing ones and keeps the manifest.
+        let mut writer = DatasetWriter::new(&dir)
+            .unwrap()
+            .with_partition_keys(vec![]);
+        let nested_fields = PointStamped::arrow_fields(false);
+        let mut nested_builder = PointStamped::new_row_builder(nested_fields.iter().collect());
+        nested_builder.add_row(&point(start)).unwrap();
+        writer
+            .write_rows("/other", &nested_fields, &mut nested_builder)
+            .unwrap();
+        assert_eq!(
+            writer.finish().unwrap(),
+            [dir.join("topic=other/part-0.parquet")]
+        );
+        let manifest = Manifest::read(&dir).unwrap();
+        assert_eq!(
+            manifest.topics.keys().collect::<Vec<_>>(),
+            ["/other", "/robot/point"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_partition_by_recv_time() {
+        let dir = temp_dir("recv");
+        let mut fields = PointStamped::flat_arrow_fields(false);
+        fields.extend(row_context_fields(false));
+        let mut row_builder = PointStamped::new_flat_row_builder(fields.iter().collect());
+        let mut writer = DatasetWriter::new(&dir)
+            .unwrap()
+            .with_partition_time(PartitionTime::RecvTime)
+            .with_partition_keys(vec![PartitionKey::Year, PartitionKey::Minute]);
+        for (seq, recv_sec) in [0, 59, 60].into_iter().enumerate() {
+            let context = RowContext::new("/header", seq as u64)
+                .with_recv_time(UNIX_EPOCH + Duration::from_secs(recv_sec));
+            // The header stamps are ignored.
+            row_builder
+                .add_row_with_context(&point(1_000_000), &context)
+                .unwrap();
+        }
+        writer
+            .write_rows("/header", &fields, &mut row_builder)
+            .unwrap();
+        let files = writer.finish().unwrap();
+        assert_eq!(
+            files,
+            [
+                dir.join("topic=header/year=1970/minute=00/part-0.parquet"),
+                dir.join("topic=header/year=1970/minute=01/part-0.parquet"),
+            ]
+        );
+        assert_eq!(
+            files.iter().map(|file| rows(file)).collect::<Vec<_>>(),
+            [2, 1]
+        );
+
+        // Without the context columns, there is no receive time.
+        let fields = PointStamped::flat_arrow_fields(false);
+        let mut row_builder = PointStamped::new_flat_row_builder(fields.iter().collect());
+        row_builder.add_row(&point(0)).unwrap();
+        let mut writer = DatasetWriter::new(&dir)
+            .unwrap()
+            .with_partition_time(PartitionTime::RecvTime);
+        assert!(writer
+            .write_rows("/no_context", &fields, &mut row_builder)
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1032 @@
+//! Writes topics to local Delta Lake tables. Requires the `delta` feature.
+//!
+//! A Delta table is a directory of Parquet files and a `_delta_log` directory of JSON commits
+//! that add files to the table, see
+//! <https://github.com/delta-io/delta/blob/master/PROTOCOL.md>. [`DeltaTable`] implements the
+//! parts of the protocol needed to append to a table on a local disk or NFS, without a service:
+//! a commit is written to a temporary file and hard linked to the next version, which fails if
+//! another writer committed that version first. The table is then read again and the commit
+//! retried.
+//!
+//! The table schema is derived from the Arrow schema of the batches, for example
+//! `arrow_schema()` or `flat_arrow_schema()` of an [`ArrowSupport`] type. Delta has no unsigned
+//! integers and stores timestamps in microseconds, so the columns are cast to the next larger
+//! signed integer and to microsecond timestamps before they are written. When a message
+//! definition changes, for example between ROS distros, the added fields are added to the
+//! table as nullable columns, and the removed ones become nullable. Other type changes are
+//! rejected. The table properties hold the [`crate::file_metadata`] of the topic, with the ROS
+//! type name under [`crate::SCHEMA_NAME_METADATA_KEY`].
+//!
+//! Checkpoints, partitioned tables and tables that need newer protocol versions are not
+//! supported.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::delta::DeltaSink;
+//! use r2a::rosbag2::Rosbag2Reader;
+//!
+//! let reader = Rosbag2Reader::open("recordings/run_42").unwrap();
+//! // One table per topic, for example lake/scan for /scan.
+//! let mut sink = DeltaSink::new("lake").unwrap().with_commit_rows(50_000);
+//! for topic in reader.topics() {
+//!     for batch in reader.topic_batches(&topic.name, true).unwrap() {
+//!         sink.write_batch(&topic.name, &topic.type_name, &batch.unwrap())
+//!             .unwrap();
+//!     }
+//! }
+//! sink.finish().unwrap();
+//! ```
+
+use crate::parquet::{topic_file_name, Compression};
+use crate::{ArrowSupport, RowBuilder, SCHEMA_NAME_METADATA_KEY};
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::file::metadata::KeyValue;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{bail, Context, Result};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_cast::{cast_with_options, CastOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of rows after which [`DeltaSink`] commits the file of a topic, unless set with
+/// [`DeltaSink::with_commit_rows`].
+pub const DEFAULT_COMMIT_ROWS: usize = 100_000;
+
+/// How many times a commit is retried when other writers commit the same versions.
+const MAX_COMMIT_ATTEMPTS: usize = 100;
+
+const DELTA_LOG_DIRECTORY: &str = "_delta_log";
+
+/// The protocol versions written and supported: no table features.
+const MIN_READER_VERSION: u32 = 1;
+const MIN_WRITER_VERSION: u32 = 2;
+
+/// Makes the names of the data files of this process unique.
+static FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A field of a Delta schema.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct DeltaField {
+    name: String,
+    #[serde(rename = "type")]
+    data_type: DeltaType,
+    nullable: bool,
+    #[serde(default)]
+    metadata: BTreeMap<String, Value>,
+}
+
+/// A Delta data type. Primitive types are names like `long`, complex types are objects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum DeltaType {
+    Primitive(String),
+    Struct(DeltaStruct),
+    Array(DeltaArray),
+    /// Maps, which r2a does not write, and other types, kept as they are.
+    Other(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct DeltaStruct {
+    #[serde(rename = "type")]
+    type_name: String,
+    fields: Vec<DeltaField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeltaArray {
+    #[serde(rename = "type")]
+    type_name: String,
+    element_type: Box<DeltaType>,
+    contains_null: bool,
+}
+
+impl DeltaType {
+    fn new_struct(fields: Vec<DeltaField>) -> Self {
+        DeltaType::Struct(DeltaStruct {
+            type_name: "struct".to_string(),
+            fields,
+        })
+    }
+}
+
+impl std::fmt::Display for DeltaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_string(self) {
+            Ok(json) => f.write_str(&json),
+            Err(_) => f.write_str("?"),
+        }
+    }
+}
+
+/// The Arrow type a column is cast to before it is written, and its Delta type.
+fn delta_type(data_type: &DataType) -> Result<(DataType, DeltaType)> {
+    let primitive = |target: DataType, name: &str| (target, DeltaType::Primitive(name.into()));
+    Ok(match data_type {
+        DataType::Boolean => primitive(DataType::Boolean, "boolean"),
+        DataType::Int8 => primitive(DataType::Int8, "byte"),
+        DataType::Int16 => primitive(DataType::Int16, "short"),
+        DataType::Int32 => primitive(DataType::Int32, "integer"),
+        DataType::Int64 => primitive(DataType::Int64, "long"),
+        DataType::UInt8 => primitive(DataType::Int16, "short"),
+        DataType::UInt16 => primitive(DataType::Int32, "integer"),
+        // Casting fails for the u64 values above i64::MAX.
+        DataType::UInt32 | DataType::UInt64 => primitive(DataType::Int64, "long"),
+        DataType::Float32 => primitive(DataType::Float32, "float"),
+        DataType::Float64 => primitive(DataType::Float64, "double"),
+        DataType::Utf8 | DataType::LargeUtf8 => primitive(data_type.clone(), "string"),
+        DataType::Binary | DataType::LargeBinary => primitive(data_type.clone(), "binary"),
+        DataType::Date32 => primitive(DataType::Date32, "date"),
+        DataType::Timestamp(_, _) => primitive(
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            "timestamp",
+        ),
+        DataType::List(field) | DataType::LargeList(field) | DataType::FixedSizeList(field, _) => {
+            let (element, element_type) = delta_type(field.data_type())?;
+            let element = Arc::new(Field::new(field.name(), element, field.is_nullable()));
+            let target = match data_type {
+                DataType::LargeList(_) => DataType::LargeList(element),
+                _ => DataType::List(element),
+            };
+            let array = DeltaType::Array(DeltaArray {
+                type_name: "array".to_string(),
+                element_type: Box::new(element_type),
+                contains_null: field.is_nullable(),
+            });
+            (target, array)
+        }
+        DataType::Struct(fields) => {
+            let (fields, delta_fields) = delta_fields(fields.iter().map(AsRef::as_ref))?;
+            (
+                DataType::Struct(fields.into()),
+                DeltaType::new_struct(delta_fields),
+            )
+        }
+        other => bail!("Delta tables do not support the Arrow type {}", other),
+    })
+}
+
+/// The Arrow fields columns are cast to, and their Delta fields.
+fn delta_fields<'f>(
+    fields: impl Iterator<Item = &'f Field>,
+) -> Result<(Vec<Field>, Vec<DeltaField>)> {
+    let mut target_fields = vec![];
+    let mut delta_fields = vec![];
+    for field in fields {
+        let (target, data_type) = delta_type(field.data_type())
+            .with_context(|| format!("Unable to map the field {}", field.name()))?;
+        target_fields.push(field.clone().with_data_type(target));
+        delta_fields.push(DeltaField {
+            name: field.name().clone(),
+            data_type,
+            nullable: field.is_nullable(),
+            metadata: BTreeMap::new(),
+        });
+    }
+    Ok((target_fields, delta_fields))
+}
+
+/// Merges the fields of a file into the fields of the table. Fields missing on either side
+/// become nullable, and the types of the fields both have must match, except for the nested
+/// fields of structs, which are merged the same way.
+fn merge_fields(table: &[DeltaField], file: &[DeltaField], path: &str) -> Result<Vec<DeltaField>> {
+    let mut merged = vec![];
+    for field in table {
+        let field_path = format!("{}{}", path, field.name);
+        match file.iter().find(|file_field| file_field.name == field.name) {
+            Some(file_field) => merged.push(DeltaField {
+                data_type: merge_types(&field.data_type, &file_field.data_type, &field_path)?,
+                nullable: field.nullable || file_field.nullable,
+                ..field.clone()
+            }),
+            None => merged.push(DeltaField {
+                nullable: true,
+                ..field.clone()
+            }),
+        }
+    }
+    for file_field in file {
+        if !table.iter().any(|field| field.name == file_field.name) {
+            merged.push(DeltaField {
+                nullable: true,
+                ..file_field.clone()
+            });
+        }
+    }
+    Ok(merged)
+}
+
+fn merge_types(table: &DeltaType, file: &DeltaType, path: &str) -> Result<DeltaType> {
+    match (table, file) {
+        (DeltaType::Struct(table), DeltaType::Struct(file)) => Ok(DeltaType::new_struct(
+            merge_fields(&table.fields, &file.fields, &format!("{}.", path))?,
+        )),
+        (DeltaType::Array(table), DeltaType::Array(file)) => Ok(DeltaType::Array(DeltaArray {
+            type_name: table.type_name.clone(),
+            element_type: Box::new(merge_types(
+                &table.element_type,
+                &file.element_type,
+                &format!("{}[]", path),
+            )?),
+            contains_null: table.contains_null || file.contains_null,
+        })),
+        (table, file) if table == file => Ok(table.clone()),
+        (table, file) => bail!("The type of {} changed from {} to {}", path, table, file),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Format {
+    provider: String,
+    #[serde(default)]
+    options: BTreeMap<String, String>,
+}
+
+/// The `metaData` action.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    format: Format,
+    schema_string: String,
+    partition_columns: Vec<String>,
+    #[serde(default)]
+    configuration: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_time: Option<i64>,
+}
+
+impl Metadata {
+    fn fields(&self) -> Result<Vec<DeltaField>> {
+        let schema: DeltaStruct =
+            serde_json::from_str(&self.schema_string).context("Invalid table schema")?;
+        Ok(schema.fields)
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// A unique identifier in the UUID format, from the time, the process and a counter.
+fn unique_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let counter = FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let process = std::process::id() as u64;
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        nanos >> 32,
+        (nanos >> 16) & 0xffff,
+        nanos & 0xffff,
+        counter & 0xffff,
+        process << 16 | (counter >> 16) & 0xffff
+    )
+}
+
+/// A data file that is being written and is not part of the table until it is committed.
+pub struct DeltaFile {
+    writer: ArrowWriter<File>,
+    /// The path relative to the table.
+    path: String,
+    schema: SchemaRef,
+    fields: Vec<DeltaField>,
+    rows: usize,
+}
+
+impl DeltaFile {
+    /// Casts a batch to the types of the table and writes it. The batch must have the fields of
+    /// the batch the file was started with.
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let batch = cast_batch(batch, &self.schema)?;
+        self.writer.write(&batch)?;
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    /// The number of rows written to the file.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Whether a batch with the `schema` can be written to this file.
+    fn accepts(&self, schema: &Schema) -> bool {
+        self.schema.fields().len() == schema.fields().len()
+            && self
+                .schema
+                .fields()
+                .iter()
+                .zip(schema.fields())
+                .all(|(target, field)| target.name() == field.name())
+            && delta_fields(schema.fields().iter().map(AsRef::as_ref))
+                .is_ok_and(|(_, fields)| fields == self.fields)
+    }
+}
+
+/// Casts the columns of a batch to the types of `schema`.
+fn cast_batch(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch> {
+    let names_match = batch.num_columns() == schema.fields().len()
+        && batch
+            .schema()
+            .fields()
+            .iter()
+            .zip(schema.fields())
+            .all(|(field, target)| field.name() == target.name());
+    if !names_match {
+        bail!("The batch has other columns than the file it is written to");
+    }
+    let options = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(schema.fields())
+        .map(|(column, field)| {
+            if column.data_type() == field.data_type() {
+                Ok(column.clone())
+            } else {
+                cast_with_options(column, field.data_type(), &options)
+                    .with_context(|| format!("Unable to cast the column {}", field.name()))
+            }
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// A Delta table on the local file system.
+pub struct DeltaTable {
+    path: PathBuf,
+    version: i64,
+    metadata: Metadata,
+    files: BTreeSet<String>,
+    /// The properties of the next commit, with the topic and schema name of the writer.
+    configuration: BTreeMap<String, String>,
+    compression: Compression,
+}
+
+impl DeltaTable {
+    /// Opens an existing table.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut table = DeltaTable {
+            path: path.to_path_buf(),
+            version: -1,
+            metadata: Metadata {
+                id: String::new(),
+                name: None,
+                description: None,
+                format: Format {
+                    provider: "parquet".to_string(),
+                    options: BTreeMap::new(),
+                },
+                schema_string: String::new(),
+                partition_columns: vec![],
+                configuration: BTreeMap::new(),
+                created_time: None,
+            },
+            files: BTreeSet::new(),
+            configuration: BTreeMap::new(),
+            compression: Compression::SNAPPY,
+        };
+        table.update()?;
+        if table.version < 0 {
+            bail!("{} is not a Delta table", path.display());
+        }
+        table.configuration = table.metadata.configuration.clone();
+        Ok(table)
+    }
+
+    /// Opens the table at `path` for the messages of `schema_name` on `topic`, or creates it
+    /// with the fields of `schema`. The fields of an existing table are only changed by the
+    /// commits of files with other fields.
+    pub fn open_or_create(
+        path: impl AsRef<Path>,
+        topic: &str,
+        schema_name: &str,
+        schema: &Schema,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let log_directory = path.join(DELTA_LOG_DIRECTORY);
+        fs::create_dir_all(&log_directory)
+            .with_context(|| format!("Unable to create {}", log_directory.display()))?;
+        let configuration: BTreeMap<String, String> = crate::file_metadata(topic, schema_name)
+            .into_iter()
+            .collect();
+
+        let mut table = match DeltaTable::open(path) {
+            Ok(table) => table,
+            Err(_) if !log_directory.join(format!("{:020}.json", 0)).exists() => {
+                let (_, fields) = delta_fields(schema.fields().iter().map(AsRef::as_ref))?;
+                let metadata = Metadata {
+                    id: unique_id(),
+                    name: Some(topic.to_string()),
+                    description: None,
+                    format: Format {
+                        provider: "parquet".to_string(),
+                        options: BTreeMap::new(),
+                    },
+                    schema_string: serde_json::to_string(&DeltaType::new_struct(fields))?,
+                    partition_columns: vec![],
+                    configuration: configuration.clone(),
+                    created_time: Some(now_millis()),
+                };
+                let actions = vec![
+                    json!({"protocol": {
+                        "minReaderVersion": MIN_READER_VERSION,
+                        "minWriterVersion": MIN_WRITER_VERSION,
+                    }}),
+                    json!({ "metaData": metadata }),
+                    commit_info("CREATE TABLE"),
+                ];
+                // Another writer may have created the table in the meantime, then its version 0
+                // is kept.
+                write_commit(&log_directory, 0, &actions)?;
+                DeltaTable::open(path)?
+            }
+            Err(e) => return Err(e),
+        };
+        table.configuration.extend(configuration);
+        Ok(table)
+    }
+
+    /// Sets the compression of the data files, snappy by default.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The directory of the table.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The version of the last commit read or written.
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    /// The table properties.
+    pub fn properties(&self) -> &BTreeMap<String, String> {
+        &self.metadata.configuration
+    }
+
+    /// The ROS type name of the messages of the table, from its properties.
+    pub fn schema_name(&self) -> Option<&str> {
+        self.properties()
+            .get(SCHEMA_NAME_METADATA_KEY)
+            .map(String::as_str)
+    }
+
+    /// The schema of the table in the JSON format of the Delta protocol.
+    pub fn schema_string(&self) -> &str {
+        &self.metadata.schema_string
+    }
+
+    /// The names of the table columns.
+    pub fn column_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .metadata
+            .fields()?
+            .into_iter()
+            .map(|field| field.name)
+            .collect())
+    }
+
+    /// The data files of the table, relative to its directory.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(String::as_str)
+    }
+
+    /// Reads the commits after the current version.
+    pub fn update(&mut self) -> Result<()> {
+        let log_directory = self.path.join(DELTA_LOG_DIRECTORY);
+        if self.version < 0
+            && !log_directory.join(format!("{:020}.json", 0)).exists()
+            && log_directory.join("_last_checkpoint").exists()
+        {
+            bail!(
+                "{} has checkpoints without the commits before them, which are not supported",
+                self.path.display()
+            );
+        }
+        loop {
+            let path = log_directory.join(format!("{:020}.json", self.version + 1));
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Unable to open {}", path.display()))
+                }
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let action: Value = serde_json::from_str(&line)
+                    .with_context(|| format!("Invalid action in {}", path.display()))?;
+                self.apply(&action)
+                    .with_context(|| format!("Unable to apply {}", path.display()))?;
+            }
+            self.version += 1;
+        }
+    }
+
+    fn apply(&mut self, action: &Value) -> Result<()> {
+        if let Some(protocol) = action.get("protocol") {
+            let version = |key: &str| protocol.get(key).and_then(Value::as_u64).unwrap_or(0);
+            if version("minReaderVersion") > MIN_READER_VERSION as u64
+                || version("minWriterVersion") > MIN_WRITER_VERSION as u64
+            {
+                bail!("The table needs a newer Delta protocol: {}", protocol);
+            }
+        }
+        if let Some(metadata) = action.get("metaData") {
+            let metadata: Metadata = serde_json::from_value(metadata.clone())?;
+            if !metadata.partition_columns.is_empty() {
+                bail!("Partitioned tables are not supported");
+            }
+            self.metadata = metadata;
+        }
+        if let Some(path) = action.pointer("/add/path").and_then(Value::as_str) {
+            self.files.insert(path.to_string());
+        }
+        if let Some(path) = action.pointer("/remove/path").and_then(Value::as_str) {
+            self.files.remove(path);
+        }
+        Ok(())
+    }
+
+    /// Starts a data file for batches with the fields of `schema`. The file is added to the
+    /// table by [`DeltaTable::commit`].
+    pub fn start_file(&self, schema: &Schema) -> Result<DeltaFile> {
+        let (target_fields, fields) = delta_fields(schema.fields().iter().map(AsRef::as_ref))?;
+        let target_schema = Arc::new(Schema::new_with_metadata(
+            target_fields,
+            schema.metadata().clone(),
+        ));
+        let path = format!("part-{}.parquet", unique_id());
+        let metadata = self
+            .configuration
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        let properties = WriterProperties::builder()
+            .set_compression(self.compression)
+            .set_key_value_metadata(Some(metadata))
+            .build();
+        let full_path = self.path.join(&path);
+        let file = File::create(&full_path)
+            .with_context(|| format!("Unable to create {}", full_path.display()))?;
+        Ok(DeltaFile {
+            writer: ArrowWriter::try_new(file, target_schema.clone(), Some(properties))?,
+            path,
+            schema: target_schema,
+            fields,
+            rows: 0,
+        })
+    }
+
+    /// Closes the file and commits it to the table, with the schema changes it needs. Returns
+    /// the version of the commit.
+    pub fn commit(&mut self, file: DeltaFile) -> Result<i64> {
+        let rows = file.rows;
+        let path = file.path.clone();
+        let fields = file.fields.clone();
+        file.writer
+            .close()
+            .with_context(|| format!("Unable to close {}", path))?;
+        let size = fs::metadata(self.path.join(&path))?.len();
+        let add = json!({"add": {
+            "path": path,
+            "partitionValues": {},
+            "size": size,
+            "modificationTime": now_millis(),
+            "dataChange": true,
+            "stats": json!({ "numRecords": rows }).to_string(),
+        }});
+
+        let result = self.commit_add(&fields, add);
+        if result.is_err() {
+            // The file is not part of the table.
+            let _ = fs::remove_file(self.path.join(&path));
+        }
+        result
+    }
+
+    fn commit_add(&mut self, fields: &[DeltaField], add: Value) -> Result<i64> {
+        let log_directory = self.path.join(DELTA_LOG_DIRECTORY);
+        for _ in 0..MAX_COMMIT_ATTEMPTS {
+            let mut actions = vec![];
+            let merged = merge_fields(&self.metadata.fields()?, fields, "")?;
+            let mut metadata = self.metadata.clone();
+            metadata.schema_string = serde_json::to_string(&DeltaType::new_struct(merged))?;
+            metadata.configuration = self.configuration.clone();
+            if metadata != self.metadata {
+                actions.push(json!({ "metaData": metadata }));
+            }
+            actions.push(add.clone());
+            actions.push(commit_info("WRITE"));
+
+            if write_commit(&log_directory, self.version + 1, &actions)? {
+                for action in &actions {
+                    self.apply(action)?;
+                }
+                self.version += 1;
+                return Ok(self.version);
+            }
+            // Another writer committed this version, append after it.
+            self.update()?;
+        }
+        bail!(
+            "Unable to commit to {} after {} attempts",
+            self.path.display(),
+            MAX_COMMIT_ATTEMPTS
+        )
+    }
+
+    /// Writes a batch as one file and commits it. Returns the version of the commit.
+    pub fn append(&mut self, batch: &RecordBatch) -> Result<i64> {
+        let mut file = self.start_file(&batch.schema())?;
+        file.write(batch)?;
+        self.commit(file)
+    }
+}
+
+fn commit_info(operation: &str) -> Value {
+    json!({"commitInfo": {
+        "timestamp": now_millis(),
+        "operation": operation,
+        "operationParameters": { "mode": "Append" },
+        "engineInfo": format!("r2a/{}", env!("CARGO_PKG_VERSION")),
+        "isBlindAppend": true,
+    }})
+}
+
+/// Writes the commit of `version`. Returns `false` if the version was already committed.
+fn write_commit(log_directory: &Path, version: i64, actions: &[Value]) -> Result<bool> {
+    let path = log_directory.join(format!("{:020}.json", version));
+    let tmp_path = log_directory.join(format!(".{:020}.json.{}.tmp", version, unique_id()));
+    let mut file = File::create(&tmp_path)
+        .with_context(|| format!("Unable to create {}", tmp_path.display()))?;
+    for action in actions {
+        writeln!(file, "{}", action)?;
+    }
+    file.sync_all()?;
+    drop(file);
+    // Linking fails if the version exists, which makes the commit atomic.
+    let result = fs::hard_link(&tmp_path, &path);
+    let _ = fs::remove_file(&tmp_path);
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Unable to commit {}", path.display())),
+    }
+}
+
+struct TopicTable {
+    table: DeltaTable,
+    file: Option<DeltaFile>,
+}
+
+/// Writes the batches of each topic to its own Delta table.
+pub struct DeltaSink {
+    directory: PathBuf,
+    commit_rows: usize,
+    compression: Compression,
+    topics: BTreeMap<String, TopicTable>,
+}
+
+impl DeltaSink {
+    /// Creates a sink that writes to tables in `directory`, which is created if needed. The
+    /// table of a topic is `<directory>/<topic>`, see [`topic_file_name`].
+    pub fn new(directory: impl AsRef<Path>) -> Result<Self> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Unable to create {}", directory.display()))?;
+        Ok(DeltaSink {
+            directory: directory.to_path_buf(),
+            commit_rows: DEFAULT_COMMIT_ROWS,
+            compression: Compression::SNAPPY,
+            topics: BTreeMap::new(),
+        })
+    }
+
+    /// Commits the file of a topic once it has `rows` rows.
+    pub fn with_commit_rows(mut self, rows: usize) -> Self {
+        self.commit_rows = rows.max(1);
+        self
+    }
+
+    /// Sets the compression of the data files, snappy by default.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Opens or creates the table of `topic` with the Arrow schema of `T`, in the flat layout
+    /// or not, before the first rows are written.
+    pub fn add_topic<'a, T: ArrowSupport<'a>>(
+        &mut self,
+        topic: &str,
+        flat: bool,
+        include_msg_struct: bool,
+    ) -> Result<&DeltaTable> {
+        let schema = if flat {
+            T::flat_arrow_schema(include_msg_struct)
+        } else {
+            T::arrow_schema(include_msg_struct)
+        };
+        Ok(&self.topic_table(topic, T::schema_name(), &schema)?.table)
+    }
+
+    /// The table of a topic, if the sink wrote to it.
+    pub fn table(&self, topic: &str) -> Option<&DeltaTable> {
+        self.topics.get(topic).map(|topic_table| &topic_table.table)
+    }
+
+    fn topic_table(
+        &mut self,
+        topic: &str,
+        schema_name: &str,
+        schema: &Schema,
+    ) -> Result<&mut TopicTable> {
+        if !self.topics.contains_key(topic) {
+            let path = self.directory.join(topic_file_name(topic));
+            let table = DeltaTable::open_or_create(&path, topic, schema_name, schema)?
+                .with_compression(self.compression);
+            self.topics
+                .insert(topic.to_string(), TopicTable { table, file: None });
+        }
+        Ok(self.topics.get_mut(topic).unwrap())
+    }
+
+    /// Writes a batch of messages of `schema_name` to the table of `topic`. A batch with other
+    /// fields than the previous one first commits the rows written before it.
+    pub fn write_batch(
+        &mut self,
+        topic: &str,
+        schema_name: &str,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        let commit_rows = self.commit_rows;
+        let topic_table = self.topic_table(topic, schema_name, &batch.schema())?;
+        if topic_table
+            .table
+            .configuration
+            .get(SCHEMA_NAME_METADATA_KEY)
+            .is_some_and(|name| name != schema_name)
+        {
+            if let Some(file) = topic_table.file.take() {
+                topic_table.table.commit(file)?;
+            }
+            topic_table.table.configuration.insert(
+                SCHEMA_NAME_METADATA_KEY.to_string(),
+                schema_name.to_string(),
+            );
+        }
+        if topic_table
+            .file
+            .as_ref()
+            .is_some_and(|file| !file.accepts(&batch.schema()))
+        {
+            let file = topic_table.file.take().unwrap();
+            topic_table.table.commit(file)?;
+        }
+        if topic_table.file.is_none() {
+            topic_table.file = Some(topic_table.table.start_file(&batch.schema())?);
+        }
+        let file = topic_table.file.as_mut().unwrap();
+        file.write(batch)?;
+        if file.rows() >= commit_rows {
+            let file = topic_table.file.take().unwrap();
+            topic_table.table.commit(file)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rows accumulated by the row builder of a topic, created with `fields`, and
+    /// clears the row builder. Does nothing if the row builder is empty.
+    pub fn write_rows<'a, T: ArrowSupport<'a>>(
+        &mut self,
+        topic: &str,
+        fields: &[Field],
+        row_builder: &mut impl RowBuilder<'a, T>,
+    ) -> Result<()> {
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(fields.to_vec())),
+            row_builder.to_arc_arrays(),
+        )?;
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        self.write_batch(topic, T::schema_name(), &batch)
+    }
+
+    /// Commits the rows written to every table since its last commit.
+    pub fn commit(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for topic_table in self.topics.values_mut() {
+            if let Some(file) = topic_table.file.take() {
+                if let Err(e) = topic_table.table.commit(file) {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Commits the rows written since the last commits.
+    pub fn finish(mut self) -> Result<()> {
+        self.commit()
+    }
+}
+
+impl Drop for DeltaSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.commit() {
+            log::error!("Unable to commit to the Delta tables: {}", e);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::std_msgs::msg::Header;
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use arrow_array::UInt32Array;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("r2a_delta_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn headers(count: usize) -> Vec<Header> {
+        (0..count)
+            .map(|i| Header {
+                frame_id: format!("frame_{}", i),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn read_rows(table: &DeltaTable) -> usize {
+        table
+            .files()
+            .map(|path| {
+                let file = File::open(table.path().join(path)).unwrap();
+                let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+                    .unwrap()
+                    .build()
+                    .unwrap();
+                reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_sink_commits() {
+        let dir = temp_dir("sink");
+        let fields = Header::flat_arrow_fields(false);
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        let mut sink = DeltaSink::new(&dir).unwrap().with_commit_rows(4);
+        let table = sink
+            .add_topic::<Header>("/robot/header", true, false)
+            .unwrap();
+        assert_eq!(table.version(), 0);
+        assert_eq!(table.schema_name(), Some("std_msgs/msg/Header"));
+        assert_eq!(
+            table.column_names().unwrap(),
+            ["stamp_sec", "stamp_nanosec", "frame_id"]
+        );
+        // The u32 nanoseconds are stored as longs.
+        assert!(table
+            .schema_string()
+            .contains(r#"{"name":"stamp_nanosec","type":"long","nullable":true,"metadata":{}}"#));
+
+        for header in headers(10) {
+            row_builder.add_row(&header).unwrap();
+            sink.write_rows("/robot/header", &fields, &mut row_builder)
+                .unwrap();
+        }
+        // Committed after 4 and 8 rows.
+        assert_eq!(sink.table("/robot/header").unwrap().version(), 2);
+        // Unsigned fields of nested structs are cast too.
+        let nested_fields = Header::arrow_fields(false);
+        let mut nested_builder = Header::new_row_builder(nested_fields.iter().collect());
+        nested_builder.add_row(&headers(1)[0]).unwrap();
+        sink.write_rows("/nested", &nested_fields, &mut nested_builder)
+            .unwrap();
+        sink.finish().unwrap();
+        let nested = DeltaTable::open(dir.join("nested")).unwrap();
+        assert_eq!(nested.version(), 1);
+        assert!(nested.schema_string().contains(
+            r#"{"name":"stamp","type":{"type":"struct","fields":[{"name":"sec","type":"integer""#
+        ));
+
+        let table = DeltaTable::open(dir.join("robot.header")).unwrap();
+        assert_eq!(table.version(), 3);
+        assert_eq!(table.files().count(), 3);
+        assert_eq!(read_rows(&table), 10);
+        let file = File::open(table.path().join(table.files().next().unwrap())).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            batch
+                .column_by_name("stamp_nanosec")
+                .unwrap()
+                .as_primitive::<Int64Type>()
+                .len(),
+            batch.num_rows()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_schema_evolution_and_conflicts() {
+        let dir = temp_dir("evolution");
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::UInt32, false),
+            Field::new("b", DataType::Utf8, false),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![
+                Arc::new(UInt32Array::from(vec![1, u32::MAX])),
+                Arc::new(arrow_array::StringArray::from(vec!["x", "y"])),
+            ],
+        )
+        .unwrap();
+        let mut table = DeltaTable::open_or_create(&dir, "/t", "pkg/msg/T", &schema).unwrap();
+        // Another writer of the same table.
+        let mut other = DeltaTable::open_or_create(&dir, "/t", "pkg/msg/T", &schema).unwrap();
+        assert_eq!(table.append(&batch).unwrap(), 1);
+        assert_eq!(other.append(&batch).unwrap(), 2);
+
+        // A definition with a new field and without `b`.
+        let evolved = Schema::new(vec![
+            Field::new("a", DataType::UInt32, false),
+            Field::new("c", DataType::Float64, false),
+        ]);
+        let evolved_batch = RecordBatch::try_new(
+            Arc::new(evolved.clone()),
+            vec![
+                Arc::new(UInt32Array::from(vec![3])),
+                Arc::new(arrow_array::Float64Array::from(vec![0.5])),
+            ],
+        )
+        .unwrap();
+        let mut table = DeltaTable::open_or_create(&dir, "/t", "pkg/msg/T2", &evolved).unwrap();
+        assert_eq!(table.append(&evolved_batch).unwrap(), 3);
+        let fields: Vec<DeltaField> = table.metadata.fields().unwrap();
+        assert_eq!(
+            fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.nullable))
+                .collect::<Vec<_>>(),
+            [("a", false), ("b", true), ("c", true)]
+        );
+        assert_eq!(table.schema_name(), Some("pkg/msg/T2"));
+        assert_eq!(read_rows(&table), 5);
+
+        let changed = Schema::new(vec![Field::new("a", DataType::Utf8, false)]);
+        let changed_batch = RecordBatch::try_new(
+            Arc::new(changed),
+            vec![Arc::new(arrow_array::StringArray::from(vec!["z"]))],
+        )
+        .unwrap();
+        assert!(table.append(&changed_batch).is_err());
+        assert_eq!(DeltaTable::open(&dir).unwrap().version(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
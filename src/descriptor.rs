@@ -0,0 +1,421 @@
+//! Static type descriptors and the generic, descriptor driven row building of the
+//! `descriptor-builders` feature.
+//!
+//! By default the build script generates a row builder, a flat row builder and struct builder
+//! functions for every message type. With `descriptor-builders` it only generates a
+//! [`TypeDescriptor`] table and a [`Reflect`] implementation, which hands out the field values
+//! by index, per type. The rows are built by [`StructRowBuilder`](crate::StructRowBuilder),
+//! which walks the descriptors at run time. The Arrow layouts are the same in both modes.
+
+use crate::flatten::flatten_fields;
+use arrow_array::builder::{
+    ArrayBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int16Builder, Int32Builder,
+    Int64Builder, Int8Builder, LargeBinaryBuilder, LargeListBuilder, StringBuilder, StructBuilder,
+    UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder,
+};
+use arrow_schema::{DataType, Field, Fields};
+use std::sync::Arc;
+
+/// The fields of a message type.
+#[derive(Debug)]
+pub struct TypeDescriptor {
+    pub schema_name: &'static str,
+    pub fields: &'static [FieldDescriptor],
+}
+
+/// A field of a message type.
+#[derive(Debug)]
+pub struct FieldDescriptor {
+    pub name: &'static str,
+    pub kind: FieldKind,
+}
+
+/// The scalar types of the fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimitiveKind {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    String,
+}
+
+/// The shape of a field.
+#[derive(Debug)]
+pub enum FieldKind {
+    Primitive(PrimitiveKind),
+    /// An array of scalars. Arrays of `u8` map to `LargeBinary`, the rest to `LargeList`.
+    PrimitiveList(PrimitiveKind),
+    Struct(&'static TypeDescriptor),
+    StructList(&'static TypeDescriptor),
+}
+
+/// The value of a field, as handed out by [`Reflect::field`].
+pub enum FieldValue<'a> {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(&'a str),
+    BoolList(&'a [bool]),
+    I8List(&'a [i8]),
+    I16List(&'a [i16]),
+    I32List(&'a [i32]),
+    I64List(&'a [i64]),
+    Bytes(&'a [u8]),
+    U16List(&'a [u16]),
+    U32List(&'a [u32]),
+    U64List(&'a [u64]),
+    F32List(&'a [f32]),
+    F64List(&'a [f64]),
+    StringList(&'a [String]),
+    Struct(&'a dyn Reflect),
+    StructList(&'a dyn ReflectList),
+}
+
+/// Access to the fields of a message in the order of its [`TypeDescriptor`].
+pub trait Reflect {
+    fn descriptor(&self) -> &'static TypeDescriptor;
+
+    /// The value of the field at `index` of the descriptor, or `None` past the last field.
+    fn field(&self, index: usize) -> Option<FieldValue<'_>>;
+}
+
+/// An array of messages.
+pub trait ReflectList {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, index: usize) -> &dyn Reflect;
+}
+
+impl<T: Reflect> ReflectList for Vec<T> {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn get(&self, index: usize) -> &dyn Reflect {
+        &self[index]
+    }
+}
+
+impl PrimitiveKind {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            PrimitiveKind::Bool => DataType::Boolean,
+            PrimitiveKind::I8 => DataType::Int8,
+            PrimitiveKind::I16 => DataType::Int16,
+            PrimitiveKind::I32 => DataType::Int32,
+            PrimitiveKind::I64 => DataType::Int64,
+            PrimitiveKind::U8 => DataType::UInt8,
+            PrimitiveKind::U16 => DataType::UInt16,
+            PrimitiveKind::U32 => DataType::UInt32,
+            PrimitiveKind::U64 => DataType::UInt64,
+            PrimitiveKind::F32 => DataType::Float32,
+            PrimitiveKind::F64 => DataType::Float64,
+            PrimitiveKind::String => DataType::Utf8,
+        }
+    }
+}
+
+impl FieldKind {
+    /// The Arrow type of the field in the regular layout.
+    pub fn data_type(&self) -> DataType {
+        match self {
+            FieldKind::Primitive(kind) => kind.data_type(),
+            FieldKind::PrimitiveList(PrimitiveKind::U8) => DataType::LargeBinary,
+            FieldKind::PrimitiveList(kind) => {
+                DataType::LargeList(Arc::new(Field::new("item", kind.data_type(), true)))
+            }
+            FieldKind::Struct(descriptor) => {
+                DataType::Struct(Fields::from(descriptor.arrow_fields()))
+            }
+            FieldKind::StructList(descriptor) => DataType::LargeList(Arc::new(Field::new(
+                "item",
+                DataType::Struct(Fields::from(descriptor.arrow_fields())),
+                true,
+            ))),
+        }
+    }
+}
+
+impl TypeDescriptor {
+    /// The fields of the regular layout, like `ArrowSupport::arrow_fields(false)`.
+    pub fn arrow_fields(&self) -> Vec<Field> {
+        self.fields
+            .iter()
+            .map(|field| Field::new(field.name, field.kind.data_type(), true))
+            .collect()
+    }
+
+    /// The fields of the flat layout, like `ArrowSupport::flat_arrow_fields(false)`.
+    pub fn flat_arrow_fields(&self) -> Vec<Field> {
+        flatten_fields(&self.arrow_fields())
+    }
+}
+
+fn field_builder<T: ArrayBuilder>(builder: &mut StructBuilder, index: usize) -> &mut T {
+    builder
+        .field_builder::<T>(index)
+        .expect("The struct builder doesn't match the type descriptor")
+}
+
+fn append_list<T: ArrayBuilder>(
+    builder: &mut StructBuilder,
+    index: usize,
+    append_values: impl FnOnce(&mut T),
+) {
+    let list_builder = field_builder::<LargeListBuilder<Box<dyn ArrayBuilder>>>(builder, index);
+    let values = list_builder
+        .values()
+        .as_any_mut()
+        .downcast_mut::<T>()
+        .expect("The list builder doesn't match the type descriptor");
+    append_values(values);
+    list_builder.append(true);
+}
+
+/// Appends `msg` to a StructBuilder with the fields of `msg.descriptor().arrow_fields()`.
+pub fn append_to_struct_builder(msg: &dyn Reflect, builder: &mut StructBuilder) {
+    let mut index = 0;
+    while let Some(value) = msg.field(index) {
+        append_field(builder, index, value);
+        index += 1;
+    }
+    builder.append(true);
+}
+
+fn append_field(builder: &mut StructBuilder, index: usize, value: FieldValue) {
+    match value {
+        FieldValue::Bool(value) => {
+            field_builder::<BooleanBuilder>(builder, index).append_value(value)
+        }
+        FieldValue::I8(value) => field_builder::<Int8Builder>(builder, index).append_value(value),
+        FieldValue::I16(value) => field_builder::<Int16Builder>(builder, index).append_value(value),
+        FieldValue::I32(value) => field_builder::<Int32Builder>(builder, index).append_value(value),
+        FieldValue::I64(value) => field_builder::<Int64Builder>(builder, index).append_value(value),
+        FieldValue::U8(value) => field_builder::<UInt8Builder>(builder, index).append_value(value),
+        FieldValue::U16(value) => {
+            field_builder::<UInt16Builder>(builder, index).append_value(value)
+        }
+        FieldValue::U32(value) => {
+            field_builder::<UInt32Builder>(builder, index).append_value(value)
+        }
+        FieldValue::U64(value) => {
+            field_builder::<UInt64Builder>(builder, index).append_value(value)
+        }
+        FieldValue::F32(value) => {
+            field_builder::<Float32Builder>(builder, index).append_value(value)
+        }
+        FieldValue::F64(value) => {
+            field_builder::<Float64Builder>(builder, index).append_value(value)
+        }
+        FieldValue::String(value) => {
+            field_builder::<StringBuilder>(builder, index).append_value(value)
+        }
+        FieldValue::Bytes(values) => {
+            field_builder::<LargeBinaryBuilder>(builder, index).append_value(values)
+        }
+        FieldValue::BoolList(values) => append_list(builder, index, |list: &mut BooleanBuilder| {
+            values.iter().for_each(|value| list.append_value(*value))
+        }),
+        FieldValue::I8List(values) => append_list(builder, index, |list: &mut Int8Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::I16List(values) => append_list(builder, index, |list: &mut Int16Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::I32List(values) => append_list(builder, index, |list: &mut Int32Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::I64List(values) => append_list(builder, index, |list: &mut Int64Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::U16List(values) => append_list(builder, index, |list: &mut UInt16Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::U32List(values) => append_list(builder, index, |list: &mut UInt32Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::U64List(values) => append_list(builder, index, |list: &mut UInt64Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::F32List(values) => append_list(builder, index, |list: &mut Float32Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::F64List(values) => append_list(builder, index, |list: &mut Float64Builder| {
+            list.append_slice(values)
+        }),
+        FieldValue::StringList(values) => {
+            append_list(builder, index, |list: &mut StringBuilder| {
+                values.iter().for_each(|value| list.append_value(value))
+            })
+        }
+        FieldValue::Struct(msg) => {
+            append_to_struct_builder(msg, field_builder::<StructBuilder>(builder, index))
+        }
+        FieldValue::StructList(msgs) => append_list(builder, index, |list: &mut StructBuilder| {
+            for i in 0..msgs.len() {
+                append_to_struct_builder(msgs.get(i), list)
+            }
+        }),
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::cdr::CdrMessage;
+    use crate::msgs::geometry_msgs::msg::{Transform, TransformStamped, Vector3};
+    use crate::msgs::sensor_msgs::msg::{LaserScan, PointCloud2};
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::msgs::tf2_msgs::msg::TFMessage;
+    use crate::{ArrowSupport, MessageStructEncoding, RowBuilder, RowFilter};
+    use arrow_array::cast::AsArray;
+    use arrow_array::Array;
+
+    fn header(sec: i32) -> Header {
+        Header {
+            stamp: crate::msgs::builtin_interfaces::msg::Time { sec, nanosec: 0 },
+            frame_id: "map".to_string(),
+        }
+    }
+
+    fn tf_message(sec: i32) -> TFMessage {
+        TFMessage {
+            transforms: vec![TransformStamped {
+                header: header(sec),
+                child_frame_id: "base_link".to_string(),
+                transform: Transform {
+                    translation: Vector3 {
+                        x: 1.0,
+                        y: 2.0,
+                        z: 3.0,
+                    },
+                    ..Default::default()
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_descriptor_fields() {
+        let scan = LaserScan::default();
+        assert_eq!(scan.descriptor().schema_name, "sensor_msgs/msg/LaserScan");
+        assert_eq!(
+            scan.descriptor().arrow_fields(),
+            LaserScan::arrow_fields(false)
+        );
+        assert_eq!(
+            scan.descriptor().flat_arrow_fields(),
+            LaserScan::flat_arrow_fields(false)
+        );
+        assert!(matches!(
+            scan.descriptor().fields[0].kind,
+            FieldKind::Struct(TypeDescriptor {
+                schema_name: "std_msgs/msg/Header",
+                ..
+            })
+        ));
+
+        let cloud = PointCloud2::default();
+        assert_eq!(
+            cloud.descriptor().arrow_fields(),
+            PointCloud2::arrow_fields(false)
+        );
+        assert_eq!(
+            cloud.descriptor().flat_arrow_fields(),
+            PointCloud2::flat_arrow_fields(false)
+        );
+
+        let tf = tf_message(0);
+        assert_eq!(
+            tf.descriptor().arrow_fields(),
+            TFMessage::arrow_fields(false)
+        );
+        assert_eq!(
+            tf.descriptor().flat_arrow_fields(),
+            TFMessage::flat_arrow_fields(false)
+        );
+        assert!(tf.field(1).is_none());
+    }
+
+    #[test]
+    fn test_descriptor_row_builders() {
+        let fields = TFMessage::arrow_fields(true);
+        let mut row_builder = TFMessage::new_row_builder(fields.iter().collect());
+        row_builder.add_row(&tf_message(1)).unwrap();
+        row_builder
+            .add_raw_row(&tf_message(2).to_serialized_bytes().unwrap())
+            .unwrap();
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), fields.len());
+        for (array, field) in arrays.iter().zip(fields.iter()) {
+            assert_eq!(array.len(), 2);
+            assert_eq!(array.data_type(), field.data_type());
+        }
+
+        let mut fields = LaserScan::flat_arrow_fields(false);
+        fields.push(LaserScan::message_struct_field(
+            "scan",
+            MessageStructEncoding::FlatStruct,
+        ));
+        let mut row_builder = LaserScan::new_flat_row_builder(fields.iter().collect());
+        row_builder.set_filter(RowFilter::new().max_rate_hz(1.0));
+        for sec in [1, 1, 2] {
+            let scan = LaserScan {
+                header: header(sec),
+                ranges: vec![1.0, 2.0],
+                ..Default::default()
+            };
+            row_builder.add_row(&scan).unwrap();
+        }
+        assert_eq!(row_builder.filter_stats().dropped_by_rate, 1);
+        let arrays = row_builder.to_arc_arrays();
+        for (array, field) in arrays.iter().zip(fields.iter()) {
+            assert_eq!(array.len(), 2);
+            assert_eq!(array.data_type(), field.data_type());
+        }
+        let sec = fields
+            .iter()
+            .position(|field| field.name() == "header_stamp_sec")
+            .unwrap();
+        assert_eq!(
+            arrays[sec]
+                .as_primitive::<arrow_array::types::Int32Type>()
+                .value(1),
+            2
+        );
+
+        let fields = PointCloud2::arrow_fields(false);
+        let mut row_builder = PointCloud2::new_row_builder(fields.iter().collect());
+        let cloud = PointCloud2 {
+            data: vec![1, 2, 3],
+            ..Default::default()
+        };
+        row_builder.add_row(&cloud).unwrap();
+        let arrays = row_builder.to_arc_arrays();
+        let data = fields
+            .iter()
+            .position(|field| field.name() == "data")
+            .unwrap();
+        assert_eq!(arrays[data].as_binary::<i64>().value(0), [1, 2, 3]);
+    }
+}
@@ -0,0 +1,587 @@
+//! Exports flat-layout batches to CSV or newline-delimited JSON. Requires the `export`
+//! feature.
+//!
+//! An [`Exporter`] writes the columns of `flat_arrow_fields` batches as text: CSV with a header
+//! row of the flat field names, or one JSON object per row. The columns that do not have a
+//! plain text form are configurable:
+//!
+//! - List columns, like the `ranges` of a `LaserScan`, are written as JSON array text or
+//!   exploded into one row per element, see [`ListEncoding`].
+//! - Binary columns, like the `data` of an `Image`, are written as base64 or hex, see
+//!   [`BinaryEncoding`].
+//! - The `message_struct` column is dropped or written as a JSON document, see
+//!   [`MessageStructOutput`].
+//!
+//! Timestamps, like the `_recv_time` context column, are written in the RFC 3339 format, in
+//! UTC. Other struct columns are written as JSON.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::export::{BinaryEncoding, ExportFormat, Exporter, ListEncoding};
+//! use r2a::rosbag2::Rosbag2Reader;
+//!
+//! let reader = Rosbag2Reader::open("recordings/run_42").unwrap();
+//! let mut exporter = Exporter::create("scan.csv", ExportFormat::Csv)
+//!     .unwrap()
+//!     .with_list_encoding(ListEncoding::Explode)
+//!     .with_binary_encoding(BinaryEncoding::Hex);
+//! for batch in reader.topic_batches("/scan", true).unwrap() {
+//!     exporter.write_batch(&batch.unwrap()).unwrap();
+//! }
+//! exporter.finish().unwrap();
+//! ```
+
+use crate::message_struct::{message_struct_encoding, write_json_string, write_json_value};
+use crate::utc_time::UtcTime;
+use anyhow::{bail, Context, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, TimestampMicrosecondType,
+    TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Schema, TimeUnit};
+use base64::Engine;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The column [`ListEncoding::Explode`] adds with the index of the list elements of a row.
+pub const LIST_INDEX_FIELD: &str = "_index";
+
+/// The output format of an [`Exporter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row.
+    Csv,
+    /// Newline-delimited JSON, one object per row.
+    NdJson,
+}
+
+/// How list columns are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEncoding {
+    /// A JSON array per row, as text in CSV and as an array in JSON.
+    Json,
+    /// One output row per list element, with the elements of all list columns side by side
+    /// and the other columns repeated. Shorter lists are padded with empty values, and the
+    /// index of the elements is written to an extra [`LIST_INDEX_FIELD`] column. A row whose
+    /// lists are all empty is written once, with empty list values.
+    Explode,
+}
+
+/// How binary columns are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    /// Standard base64 with padding.
+    Base64,
+    /// Lowercase hex digits.
+    Hex,
+}
+
+/// What happens to the message struct column, see [`crate::MESSAGE_STRUCT_FIELD`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageStructOutput {
+    /// The column is not written.
+    Drop,
+    /// The whole message is written as a JSON document, as text in CSV and as an object in
+    /// JSON.
+    Json,
+}
+
+/// The text form of a value.
+enum Cell {
+    Null,
+    /// A number or a boolean, written as it is.
+    Literal(String),
+    /// A floating point number, which is `null` in JSON if it is not finite.
+    Float(String, bool),
+    Text(String),
+    /// A JSON value, quoted in CSV.
+    Json(String),
+}
+
+/// How a column of the batches is written.
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    index: usize,
+    name: String,
+    list: bool,
+    message_struct: bool,
+}
+
+/// Writes flat-layout batches as CSV or newline-delimited JSON.
+pub struct Exporter<W: Write> {
+    writer: W,
+    format: ExportFormat,
+    list_encoding: ListEncoding,
+    binary_encoding: BinaryEncoding,
+    message_struct: MessageStructOutput,
+    delimiter: char,
+    header: bool,
+    /// The columns of the first batch, which the other batches must have too.
+    columns: Option<Vec<Column>>,
+    line: String,
+}
+
+impl Exporter<BufWriter<File>> {
+    /// Creates or replaces the file at `path`.
+    pub fn create(path: impl AsRef<Path>, format: ExportFormat) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+        Ok(Exporter::new(BufWriter::new(file), format))
+    }
+}
+
+impl<W: Write> Exporter<W> {
+    /// Creates an exporter that writes to `writer`. Lists are written as JSON, binary columns
+    /// as base64 and the message struct column is dropped, unless configured otherwise.
+    pub fn new(writer: W, format: ExportFormat) -> Self {
+        Exporter {
+            writer,
+            format,
+            list_encoding: ListEncoding::Json,
+            binary_encoding: BinaryEncoding::Base64,
+            message_struct: MessageStructOutput::Drop,
+            delimiter: ',',
+            header: true,
+            columns: None,
+            line: String::new(),
+        }
+    }
+
+    /// Sets how list columns are written.
+    pub fn with_list_encoding(mut self, list_encoding: ListEncoding) -> Self {
+        self.list_encoding = list_encoding;
+        self
+    }
+
+    /// Sets how binary columns are written.
+    pub fn with_binary_encoding(mut self, binary_encoding: BinaryEncoding) -> Self {
+        self.binary_encoding = binary_encoding;
+        self
+    }
+
+    /// Sets what happens to the message struct column.
+    pub fn with_message_struct(mut self, message_struct: MessageStructOutput) -> Self {
+        self.message_struct = message_struct;
+        self
+    }
+
+    /// Sets the CSV delimiter, `,` by default.
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets whether CSV output starts with a header row, true by default.
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Returns the names of the columns written for batches with `schema`: the flat field
+    /// names, without a dropped message struct column, and with the [`LIST_INDEX_FIELD`] of
+    /// exploded lists.
+    pub fn column_names(&self, schema: &Schema) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .columns(schema)
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+        if self.explodes(schema) {
+            names.push(LIST_INDEX_FIELD.to_string());
+        }
+        names
+    }
+
+    fn columns(&self, schema: &Schema) -> Vec<Column> {
+        schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, field)| {
+                let message_struct = message_struct_encoding(field).is_some();
+                if message_struct && self.message_struct == MessageStructOutput::Drop {
+                    return None;
+                }
+                Some(Column {
+                    index,
+                    name: field.name().clone(),
+                    list: !message_struct && is_list(field.data_type()),
+                    message_struct,
+                })
+            })
+            .collect()
+    }
+
+    fn explodes(&self, schema: &Schema) -> bool {
+        self.list_encoding == ListEncoding::Explode
+            && self.columns(schema).iter().any(|column| column.list)
+    }
+
+    /// Writes the rows of a batch. All batches must have the same columns.
+    pub fn write_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let schema = batch.schema();
+        let columns = self.columns(&schema);
+        match &self.columns {
+            Some(known) if *known != columns => {
+                bail!("The batch has other columns than the batches written before it")
+            }
+            Some(_) => {}
+            None => {
+                if self.format == ExportFormat::Csv && self.header {
+                    self.line.clear();
+                    for (i, name) in self.column_names(&schema).iter().enumerate() {
+                        if i > 0 {
+                            self.line.push(self.delimiter);
+                        }
+                        write_csv_text(&mut self.line, name, self.delimiter);
+                    }
+                    self.line.push('\n');
+                    self.writer.write_all(self.line.as_bytes())?;
+                }
+                self.columns = Some(columns.clone());
+            }
+        }
+        let explode = self.explodes(&schema);
+
+        for row in 0..batch.num_rows() {
+            if !explode {
+                let cells: Vec<(&str, Cell)> = columns
+                    .iter()
+                    .map(|column| {
+                        let array = batch.column(column.index);
+                        (column.name.as_str(), self.cell(column, array, row))
+                    })
+                    .collect();
+                self.write_line(cells)?;
+                continue;
+            }
+
+            let lists: Vec<Option<ArrayRef>> = columns
+                .iter()
+                .map(|column| {
+                    let array = batch.column(column.index);
+                    (column.list && array.is_valid(row)).then(|| list_value(array, row))
+                })
+                .collect();
+            let elements = lists
+                .iter()
+                .flatten()
+                .map(|values| values.len())
+                .max()
+                .unwrap_or(0)
+                .max(1);
+            for element in 0..elements {
+                let mut cells: Vec<(&str, Cell)> = columns
+                    .iter()
+                    .zip(&lists)
+                    .map(|(column, list)| {
+                        let cell = match (column.list, list) {
+                            (true, Some(values)) if element < values.len() => {
+                                self.cell(column, values, element)
+                            }
+                            (true, _) => Cell::Null,
+                            (false, _) => self.cell(column, batch.column(column.index), row),
+                        };
+                        (column.name.as_str(), cell)
+                    })
+                    .collect();
+                cells.push((LIST_INDEX_FIELD, Cell::Literal(element.to_string())));
+                self.write_line(cells)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The text form of the value at `row` of a column, or of a list element.
+    fn cell(&self, column: &Column, array: &dyn Array, row: usize) -> Cell {
+        if array.is_null(row) {
+            return Cell::Null;
+        }
+        if column.message_struct {
+            return match array.data_type() {
+                // The JSON encoding of the message struct column.
+                DataType::Utf8 => Cell::Json(array.as_string::<i32>().value(row).to_string()),
+                _ => Cell::Json(json_text(array, row)),
+            };
+        }
+        let literal = |value: &dyn std::fmt::Display| Cell::Literal(value.to_string());
+        match array.data_type() {
+            DataType::Boolean => literal(&array.as_boolean().value(row)),
+            DataType::Int8 => literal(&array.as_primitive::<Int8Type>().value(row)),
+            DataType::Int16 => literal(&array.as_primitive::<Int16Type>().value(row)),
+            DataType::Int32 => literal(&array.as_primitive::<Int32Type>().value(row)),
+            DataType::Int64 => literal(&array.as_primitive::<Int64Type>().value(row)),
+            DataType::UInt8 => literal(&array.as_primitive::<UInt8Type>().value(row)),
+            DataType::UInt16 => literal(&array.as_primitive::<UInt16Type>().value(row)),
+            DataType::UInt32 => literal(&array.as_primitive::<UInt32Type>().value(row)),
+            DataType::UInt64 => literal(&array.as_primitive::<UInt64Type>().value(row)),
+            DataType::Float32 => {
+                let value = array.as_primitive::<Float32Type>().value(row);
+                Cell::Float(value.to_string(), value.is_finite())
+            }
+            DataType::Float64 => {
+                let value = array.as_primitive::<Float64Type>().value(row);
+                Cell::Float(value.to_string(), value.is_finite())
+            }
+            DataType::Utf8 => Cell::Text(array.as_string::<i32>().value(row).to_string()),
+            DataType::LargeUtf8 => Cell::Text(array.as_string::<i64>().value(row).to_string()),
+            DataType::Binary => Cell::Text(self.encode(array.as_binary::<i32>().value(row))),
+            DataType::LargeBinary => Cell::Text(self.encode(array.as_binary::<i64>().value(row))),
+            DataType::FixedSizeBinary(_) => {
+                Cell::Text(self.encode(array.as_fixed_size_binary().value(row)))
+            }
+            DataType::Timestamp(unit, _) => {
+                let nanos = match unit {
+                    TimeUnit::Second => array
+                        .as_primitive::<TimestampSecondType>()
+                        .value(row)
+                        .saturating_mul(1_000_000_000),
+                    TimeUnit::Millisecond => array
+                        .as_primitive::<TimestampMillisecondType>()
+                        .value(row)
+                        .saturating_mul(1_000_000),
+                    TimeUnit::Microsecond => array
+                        .as_primitive::<TimestampMicrosecondType>()
+                        .value(row)
+                        .saturating_mul(1_000),
+                    TimeUnit::Nanosecond => {
+                        array.as_primitive::<TimestampNanosecondType>().value(row)
+                    }
+                };
+                Cell::Text(UtcTime::from_nanos(nanos).to_rfc3339())
+            }
+            _ => Cell::Json(json_text(array, row)),
+        }
+    }
+
+    fn encode(&self, bytes: &[u8]) -> String {
+        match self.binary_encoding {
+            BinaryEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+            BinaryEncoding::Hex => {
+                let mut hex = String::with_capacity(bytes.len() * 2);
+                for byte in bytes {
+                    let _ = write!(hex, "{:02x}", byte);
+                }
+                hex
+            }
+        }
+    }
+
+    fn write_line(&mut self, cells: Vec<(&str, Cell)>) -> Result<()> {
+        self.line.clear();
+        match self.format {
+            ExportFormat::Csv => {
+                for (i, (_, cell)) in cells.into_iter().enumerate() {
+                    if i > 0 {
+                        self.line.push(self.delimiter);
+                    }
+                    match cell {
+                        Cell::Null => {}
+                        Cell::Literal(value) | Cell::Float(value, _) => self.line.push_str(&value),
+                        Cell::Text(value) | Cell::Json(value) => {
+                            write_csv_text(&mut self.line, &value, self.delimiter)
+                        }
+                    }
+                }
+            }
+            ExportFormat::NdJson => {
+                self.line.push('{');
+                for (i, (name, cell)) in cells.into_iter().enumerate() {
+                    if i > 0 {
+                        self.line.push(',');
+                    }
+                    write_json_string(&mut self.line, name);
+                    self.line.push(':');
+                    match cell {
+                        Cell::Null | Cell::Float(_, false) => self.line.push_str("null"),
+                        Cell::Literal(value) | Cell::Float(value, true) | Cell::Json(value) => {
+                            self.line.push_str(&value)
+                        }
+                        Cell::Text(value) => write_json_string(&mut self.line, &value),
+                    }
+                }
+                self.line.push('}');
+            }
+        }
+        self.line.push('\n');
+        self.writer.write_all(self.line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Flushes the output and returns the writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn is_list(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(_, _)
+    )
+}
+
+/// The elements of the list at `row`.
+fn list_value(array: &dyn Array, row: usize) -> ArrayRef {
+    match array.data_type() {
+        DataType::List(_) => array.as_list::<i32>().value(row),
+        DataType::LargeList(_) => array.as_list::<i64>().value(row),
+        _ => array.as_fixed_size_list().value(row),
+    }
+}
+
+fn json_text(array: &dyn Array, row: usize) -> String {
+    let mut json = String::new();
+    write_json_value(array, row, &mut json);
+    json
+}
+
+/// Writes a CSV field, quoted if it contains the delimiter, a quote or a line break.
+fn write_csv_text(out: &mut String, value: &str, delimiter: char) {
+    if value.contains([delimiter, '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&value.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(value);
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::sensor_msgs::msg::{Image, LaserScan};
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::{row_context_fields, ArrowSupport, RowBuilder, RowContext};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn scan_batch(include_msg_struct: bool) -> RecordBatch {
+        let fields = LaserScan::flat_arrow_fields(include_msg_struct);
+        let mut row_builder = LaserScan::new_flat_row_builder(fields.iter().collect());
+        for (frame_id, ranges) in [("a,b", vec![1.0, 2.5]), ("c", vec![])] {
+            let scan = LaserScan {
+                header: Header {
+                    frame_id: frame_id.to_string(),
+                    ..Default::default()
+                },
+                ranges,
+                intensities: vec![f32::NAN],
+                ..Default::default()
+            };
+            row_builder.add_row(&scan).unwrap();
+        }
+        RecordBatch::try_new(
+            Arc::new(Schema::new(fields.clone())),
+            row_builder.to_arc_arrays(),
+        )
+        .unwrap()
+    }
+
+    fn export(exporter: Exporter<Vec<u8>>, batch: &RecordBatch) -> Vec<String> {
+        let mut exporter = exporter;
+        exporter.write_batch(batch).unwrap();
+        let output = String::from_utf8(exporter.finish().unwrap()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_csv() {
+        let batch = scan_batch(true);
+        let lines = export(Exporter::new(vec![], ExportFormat::Csv), &batch);
+        assert_eq!(
+            lines,
+            [
+                "header_stamp_sec,header_stamp_nanosec,header_frame_id,angle_min,angle_max,\
+                 angle_increment,time_increment,scan_time,range_min,range_max,ranges,intensities",
+                r#"0,0,"a,b",0,0,0,0,0,0,0,"[1,2.5]",[null]"#,
+                "0,0,c,0,0,0,0,0,0,0,[],[null]",
+            ]
+        );
+
+        let exporter = Exporter::new(vec![], ExportFormat::Csv)
+            .with_list_encoding(ListEncoding::Explode)
+            .with_message_struct(MessageStructOutput::Json)
+            .with_delimiter(';');
+        let lines = export(exporter, &batch);
+        assert!(lines[0].ends_with(";ranges;intensities;message_struct;_index"));
+        assert!(lines[1].starts_with("0;0;a,b;0;0;0;0;0;0;0;1;NaN;"));
+        assert!(lines[1].ends_with(";0"));
+        assert!(lines[1].contains(
+            r#""{""header"":{""stamp"":{""sec"":0,""nanosec"":0},""frame_id"":""a,b""}"#
+        ));
+        assert!(lines[2].starts_with("0;0;a,b;0;0;0;0;0;0;0;2.5;;"));
+        assert!(lines[2].ends_with(";1"));
+        // No elements in the lists of the second row.
+        assert!(lines[3].starts_with("0;0;c;0;0;0;0;0;0;0;;NaN;"));
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let fields = Image::flat_arrow_fields(false);
+        let mut row_builder = Image::new_flat_row_builder(fields.iter().collect());
+        let image = Image {
+            encoding: "mono8".to_string(),
+            data: vec![0, 1, 254, 255],
+            ..Default::default()
+        };
+        row_builder.add_row(&image).unwrap();
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(fields.clone())),
+            row_builder.to_arc_arrays(),
+        )
+        .unwrap();
+        let lines = export(Exporter::new(vec![], ExportFormat::NdJson), &batch);
+        assert_eq!(
+            lines,
+            [
+                r#"{"header_stamp_sec":0,"header_stamp_nanosec":0,"header_frame_id":"","height":0,"width":0,"encoding":"mono8","is_bigendian":0,"step":0,"data":"AAH+/w=="}"#
+            ]
+        );
+        let exporter =
+            Exporter::new(vec![], ExportFormat::NdJson).with_binary_encoding(BinaryEncoding::Hex);
+        assert!(export(exporter, &batch)[0].ends_with(r#""data":"0001feff"}"#));
+
+        let batch = scan_batch(false);
+        let exporter =
+            Exporter::new(vec![], ExportFormat::NdJson).with_list_encoding(ListEncoding::Explode);
+        let lines = export(exporter, &batch);
+        assert!(lines[0].ends_with(r#""ranges":1,"intensities":null,"_index":0}"#));
+        assert!(lines[1].ends_with(r#""ranges":2.5,"intensities":null,"_index":1}"#));
+        let lines = export(Exporter::new(vec![], ExportFormat::NdJson), &batch);
+        assert!(lines[0].ends_with(r#""ranges":[1,2.5],"intensities":[null]}"#));
+    }
+
+    #[test]
+    fn test_recv_time_and_columns() {
+        let mut fields = Header::flat_arrow_fields(false);
+        fields.extend(row_context_fields(false));
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        let context = RowContext::new("/header", 7)
+            .with_recv_time(UNIX_EPOCH + Duration::from_nanos(1_728_830_096_000_000_001));
+        row_builder
+            .add_row_with_context(&Header::default(), &context)
+            .unwrap();
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(fields.clone())),
+            row_builder.to_arc_arrays(),
+        )
+        .unwrap();
+        let mut exporter = Exporter::new(vec![], ExportFormat::Csv).with_header(false);
+        exporter.write_batch(&batch).unwrap();
+        let other = scan_batch(false);
+        assert!(exporter.write_batch(&other).is_err());
+        let output = String::from_utf8(exporter.finish().unwrap()).unwrap();
+        assert!(output.contains(",2024-10-13T14:34:56.000000001Z,"));
+        assert_eq!(output.lines().count(), 1);
+    }
+}
@@ -0,0 +1,25 @@
+/// The file metadata key with the schema name of the messages in a file written by r2a, for
+/// example `sensor_msgs/msg/LaserScan`.
+pub const SCHEMA_NAME_METADATA_KEY: &str = "r2a.schema_name";
+
+/// The file metadata key with the topic of the messages in a file written by r2a.
+pub const TOPIC_METADATA_KEY: &str = "r2a.topic";
+
+/// The file metadata key with the version of r2a that wrote a file.
+pub const VERSION_METADATA_KEY: &str = "r2a.version";
+
+/// Returns the metadata r2a writes into the files of a topic: its schema name, the topic and the
+/// version of r2a.
+pub fn file_metadata(topic: &str, schema_name: &str) -> Vec<(String, String)> {
+    vec![
+        (
+            SCHEMA_NAME_METADATA_KEY.to_string(),
+            schema_name.to_string(),
+        ),
+        (TOPIC_METADATA_KEY.to_string(), topic.to_string()),
+        (
+            VERSION_METADATA_KEY.to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+    ]
+}
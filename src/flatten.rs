@@ -0,0 +1,194 @@
+use anyhow::{anyhow, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, ArrayRef, LargeListArray, StructArray};
+use arrow_schema::{DataType, Field, Fields};
+use std::sync::Arc;
+
+/// Converts fields in the nested layout of `arrow_fields` to the flat layout of
+/// `flat_arrow_fields`: struct fields are replaced by their children, named
+/// `<parent>_<child>`, and lists of structs keep a single column with flattened items.
+pub fn flatten_fields(fields: &[Field]) -> Vec<Field> {
+    let mut flat_fields = vec![];
+    flatten_fields_into("", &Fields::from(fields.to_vec()), &mut flat_fields);
+    flat_fields
+}
+
+fn flatten_fields_into(prefix: &str, fields: &Fields, out: &mut Vec<Field>) {
+    for field in fields {
+        let name = flat_name(prefix, field.name());
+        match field.data_type() {
+            DataType::Struct(children) => flatten_fields_into(&name, children, out),
+            DataType::LargeList(item) if matches!(item.data_type(), DataType::Struct(_)) => out
+                .push(Field::new(
+                    name,
+                    DataType::LargeList(flatten_list_item(item)),
+                    field.is_nullable(),
+                )),
+            _ => out.push(field.as_ref().clone().with_name(name)),
+        }
+    }
+}
+
+/// Converts a StructArray in the nested layout to the columns of the flat layout, in the order
+/// of `flatten_fields`. The validity of flattened parent structs is not carried over to their
+/// children; r2a never appends null structs below the top level.
+pub(crate) fn flatten_struct_array(array: &StructArray) -> Vec<(Field, ArrayRef)> {
+    let mut columns = vec![];
+    flatten_columns_into("", array.fields(), array.columns(), &mut columns);
+    columns
+}
+
+fn flatten_columns_into(
+    prefix: &str,
+    fields: &Fields,
+    arrays: &[ArrayRef],
+    out: &mut Vec<(Field, ArrayRef)>,
+) {
+    for (field, array) in fields.iter().zip(arrays) {
+        let name = flat_name(prefix, field.name());
+        match field.data_type() {
+            DataType::Struct(children) => {
+                flatten_columns_into(&name, children, array.as_struct().columns(), out)
+            }
+            DataType::LargeList(item) if matches!(item.data_type(), DataType::Struct(_)) => {
+                let list = array.as_list::<i64>();
+                let (flat_fields, flat_arrays): (Vec<Field>, Vec<ArrayRef>) =
+                    flatten_struct_array(list.values().as_struct())
+                        .into_iter()
+                        .unzip();
+                let values = StructArray::new(
+                    Fields::from(flat_fields),
+                    flat_arrays,
+                    list.values().nulls().cloned(),
+                );
+                let item = flatten_list_item(item);
+                let flat_list = LargeListArray::new(
+                    item.clone(),
+                    list.offsets().clone(),
+                    Arc::new(values),
+                    list.nulls().cloned(),
+                );
+                out.push((
+                    Field::new(name, DataType::LargeList(item), field.is_nullable()),
+                    Arc::new(flat_list),
+                ))
+            }
+            _ => out.push((field.as_ref().clone().with_name(name), array.clone())),
+        }
+    }
+}
+
+fn flatten_list_item(item: &Field) -> Arc<Field> {
+    let item_fields = match item.data_type() {
+        DataType::Struct(children) => flatten_fields(
+            &children
+                .iter()
+                .map(|f| f.as_ref().clone())
+                .collect::<Vec<_>>(),
+        ),
+        _ => unreachable!("only lists of structs are flattened"),
+    };
+    Arc::new(Field::new(
+        item.name(),
+        DataType::Struct(Fields::from(item_fields)),
+        item.is_nullable(),
+    ))
+}
+
+/// Converts the columns of the flat layout back to a StructArray in the nested layout of
+/// `fields`, the reverse of `flatten_struct_array`. `column` returns the flat column of a name.
+#[allow(dead_code)]
+pub(crate) fn unflatten_columns(
+    fields: &Fields,
+    column: &dyn Fn(&str) -> Option<ArrayRef>,
+) -> Result<StructArray> {
+    unflatten_columns_from("", fields, column)
+}
+
+#[allow(dead_code)]
+fn unflatten_columns_from(
+    prefix: &str,
+    fields: &Fields,
+    column: &dyn Fn(&str) -> Option<ArrayRef>,
+) -> Result<StructArray> {
+    let mut arrays: Vec<ArrayRef> = vec![];
+    for field in fields {
+        let name = flat_name(prefix, field.name());
+        let array: ArrayRef = match field.data_type() {
+            DataType::Struct(children) => {
+                Arc::new(unflatten_columns_from(&name, children, column)?)
+            }
+            DataType::LargeList(item) if matches!(item.data_type(), DataType::Struct(_)) => {
+                let DataType::Struct(children) = item.data_type() else {
+                    unreachable!()
+                };
+                let flat_list = column(&name).ok_or_else(|| anyhow!("Missing column {}", name))?;
+                let flat_list = flat_list
+                    .as_list_opt::<i64>()
+                    .ok_or_else(|| anyhow!("Column {} is not a LargeList", name))?;
+                let flat_values = flat_list
+                    .values()
+                    .as_struct_opt()
+                    .ok_or_else(|| anyhow!("The items of column {} are not structs", name))?;
+                let values = unflatten_columns(children, &|child| {
+                    flat_values.column_by_name(child).cloned()
+                })?;
+                Arc::new(LargeListArray::try_new(
+                    item.clone(),
+                    flat_list.offsets().clone(),
+                    Arc::new(values),
+                    flat_list.nulls().cloned(),
+                )?)
+            }
+            _ => column(&name).ok_or_else(|| anyhow!("Missing column {}", name))?,
+        };
+        arrays.push(array);
+    }
+    Ok(StructArray::try_new(fields.clone(), arrays, None)?)
+}
+
+fn flat_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}_{}", prefix, name)
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+
+    use super::{flatten_fields, flatten_struct_array};
+    use crate::ArrowSupport;
+    use arrow_array::builder::StructBuilder;
+    use arrow_array::Array;
+    use r2r::geometry_msgs::msg::{Pose, PoseArray};
+
+    #[test]
+    fn test_flatten_matches_flat_layout() {
+        assert_eq!(
+            flatten_fields(&PoseArray::arrow_fields(false)),
+            PoseArray::flat_arrow_fields(false)
+        );
+        assert_eq!(
+            flatten_fields(&r2r::sensor_msgs::msg::PointCloud2::arrow_fields(false)),
+            r2r::sensor_msgs::msg::PointCloud2::flat_arrow_fields(false)
+        );
+
+        let msg = PoseArray {
+            poses: vec![Pose::default(), Pose::default()],
+            ..Default::default()
+        };
+        let mut builder = StructBuilder::from_fields(PoseArray::arrow_fields(false), 0);
+        msg.append_to_struct_builder(&mut builder);
+        let columns = flatten_struct_array(&builder.finish());
+
+        let flat_fields = PoseArray::flat_arrow_fields(false);
+        assert_eq!(columns.len(), flat_fields.len());
+        for ((field, array), flat_field) in columns.iter().zip(flat_fields.iter()) {
+            assert_eq!(field, flat_field);
+            assert_eq!(array.data_type(), flat_field.data_type());
+            assert_eq!(array.len(), 1);
+        }
+    }
+}
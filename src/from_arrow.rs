@@ -0,0 +1,273 @@
+use crate::flatten::unflatten_columns;
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, ArrayRef, RecordBatch, StructArray, UInt8Array};
+use arrow_schema::{DataType, Fields};
+use std::sync::Arc;
+
+/// Serializes the row of a StructArray in the nested layout of a schema, see
+/// [`message_serializer`].
+pub(crate) type MessageSerializer = fn(&dyn Array, usize) -> Result<Vec<u8>>;
+
+/// Reads a value back from the Arrow array the row builders wrote it to. The build script
+/// implements it for the message types, field by field.
+pub(crate) trait FromArrowValue: Sized {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self>;
+}
+
+macro_rules! impl_from_arrow_primitive {
+    ($($native:ty => $arrow:ty),*) => {
+        $(
+            impl FromArrowValue for $native {
+                fn from_arrow(array: &dyn Array, row: usize) -> Result<Self> {
+                    let values = array.as_primitive_opt::<$arrow>().ok_or_else(|| {
+                        anyhow!(
+                            "Expected {} values, got {}",
+                            stringify!($native),
+                            array.data_type()
+                        )
+                    })?;
+                    check_valid(array, row)?;
+                    Ok(values.value(row))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_arrow_primitive!(
+    i8 => Int8Type,
+    i16 => Int16Type,
+    i32 => Int32Type,
+    i64 => Int64Type,
+    u8 => UInt8Type,
+    u16 => UInt16Type,
+    u32 => UInt32Type,
+    u64 => UInt64Type,
+    f32 => Float32Type,
+    f64 => Float64Type
+);
+
+impl FromArrowValue for bool {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self> {
+        let values = array
+            .as_boolean_opt()
+            .ok_or_else(|| anyhow!("Expected bool values, got {}", array.data_type()))?;
+        check_valid(array, row)?;
+        Ok(values.value(row))
+    }
+}
+
+impl FromArrowValue for String {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self> {
+        check_valid(array, row)?;
+        match array.data_type() {
+            DataType::Utf8 => Ok(array.as_string::<i32>().value(row).to_string()),
+            DataType::LargeUtf8 => Ok(array.as_string::<i64>().value(row).to_string()),
+            other => bail!("Expected string values, got {}", other),
+        }
+    }
+}
+
+/// Arrays are `LargeList`s, except `uint8[]` and `byte[]`, which are `LargeBinary`.
+impl<T: FromArrowValue> FromArrowValue for Vec<T> {
+    fn from_arrow(array: &dyn Array, row: usize) -> Result<Self> {
+        check_valid(array, row)?;
+        let values: ArrayRef = match array.data_type() {
+            DataType::LargeBinary => Arc::new(UInt8Array::from(
+                array.as_binary::<i64>().value(row).to_vec(),
+            )),
+            DataType::Binary => Arc::new(UInt8Array::from(
+                array.as_binary::<i32>().value(row).to_vec(),
+            )),
+            DataType::LargeList(_) => array.as_list::<i64>().value(row),
+            DataType::List(_) => array.as_list::<i32>().value(row),
+            other => bail!("Expected list values, got {}", other),
+        };
+        (0..values.len())
+            .map(|index| T::from_arrow(values.as_ref(), index))
+            .collect()
+    }
+}
+
+fn check_valid(array: &dyn Array, row: usize) -> Result<()> {
+    if array.is_null(row) {
+        bail!("Unexpected null value in row {}", row);
+    }
+    Ok(())
+}
+
+/// Returns the StructArray of a message, or fails if the row is null.
+pub(crate) fn struct_value(array: &dyn Array, row: usize) -> Result<&StructArray> {
+    let array = array
+        .as_struct_opt()
+        .ok_or_else(|| anyhow!("Expected struct values, got {}", array.data_type()))?;
+    check_valid(array, row)?;
+    Ok(array)
+}
+
+/// Reads the value of the field `name` of a message.
+pub(crate) fn field_value<T: FromArrowValue>(
+    array: &StructArray,
+    name: &str,
+    row: usize,
+) -> Result<T> {
+    let column = array
+        .column_by_name(name)
+        .ok_or_else(|| anyhow!("Missing field {}", name))?;
+    T::from_arrow(column.as_ref(), row).with_context(|| format!("Unable to read field {}", name))
+}
+
+/// Returns the serializer of the messages of a supported schema, or `None` for unknown schemas.
+pub(crate) fn message_serializer(schema_name: &str) -> Option<MessageSerializer> {
+    crate::ros_mapper::message_serializer(schema_name)
+}
+
+/// Serializes the rows of a batch produced by r2a back to messages of `schema_name`, in the
+/// same format `add_raw_row` reads. The batch can have the regular or, if `flat` is true, the
+/// flat layout of the schema. Other columns, like the context columns or `message_struct`, are
+/// ignored.
+///
+/// # Example
+///
+/// ```
+/// use arrow_array::RecordBatch;
+/// use std::sync::Arc;
+///
+/// let fields = r2a::schema_arrow_fields("std_msgs/msg/Header", true, false).unwrap();
+/// let mut row_builder =
+///     r2a::new_raw_row_builder("std_msgs/msg/Header", fields.iter().collect(), true).unwrap();
+/// let batch = RecordBatch::try_new(
+///     Arc::new(arrow_schema::Schema::new(fields.clone())),
+///     row_builder.to_arc_arrays(),
+/// )
+/// .unwrap();
+/// let messages = r2a::raw_messages_from_batch("std_msgs/msg/Header", &batch, true).unwrap();
+/// assert!(messages.is_empty());
+/// ```
+pub fn raw_messages_from_batch(
+    schema_name: &str,
+    batch: &RecordBatch,
+    flat: bool,
+) -> Result<Vec<Vec<u8>>> {
+    let serializer = message_serializer(schema_name)
+        .ok_or_else(|| anyhow!("Unsupported message type {}", schema_name))?;
+    let fields = Fields::from(crate::ros_mapper::map_ros_schema_to_arrow_fields(
+        schema_name,
+        false,
+    ));
+    let messages = if flat {
+        unflatten_columns(&fields, &|name| batch.column_by_name(name).cloned())?
+    } else {
+        let columns = fields
+            .iter()
+            .map(|field| {
+                batch
+                    .column_by_name(field.name())
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Missing column {}", field.name()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        StructArray::try_new(fields, columns, None)?
+    };
+    (0..messages.len())
+        .map(|row| {
+            serializer(&messages, row).with_context(|| format!("Unable to serialize row {}", row))
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::raw_messages_from_batch;
+    use crate::cdr::CdrMessage;
+    use crate::msgs::geometry_msgs::msg::{Point, Pose, PoseArray, Quaternion};
+    use crate::msgs::sensor_msgs::msg::{CameraInfo, PointCloud2, PointField};
+    use arrow_array::RecordBatch;
+    use std::sync::Arc;
+
+    fn round_trip<T: CdrMessage>(schema_name: &str, messages: &[T], flat: bool) -> Vec<T> {
+        let fields = crate::schema_arrow_fields(schema_name, flat, true).unwrap();
+        let mut row_builder =
+            crate::new_raw_row_builder(schema_name, fields.iter().collect(), flat).unwrap();
+        for msg in messages {
+            row_builder
+                .add_raw_row(&msg.to_serialized_bytes().unwrap())
+                .unwrap();
+        }
+        let batch = RecordBatch::try_new(
+            Arc::new(arrow_schema::Schema::new(fields.clone())),
+            row_builder.to_arc_arrays(),
+        )
+        .unwrap();
+        raw_messages_from_batch(schema_name, &batch, flat)
+            .unwrap()
+            .iter()
+            .map(|bytes| T::from_serialized_bytes(bytes).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_raw_messages_from_batch() {
+        let pose_array = PoseArray {
+            poses: vec![
+                Pose {
+                    position: Point {
+                        x: 1.0,
+                        y: 2.0,
+                        z: 3.0,
+                    },
+                    orientation: Quaternion::default(),
+                },
+                Pose::default(),
+            ],
+            ..Default::default()
+        };
+        let messages = vec![pose_array.clone(), PoseArray::default()];
+        assert_eq!(
+            round_trip("geometry_msgs/msg/PoseArray", &messages, false),
+            messages
+        );
+        assert_eq!(
+            round_trip("geometry_msgs/msg/PoseArray", &messages, true),
+            messages
+        );
+
+        let point_cloud = PointCloud2 {
+            fields: vec![PointField {
+                name: "x".to_string(),
+                offset: 0,
+                datatype: 7,
+                count: 1,
+            }],
+            data: vec![1, 2, 3, 4],
+            is_dense: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            round_trip(
+                "sensor_msgs/msg/PointCloud2",
+                std::slice::from_ref(&point_cloud),
+                true
+            ),
+            [point_cloud]
+        );
+
+        let camera_info = CameraInfo {
+            k: vec![1.0; 9],
+            ..Default::default()
+        };
+        assert_eq!(
+            round_trip(
+                "sensor_msgs/msg/CameraInfo",
+                std::slice::from_ref(&camera_info),
+                false
+            ),
+            [camera_info]
+        );
+    }
+}
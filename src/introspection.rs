@@ -0,0 +1,291 @@
+use crate::flatten::flatten_fields;
+use arrow_schema::{DataType, Field};
+
+/// The size of an array field, as declared in the interface definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArraySize {
+    /// `type[]`
+    Unbounded,
+    /// `type[N]`
+    Fixed(usize),
+    /// `type[<=N]`
+    Bounded(usize),
+}
+
+/// The structure of a ROS type, as returned by [`crate::type_descriptor`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RosTypeDescriptor {
+    /// The schema name, for example `sensor_msgs/msg/LaserScan`.
+    pub schema_name: &'static str,
+    /// The fields, in the order of the message and of the regular layout.
+    pub fields: Vec<RosFieldDescriptor>,
+}
+
+/// A field of a ROS type.
+///
+/// The bounds are only known when r2a was built from the interface definitions, with
+/// `R2A_INTERFACE_SOURCE` or the `offline` feature. Built from the code r2r generated, every
+/// array is reported as [`ArraySize::Unbounded`] and strings have no bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RosFieldDescriptor {
+    /// The name of the field, which is also the name of its column in the regular layout.
+    pub name: &'static str,
+    /// The element type as written in the interface definition, for example `float32`,
+    /// `string` or `geometry_msgs/Point`.
+    pub ros_type: &'static str,
+    /// Set if the field is an array.
+    pub array: Option<ArraySize>,
+    /// The bound of a `string<=N` field, or of the strings of a `string<=N[]` field.
+    pub string_bound: Option<usize>,
+    /// The type of the field, or of its elements, if it is a ROS type.
+    pub nested: Option<Box<RosTypeDescriptor>>,
+    /// The field in the regular layout, like in `ArrowSupport::arrow_fields`.
+    pub arrow_field: Field,
+    /// The columns of the field in the flat layout, like in `ArrowSupport::flat_arrow_fields`.
+    pub flat_arrow_fields: Vec<Field>,
+}
+
+impl RosTypeDescriptor {
+    /// The fields of the regular layout, like `ArrowSupport::arrow_fields(false)`.
+    pub fn arrow_fields(&self) -> Vec<Field> {
+        self.fields
+            .iter()
+            .map(|field| field.arrow_field.clone())
+            .collect()
+    }
+
+    /// The fields of the flat layout, like `ArrowSupport::flat_arrow_fields(false)`.
+    pub fn flat_arrow_fields(&self) -> Vec<Field> {
+        self.fields
+            .iter()
+            .flat_map(|field| field.flat_arrow_fields.iter().cloned())
+            .collect()
+    }
+
+    /// The definition of the type in the `ros2msg` format, as stored in rosbag2 and MCAP files:
+    /// the fields of the type, followed by the definitions of the nested types, each after a
+    /// `MSG: <package>/<Type>` separator line. Constants and comments are not included, and the
+    /// array and string bounds only if they are known.
+    pub fn message_definition(&self) -> String {
+        let mut definition = self.field_definitions();
+        let mut nested_types: Vec<&RosTypeDescriptor> = vec![];
+        self.collect_nested_types(&mut nested_types);
+        for nested in nested_types {
+            definition.push_str(&"=".repeat(80));
+            definition.push_str(&format!("\nMSG: {}\n", short_type_name(nested.schema_name)));
+            definition.push_str(&nested.field_definitions());
+        }
+        definition
+    }
+
+    fn field_definitions(&self) -> String {
+        self.fields
+            .iter()
+            .map(|field| {
+                let mut line = match field.nested_type_name() {
+                    Some(nested) => short_type_name(nested),
+                    None => field.ros_type.to_string(),
+                };
+                if let Some(bound) = field.string_bound {
+                    line.push_str(&format!("<={}", bound));
+                }
+                match field.array {
+                    Some(ArraySize::Unbounded) => line.push_str("[]"),
+                    Some(ArraySize::Fixed(size)) => line.push_str(&format!("[{}]", size)),
+                    Some(ArraySize::Bounded(bound)) => line.push_str(&format!("[<={}]", bound)),
+                    None => {}
+                }
+                format!("{} {}\n", line, ros_field_name(field.name))
+            })
+            .collect()
+    }
+
+    /// Collects the nested types, depth first, each once.
+    fn collect_nested_types<'s>(&'s self, nested_types: &mut Vec<&'s RosTypeDescriptor>) {
+        for field in &self.fields {
+            if let Some(nested) = &field.nested {
+                if !nested_types
+                    .iter()
+                    .any(|known| known.schema_name == nested.schema_name)
+                {
+                    nested_types.push(nested);
+                    nested.collect_nested_types(nested_types);
+                }
+            }
+        }
+    }
+}
+
+/// `sensor_msgs/msg/LaserScan` is written as `sensor_msgs/LaserScan` in definitions.
+fn short_type_name(schema_name: &str) -> String {
+    schema_name.replacen("/msg/", "/", 1)
+}
+
+/// The name of a field in the definition. r2r appends an underscore to field names that are
+/// Rust keywords.
+pub(crate) fn ros_field_name(name: &str) -> &str {
+    match name.strip_suffix('_') {
+        Some(stripped) if crate::schema::RUST_KEYWORDS.contains(&stripped) => stripped,
+        _ => name,
+    }
+}
+
+impl RosFieldDescriptor {
+    /// The schema name of the nested type, for example `std_msgs/msg/Header`.
+    pub fn nested_type_name(&self) -> Option<&'static str> {
+        self.nested.as_ref().map(|nested| nested.schema_name)
+    }
+
+    /// The Arrow type of the field in the regular layout.
+    pub fn arrow_type(&self) -> &DataType {
+        self.arrow_field.data_type()
+    }
+}
+
+/// A ROS type in the table the build script generates.
+pub(crate) struct TypeEntry {
+    pub schema_name: &'static str,
+    pub arrow_fields: fn(bool) -> Vec<Field>,
+    pub fields: &'static [FieldEntry],
+}
+
+/// A field of a [`TypeEntry`].
+pub(crate) struct FieldEntry {
+    pub name: &'static str,
+    pub ros_type: &'static str,
+    pub array: Option<ArraySize>,
+    pub string_bound: Option<usize>,
+    pub nested_type: Option<&'static str>,
+}
+
+/// Builds the descriptor of `schema_name` from `table`, which is sorted by schema name.
+pub(crate) fn type_descriptor(
+    table: &'static [TypeEntry],
+    schema_name: &str,
+) -> Option<RosTypeDescriptor> {
+    let index = table
+        .binary_search_by_key(&schema_name, |entry| entry.schema_name)
+        .ok()?;
+    let entry = &table[index];
+    let arrow_fields = (entry.arrow_fields)(false);
+
+    let fields = entry
+        .fields
+        .iter()
+        .zip(arrow_fields)
+        .map(|(field, arrow_field)| RosFieldDescriptor {
+            name: field.name,
+            ros_type: field.ros_type,
+            array: field.array,
+            string_bound: field.string_bound,
+            nested: field
+                .nested_type
+                .and_then(|nested_type| type_descriptor(table, nested_type))
+                .map(Box::new),
+            flat_arrow_fields: flatten_fields(std::slice::from_ref(&arrow_field)),
+            arrow_field,
+        })
+        .collect();
+
+    Some(RosTypeDescriptor {
+        schema_name: entry.schema_name,
+        fields,
+    })
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::ArraySize;
+    use crate::msgs::sensor_msgs::msg::{CameraInfo, LaserScan};
+    use crate::ArrowSupport;
+    use arrow_schema::DataType;
+
+    #[test]
+    fn test_type_descriptor() {
+        let scan = crate::type_descriptor("sensor_msgs/msg/LaserScan").unwrap();
+        assert_eq!(scan.schema_name, "sensor_msgs/msg/LaserScan");
+        assert_eq!(scan.arrow_fields(), LaserScan::arrow_fields(false));
+        assert_eq!(
+            scan.flat_arrow_fields(),
+            LaserScan::flat_arrow_fields(false)
+        );
+
+        let header = &scan.fields[0];
+        assert_eq!(header.name, "header");
+        assert_eq!(header.nested_type_name(), Some("std_msgs/msg/Header"));
+        let header_fields: Vec<&str> = header
+            .flat_arrow_fields
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(
+            header_fields,
+            [
+                "header_stamp_sec",
+                "header_stamp_nanosec",
+                "header_frame_id"
+            ]
+        );
+        let stamp = &header.nested.as_ref().unwrap().fields[0];
+        assert_eq!(
+            stamp.nested_type_name(),
+            Some("builtin_interfaces/msg/Time")
+        );
+
+        let ranges = scan
+            .fields
+            .iter()
+            .find(|field| field.name == "ranges")
+            .unwrap();
+        assert_eq!(ranges.ros_type, "float32");
+        assert_eq!(ranges.array, Some(ArraySize::Unbounded));
+        assert!(matches!(ranges.arrow_type(), DataType::LargeList(_)));
+
+        let camera_info = crate::type_descriptor("sensor_msgs/msg/CameraInfo").unwrap();
+        assert_eq!(camera_info.arrow_fields(), CameraInfo::arrow_fields(false));
+        let k = camera_info
+            .fields
+            .iter()
+            .find(|field| field.name == "k")
+            .unwrap();
+        assert_eq!(k.ros_type, "float64");
+        assert_eq!(k.array, Some(ArraySize::Fixed(9)));
+        assert_eq!(k.nested_type_name(), None);
+
+        assert!(crate::type_descriptor("unknown_msgs/msg/Unknown").is_none());
+    }
+
+    #[test]
+    fn test_message_definition() {
+        let scan = crate::type_descriptor("sensor_msgs/msg/LaserScan").unwrap();
+        let separator = "=".repeat(80);
+        assert_eq!(
+            scan.message_definition(),
+            format!(
+                "std_msgs/Header header\n\
+                 float32 angle_min\n\
+                 float32 angle_max\n\
+                 float32 angle_increment\n\
+                 float32 time_increment\n\
+                 float32 scan_time\n\
+                 float32 range_min\n\
+                 float32 range_max\n\
+                 float32[] ranges\n\
+                 float32[] intensities\n\
+                 {separator}\n\
+                 MSG: std_msgs/Header\n\
+                 builtin_interfaces/Time stamp\n\
+                 string frame_id\n\
+                 {separator}\n\
+                 MSG: builtin_interfaces/Time\n\
+                 int32 sec\n\
+                 uint32 nanosec\n"
+            )
+        );
+
+        let camera_info = crate::type_descriptor("sensor_msgs/msg/CameraInfo").unwrap();
+        assert!(camera_info
+            .message_definition()
+            .contains("\nfloat64[9] k\n"));
+    }
+}
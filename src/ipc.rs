@@ -0,0 +1,653 @@
+//! Writes and reads Arrow IPC files and streams. Requires the `ipc` feature.
+//!
+//! An [`IpcSink`] writes the batches of one topic in the IPC file format, which has a footer
+//! for random access, or in the IPC stream format, which can go through a pipe or a socket. The
+//! schema of both holds the [`crate::file_metadata`] of the topic next to the metadata of its
+//! fields.
+//!
+//! A stream file opened with [`IpcSink::append`] is flushed after every batch and can be
+//! appended to by later runs. If the writing process dies, the file keeps every complete batch:
+//! [`IpcReader`] stops at the last complete batch, and the next [`IpcSink::append`] cuts the
+//! partial one off before it appends.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::ipc::{IpcReader, IpcSink};
+//! use r2a::ArrowSupport;
+//! use r2a::RowBuilder;
+//!
+//! # fn headers() -> Vec<r2a::msgs::std_msgs::msg::Header> { vec![] }
+//! use r2a::msgs::std_msgs::msg::Header;
+//!
+//! let fields = Header::arrow_fields(false);
+//! let mut row_builder = Header::new_row_builder(fields.iter().collect());
+//! let mut sink = IpcSink::append(
+//!     "header.arrows",
+//!     "/header",
+//!     "std_msgs/msg/Header",
+//!     arrow_schema::Schema::new(fields.clone()),
+//! )
+//! .unwrap();
+//! for header in headers() {
+//!     row_builder.add_row(&header).unwrap();
+//! }
+//! sink.write_rows(&mut row_builder).unwrap();
+//! sink.finish().unwrap();
+//!
+//! let reader = IpcReader::open("header.arrows").unwrap();
+//! assert_eq!(reader.schema_name(), Some("std_msgs/msg/Header"));
+//! for batch in reader {
+//!     println!("{} rows", batch.unwrap().num_rows());
+//! }
+//! ```
+
+use crate::{ArrowSupport, RowBuilder, SCHEMA_NAME_METADATA_KEY, TOPIC_METADATA_KEY};
+use anyhow::{bail, Context, Result};
+use arrow_array::RecordBatch;
+use arrow_ipc::reader::{FileReader, StreamReader};
+use arrow_ipc::writer::{
+    write_message, DictionaryTracker, FileWriter, IpcDataGenerator, IpcWriteOptions,
+};
+use arrow_schema::{ArrowError, Schema, SchemaRef};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The magic bytes at the start of an IPC file, padded to the alignment of the writer.
+const FILE_MAGIC: &[u8; 6] = b"ARROW1";
+
+/// The alignments an IPC writer can pad to.
+const ALIGNMENTS: [usize; 4] = [8, 16, 32, 64];
+
+/// The continuation marker in front of every message of the stream format.
+const CONTINUATION_MARKER: [u8; 4] = [0xff; 4];
+
+/// The end of stream marker: a continuation marker and a zero message length.
+const END_OF_STREAM: [u8; 8] = [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+
+/// The IPC format of a file or stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcFormat {
+    /// The IPC file format, with a footer that indexes the batches.
+    File,
+    /// The IPC stream format, readable up to the last complete batch at any time.
+    Stream,
+}
+
+/// Writes the encapsulated messages of the stream format. Unlike `StreamWriter`, it can
+/// continue a stream whose schema was written by an earlier writer.
+struct StreamEncoder<W: Write> {
+    writer: W,
+    generator: IpcDataGenerator,
+    dictionary_tracker: DictionaryTracker,
+    options: IpcWriteOptions,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    fn new(writer: W, schema: &Schema, write_schema: bool) -> Result<Self> {
+        let mut encoder = StreamEncoder {
+            writer,
+            generator: IpcDataGenerator::default(),
+            dictionary_tracker: DictionaryTracker::new(false),
+            options: IpcWriteOptions::default(),
+        };
+        let encoded = encoder.generator.schema_to_bytes_with_dictionary_tracker(
+            schema,
+            &mut encoder.dictionary_tracker,
+            &encoder.options,
+        );
+        if write_schema {
+            write_message(&mut encoder.writer, encoded, &encoder.options)?;
+        }
+        Ok(encoder)
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let (dictionaries, message) =
+            self.generator
+                .encoded_batch(batch, &mut self.dictionary_tracker, &self.options)?;
+        for dictionary in dictionaries {
+            write_message(&mut self.writer, dictionary, &self.options)?;
+        }
+        write_message(&mut self.writer, message, &self.options)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<W> {
+        self.writer.write_all(&END_OF_STREAM)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+enum IpcWriter<W: Write> {
+    File(FileWriter<W>),
+    Stream(StreamEncoder<W>),
+}
+
+/// Writes the batches of one topic as an Arrow IPC file or stream.
+pub struct IpcSink<W: Write = BufWriter<File>> {
+    topic: String,
+    schema_name: String,
+    schema: SchemaRef,
+    writer: Option<IpcWriter<W>>,
+    rows: usize,
+}
+
+impl IpcSink<BufWriter<File>> {
+    /// Creates or replaces the file at `path` to write the messages of `schema_name` on
+    /// `topic`, with the Arrow `schema` of the row builder.
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: IpcFormat,
+        topic: &str,
+        schema_name: &str,
+        schema: impl Into<SchemaRef>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+        IpcSink::new(BufWriter::new(file), format, topic, schema_name, schema)
+    }
+
+    /// Opens the stream file at `path` to append to it, or creates it. The topic, the schema
+    /// name and the fields of an existing file must match. A partial batch at the end of the
+    /// file, left by a writer that did not finish, is removed first.
+    pub fn append(
+        path: impl AsRef<Path>,
+        topic: &str,
+        schema_name: &str,
+        schema: impl Into<SchemaRef>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        let existing = complete_stream(&mut file)
+            .with_context(|| format!("Unable to read the stream in {}", path.display()))?;
+        let Some((existing_schema, len)) = existing else {
+            file.set_len(0)?;
+            return IpcSink::new(
+                BufWriter::new(file),
+                IpcFormat::Stream,
+                topic,
+                schema_name,
+                schema,
+            );
+        };
+
+        let schema = with_file_metadata(schema.into(), topic, schema_name);
+        let metadata = existing_schema.metadata();
+        if existing_schema.fields() != schema.fields()
+            || metadata.get(TOPIC_METADATA_KEY).map(String::as_str) != Some(topic)
+            || metadata.get(SCHEMA_NAME_METADATA_KEY).map(String::as_str) != Some(schema_name)
+        {
+            bail!(
+                "{} holds a different topic or schema than {} of {}",
+                path.display(),
+                topic,
+                schema_name
+            );
+        }
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+        let encoder = StreamEncoder::new(BufWriter::new(file), &schema, false)?;
+        Ok(IpcSink {
+            topic: topic.to_string(),
+            schema_name: schema_name.to_string(),
+            // Keep the metadata of the existing file, including the version that created it.
+            schema: Arc::new(existing_schema),
+            writer: Some(IpcWriter::Stream(encoder)),
+            rows: 0,
+        })
+    }
+}
+
+impl<W: Write> IpcSink<W> {
+    /// Starts writing the messages of `schema_name` on `topic` to `writer`, with the Arrow
+    /// `schema` of the row builder.
+    pub fn new(
+        writer: W,
+        format: IpcFormat,
+        topic: &str,
+        schema_name: &str,
+        schema: impl Into<SchemaRef>,
+    ) -> Result<Self> {
+        let schema = with_file_metadata(schema.into(), topic, schema_name);
+        let writer = match format {
+            IpcFormat::File => IpcWriter::File(FileWriter::try_new(writer, &schema)?),
+            IpcFormat::Stream => IpcWriter::Stream(StreamEncoder::new(writer, &schema, true)?),
+        };
+        Ok(IpcSink {
+            topic: topic.to_string(),
+            schema_name: schema_name.to_string(),
+            schema,
+            writer: Some(writer),
+            rows: 0,
+        })
+    }
+
+    /// The schema written to the file, with the r2a metadata.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The number of rows written by this sink.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Writes a batch, whose fields must be the fields of the sink, and flushes the writer.
+    pub fn write_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        if batch.schema().fields() != self.schema.fields() {
+            bail!(
+                "The batch has different fields than the {} messages of {}",
+                self.schema_name,
+                self.topic
+            );
+        }
+        let batch = batch.clone().with_schema(self.schema.clone())?;
+        match self.writer.as_mut() {
+            Some(IpcWriter::File(writer)) => {
+                writer.write(&batch)?;
+                writer.flush()?;
+            }
+            Some(IpcWriter::Stream(encoder)) => encoder.write(&batch)?,
+            None => bail!("The sink of {} is finished", self.topic),
+        }
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    /// Writes the rows accumulated by the row builder and clears the row builder. Does nothing
+    /// if the row builder is empty.
+    pub fn write_rows<'a, T: ArrowSupport<'a>>(
+        &mut self,
+        row_builder: &mut impl RowBuilder<'a, T>,
+    ) -> Result<()> {
+        if T::schema_name() != self.schema_name {
+            bail!(
+                "The sink of {} writes {} messages, not {}",
+                self.topic,
+                self.schema_name,
+                T::schema_name()
+            );
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), row_builder.to_arc_arrays())?;
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        self.write_batch(&batch)
+    }
+
+    fn finish_writer(&mut self) -> Result<Option<W>> {
+        let writer = match self.writer.take() {
+            Some(IpcWriter::File(mut writer)) => {
+                writer.finish()?;
+                writer.into_inner()?
+            }
+            Some(IpcWriter::Stream(encoder)) => encoder.finish()?,
+            None => return Ok(None),
+        };
+        Ok(Some(writer))
+    }
+
+    /// Writes the footer of a file or the end of a stream, and returns the writer.
+    pub fn finish(mut self) -> Result<W> {
+        Ok(self
+            .finish_writer()?
+            .expect("The writer is only taken here or on drop"))
+    }
+}
+
+impl<W: Write> Drop for IpcSink<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_writer() {
+            log::error!("Unable to finish the IPC output of {}: {}", self.topic, e);
+        }
+    }
+}
+
+/// Adds the metadata of the topic to the metadata of `schema`.
+fn with_file_metadata(schema: SchemaRef, topic: &str, schema_name: &str) -> SchemaRef {
+    let mut metadata = schema.metadata().clone();
+    metadata.extend(crate::file_metadata(topic, schema_name));
+    Arc::new(Schema::new_with_metadata(schema.fields().clone(), metadata))
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+    reader: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// Whether reading a stream failed because it ends in the middle of a message.
+fn is_truncated(error: &ArrowError) -> bool {
+    matches!(error, ArrowError::IoError(_, e) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+/// Returns the schema of the stream in `file` and the length of its schema and complete
+/// batches, without the end of stream marker. Returns `None` if the file does not even hold a
+/// complete schema.
+fn complete_stream(file: &mut File) -> Result<Option<(Schema, u64)>> {
+    file.seek(SeekFrom::Start(0))?;
+    // The stream reader does not buffer, so the count is the end of the last message read.
+    let counter = CountingReader {
+        reader: &mut *file,
+        count: 0,
+    };
+    let mut reader = match StreamReader::try_new(counter, None) {
+        Ok(reader) => reader,
+        Err(e) if is_truncated(&e) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut len = reader.get_ref().count;
+    loop {
+        match reader.next() {
+            Some(Ok(_)) => len = reader.get_ref().count,
+            Some(Err(e)) if is_truncated(&e) => break,
+            Some(Err(e)) => return Err(e.into()),
+            None => break,
+        }
+    }
+    Ok(Some((reader.schema().as_ref().clone(), len)))
+}
+
+enum IpcBatches {
+    File(FileReader<BufReader<File>>),
+    Stream(StreamReader<BufReader<Box<dyn Read + Send>>>),
+    Finished,
+}
+
+/// Reads an Arrow IPC file or stream written by [`IpcSink`], or by any other Arrow writer.
+pub struct IpcReader {
+    format: IpcFormat,
+    schema: SchemaRef,
+    batches: IpcBatches,
+}
+
+impl IpcReader {
+    /// Opens an IPC file or stream file. A stream that ends in the middle of a batch is read up
+    /// to the last complete batch, and so is a file without a footer.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file =
+            File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        let mut magic = [0; 6];
+        let is_file = file.read_exact(&mut magic).is_ok() && &magic == FILE_MAGIC;
+        file.seek(SeekFrom::Start(0))?;
+        if !is_file {
+            return IpcReader::from_stream(file)
+                .with_context(|| format!("Unable to read {}", path.display()));
+        }
+
+        match FileReader::try_new_buffered(file, None) {
+            Ok(reader) => Ok(IpcReader {
+                format: IpcFormat::File,
+                schema: reader.schema(),
+                batches: IpcBatches::File(reader),
+            }),
+            Err(e) => {
+                // The messages of a file follow the magic like a stream, only the footer is
+                // missing if the writer did not finish.
+                log::warn!(
+                    "Unable to read the footer of {}, reading it as a stream: {}",
+                    path.display(),
+                    e
+                );
+                let mut file = File::open(path)?;
+                let mut header = vec![];
+                (&mut file).take(68).read_to_end(&mut header)?;
+                let start = ALIGNMENTS
+                    .into_iter()
+                    .find(|&start| header.get(start..start + 4) == Some(&CONTINUATION_MARKER))
+                    .unwrap_or(ALIGNMENTS[0]);
+                file.seek(SeekFrom::Start(start as u64))?;
+                let mut reader = IpcReader::from_stream(file)
+                    .with_context(|| format!("Unable to read {}", path.display()))?;
+                reader.format = IpcFormat::File;
+                Ok(reader)
+            }
+        }
+    }
+
+    /// Reads an IPC stream, for example the standard output of another process.
+    pub fn from_stream(reader: impl Read + Send + 'static) -> Result<Self> {
+        let reader = StreamReader::try_new_buffered(Box::new(reader) as Box<dyn Read + Send>, None)
+            .context("Unable to read the schema of the stream")?;
+        Ok(IpcReader {
+            format: IpcFormat::Stream,
+            schema: reader.schema(),
+            batches: IpcBatches::Stream(reader),
+        })
+    }
+
+    /// The format of the file.
+    pub fn format(&self) -> IpcFormat {
+        self.format
+    }
+
+    /// The schema of the batches, with the metadata of the file.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The metadata of the schema, with the [`crate::file_metadata`] of files written by r2a.
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        self.schema.metadata()
+    }
+
+    /// The ROS schema name of the messages, for example `sensor_msgs/msg/LaserScan`, if the
+    /// file was written by r2a.
+    pub fn schema_name(&self) -> Option<&str> {
+        self.metadata()
+            .get(SCHEMA_NAME_METADATA_KEY)
+            .map(String::as_str)
+    }
+
+    /// The topic of the messages, if the file was written by r2a.
+    pub fn topic(&self) -> Option<&str> {
+        self.metadata().get(TOPIC_METADATA_KEY).map(String::as_str)
+    }
+}
+
+impl Iterator for IpcReader {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match &mut self.batches {
+            IpcBatches::File(reader) => reader.next(),
+            IpcBatches::Stream(reader) => match reader.next() {
+                Some(Err(e)) if is_truncated(&e) => {
+                    log::warn!("The IPC stream ends with a partial batch: {}", e);
+                    None
+                }
+                next => next,
+            },
+            IpcBatches::Finished => None,
+        };
+        match next {
+            Some(Ok(batch)) => Some(Ok(batch)),
+            Some(Err(e)) => {
+                self.batches = IpcBatches::Finished;
+                Some(Err(e.into()))
+            }
+            None => {
+                self.batches = IpcBatches::Finished;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::VERSION_METADATA_KEY;
+    use arrow_array::{Array, StringArray};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("r2a_ipc_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn frame_ids(reader: IpcReader) -> Vec<String> {
+        reader
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                let frame_ids = batch
+                    .column_by_name("frame_id")
+                    .unwrap()
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap()
+                    .clone();
+                (0..frame_ids.len())
+                    .map(|i| frame_ids.value(i).to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn write_headers<'a, W: Write>(
+        sink: &mut IpcSink<W>,
+        row_builder: &mut impl RowBuilder<'a, Header>,
+        frame_ids: &[&str],
+    ) {
+        for frame_id in frame_ids {
+            let header = Header {
+                frame_id: frame_id.to_string(),
+                ..Default::default()
+            };
+            row_builder.add_row(&header).unwrap();
+            sink.write_rows(row_builder).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_file_and_stream() {
+        let fields = Header::flat_arrow_fields(false);
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        for format in [IpcFormat::File, IpcFormat::Stream] {
+            let path = temp_path(&format!("{:?}", format));
+            let mut sink = IpcSink::create(
+                &path,
+                format,
+                "/header",
+                "std_msgs/msg/Header",
+                Schema::new(fields.clone()),
+            )
+            .unwrap();
+            write_headers(&mut sink, &mut row_builder, &["a", "b", "c"]);
+            assert_eq!(sink.rows(), 3);
+            sink.finish().unwrap();
+
+            let reader = IpcReader::open(&path).unwrap();
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.topic(), Some("/header"));
+            assert_eq!(reader.schema_name(), Some("std_msgs/msg/Header"));
+            assert!(reader.metadata().contains_key(VERSION_METADATA_KEY));
+            assert_eq!(frame_ids(reader), ["a", "b", "c"]);
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        // Streams work over any writer, and the batches must match the schema of the sink.
+        let mut sink = IpcSink::new(
+            vec![],
+            IpcFormat::Stream,
+            "/header",
+            "std_msgs/msg/Header",
+            Schema::new(fields.clone()),
+        )
+        .unwrap();
+        write_headers(&mut sink, &mut row_builder, &["d"]);
+        let other = Schema::new(Header::arrow_fields(false));
+        assert!(sink
+            .write_batch(&RecordBatch::new_empty(other.into()))
+            .is_err());
+        let stream = sink.finish().unwrap();
+        let reader = IpcReader::from_stream(std::io::Cursor::new(stream)).unwrap();
+        assert_eq!(frame_ids(reader), ["d"]);
+    }
+
+    #[test]
+    fn test_append_after_crash() {
+        let path = temp_path("append");
+        let fields = Header::flat_arrow_fields(false);
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        let schema = Schema::new(fields.clone());
+
+        let mut sink =
+            IpcSink::append(&path, "/header", "std_msgs/msg/Header", schema.clone()).unwrap();
+        write_headers(&mut sink, &mut row_builder, &["a", "b"]);
+        // A crash before the end of the stream and in the middle of the next batch.
+        std::mem::forget(sink);
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xff, 0xff, 0xff, 0xff, 0x40, 0, 0, 0, 1, 2, 3])
+            .unwrap();
+        drop(file);
+        assert_eq!(frame_ids(IpcReader::open(&path).unwrap()), ["a", "b"]);
+
+        let mut sink =
+            IpcSink::append(&path, "/header", "std_msgs/msg/Header", schema.clone()).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        write_headers(&mut sink, &mut row_builder, &["c"]);
+        sink.finish().unwrap();
+
+        // The end of stream marker of a finished stream is removed too.
+        let mut sink =
+            IpcSink::append(&path, "/header", "std_msgs/msg/Header", schema.clone()).unwrap();
+        write_headers(&mut sink, &mut row_builder, &["d"]);
+        drop(sink);
+        assert_eq!(
+            frame_ids(IpcReader::open(&path).unwrap()),
+            ["a", "b", "c", "d"]
+        );
+
+        assert!(IpcSink::append(&path, "/other", "std_msgs/msg/Header", schema).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unfinished_file() {
+        let path = temp_path("unfinished");
+        let fields = Header::flat_arrow_fields(false);
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        let mut sink = IpcSink::create(
+            &path,
+            IpcFormat::File,
+            "/header",
+            "std_msgs/msg/Header",
+            Schema::new(fields.clone()),
+        )
+        .unwrap();
+        write_headers(&mut sink, &mut row_builder, &["a", "b"]);
+        std::mem::forget(sink);
+
+        let reader = IpcReader::open(&path).unwrap();
+        assert_eq!(reader.format(), IpcFormat::File);
+        assert_eq!(reader.topic(), Some("/header"));
+        assert_eq!(frame_ids(reader), ["a", "b"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -27,7 +27,25 @@
 //! - Convert ROS schema to Arrow fields.
 //! - Support for 1-1 match and flat Arrow fields mapping. 1-1 match follows the exact structure of the original ROS message, while flat is a "more tabular" format.
 //! - A row builder for storing converted rows.
+//! - Optional per-row context columns (receive time, topic, sequence number, publisher GID) via `add_row_with_context`.
+//! - The `message_struct` column can hold the whole message as a nested struct, a flat struct or a JSON document, under any name, with or without the top-level columns.
+//! - Predicate filtering, decimation and rate limiting of rows with `RowFilter`.
 //! - All ROS message schemas are supported as long as they are properly sourced.
+//! - `type_descriptor` describes the structure of a supported schema at run time, with the ROS types, array sizes, nested types and Arrow types of its fields.
+//! - Service requests and responses are supported too, under the schema names `<pkg>/srv/<Service>_Request` and `<pkg>/srv/<Service>_Response`.
+//! - Service traffic can be logged to an audit table with `ServiceCallRowBuilder` and `ServiceCallLogger`, with the request and response of each call in one row.
+//! - The generated code is split into one module per ROS package that mirrors the r2r modules, so the schema functions, row builders and struct builders of a type can also be used directly, for example `r2a::sensor_msgs::msg::LaserScan_Schema`, `LaserScan_RowBuilder` and `LaserScan_StructBuilder`.
+//! - User-defined structs get the same mapping with `#[derive(ArrowSupport)]` (`derive` feature). Their fields can be r2r messages too.
+//! - Without a ROS installation, the `offline` feature (with `default-features = false`) generates pure Rust message types, their CDR serialization and the Arrow mappers for `builtin_interfaces`, `std_msgs`, `geometry_msgs`, `sensor_msgs`, `nav_msgs` and `tf2_msgs` from bundled interface definitions. The types are in `r2a::msgs`, which mirrors the root of `r2r`, for example `r2a::msgs::sensor_msgs::msg::LaserScan`.
+//! - The `descriptor-builders` feature replaces the generated row builders and struct builders with static type descriptors and a single generic row builder, which cuts compile times and binary size. The `ArrowSupport` API and the Arrow layouts stay the same.
+//! - Action types are supported under `<pkg>/action/<Action>_Goal`, `_Result`, `_Feedback`, `_FeedbackMessage`, `_SendGoal_Request`, `_SendGoal_Response`, `_GetResult_Request` and `_GetResult_Response`. Goal status streams use the regular `action_msgs/msg/GoalStatusArray` message.
+//! - Recorded data can be converted without knowing the message types at compile time: `new_raw_row_builder` creates the row builder of a schema name for serialized messages, and the `rosbag2` feature reads rosbag2 SQLite3 bags (`.db3`) into per-topic `RecordBatch`es with `r2a::rosbag2::Rosbag2Reader`, and the `mcap` feature reads MCAP files with `r2a::mcap::McapReader`, with zstd and lz4 chunks and time range seeking. `r2a::mcap::McapWriter` writes batches back to MCAP, serialized with `raw_messages_from_batch`. ROS 1 bags are read with `r2a::rosbag1::Rosbag1Reader` (`rosbag1` feature) into the layouts of the equivalent ROS 2 types. The readers filter topics by bag timestamp with `topic_batches_in_range`, and `with_projection` only builds the requested columns.
+//! - The `parquet` feature writes topics to rolling Parquet files with `r2a::parquet::ParquetSink`, with the schema name and topic in the file metadata.
+//! - The `dataset` feature writes topics to a Hive-partitioned Parquet dataset, `topic=<name>/date=YYYY-MM-DD/hour=HH/part-N.parquet` by default, with `r2a::dataset::DatasetWriter`. Rows are partitioned by header stamp or receive time, and a `_manifest.json` describes the ROS schema of every topic.
+//! - The `delta` feature commits topics to local Delta Lake tables with `r2a::delta::DeltaSink`, one table per topic, without a service. Added message fields evolve the table schema, and the ROS type name is a table property.
+//! - The `ipc` feature writes topics to Arrow IPC files and streams with `r2a::ipc::IpcSink`, including stream files that can be appended to and keep every complete batch when the writer crashes, and reads them back with their schema name and topic with `r2a::ipc::IpcReader`.
+//! - The `export` feature writes flat batches to CSV or newline-delimited JSON with `r2a::export::Exporter`. List columns are written as JSON arrays or exploded into one row per element, binary columns as base64 or hex, and the message struct column is dropped or written as JSON.
+//! - The `datafusion` feature queries the topics of rosbag2 bags, MCAP files and ROS 1 bags with SQL, one table per topic, with `r2a::datafusion::register_topics`. Only the queried columns are decoded, and `_recv_time` filters only read the matching time range.
 //!
 //! ## Example
 //! ```rust
@@ -68,16 +86,125 @@
 //!
 //!
 
+// Lets the code generated by `#[derive(ArrowSupport)]`, which refers to `::r2a`, compile in
+// this crate too.
+extern crate self as r2a;
+
+#[cfg(all(feature = "default", feature = "offline"))]
+compile_error!("The offline feature replaces r2r, build it with default-features = false");
+
+#[cfg(any(feature = "mcap", feature = "dataset"))]
+mod batch_times;
+#[cfg(any(feature = "offline", feature = "rosbag1"))]
+pub mod cdr;
+#[cfg(all(
+    feature = "datafusion",
+    any(feature = "mcap", feature = "rosbag1", feature = "rosbag2")
+))]
+pub mod datafusion;
+#[cfg(feature = "dataset")]
+pub mod dataset;
+#[cfg(feature = "delta")]
+pub mod delta;
+#[cfg(feature = "descriptor-builders")]
+pub mod descriptor;
+#[cfg(feature = "export")]
+pub mod export;
+mod file_metadata;
+mod flatten;
+#[cfg(any(feature = "default", feature = "offline"))]
+mod from_arrow;
+mod introspection;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+#[cfg(feature = "mcap")]
+pub mod mcap;
+mod message_struct;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(any(feature = "mcap", feature = "rosbag1", feature = "rosbag2"))]
+mod projection;
+#[cfg(any(feature = "default", feature = "offline"))]
+mod raw_row_builder;
 mod ros_mapper;
+#[cfg(feature = "rosbag1")]
+pub mod rosbag1;
+#[cfg(feature = "rosbag2")]
+pub mod rosbag2;
+mod row_context;
+mod row_filter;
 mod schema;
+#[cfg(feature = "default")]
+mod service_log;
+mod struct_row_builder;
+#[cfg(any(feature = "dataset", feature = "export"))]
+mod utc_time;
 
+pub use file_metadata::{
+    file_metadata, SCHEMA_NAME_METADATA_KEY, TOPIC_METADATA_KEY, VERSION_METADATA_KEY,
+};
+#[cfg(any(feature = "default", feature = "offline"))]
+pub use from_arrow::raw_messages_from_batch;
+pub use introspection::{ArraySize, RosFieldDescriptor, RosTypeDescriptor};
+pub use message_struct::message_struct_to_json;
+pub use message_struct::MessageStructEncoding;
+pub use message_struct::{MESSAGE_STRUCT_ENCODING_KEY, MESSAGE_STRUCT_FIELD};
+#[cfg(any(feature = "default", feature = "offline"))]
+pub use raw_row_builder::{new_raw_row_builder, schema_arrow_fields, RawRowBuilder};
 pub use ros_mapper::ArrowSupport;
 pub use ros_mapper::RowBuilder;
+pub use row_context::row_context_fields;
+pub use row_context::RowContext;
+pub use row_context::{PUBLISHER_GID_FIELD, RECV_TIME_FIELD, SEQ_FIELD, TOPIC_FIELD};
+pub use row_filter::RowFilter;
+pub use row_filter::RowFilterStats;
+#[cfg(feature = "default")]
+pub use service_log::{ServiceCallLogger, ServiceCallRowBuilder};
+pub use struct_row_builder::StructRowBuilder;
+
+/// Derives `ArrowSupport` for user-defined structs. Requires the `derive` feature. See the
+/// [r2a-derive](https://docs.rs/r2a-derive) documentation for the supported fields and
+/// attributes.
+#[cfg(feature = "derive")]
+pub use r2a_derive::ArrowSupport;
+
+/// Items used by the code generated by `#[derive(ArrowSupport)]`. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::flatten::flatten_fields;
+    pub use arrow_array;
+    pub use arrow_schema;
+}
+
+// One public module per ROS package, mirroring the r2r modules, for example
+// `r2a::sensor_msgs::msg::LaserScan_RowBuilder`.
+#[cfg(any(feature = "default", feature = "offline"))]
+include!(concat!(env!("OUT_DIR"), "/generated_packages.rs"));
+
+/// The message types generated by the `offline` feature, mirroring the root of `r2r`, for
+/// example `r2a::msgs::sensor_msgs::msg::LaserScan`. They implement [`cdr::CdrMessage`].
+#[cfg(feature = "offline")]
+pub mod msgs {
+    include!(concat!(env!("OUT_DIR"), "/generated_msgs.rs"));
+}
 
 /// Returns an array of supported ROS message schemas. The list is automatically generated in compilation time.
 pub fn get_supported_schemas() -> &'static [&'static str] {
     schema::SUPPORTED_SCHEMAS
 }
 
+/// Returns the structure of a supported ROS type: its fields with their ROS types, array sizes,
+/// nested types and Arrow types in both layouts, or `None` if the schema is not supported.
+///
+/// # Example
+/// ```
+/// let scan = r2a::type_descriptor("sensor_msgs/msg/LaserScan").unwrap();
+/// let header = &scan.fields[0];
+/// assert_eq!(header.nested_type_name(), Some("std_msgs/msg/Header"));
+/// ```
+pub fn type_descriptor(schema_name: &str) -> Option<RosTypeDescriptor> {
+    introspection::type_descriptor(schema::TYPE_TABLE, schema_name)
+}
+
 #[cfg(test)]
 mod tests {}
@@ -0,0 +1,38 @@
+//! Reads MCAP files, the default storage of rosbag2 since Jazzy, into Arrow. Requires the `mcap`
+//! feature.
+//!
+//! Channels with `cdr` message encoding, as written by rosbag2 with `ros2msg` schemas, are read
+//! with the row builder of their schema name. Each topic is returned as a stream of
+//! `RecordBatch`es with the context columns of [`crate::row_context_fields`], where `_recv_time`
+//! holds the log time of the message, followed by a [`PUBLISH_TIME_FIELD`] column with its
+//! publish time.
+//!
+//! Chunks can be uncompressed or compressed with zstd or lz4. When the file has a summary, only
+//! the chunks that overlap the requested time range and hold messages of the topic are read.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::mcap::McapReader;
+//!
+//! let reader = McapReader::open("recordings/run_42/run_42_0.mcap").unwrap();
+//! // The first ten seconds of the recording.
+//! let start = reader.message_start_time().unwrap_or(0);
+//! for batch in reader
+//!     .topic_batches_in_range("/scan", true, start..start + 10_000_000_000)
+//!     .unwrap()
+//! {
+//!     println!("{} rows", batch.unwrap().num_rows());
+//! }
+//! ```
+
+mod reader;
+mod records;
+mod writer;
+
+pub use reader::{McapChannel, McapReader, McapTopicBatches};
+pub use writer::{McapCompression, McapWriter, DEFAULT_CHUNK_SIZE};
+
+/// Name of the column holding the publish time of the message, as nanoseconds since the UNIX
+/// epoch.
+pub const PUBLISH_TIME_FIELD: &str = "_publish_time";
@@ -0,0 +1,939 @@
+use super::records::{
+    ChannelRecord, ChunkIndexRecord, ChunkRecord, FooterRecord, MessageRecord, RecordReader,
+    SchemaRecord, FOOTER_RECORD_LEN, MAGIC, OP_CHANNEL, OP_CHUNK, OP_CHUNK_INDEX, OP_DATA_END,
+    OP_FOOTER, OP_MESSAGE, OP_SCHEMA,
+};
+use super::PUBLISH_TIME_FIELD;
+use crate::projection::Projection;
+use crate::{new_raw_row_builder, row_context_fields, schema_arrow_fields, RowContext};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::builder::TimestampNanosecondBuilder;
+use arrow_array::RecordBatch;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The number of rows of the batches returned by [`McapReader::topic_batches`], unless set with
+/// [`McapReader::with_batch_size`].
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// A channel of an MCAP file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct McapChannel {
+    pub id: u16,
+    pub topic: String,
+    /// The schema name, for example `sensor_msgs/msg/LaserScan`.
+    pub schema_name: String,
+    /// The encoding of the schema, `ros2msg` or `ros2idl` for rosbag2 files.
+    pub schema_encoding: String,
+    /// The encoding of the messages, `cdr` for rosbag2 files.
+    pub message_encoding: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// A part of the data section that holds messages.
+#[derive(Debug, Clone)]
+enum DataBlock {
+    /// A chunk record. `channels` is `None` if the channels in the chunk are not known.
+    Chunk {
+        offset: u64,
+        length: u64,
+        message_start_time: u64,
+        message_end_time: u64,
+        channels: Option<BTreeSet<u16>>,
+    },
+    /// A message record outside of chunks.
+    Message {
+        offset: u64,
+        length: u64,
+        channel_id: u16,
+        log_time: u64,
+    },
+}
+
+impl DataBlock {
+    fn start_time(&self) -> u64 {
+        match self {
+            DataBlock::Chunk {
+                message_start_time, ..
+            } => *message_start_time,
+            DataBlock::Message { log_time, .. } => *log_time,
+        }
+    }
+
+    fn end_time(&self) -> u64 {
+        match self {
+            DataBlock::Chunk {
+                message_end_time, ..
+            } => *message_end_time,
+            DataBlock::Message { log_time, .. } => *log_time,
+        }
+    }
+
+    fn has_channel(&self, channel_ids: &BTreeSet<u16>) -> bool {
+        match self {
+            DataBlock::Chunk { channels, .. } => !matches!(
+                channels,
+                Some(channels) if channels.is_disjoint(channel_ids)
+            ),
+            DataBlock::Message { channel_id, .. } => channel_ids.contains(channel_id),
+        }
+    }
+}
+
+/// Reads the topics of an MCAP file.
+pub struct McapReader {
+    path: PathBuf,
+    file_len: u64,
+    channels: BTreeMap<u16, McapChannel>,
+    blocks: Vec<DataBlock>,
+    batch_size: usize,
+}
+
+impl McapReader {
+    /// Opens the MCAP file at `path`. The channels and chunks are read from the summary if the
+    /// file has one, otherwise from the data section, which also recovers the complete records
+    /// of files that were not closed properly.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file =
+            File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        let file_len = file.metadata()?.len();
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)
+            .with_context(|| format!("{} is not an MCAP file", path.display()))?;
+        if &magic != MAGIC {
+            bail!("{} is not an MCAP file", path.display());
+        }
+
+        let summary = match read_footer(&mut file, file_len)? {
+            Some(footer) if footer.summary_start != 0 => {
+                let summary_end = file_len - (MAGIC.len() + FOOTER_RECORD_LEN) as u64;
+                let summary_len = summary_end
+                    .checked_sub(footer.summary_start)
+                    .filter(|_| footer.summary_start >= MAGIC.len() as u64)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Invalid summary start {} in {}",
+                            footer.summary_start,
+                            path.display()
+                        )
+                    })?;
+                let mut summary = vec![0u8; usize::try_from(summary_len)?];
+                file.seek(SeekFrom::Start(footer.summary_start))?;
+                file.read_exact(&mut summary)?;
+                Some(
+                    read_summary(&summary)
+                        .with_context(|| format!("Invalid summary in {}", path.display()))?,
+                )
+            }
+            _ => None,
+        };
+
+        let (channels, blocks) = match summary {
+            Some(summary) if !summary.blocks.is_empty() => (summary.channels, summary.blocks),
+            _ => scan_data_section(&mut file, file_len)
+                .with_context(|| format!("Unable to read {}", path.display()))?,
+        };
+
+        Ok(McapReader {
+            path: path.to_path_buf(),
+            file_len,
+            channels,
+            blocks,
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// Sets the maximum number of rows of the batches.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The channels of the file, ordered by id.
+    pub fn channels(&self) -> Vec<&McapChannel> {
+        self.channels.values().collect()
+    }
+
+    /// The topics of the file, in the order of their first channel.
+    pub fn topics(&self) -> Vec<&str> {
+        let mut topics: Vec<&str> = vec![];
+        for channel in self.channels.values() {
+            if !topics.contains(&channel.topic.as_str()) {
+                topics.push(&channel.topic);
+            }
+        }
+        topics
+    }
+
+    /// The log time of the first message, in nanoseconds since the UNIX epoch.
+    pub fn message_start_time(&self) -> Option<u64> {
+        self.blocks.iter().map(DataBlock::start_time).min()
+    }
+
+    /// The log time of the last message, in nanoseconds since the UNIX epoch.
+    pub fn message_end_time(&self) -> Option<u64> {
+        self.blocks.iter().map(DataBlock::end_time).max()
+    }
+
+    /// The channels of `topic` and their schema name.
+    fn topic_channels(&self, topic: &str) -> Result<(BTreeSet<u16>, &str)> {
+        let channels: Vec<&McapChannel> = self
+            .channels
+            .values()
+            .filter(|channel| channel.topic == topic)
+            .collect();
+        let Some(first) = channels.first() else {
+            bail!("Topic {} is not in {}", topic, self.path.display());
+        };
+        for channel in &channels {
+            if channel.schema_name != first.schema_name {
+                bail!(
+                    "Topic {} has messages of both {} and {}",
+                    topic,
+                    first.schema_name,
+                    channel.schema_name
+                );
+            }
+            if channel.message_encoding != "cdr" {
+                bail!(
+                    "Unsupported message encoding {} of topic {}",
+                    channel.message_encoding,
+                    topic
+                );
+            }
+        }
+        Ok((
+            channels.iter().map(|channel| channel.id).collect(),
+            &first.schema_name,
+        ))
+    }
+
+    /// The schema of the batches of `topic`: the fields of its type in the regular or, if
+    /// `flat` is true, the flat layout, followed by the context columns and the publish time.
+    pub fn topic_schema(&self, topic: &str, flat: bool) -> Result<Schema> {
+        let (_, schema_name) = self.topic_channels(topic)?;
+        Ok(Schema::new(topic_fields(topic, schema_name, flat)?))
+    }
+
+    /// Returns the messages of `topic` as batches of at most `batch_size` rows.
+    pub fn topic_batches(&self, topic: &str, flat: bool) -> Result<McapTopicBatches<'_>> {
+        self.topic_batches_in_range(topic, flat, ..)
+    }
+
+    /// Returns the messages of `topic` with a log time in `range`, in nanoseconds since the UNIX
+    /// epoch, as batches of at most `batch_size` rows. The messages of a chunk are sorted by log
+    /// time, and the chunks are read in the order of their first message.
+    pub fn topic_batches_in_range(
+        &self,
+        topic: &str,
+        flat: bool,
+        range: impl RangeBounds<u64>,
+    ) -> Result<McapTopicBatches<'_>> {
+        let (channel_ids, schema_name) = self.topic_channels(topic)?;
+        let fields = topic_fields(topic, schema_name, flat)?;
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        let mut blocks: Vec<DataBlock> = self
+            .blocks
+            .iter()
+            .filter(|block| {
+                block.has_channel(&channel_ids)
+                    && overlaps(&range, block.start_time(), block.end_time())
+            })
+            .cloned()
+            .collect();
+        blocks.sort_by_key(DataBlock::start_time);
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Unable to open {}", self.path.display()))?;
+        Ok(McapTopicBatches {
+            reader: self,
+            file: BufReader::new(file),
+            topic: topic.to_string(),
+            schema_name: schema_name.to_string(),
+            projection: Projection::all(&fields),
+            fields,
+            flat,
+            channel_ids,
+            range,
+            blocks: blocks.into(),
+            pending: VecDeque::new(),
+            seq: 0,
+        })
+    }
+}
+
+fn overlaps(range: &(Bound<u64>, Bound<u64>), start: u64, end: u64) -> bool {
+    let after_start = match range.0 {
+        Bound::Included(bound) => end >= bound,
+        Bound::Excluded(bound) => end > bound,
+        Bound::Unbounded => true,
+    };
+    let before_end = match range.1 {
+        Bound::Included(bound) => start <= bound,
+        Bound::Excluded(bound) => start < bound,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+fn topic_fields(topic: &str, schema_name: &str, flat: bool) -> Result<Vec<Field>> {
+    let mut fields = schema_arrow_fields(schema_name, flat, false).ok_or_else(|| {
+        anyhow!(
+            "Unsupported message type {} of topic {}",
+            schema_name,
+            topic
+        )
+    })?;
+    fields.extend(row_context_fields(false));
+    fields.push(Field::new(
+        PUBLISH_TIME_FIELD,
+        DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+        true,
+    ));
+    Ok(fields)
+}
+
+/// Reads the footer at the end of the file, or returns `None` if the file doesn't end with one,
+/// for example because it was not closed properly.
+fn read_footer(file: &mut File, file_len: u64) -> Result<Option<FooterRecord>> {
+    let footer_len = (FOOTER_RECORD_LEN + MAGIC.len()) as u64;
+    if file_len < MAGIC.len() as u64 + footer_len {
+        return Ok(None);
+    }
+    let mut footer = vec![0u8; footer_len as usize];
+    file.seek(SeekFrom::Start(file_len - footer_len))?;
+    file.read_exact(&mut footer)?;
+    if &footer[FOOTER_RECORD_LEN..] != MAGIC {
+        return Ok(None);
+    }
+    let (opcode, content) = RecordReader::new(&footer[..FOOTER_RECORD_LEN]).record()?;
+    if opcode != OP_FOOTER {
+        return Ok(None);
+    }
+    Ok(Some(FooterRecord::parse(content)?))
+}
+
+/// Checks that the record at `offset` with `length` bytes is inside of the file, before its
+/// buffer is allocated.
+fn check_record(offset: u64, length: u64, file_len: u64) -> Result<()> {
+    match file_len.checked_sub(offset) {
+        Some(remaining) if length <= remaining => Ok(()),
+        _ => bail!(
+            "The record at offset {} with {} bytes is outside of the file of {} bytes",
+            offset,
+            length,
+            file_len
+        ),
+    }
+}
+
+struct Summary {
+    channels: BTreeMap<u16, McapChannel>,
+    blocks: Vec<DataBlock>,
+}
+
+/// Collects schema and channel records and resolves the schema names of the channels.
+#[derive(Default)]
+struct ChannelCollector {
+    schemas: HashMap<u16, SchemaRecord>,
+    channels: Vec<ChannelRecord>,
+}
+
+impl ChannelCollector {
+    fn add(&mut self, opcode: u8, content: &[u8]) -> Result<()> {
+        match opcode {
+            OP_SCHEMA => {
+                let schema = SchemaRecord::parse(content)?;
+                self.schemas.insert(schema.id, schema);
+            }
+            OP_CHANNEL => self.channels.push(ChannelRecord::parse(content)?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> BTreeMap<u16, McapChannel> {
+        self.channels
+            .into_iter()
+            .map(|channel| {
+                let schema = self.schemas.get(&channel.schema_id);
+                let channel = McapChannel {
+                    id: channel.id,
+                    topic: channel.topic,
+                    schema_name: schema.map(|schema| schema.name.clone()).unwrap_or_default(),
+                    schema_encoding: schema
+                        .map(|schema| schema.encoding.clone())
+                        .unwrap_or_default(),
+                    message_encoding: channel.message_encoding,
+                    metadata: channel.metadata,
+                };
+                (channel.id, channel)
+            })
+            .collect()
+    }
+}
+
+fn read_summary(summary: &[u8]) -> Result<Summary> {
+    let mut reader = RecordReader::new(summary);
+    let mut channels = ChannelCollector::default();
+    let mut blocks = vec![];
+    while !reader.is_empty() {
+        let (opcode, content) = reader.record()?;
+        match opcode {
+            OP_CHUNK_INDEX => {
+                let index = ChunkIndexRecord::parse(content)?;
+                blocks.push(DataBlock::Chunk {
+                    offset: index.chunk_start_offset,
+                    length: index.chunk_length,
+                    message_start_time: index.message_start_time,
+                    message_end_time: index.message_end_time,
+                    channels: (!index.message_index_offsets.is_empty())
+                        .then(|| index.message_index_offsets.keys().copied().collect()),
+                });
+            }
+            opcode => channels.add(opcode, content)?,
+        }
+    }
+    Ok(Summary {
+        channels: channels.finish(),
+        blocks,
+    })
+}
+
+/// Reads the records of the data section, up to the data end record or the last complete record.
+fn scan_data_section(
+    file: &mut File,
+    file_len: u64,
+) -> Result<(BTreeMap<u16, McapChannel>, Vec<DataBlock>)> {
+    let mut file = BufReader::new(file);
+    file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+    let mut offset = MAGIC.len() as u64;
+    let mut channels = ChannelCollector::default();
+    let mut blocks = vec![];
+
+    loop {
+        let mut prefix = [0u8; 9];
+        if file.read_exact(&mut prefix).is_err() {
+            break;
+        }
+        let opcode = prefix[0];
+        let len = u64::from_le_bytes(prefix[1..].try_into()?);
+        // A length past the end of the file is a truncated or corrupt record.
+        if check_record(offset + 9, len, file_len).is_err() {
+            break;
+        }
+        let mut content = vec![0u8; usize::try_from(len)?];
+        if file.read_exact(&mut content).is_err() {
+            break;
+        }
+        let length = 9 + len;
+
+        match opcode {
+            OP_CHUNK => {
+                let chunk = ChunkRecord::parse(&content)?;
+                let records = chunk.decompress()?;
+                let mut records = RecordReader::new(&records);
+                let mut chunk_channels = BTreeSet::new();
+                while !records.is_empty() {
+                    let (opcode, content) = records.record()?;
+                    match opcode {
+                        OP_MESSAGE => {
+                            chunk_channels.insert(MessageRecord::parse(content)?.channel_id);
+                        }
+                        opcode => channels.add(opcode, content)?,
+                    }
+                }
+                blocks.push(DataBlock::Chunk {
+                    offset,
+                    length,
+                    message_start_time: chunk.message_start_time,
+                    message_end_time: chunk.message_end_time,
+                    channels: Some(chunk_channels),
+                });
+            }
+            OP_MESSAGE => {
+                let message = MessageRecord::parse(&content)?;
+                blocks.push(DataBlock::Message {
+                    offset,
+                    length,
+                    channel_id: message.channel_id,
+                    log_time: message.log_time,
+                });
+            }
+            OP_DATA_END | OP_FOOTER => break,
+            opcode => channels.add(opcode, &content)?,
+        }
+        offset += length;
+    }
+    Ok((channels.finish(), blocks))
+}
+
+/// The batches of a topic, returned by [`McapReader::topic_batches`].
+pub struct McapTopicBatches<'r> {
+    reader: &'r McapReader,
+    file: BufReader<File>,
+    topic: String,
+    schema_name: String,
+    fields: Vec<Field>,
+    projection: Projection,
+    flat: bool,
+    channel_ids: BTreeSet<u16>,
+    range: (Bound<u64>, Bound<u64>),
+    blocks: VecDeque<DataBlock>,
+    /// The log time, publish time and data of the messages read from the current block.
+    pending: VecDeque<(u64, u64, Vec<u8>)>,
+    seq: u64,
+}
+
+impl McapTopicBatches<'_> {
+    /// Only builds the columns at the indices of `projection` in [`McapReader::topic_schema`],
+    /// and returns them in the order of `projection`. The messages are still deserialized, but
+    /// the other columns are skipped. An empty projection returns batches without columns, which
+    /// only have a row count.
+    pub fn with_projection(mut self, projection: &[usize]) -> Result<Self> {
+        self.projection = Projection::new(&self.fields, projection)?;
+        Ok(self)
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.projection.schema()
+    }
+
+    fn read_record(&mut self, offset: u64, length: u64) -> Result<Vec<u8>> {
+        check_record(offset, length, self.reader.file_len)?;
+        let mut record = vec![0u8; usize::try_from(length)?];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut record)?;
+        Ok(record)
+    }
+
+    fn add_message(&mut self, message: MessageRecord) {
+        if self.channel_ids.contains(&message.channel_id) && self.range.contains(&message.log_time)
+        {
+            self.pending.push_back((
+                message.log_time,
+                message.publish_time,
+                message.data.to_vec(),
+            ));
+        }
+    }
+
+    /// Reads the messages of the topic from the next block into `pending`.
+    fn read_block(&mut self, block: DataBlock) -> Result<()> {
+        match block {
+            DataBlock::Chunk { offset, length, .. } => {
+                let record = self.read_record(offset, length)?;
+                let (opcode, content) = RecordReader::new(&record).record()?;
+                if opcode != OP_CHUNK {
+                    bail!(
+                        "Expected a chunk at offset {}, got opcode {}",
+                        offset,
+                        opcode
+                    );
+                }
+                let records = ChunkRecord::parse(content)?.decompress()?;
+                let mut records = RecordReader::new(&records);
+                while !records.is_empty() {
+                    let (opcode, content) = records.record()?;
+                    if opcode == OP_MESSAGE {
+                        self.add_message(MessageRecord::parse(content)?);
+                    }
+                }
+                self.pending
+                    .make_contiguous()
+                    .sort_by_key(|(log_time, _, _)| *log_time);
+            }
+            DataBlock::Message { offset, length, .. } => {
+                let record = self.read_record(offset, length)?;
+                let (_, content) = RecordReader::new(&record).record()?;
+                self.add_message(MessageRecord::parse(content)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let batch_size = self.reader.batch_size;
+        while self.pending.len() < batch_size {
+            let Some(block) = self.blocks.pop_front() else {
+                break;
+            };
+            self.read_block(block)
+                .with_context(|| format!("Unable to read {}", self.reader.path.display()))?;
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+
+        let mut layout_fields = self.projection.fields(&self.fields);
+        let with_publish_time = layout_fields
+            .last()
+            .is_some_and(|field| field.name() == PUBLISH_TIME_FIELD);
+        if with_publish_time {
+            layout_fields.pop();
+        }
+        let mut row_builder = new_raw_row_builder(&self.schema_name, layout_fields, self.flat)
+            .ok_or_else(|| anyhow!("Unsupported message type {}", self.schema_name))?;
+        let rows = self.pending.len().min(batch_size);
+        let mut publish_time = TimestampNanosecondBuilder::with_capacity(rows).with_timezone("UTC");
+        for (log_time, message_publish_time, data) in self.pending.drain(..rows) {
+            let ctx = RowContext::new(&self.topic, self.seq)
+                .with_recv_time(UNIX_EPOCH + Duration::from_nanos(log_time));
+            row_builder
+                .add_raw_row_with_context(&data, &ctx)
+                .with_context(|| {
+                    format!("Unable to read message {} of {}", self.seq, self.topic)
+                })?;
+            publish_time.append_value(message_publish_time as i64);
+            self.seq += 1;
+        }
+
+        let mut arrays = row_builder.to_arc_arrays();
+        if with_publish_time {
+            arrays.push(Arc::new(publish_time.finish()));
+        }
+        Ok(Some(self.projection.batch(arrays, rows)?))
+    }
+}
+
+impl Iterator for McapTopicBatches<'_> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::super::records::{RecordWriter, OP_HEADER};
+    use super::*;
+    use crate::cdr::CdrMessage;
+    use crate::msgs::builtin_interfaces::msg::Time;
+    use crate::msgs::sensor_msgs::msg::LaserScan;
+    use crate::msgs::std_msgs::msg::{Header, String as StringMsg};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, TimestampNanosecondType};
+    use arrow_array::Array;
+
+    /// Writes /scan messages at 1..=6 s and a /chatter message at 2.5 s, in chunks of three
+    /// messages.
+    fn write_mcap(name: &str, compression: &str, summary: bool) -> PathBuf {
+        let mut messages: Vec<(u16, u64, Vec<u8>)> = (1..=6)
+            .map(|sec| {
+                let scan = LaserScan {
+                    header: Header {
+                        stamp: Time { sec, nanosec: 0 },
+                        frame_id: "laser".to_string(),
+                    },
+                    ranges: vec![sec as f32],
+                    ..Default::default()
+                };
+                (
+                    1,
+                    sec as u64 * 1_000_000_000,
+                    scan.to_serialized_bytes().unwrap(),
+                )
+            })
+            .collect();
+        let chatter = StringMsg {
+            data: "hello".to_string(),
+        };
+        messages.insert(
+            2,
+            (2, 2_500_000_000, chatter.to_serialized_bytes().unwrap()),
+        );
+
+        let schemas = [
+            SchemaRecord {
+                id: 1,
+                name: "sensor_msgs/msg/LaserScan".to_string(),
+                encoding: "ros2msg".to_string(),
+                data: vec![],
+            },
+            SchemaRecord {
+                id: 2,
+                name: "std_msgs/msg/String".to_string(),
+                encoding: "ros2msg".to_string(),
+                data: b"string data".to_vec(),
+            },
+        ];
+        let channels = [
+            ChannelRecord {
+                id: 1,
+                schema_id: 1,
+                topic: "/scan".to_string(),
+                message_encoding: "cdr".to_string(),
+                metadata: BTreeMap::new(),
+            },
+            ChannelRecord {
+                id: 2,
+                schema_id: 2,
+                topic: "/chatter".to_string(),
+                message_encoding: "cdr".to_string(),
+                metadata: BTreeMap::new(),
+            },
+        ];
+
+        let mut data = MAGIC.to_vec();
+        let mut header = RecordWriter::default();
+        header.string("ros2").string("r2a");
+        data.extend(header.record(OP_HEADER));
+        for schema in &schemas {
+            data.extend(schema.to_record());
+        }
+        for channel in &channels {
+            data.extend(channel.to_record());
+        }
+
+        let mut chunk_indexes = vec![];
+        for chunk in messages.chunks(3) {
+            let mut records = vec![];
+            for (channel_id, log_time, data) in chunk {
+                records.extend(
+                    MessageRecord {
+                        channel_id: *channel_id,
+                        sequence: 0,
+                        log_time: *log_time,
+                        publish_time: log_time + 1,
+                        data,
+                    }
+                    .to_record(),
+                );
+            }
+            let compressed = ChunkRecord::compress(&records, compression).unwrap();
+            let chunk_record = ChunkRecord {
+                message_start_time: chunk.iter().map(|m| m.1).min().unwrap(),
+                message_end_time: chunk.iter().map(|m| m.1).max().unwrap(),
+                uncompressed_size: records.len() as u64,
+                uncompressed_crc: 0,
+                compression: compression.to_string(),
+                records: &compressed,
+            }
+            .to_record();
+            chunk_indexes.push(ChunkIndexRecord {
+                message_start_time: chunk.iter().map(|m| m.1).min().unwrap(),
+                message_end_time: chunk.iter().map(|m| m.1).max().unwrap(),
+                chunk_start_offset: data.len() as u64,
+                chunk_length: chunk_record.len() as u64,
+                message_index_offsets: chunk.iter().map(|m| (m.0, 0)).collect(),
+                message_index_length: 0,
+                compression: compression.to_string(),
+                compressed_size: compressed.len() as u64,
+                uncompressed_size: records.len() as u64,
+            });
+            data.extend(chunk_record);
+        }
+        let mut data_end = RecordWriter::default();
+        data_end.u32(0);
+        data.extend(data_end.record(OP_DATA_END));
+
+        let mut summary_start = 0;
+        if summary {
+            summary_start = data.len() as u64;
+            for schema in &schemas {
+                data.extend(schema.to_record());
+            }
+            for channel in &channels {
+                data.extend(channel.to_record());
+            }
+            for index in &chunk_indexes {
+                data.extend(index.to_record());
+            }
+        }
+        data.extend(
+            FooterRecord {
+                summary_start,
+                summary_offset_start: 0,
+                summary_crc: 0,
+            }
+            .to_record(),
+        );
+        data.extend(MAGIC);
+
+        let path = std::env::temp_dir().join(format!("r2a_{}_{}.mcap", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn read_ranges(batches: &[RecordBatch]) -> Vec<f32> {
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let ranges = batch.column_by_name("ranges").unwrap().as_list::<i64>();
+                (0..ranges.len())
+                    .map(|i| ranges.value(i).as_primitive::<Float32Type>().value(0))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn check_reader(reader: &McapReader) {
+        assert_eq!(reader.topics(), ["/scan", "/chatter"]);
+        assert_eq!(
+            reader.channels()[0].schema_name,
+            "sensor_msgs/msg/LaserScan"
+        );
+        assert_eq!(reader.message_start_time(), Some(1_000_000_000));
+        assert_eq!(reader.message_end_time(), Some(6_000_000_000));
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/scan", true)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            batches
+                .iter()
+                .map(|batch| batch.num_rows())
+                .collect::<Vec<_>>(),
+            [4, 2]
+        );
+        assert_eq!(
+            batches[0].schema().as_ref(),
+            &reader.topic_schema("/scan", true).unwrap()
+        );
+        assert_eq!(read_ranges(&batches), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let log_time = batches[1]
+            .column_by_name("_recv_time")
+            .unwrap()
+            .as_primitive::<TimestampNanosecondType>();
+        assert_eq!(log_time.value(0), 5_000_000_000);
+        let publish_time = batches[1]
+            .column_by_name(PUBLISH_TIME_FIELD)
+            .unwrap()
+            .as_primitive::<TimestampNanosecondType>();
+        assert_eq!(publish_time.value(0), 5_000_000_001);
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches_in_range("/scan", false, 2_000_000_000..5_000_000_000)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read_ranges(&batches), [2.0, 3.0, 4.0]);
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/chatter", false)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            batches[0]
+                .column_by_name("data")
+                .unwrap()
+                .as_string::<i32>()
+                .value(0),
+            "hello"
+        );
+
+        assert!(reader.topic_batches("/unknown", false).is_err());
+    }
+
+    #[test]
+    fn test_read_with_summary() {
+        for compression in ["", "zstd", "lz4"] {
+            let path = write_mcap(&format!("summary_{}", compression), compression, true);
+            let reader = McapReader::open(&path).unwrap().with_batch_size(4);
+            check_reader(&reader);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_projection() {
+        let path = write_mcap("projection", "zstd", true);
+        let reader = McapReader::open(&path).unwrap().with_batch_size(4);
+        let schema = reader.topic_schema("/scan", true).unwrap();
+        let publish_time = schema.index_of(PUBLISH_TIME_FIELD).unwrap();
+        let ranges = schema.index_of("ranges").unwrap();
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches_in_range("/scan", true, 2_000_000_000..)
+            .unwrap()
+            .with_projection(&[publish_time, ranges])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        let names: Vec<&String> = batches[0]
+            .schema_ref()
+            .fields()
+            .iter()
+            .map(|field| field.name())
+            .collect();
+        assert_eq!(names, [PUBLISH_TIME_FIELD, "ranges"]);
+        assert_eq!(read_ranges(&batches), [2.0, 3.0, 4.0, 5.0, 6.0]);
+        let publish_time = batches[0]
+            .column(0)
+            .as_primitive::<TimestampNanosecondType>();
+        assert_eq!(publish_time.value(0), 2_000_000_001);
+
+        // Only the row count.
+        let rows: Vec<usize> = reader
+            .topic_batches("/scan", false)
+            .unwrap()
+            .with_projection(&[])
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .collect();
+        assert_eq!(rows, [4, 2]);
+
+        let batches = reader.topic_batches("/scan", true).unwrap();
+        assert!(batches.with_projection(&[ranges, ranges]).is_err());
+        let batches = reader.topic_batches("/scan", true).unwrap();
+        assert!(batches.with_projection(&[schema.fields().len()]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        // A footer with a summary start after the summary end.
+        let path = write_mcap("corrupt_footer", "", true);
+        let mut data = std::fs::read(&path).unwrap();
+        let summary_start = data.len() - MAGIC.len() - FOOTER_RECORD_LEN + 9;
+        data[summary_start..summary_start + 8].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        let error = McapReader::open(&path).err().unwrap();
+        assert!(error.to_string().starts_with("Invalid summary start"));
+        std::fs::remove_file(&path).unwrap();
+
+        // An unfinished file that ends with the prefix of a record of several exabytes.
+        let path = write_mcap("corrupt_record", "lz4", false);
+        let mut data = std::fs::read(&path).unwrap();
+        data.truncate(data.len() - 50);
+        data.push(OP_MESSAGE);
+        data.extend_from_slice(&(u64::MAX / 2).to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        let reader = McapReader::open(&path).unwrap().with_batch_size(4);
+        check_reader(&reader);
+
+        assert!(check_record(10, 90, 100).is_ok());
+        assert!(check_record(10, 91, 100).is_err());
+        assert!(check_record(101, 0, 100).is_err());
+        assert!(check_record(10, u64::MAX, 100).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_without_summary() {
+        let path = write_mcap("no_summary", "lz4", false);
+        let reader = McapReader::open(&path).unwrap().with_batch_size(4);
+        check_reader(&reader);
+
+        // A file that was not closed properly, without the data end record and the footer.
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 50]).unwrap();
+        let reader = McapReader::open(&path).unwrap().with_batch_size(4);
+        check_reader(&reader);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+//! The MCAP records r2a reads and writes, see <https://mcap.dev/spec>.
+
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+pub(crate) const MAGIC: &[u8; 8] = b"\x89MCAP0\r\n";
+
+pub(crate) const OP_HEADER: u8 = 0x01;
+pub(crate) const OP_FOOTER: u8 = 0x02;
+pub(crate) const OP_SCHEMA: u8 = 0x03;
+pub(crate) const OP_CHANNEL: u8 = 0x04;
+pub(crate) const OP_MESSAGE: u8 = 0x05;
+pub(crate) const OP_CHUNK: u8 = 0x06;
+pub(crate) const OP_MESSAGE_INDEX: u8 = 0x07;
+pub(crate) const OP_CHUNK_INDEX: u8 = 0x08;
+pub(crate) const OP_STATISTICS: u8 = 0x0B;
+pub(crate) const OP_SUMMARY_OFFSET: u8 = 0x0E;
+pub(crate) const OP_DATA_END: u8 = 0x0F;
+
+/// The length of a footer record, including its opcode and length.
+pub(crate) const FOOTER_RECORD_LEN: usize = 1 + 8 + 8 + 8 + 4;
+
+/// Reads the fields of a record.
+pub(crate) struct RecordReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        RecordReader { data, position: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unexpected end of MCAP record, reading {} bytes at {} of {}",
+                    len,
+                    self.position,
+                    self.data.len()
+                )
+            })?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.data[self.position.min(self.data.len())..];
+        self.position = self.data.len();
+        bytes
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(std::str::from_utf8(self.bytes(len)?)?.to_string())
+    }
+
+    /// Reads a `u32` length prefixed byte array.
+    pub(crate) fn byte_array(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub(crate) fn string_map(&mut self) -> Result<BTreeMap<String, String>> {
+        let mut entries = RecordReader::new(self.byte_array()?);
+        let mut map = BTreeMap::new();
+        while !entries.is_empty() {
+            let key = entries.string()?;
+            map.insert(key, entries.string()?);
+        }
+        Ok(map)
+    }
+
+    /// Reads the next record, returning its opcode and content.
+    pub(crate) fn record(&mut self) -> Result<(u8, &'a [u8])> {
+        let opcode = self.u8()?;
+        let len = self.u64()?;
+        let len = usize::try_from(len)?;
+        Ok((opcode, self.bytes(len)?))
+    }
+}
+
+/// Writes the fields of a record.
+#[derive(Default)]
+pub(crate) struct RecordWriter {
+    data: Vec<u8>,
+}
+
+impl RecordWriter {
+    pub(crate) fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn string(&mut self, value: &str) -> &mut Self {
+        self.byte_array(value.as_bytes())
+    }
+
+    pub(crate) fn byte_array(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.bytes(value)
+    }
+
+    pub(crate) fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(value);
+        self
+    }
+
+    pub(crate) fn string_map(&mut self, map: &BTreeMap<String, String>) -> &mut Self {
+        let mut entries = RecordWriter::default();
+        for (key, value) in map {
+            entries.string(key).string(value);
+        }
+        self.byte_array(&entries.data)
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns the record with `opcode` and the written content.
+    pub(crate) fn record(self, opcode: u8) -> Vec<u8> {
+        let mut record = Vec::with_capacity(9 + self.data.len());
+        record.push(opcode);
+        record.extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        record.extend_from_slice(&self.data);
+        record
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SchemaRecord {
+    pub id: u16,
+    pub name: String,
+    pub encoding: String,
+    pub data: Vec<u8>,
+}
+
+impl SchemaRecord {
+    pub(crate) fn parse(content: &[u8]) -> Result<Self> {
+        let mut reader = RecordReader::new(content);
+        Ok(SchemaRecord {
+            id: reader.u16()?,
+            name: reader.string()?,
+            encoding: reader.string()?,
+            data: reader.byte_array()?.to_vec(),
+        })
+    }
+
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut writer = RecordWriter::default();
+        writer
+            .u16(self.id)
+            .string(&self.name)
+            .string(&self.encoding)
+            .byte_array(&self.data);
+        writer.record(OP_SCHEMA)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChannelRecord {
+    pub id: u16,
+    pub schema_id: u16,
+    pub topic: String,
+    pub message_encoding: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl ChannelRecord {
+    pub(crate) fn parse(content: &[u8]) -> Result<Self> {
+        let mut reader = RecordReader::new(content);
+        Ok(ChannelRecord {
+            id: reader.u16()?,
+            schema_id: reader.u16()?,
+            topic: reader.string()?,
+            message_encoding: reader.string()?,
+            metadata: reader.string_map()?,
+        })
+    }
+
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut writer = RecordWriter::default();
+        writer
+            .u16(self.id)
+            .u16(self.schema_id)
+            .string(&self.topic)
+            .string(&self.message_encoding)
+            .string_map(&self.metadata);
+        writer.record(OP_CHANNEL)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MessageRecord<'a> {
+    pub channel_id: u16,
+    pub sequence: u32,
+    pub log_time: u64,
+    pub publish_time: u64,
+    pub data: &'a [u8],
+}
+
+impl<'a> MessageRecord<'a> {
+    pub(crate) fn parse(content: &'a [u8]) -> Result<Self> {
+        let mut reader = RecordReader::new(content);
+        Ok(MessageRecord {
+            channel_id: reader.u16()?,
+            sequence: reader.u32()?,
+            log_time: reader.u64()?,
+            publish_time: reader.u64()?,
+            data: reader.rest(),
+        })
+    }
+
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut writer = RecordWriter::default();
+        writer
+            .u16(self.channel_id)
+            .u32(self.sequence)
+            .u64(self.log_time)
+            .u64(self.publish_time)
+            .bytes(self.data);
+        writer.record(OP_MESSAGE)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChunkRecord<'a> {
+    pub message_start_time: u64,
+    pub message_end_time: u64,
+    pub uncompressed_size: u64,
+    pub uncompressed_crc: u32,
+    pub compression: String,
+    pub records: &'a [u8],
+}
+
+/// The largest compression ratio of lz4, which bounds the size of a decompressed chunk.
+const LZ4_MAX_RATIO: usize = 255;
+
+impl<'a> ChunkRecord<'a> {
+    pub(crate) fn parse(content: &'a [u8]) -> Result<Self> {
+        let mut reader = RecordReader::new(content);
+        let message_start_time = reader.u64()?;
+        let message_end_time = reader.u64()?;
+        let uncompressed_size = reader.u64()?;
+        let uncompressed_crc = reader.u32()?;
+        let compression = reader.string()?;
+        let len = usize::try_from(reader.u64()?)?;
+        Ok(ChunkRecord {
+            message_start_time,
+            message_end_time,
+            uncompressed_size,
+            uncompressed_crc,
+            compression,
+            records: reader.bytes(len)?,
+        })
+    }
+
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut writer = RecordWriter::default();
+        writer
+            .u64(self.message_start_time)
+            .u64(self.message_end_time)
+            .u64(self.uncompressed_size)
+            .u32(self.uncompressed_crc)
+            .string(&self.compression)
+            .u64(self.records.len() as u64)
+            .bytes(self.records);
+        writer.record(OP_CHUNK)
+    }
+
+    /// Compresses the records of a chunk with `compression`, `""`, `"zstd"` or `"lz4"`.
+    pub(crate) fn compress(records: &[u8], compression: &str) -> Result<Vec<u8>> {
+        Ok(match compression {
+            "" => records.to_vec(),
+            "zstd" => zstd::stream::encode_all(records, 0)?,
+            "lz4" => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+                encoder.write_all(records)?;
+                encoder.finish()?
+            }
+            other => bail!("Unsupported MCAP chunk compression {}", other),
+        })
+    }
+
+    /// Decompresses the records of the chunk.
+    pub(crate) fn decompress(&self) -> Result<Vec<u8>> {
+        let records = match self.compression.as_str() {
+            "" => self.records.to_vec(),
+            "zstd" => zstd::stream::decode_all(self.records)?,
+            "lz4" => {
+                // The size comes from the file, it only bounds the initial allocation.
+                let capacity = usize::try_from(self.uncompressed_size)
+                    .unwrap_or(usize::MAX)
+                    .min(self.records.len().saturating_mul(LZ4_MAX_RATIO));
+                let mut records = Vec::with_capacity(capacity);
+                std::io::copy(
+                    &mut lz4_flex::frame::FrameDecoder::new(self.records),
+                    &mut records,
+                )?;
+                records
+            }
+            other => bail!("Unsupported MCAP chunk compression {}", other),
+        };
+        if records.len() as u64 != self.uncompressed_size {
+            bail!(
+                "MCAP chunk decompressed to {} bytes, expected {}",
+                records.len(),
+                self.uncompressed_size
+            );
+        }
+        Ok(records)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChunkIndexRecord {
+    pub message_start_time: u64,
+    pub message_end_time: u64,
+    pub chunk_start_offset: u64,
+    pub chunk_length: u64,
+    pub message_index_offsets: BTreeMap<u16, u64>,
+    pub message_index_length: u64,
+    pub compression: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+impl ChunkIndexRecord {
+    pub(crate) fn parse(content: &[u8]) -> Result<Self> {
+        let mut reader = RecordReader::new(content);
+        let message_start_time = reader.u64()?;
+        let message_end_time = reader.u64()?;
+        let chunk_start_offset = reader.u64()?;
+        let chunk_length = reader.u64()?;
+        let mut offsets = RecordReader::new(reader.byte_array()?);
+        let mut message_index_offsets = BTreeMap::new();
+        while !offsets.is_empty() {
+            let channel_id = offsets.u16()?;
+            message_index_offsets.insert(channel_id, offsets.u64()?);
+        }
+        Ok(ChunkIndexRecord {
+            message_start_time,
+            message_end_time,
+            chunk_start_offset,
+            chunk_length,
+            message_index_offsets,
+            message_index_length: reader.u64()?,
+            compression: reader.string()?,
+            compressed_size: reader.u64()?,
+            uncompressed_size: reader.u64()?,
+        })
+    }
+
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut offsets = RecordWriter::default();
+        for (channel_id, offset) in &self.message_index_offsets {
+            offsets.u16(*channel_id).u64(*offset);
+        }
+        let mut writer = RecordWriter::default();
+        writer
+            .u64(self.message_start_time)
+            .u64(self.message_end_time)
+            .u64(self.chunk_start_offset)
+            .u64(self.chunk_length)
+            .byte_array(&offsets.finish())
+            .u64(self.message_index_length)
+            .string(&self.compression)
+            .u64(self.compressed_size)
+            .u64(self.uncompressed_size);
+        writer.record(OP_CHUNK_INDEX)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FooterRecord {
+    pub summary_start: u64,
+    pub summary_offset_start: u64,
+    pub summary_crc: u32,
+}
+
+impl FooterRecord {
+    pub(crate) fn parse(content: &[u8]) -> Result<Self> {
+        let mut reader = RecordReader::new(content);
+        Ok(FooterRecord {
+            summary_start: reader.u64()?,
+            summary_offset_start: reader.u64()?,
+            summary_crc: reader.u32()?,
+        })
+    }
+
+    pub(crate) fn to_record(self) -> Vec<u8> {
+        let mut writer = RecordWriter::default();
+        writer
+            .u64(self.summary_start)
+            .u64(self.summary_offset_start)
+            .u32(self.summary_crc);
+        writer.record(OP_FOOTER)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MessageIndexRecord {
+    pub channel_id: u16,
+    /// The log time and the offset in the uncompressed chunk records of each message.
+    pub records: Vec<(u64, u64)>,
+}
+
+impl MessageIndexRecord {
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut records = RecordWriter::default();
+        for (log_time, offset) in &self.records {
+            records.u64(*log_time).u64(*offset);
+        }
+        let mut writer = RecordWriter::default();
+        writer.u16(self.channel_id).byte_array(&records.finish());
+        writer.record(OP_MESSAGE_INDEX)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct StatisticsRecord {
+    pub message_count: u64,
+    pub schema_count: u16,
+    pub channel_count: u32,
+    pub chunk_count: u32,
+    pub message_start_time: u64,
+    pub message_end_time: u64,
+    pub channel_message_counts: BTreeMap<u16, u64>,
+}
+
+impl StatisticsRecord {
+    pub(crate) fn to_record(&self) -> Vec<u8> {
+        let mut counts = RecordWriter::default();
+        for (channel_id, count) in &self.channel_message_counts {
+            counts.u16(*channel_id).u64(*count);
+        }
+        let mut writer = RecordWriter::default();
+        writer
+            .u64(self.message_count)
+            .u16(self.schema_count)
+            .u32(self.channel_count)
+            // Attachments and metadata records.
+            .u32(0)
+            .u32(0)
+            .u32(self.chunk_count)
+            .u64(self.message_start_time)
+            .u64(self.message_end_time)
+            .byte_array(&counts.finish());
+        writer.record(OP_STATISTICS)
+    }
+}
+
+/// Points to the records with `group_opcode` in the summary section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SummaryOffsetRecord {
+    pub group_opcode: u8,
+    pub group_start: u64,
+    pub group_length: u64,
+}
+
+impl SummaryOffsetRecord {
+    pub(crate) fn to_record(self) -> Vec<u8> {
+        let mut writer = RecordWriter::default();
+        writer
+            .bytes(&[self.group_opcode])
+            .u64(self.group_start)
+            .u64(self.group_length);
+        writer.record(OP_SUMMARY_OFFSET)
+    }
+}
@@ -0,0 +1,597 @@
+use super::records::{
+    ChannelRecord, ChunkIndexRecord, ChunkRecord, FooterRecord, MessageIndexRecord, MessageRecord,
+    RecordWriter, SchemaRecord, StatisticsRecord, SummaryOffsetRecord, MAGIC, OP_CHANNEL,
+    OP_CHUNK_INDEX, OP_DATA_END, OP_HEADER, OP_SCHEMA, OP_STATISTICS,
+};
+use super::PUBLISH_TIME_FIELD;
+use crate::batch_times::{header_stamps, timestamp_column};
+use crate::{raw_messages_from_batch, RECV_TIME_FIELD};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::RecordBatch;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The uncompressed size of the chunks written by [`McapWriter`], unless set with
+/// [`McapWriter::with_chunk_size`]. The same as the MCAP CLI and rosbag2 use.
+pub const DEFAULT_CHUNK_SIZE: usize = 768 * 1024;
+
+/// The compression of the chunks written by [`McapWriter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum McapCompression {
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl McapCompression {
+    fn as_str(&self) -> &'static str {
+        match self {
+            McapCompression::None => "",
+            McapCompression::Zstd => "zstd",
+            McapCompression::Lz4 => "lz4",
+        }
+    }
+}
+
+/// The records of the chunk that is being written.
+#[derive(Default)]
+struct ChunkBuffer {
+    records: Vec<u8>,
+    message_start_time: u64,
+    message_end_time: u64,
+    message_indexes: BTreeMap<u16, Vec<(u64, u64)>>,
+}
+
+/// Writes r2a batches to an MCAP file with the `ros2` profile, which can be read by the MCAP
+/// tools, Foxglove and `ros2 bag play`.
+///
+/// The rows are converted back to the typed messages and serialized to CDR. Each topic gets a
+/// channel with `cdr` message encoding and a `ros2msg` schema, whose text is
+/// [`crate::RosTypeDescriptor::message_definition`]. The summary with the chunk indexes and the
+/// statistics is written by [`McapWriter::finish`], or when the writer is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use r2a::mcap::{McapCompression, McapReader, McapWriter};
+///
+/// let reader = McapReader::open("run_42.mcap").unwrap();
+/// let mut writer = McapWriter::create("run_42_scan.mcap")
+///     .unwrap()
+///     .with_compression(McapCompression::Zstd);
+/// for batch in reader.topic_batches("/scan", true).unwrap() {
+///     writer
+///         .write_batch("/scan", "sensor_msgs/msg/LaserScan", &batch.unwrap(), true)
+///         .unwrap();
+/// }
+/// writer.finish().unwrap();
+/// ```
+pub struct McapWriter {
+    file: BufWriter<File>,
+    offset: u64,
+    compression: McapCompression,
+    chunk_size: usize,
+    /// The schemas by schema name.
+    schemas: BTreeMap<String, SchemaRecord>,
+    /// The channels by topic.
+    channels: BTreeMap<String, ChannelRecord>,
+    sequences: BTreeMap<u16, u32>,
+    chunk: ChunkBuffer,
+    chunk_indexes: Vec<ChunkIndexRecord>,
+    statistics: StatisticsRecord,
+    finished: bool,
+}
+
+impl McapWriter {
+    /// Creates the MCAP file at `path`, replacing an existing file.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+        let mut writer = McapWriter {
+            file: BufWriter::new(file),
+            offset: 0,
+            compression: McapCompression::Zstd,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            schemas: BTreeMap::new(),
+            channels: BTreeMap::new(),
+            sequences: BTreeMap::new(),
+            chunk: ChunkBuffer::default(),
+            chunk_indexes: vec![],
+            statistics: StatisticsRecord::default(),
+            finished: false,
+        };
+        writer.write(MAGIC)?;
+        let mut header = RecordWriter::default();
+        header
+            .string("ros2")
+            .string(concat!("r2a ", env!("CARGO_PKG_VERSION")));
+        writer.write(&header.record(OP_HEADER))?;
+        Ok(writer)
+    }
+
+    /// Sets the compression of the chunks, zstd by default.
+    pub fn with_compression(mut self, compression: McapCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the uncompressed size in bytes after which a chunk is written.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the id of the channel of `topic`, writing the schema and the channel first if
+    /// the topic is new.
+    fn channel_id(&mut self, topic: &str, schema_name: &str) -> Result<u16> {
+        if let Some(channel) = self.channels.get(topic) {
+            let schema = self
+                .schemas
+                .values()
+                .find(|schema| schema.id == channel.schema_id);
+            if schema.map(|schema| schema.name.as_str()) != Some(schema_name) {
+                bail!(
+                    "Topic {} was written with a different type than {}",
+                    topic,
+                    schema_name
+                );
+            }
+            return Ok(channel.id);
+        }
+
+        let schema_id = match self.schemas.get(schema_name) {
+            Some(schema) => schema.id,
+            None => {
+                let descriptor = crate::type_descriptor(schema_name)
+                    .ok_or_else(|| anyhow!("Unsupported message type {}", schema_name))?;
+                let schema = SchemaRecord {
+                    id: self.schemas.len() as u16 + 1,
+                    name: schema_name.to_string(),
+                    encoding: "ros2msg".to_string(),
+                    data: descriptor.message_definition().into_bytes(),
+                };
+                self.write(&schema.to_record())?;
+                let id = schema.id;
+                self.schemas.insert(schema_name.to_string(), schema);
+                id
+            }
+        };
+
+        let channel = ChannelRecord {
+            id: self.channels.len() as u16,
+            schema_id,
+            topic: topic.to_string(),
+            message_encoding: "cdr".to_string(),
+            metadata: BTreeMap::new(),
+        };
+        self.write(&channel.to_record())?;
+        let id = channel.id;
+        self.channels.insert(topic.to_string(), channel);
+        Ok(id)
+    }
+
+    /// Writes a serialized message of `schema_name` to `topic`. The times are in nanoseconds
+    /// since the UNIX epoch.
+    pub fn write_raw_message(
+        &mut self,
+        topic: &str,
+        schema_name: &str,
+        log_time: u64,
+        publish_time: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let channel_id = self.channel_id(topic, schema_name)?;
+        let sequence = self.sequences.entry(channel_id).or_default();
+        let message = MessageRecord {
+            channel_id,
+            sequence: *sequence,
+            log_time,
+            publish_time,
+            data,
+        };
+        *sequence = sequence.wrapping_add(1);
+
+        let chunk = &mut self.chunk;
+        if chunk.records.is_empty() {
+            chunk.message_start_time = log_time;
+            chunk.message_end_time = log_time;
+        }
+        chunk.message_start_time = chunk.message_start_time.min(log_time);
+        chunk.message_end_time = chunk.message_end_time.max(log_time);
+        chunk
+            .message_indexes
+            .entry(channel_id)
+            .or_default()
+            .push((log_time, chunk.records.len() as u64));
+        chunk.records.extend(message.to_record());
+
+        let statistics = &mut self.statistics;
+        if statistics.message_count == 0 {
+            statistics.message_start_time = log_time;
+            statistics.message_end_time = log_time;
+        }
+        statistics.message_count += 1;
+        statistics.message_start_time = statistics.message_start_time.min(log_time);
+        statistics.message_end_time = statistics.message_end_time.max(log_time);
+        *statistics
+            .channel_message_counts
+            .entry(channel_id)
+            .or_default() += 1;
+
+        if self.chunk.records.len() >= self.chunk_size {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rows of a batch produced by r2a for messages of `schema_name`, in the regular
+    /// or, if `flat` is true, the flat layout, to `topic`.
+    ///
+    /// The log time of a row is taken from the `_recv_time` column or, without it, from the
+    /// header stamp of the message. Rows without a valid time, for example with a null or
+    /// negative header stamp and no `_recv_time`, are rejected. The publish time is taken from the `_publish_time` column
+    /// that [`super::McapReader`] adds, and is the log time otherwise.
+    pub fn write_batch(
+        &mut self,
+        topic: &str,
+        schema_name: &str,
+        batch: &RecordBatch,
+        flat: bool,
+    ) -> Result<()> {
+        let messages = raw_messages_from_batch(schema_name, batch, flat)?;
+        let log_times = log_times(batch, flat)?;
+        let publish_times = timestamp_column(batch, PUBLISH_TIME_FIELD)?;
+        for (row, data) in messages.iter().enumerate() {
+            let log_time = log_times[row];
+            let publish_time = publish_times
+                .as_ref()
+                .and_then(|times| times[row])
+                .unwrap_or(log_time);
+            self.write_raw_message(topic, schema_name, log_time, publish_time, data)?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> Result<()> {
+        if self.chunk.records.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.chunk);
+        let compression = self.compression.as_str();
+        let compressed = ChunkRecord::compress(&chunk.records, compression)?;
+        let chunk_start_offset = self.offset;
+        self.write(
+            &ChunkRecord {
+                message_start_time: chunk.message_start_time,
+                message_end_time: chunk.message_end_time,
+                uncompressed_size: chunk.records.len() as u64,
+                uncompressed_crc: 0,
+                compression: compression.to_string(),
+                records: &compressed,
+            }
+            .to_record(),
+        )?;
+        let chunk_length = self.offset - chunk_start_offset;
+
+        let message_index_start = self.offset;
+        let mut message_index_offsets = BTreeMap::new();
+        for (channel_id, records) in chunk.message_indexes {
+            message_index_offsets.insert(channel_id, self.offset);
+            self.write(
+                &MessageIndexRecord {
+                    channel_id,
+                    records,
+                }
+                .to_record(),
+            )?;
+        }
+
+        self.chunk_indexes.push(ChunkIndexRecord {
+            message_start_time: chunk.message_start_time,
+            message_end_time: chunk.message_end_time,
+            chunk_start_offset,
+            chunk_length,
+            message_index_offsets,
+            message_index_length: self.offset - message_index_start,
+            compression: compression.to_string(),
+            compressed_size: compressed.len() as u64,
+            uncompressed_size: chunk.records.len() as u64,
+        });
+        Ok(())
+    }
+
+    /// Writes the last chunk, the summary and the footer, and flushes the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.finish_file()
+    }
+
+    fn finish_file(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_chunk()?;
+        let mut data_end = RecordWriter::default();
+        data_end.u32(0);
+        self.write(&data_end.record(OP_DATA_END))?;
+
+        let summary_start = self.offset;
+        let mut summary_offsets = vec![];
+        let schemas: Vec<Vec<u8>> = self.schemas.values().map(SchemaRecord::to_record).collect();
+        let channels: Vec<Vec<u8>> = self
+            .channels
+            .values()
+            .map(ChannelRecord::to_record)
+            .collect();
+        self.statistics.schema_count = self.schemas.len() as u16;
+        self.statistics.channel_count = self.channels.len() as u32;
+        self.statistics.chunk_count = self.chunk_indexes.len() as u32;
+        let statistics = vec![self.statistics.to_record()];
+        let chunk_indexes: Vec<Vec<u8>> = self
+            .chunk_indexes
+            .iter()
+            .map(ChunkIndexRecord::to_record)
+            .collect();
+        for (group_opcode, records) in [
+            (OP_SCHEMA, schemas),
+            (OP_CHANNEL, channels),
+            (OP_STATISTICS, statistics),
+            (OP_CHUNK_INDEX, chunk_indexes),
+        ] {
+            if records.is_empty() {
+                continue;
+            }
+            let group_start = self.offset;
+            for record in records {
+                self.write(&record)?;
+            }
+            summary_offsets.push(SummaryOffsetRecord {
+                group_opcode,
+                group_start,
+                group_length: self.offset - group_start,
+            });
+        }
+
+        let summary_offset_start = self.offset;
+        for summary_offset in summary_offsets {
+            self.write(&summary_offset.to_record())?;
+        }
+        self.write(
+            &FooterRecord {
+                summary_start,
+                summary_offset_start,
+                summary_crc: 0,
+            }
+            .to_record(),
+        )?;
+        self.write(MAGIC)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for McapWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_file() {
+            log::error!("Unable to finish the MCAP file: {}", e);
+        }
+    }
+}
+
+/// The log time of each row, from the `_recv_time` column or the header stamp. Null and
+/// negative times are skipped.
+fn log_times(batch: &RecordBatch, flat: bool) -> Result<Vec<u64>> {
+    let recv_times = timestamp_column(batch, RECV_TIME_FIELD)?;
+    let stamps = header_stamps(batch, flat);
+    (0..batch.num_rows())
+        .map(|row| {
+            recv_times
+                .as_ref()
+                .and_then(|times| times[row])
+                .or_else(|| stamps.as_ref().and_then(|stamps| stamps[row]))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Row {} has no log time, it needs a valid {} or header stamp",
+                        row,
+                        RECV_TIME_FIELD
+                    )
+                })
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::cdr::CdrMessage;
+    use crate::mcap::McapReader;
+    use crate::msgs::builtin_interfaces::msg::Time;
+    use crate::msgs::sensor_msgs::msg::LaserScan;
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::{new_raw_row_builder, row_context_fields, schema_arrow_fields, RowContext};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, TimestampNanosecondType};
+    use arrow_array::Array;
+    use arrow_schema::Schema;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn scan_batch(flat: bool, with_context: bool) -> RecordBatch {
+        let mut fields = schema_arrow_fields("sensor_msgs/msg/LaserScan", flat, false).unwrap();
+        if with_context {
+            fields.extend(row_context_fields(false));
+        }
+        let mut row_builder =
+            new_raw_row_builder("sensor_msgs/msg/LaserScan", fields.iter().collect(), flat)
+                .unwrap();
+        for sec in 1..=5 {
+            let scan = LaserScan {
+                header: Header {
+                    stamp: Time { sec, nanosec: 0 },
+                    frame_id: "laser".to_string(),
+                },
+                ranges: vec![sec as f32],
+                ..Default::default()
+            };
+            let bytes = scan.to_serialized_bytes().unwrap();
+            if with_context {
+                let ctx = RowContext::new("/scan", sec as u64)
+                    .with_recv_time(UNIX_EPOCH + Duration::from_millis(sec as u64 * 1000 + 1));
+                row_builder.add_raw_row_with_context(&bytes, &ctx).unwrap();
+            } else {
+                row_builder.add_raw_row(&bytes).unwrap();
+            }
+        }
+        RecordBatch::try_new(
+            Arc::new(Schema::new(fields.clone())),
+            row_builder.to_arc_arrays(),
+        )
+        .unwrap()
+    }
+
+    fn read_ranges(batches: &[RecordBatch]) -> Vec<f32> {
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let ranges = batch.column_by_name("ranges").unwrap().as_list::<i64>();
+                (0..ranges.len())
+                    .map(|i| ranges.value(i).as_primitive::<Float32Type>().value(0))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        for compression in [
+            McapCompression::None,
+            McapCompression::Zstd,
+            McapCompression::Lz4,
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "r2a_write_{:?}_{}.mcap",
+                compression,
+                std::process::id()
+            ));
+            let mut writer = McapWriter::create(&path)
+                .unwrap()
+                .with_compression(compression)
+                .with_chunk_size(300);
+            writer
+                .write_batch(
+                    "/scan",
+                    "sensor_msgs/msg/LaserScan",
+                    &scan_batch(true, true),
+                    true,
+                )
+                .unwrap();
+            writer
+                .write_batch(
+                    "/scan_stamped",
+                    "sensor_msgs/msg/LaserScan",
+                    &scan_batch(false, false),
+                    false,
+                )
+                .unwrap();
+            // A topic keeps the type of its first message.
+            let header = Header::default().to_serialized_bytes().unwrap();
+            assert!(writer
+                .write_raw_message("/scan", "std_msgs/msg/Header", 0, 0, &header)
+                .is_err());
+            writer.finish().unwrap();
+
+            let reader = McapReader::open(&path).unwrap();
+            assert_eq!(reader.topics(), ["/scan", "/scan_stamped"]);
+            assert_eq!(reader.channels()[0].schema_encoding, "ros2msg");
+            assert_eq!(reader.message_start_time(), Some(1_000_000_000));
+
+            let batches: Vec<RecordBatch> = reader
+                .topic_batches("/scan", true)
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(read_ranges(&batches), [1.0, 2.0, 3.0, 4.0, 5.0]);
+            let log_times = batches[0]
+                .column_by_name(RECV_TIME_FIELD)
+                .unwrap()
+                .as_primitive::<TimestampNanosecondType>();
+            assert_eq!(log_times.value(0), 1_001_000_000);
+
+            // Without a _recv_time column the header stamps are the log times.
+            let batches: Vec<RecordBatch> = reader
+                .topic_batches_in_range("/scan_stamped", false, 2_000_000_000..=3_000_000_000)
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(read_ranges(&batches), [2.0, 3.0]);
+            let publish_times = batches[0]
+                .column_by_name(PUBLISH_TIME_FIELD)
+                .unwrap()
+                .as_primitive::<TimestampNanosecondType>();
+            assert_eq!(publish_times.value(0), 2_000_000_000);
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_invalid_log_times() {
+        let stamped_batch = |secs: &[i32], recv_times: Option<&[i64]>| {
+            let mut fields = schema_arrow_fields("sensor_msgs/msg/LaserScan", true, false).unwrap();
+            if recv_times.is_some() {
+                fields.extend(row_context_fields(false));
+            }
+            let mut row_builder =
+                new_raw_row_builder("sensor_msgs/msg/LaserScan", fields.iter().collect(), true)
+                    .unwrap();
+            for (row, &sec) in secs.iter().enumerate() {
+                let scan = LaserScan {
+                    header: Header {
+                        stamp: Time { sec, nanosec: 0 },
+                        frame_id: "laser".to_string(),
+                    },
+                    ..Default::default()
+                };
+                let bytes = scan.to_serialized_bytes().unwrap();
+                match recv_times {
+                    Some(times) => {
+                        let ctx = RowContext::new("/scan", row as u64)
+                            .with_recv_time(UNIX_EPOCH + Duration::from_nanos(times[row] as u64));
+                        row_builder.add_raw_row_with_context(&bytes, &ctx).unwrap();
+                    }
+                    None => row_builder.add_raw_row(&bytes).unwrap(),
+                }
+            }
+            RecordBatch::try_new(
+                Arc::new(Schema::new(fields.clone())),
+                row_builder.to_arc_arrays(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            log_times(&stamped_batch(&[1, 2], None), true).unwrap(),
+            [1_000_000_000, 2_000_000_000]
+        );
+        // A stamp before the UNIX epoch is not a log time.
+        let error = log_times(&stamped_batch(&[1, -1], None), true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Row 1 has no log time, it needs a valid _recv_time or header stamp"
+        );
+        // The receive time takes precedence over the header stamp.
+        assert_eq!(
+            log_times(&stamped_batch(&[1, -1], Some(&[5, 6])), true).unwrap(),
+            [5, 6]
+        );
+    }
+}
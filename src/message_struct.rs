@@ -0,0 +1,212 @@
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, StringArray, StructArray};
+use arrow_schema::{DataType, Field};
+use std::fmt::Write;
+
+/// The default name of the column holding the whole message.
+pub const MESSAGE_STRUCT_FIELD: &str = "message_struct";
+
+/// The field metadata key that marks a column as the message struct column, independent of its
+/// name. The value is the encoding, see [`MessageStructEncoding::as_str`].
+pub const MESSAGE_STRUCT_ENCODING_KEY: &str = "r2a.message_struct";
+
+/// Selects how the `message_struct` column stores the whole message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageStructEncoding {
+    /// The message is stored as an Arrow StructArray that follows the exact structure of the
+    /// ROS 2 message. This is what `arrow_fields(true)` and `flat_arrow_fields(true)` produce.
+    Struct,
+    /// The message is stored as an Arrow StructArray with the flattened layout of
+    /// `flat_arrow_fields`.
+    FlatStruct,
+    /// The message is stored as a `Utf8` JSON document per row. Objects use the ROS 2 field
+    /// names, the same as `ros2 topic echo` does.
+    Json,
+}
+
+impl MessageStructEncoding {
+    /// The value stored under [`MESSAGE_STRUCT_ENCODING_KEY`] in the field metadata.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageStructEncoding::Struct => "struct",
+            MessageStructEncoding::FlatStruct => "flat_struct",
+            MessageStructEncoding::Json => "json",
+        }
+    }
+}
+
+/// Returns the encoding of the message struct column if `field` is one. A field is a message
+/// struct column if it carries the [`MESSAGE_STRUCT_ENCODING_KEY`] metadata, or if it is
+/// called `message_struct`, in which case a `Utf8` type means JSON and anything else the nested
+/// struct.
+#[allow(dead_code)]
+pub(crate) fn message_struct_encoding(field: &Field) -> Option<MessageStructEncoding> {
+    match field
+        .metadata()
+        .get(MESSAGE_STRUCT_ENCODING_KEY)
+        .map(String::as_str)
+    {
+        Some("struct") => Some(MessageStructEncoding::Struct),
+        Some("flat_struct") => Some(MessageStructEncoding::FlatStruct),
+        Some("json") => Some(MessageStructEncoding::Json),
+        Some(other) => {
+            log::error!(
+                "Invalid message struct encoding {} on field {}",
+                other,
+                field.name()
+            );
+            None
+        }
+        None if field.name() == MESSAGE_STRUCT_FIELD => match field.data_type() {
+            DataType::Utf8 => Some(MessageStructEncoding::Json),
+            _ => Some(MessageStructEncoding::Struct),
+        },
+        None => None,
+    }
+}
+
+/// Converts an existing `message_struct` StructArray (or any StructArray produced by r2a) to a
+/// `Utf8` array with one JSON document per row. Null rows stay null.
+///
+/// Nested structs become JSON objects with the field names of the struct, arrays become JSON
+/// arrays, `uint8[]` (`LargeBinary`) columns become arrays of numbers, and non-finite floats
+/// become `null`.
+///
+/// # Example
+///
+/// ```
+/// use r2a::ArrowSupport;
+/// use r2a::RowBuilder;
+/// use arrow_array::Array;
+///
+/// let fields = r2r::std_msgs::msg::Header::arrow_fields(true);
+/// let mut row_builder = r2r::std_msgs::msg::Header::new_row_builder(fields.iter().collect());
+/// row_builder.add_row(&r2r::std_msgs::msg::Header::default()).unwrap();
+/// let arrays = row_builder.to_arc_arrays();
+///
+/// let message_struct = arrays[2].as_any().downcast_ref::<arrow_array::StructArray>().unwrap();
+/// let json = r2a::message_struct_to_json(message_struct);
+/// assert_eq!(json.value(0), r#"{"stamp":{"sec":0,"nanosec":0},"frame_id":""}"#);
+/// ```
+pub fn message_struct_to_json(array: &StructArray) -> StringArray {
+    let mut buffer = String::new();
+    (0..array.len())
+        .map(|row| {
+            if array.is_null(row) {
+                None
+            } else {
+                buffer.clear();
+                write_json_value(array, row, &mut buffer);
+                Some(buffer.clone())
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn write_json_value(array: &dyn Array, row: usize, out: &mut String) {
+    if array.is_null(row) {
+        out.push_str("null");
+        return;
+    }
+    match array.data_type() {
+        DataType::Null => out.push_str("null"),
+        DataType::Boolean => {
+            let _ = write!(out, "{}", array.as_boolean().value(row));
+        }
+        DataType::Int8 => write_number(out, array.as_primitive::<Int8Type>().value(row)),
+        DataType::Int16 => write_number(out, array.as_primitive::<Int16Type>().value(row)),
+        DataType::Int32 => write_number(out, array.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => write_number(out, array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => write_number(out, array.as_primitive::<UInt8Type>().value(row)),
+        DataType::UInt16 => write_number(out, array.as_primitive::<UInt16Type>().value(row)),
+        DataType::UInt32 => write_number(out, array.as_primitive::<UInt32Type>().value(row)),
+        DataType::UInt64 => write_number(out, array.as_primitive::<UInt64Type>().value(row)),
+        DataType::Float32 => {
+            let value = array.as_primitive::<Float32Type>().value(row);
+            if value.is_finite() {
+                write_number(out, value)
+            } else {
+                out.push_str("null")
+            }
+        }
+        DataType::Float64 => {
+            let value = array.as_primitive::<Float64Type>().value(row);
+            if value.is_finite() {
+                write_number(out, value)
+            } else {
+                out.push_str("null")
+            }
+        }
+        DataType::Utf8 => write_json_string(out, array.as_string::<i32>().value(row)),
+        DataType::LargeUtf8 => write_json_string(out, array.as_string::<i64>().value(row)),
+        DataType::Binary => write_json_bytes(out, array.as_binary::<i32>().value(row)),
+        DataType::LargeBinary => write_json_bytes(out, array.as_binary::<i64>().value(row)),
+        DataType::List(_) => write_json_list(out, array.as_list::<i32>().value(row).as_ref()),
+        DataType::LargeList(_) => write_json_list(out, array.as_list::<i64>().value(row).as_ref()),
+        DataType::Struct(fields) => {
+            let struct_array = array.as_struct();
+            out.push('{');
+            for (i, (field, column)) in fields.iter().zip(struct_array.columns()).enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(out, field.name());
+                out.push(':');
+                write_json_value(column.as_ref(), row, out);
+            }
+            out.push('}');
+        }
+        other => {
+            log::warn!("Unsupported data type in JSON conversion: {}", other);
+            out.push_str("null");
+        }
+    }
+}
+
+fn write_number<N: std::fmt::Display>(out: &mut String, value: N) {
+    let _ = write!(out, "{}", value);
+}
+
+fn write_json_list(out: &mut String, values: &dyn Array) {
+    out.push('[');
+    for i in 0..values.len() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json_value(values, i, out);
+    }
+    out.push(']');
+}
+
+fn write_json_bytes(out: &mut String, bytes: &[u8]) {
+    out.push('[');
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_number(out, byte);
+    }
+    out.push(']');
+}
+
+pub(crate) fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
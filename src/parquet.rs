@@ -0,0 +1,437 @@
+//! Writes topics to rolling Parquet files. Requires the `parquet` feature.
+//!
+//! A [`ParquetSink`] keeps one `ArrowWriter` per topic, in a directory per topic. A file is
+//! closed and the next one started when it reaches the configured size, row count or age.
+//! Files are written with a `.tmp` suffix that is removed when they are closed, so readers of
+//! the directory only see complete files. The footer of every file holds the
+//! [`crate::file_metadata`] of its topic, and the Arrow schema with the metadata of its fields.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::parquet::{Compression, ParquetSink};
+//! use r2a::rosbag2::Rosbag2Reader;
+//!
+//! let reader = Rosbag2Reader::open("recordings/run_42").unwrap();
+//! let mut sink = ParquetSink::new("datasets/run_42")
+//!     .unwrap()
+//!     .with_max_file_rows(100_000)
+//!     .with_compression(Compression::SNAPPY);
+//! for topic in reader.topics() {
+//!     for batch in reader.topic_batches(&topic.name, true).unwrap() {
+//!         sink.write_batch(&topic.name, &topic.type_name, &batch.unwrap())
+//!             .unwrap();
+//!     }
+//! }
+//! for file in sink.finish().unwrap() {
+//!     println!("{}", file.display());
+//! }
+//! ```
+
+use crate::{ArrowSupport, RowBuilder};
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::file::metadata::KeyValue;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{bail, Context, Result};
+use arrow_array::RecordBatch;
+use arrow_schema::{Field, Schema, SchemaRef};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use ::parquet::basic::{Compression, ZstdLevel};
+
+/// The maximum number of rows of a row group, unless set with
+/// [`ParquetSink::with_row_group_size`].
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// The name of the directory and the files of a topic: the topic without its leading `/`, and
+/// `.` instead of the other `/`, for example `camera.image_raw` for `/camera/image_raw`.
+pub fn topic_file_name(topic: &str) -> String {
+    topic.trim_start_matches('/').replace('/', ".")
+}
+
+/// The file a topic is being written to.
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    rows: usize,
+    opened_at: Instant,
+}
+
+impl OpenFile {
+    fn close(self) -> Result<PathBuf> {
+        self.writer
+            .close()
+            .with_context(|| format!("Unable to close {}", self.tmp_path.display()))?;
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Unable to rename {}", self.tmp_path.display()))?;
+        Ok(self.path)
+    }
+}
+
+struct TopicFiles {
+    schema_name: String,
+    schema: SchemaRef,
+    file: Option<OpenFile>,
+    next_index: usize,
+}
+
+/// Writes the batches of each topic to its own sequence of Parquet files.
+pub struct ParquetSink {
+    directory: PathBuf,
+    max_file_size: Option<usize>,
+    max_file_rows: Option<usize>,
+    max_file_duration: Option<Duration>,
+    row_group_size: usize,
+    compression: Compression,
+    topics: BTreeMap<String, TopicFiles>,
+    /// The files that were closed.
+    files: Vec<PathBuf>,
+}
+
+impl ParquetSink {
+    /// Creates a sink that writes to `directory`, which is created if needed. The files of a
+    /// topic are `<directory>/<topic>/<topic>_<index>.parquet`, see [`topic_file_name`].
+    pub fn new(directory: impl AsRef<Path>) -> Result<Self> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Unable to create {}", directory.display()))?;
+        Ok(ParquetSink {
+            directory: directory.to_path_buf(),
+            max_file_size: None,
+            max_file_rows: None,
+            max_file_duration: None,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            compression: Compression::SNAPPY,
+            topics: BTreeMap::new(),
+            files: vec![],
+        })
+    }
+
+    /// Starts a new file once a file reaches `bytes`, counting the rows it buffers for the
+    /// current row group. Files are at least one batch or row group long.
+    pub fn with_max_file_size(mut self, bytes: usize) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Starts a new file after `rows` rows. Batches are split if needed.
+    pub fn with_max_file_rows(mut self, rows: usize) -> Self {
+        self.max_file_rows = Some(rows.max(1));
+        self
+    }
+
+    /// Starts a new file for the first batch written `duration` after the current file was
+    /// opened.
+    pub fn with_max_file_duration(mut self, duration: Duration) -> Self {
+        self.max_file_duration = Some(duration);
+        self
+    }
+
+    /// Sets the maximum number of rows of a row group.
+    pub fn with_row_group_size(mut self, rows: usize) -> Self {
+        self.row_group_size = rows.max(1);
+        self
+    }
+
+    /// Sets the compression of the columns, snappy by default.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Writes a batch of messages of `schema_name` to the files of `topic`. All batches of a
+    /// topic must have the same schema.
+    pub fn write_batch(
+        &mut self,
+        topic: &str,
+        schema_name: &str,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        let properties = self.writer_properties(topic, schema_name);
+        let topic_files = self
+            .topics
+            .entry(topic.to_string())
+            .or_insert_with(|| TopicFiles {
+                schema_name: schema_name.to_string(),
+                schema: batch.schema(),
+                file: None,
+                next_index: 0,
+            });
+        if topic_files.schema_name != schema_name || topic_files.schema != batch.schema() {
+            bail!(
+                "Topic {} was written with a different schema than this batch of {}",
+                topic,
+                schema_name
+            );
+        }
+
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            if let Some(file) = &topic_files.file {
+                let expired = self
+                    .max_file_duration
+                    .is_some_and(|duration| file.opened_at.elapsed() >= duration);
+                if expired {
+                    self.files.push(topic_files.file.take().unwrap().close()?);
+                }
+            }
+            if topic_files.file.is_none() {
+                topic_files.file = Some(open_file(
+                    &self.directory,
+                    topic,
+                    topic_files,
+                    properties.clone(),
+                )?);
+            }
+            let file = topic_files.file.as_mut().unwrap();
+
+            let rows = match self.max_file_rows {
+                Some(max_rows) => (max_rows - file.rows).min(batch.num_rows() - offset),
+                None => batch.num_rows() - offset,
+            };
+            file.writer
+                .write(&batch.slice(offset, rows))
+                .with_context(|| format!("Unable to write to {}", file.tmp_path.display()))?;
+            file.rows += rows;
+            offset += rows;
+
+            let full = self
+                .max_file_rows
+                .is_some_and(|max_rows| file.rows >= max_rows)
+                || self.max_file_size.is_some_and(|max_size| {
+                    file.writer.bytes_written() + file.writer.in_progress_size() >= max_size
+                });
+            if full {
+                self.files.push(topic_files.file.take().unwrap().close()?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the rows accumulated by the row builder of a topic, created with `fields`, and
+    /// clears the row builder. Does nothing if the row builder is empty.
+    pub fn write_rows<'a, T: ArrowSupport<'a>>(
+        &mut self,
+        topic: &str,
+        fields: &[Field],
+        row_builder: &mut impl RowBuilder<'a, T>,
+    ) -> Result<()> {
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(fields.to_vec())),
+            row_builder.to_arc_arrays(),
+        )?;
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        self.write_batch(topic, T::schema_name(), &batch)
+    }
+
+    fn writer_properties(&self, topic: &str, schema_name: &str) -> WriterProperties {
+        let metadata = crate::file_metadata(topic, schema_name)
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value))
+            .collect();
+        WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_size)
+            .set_compression(self.compression)
+            .set_key_value_metadata(Some(metadata))
+            .build()
+    }
+
+    /// Closes the open file of `topic`, if any. The next batch of the topic starts a new file.
+    pub fn close_topic(&mut self, topic: &str) -> Result<()> {
+        if let Some(file) = self
+            .topics
+            .get_mut(topic)
+            .and_then(|topic_files| topic_files.file.take())
+        {
+            self.files.push(file.close()?);
+        }
+        Ok(())
+    }
+
+    fn close_files(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for topic_files in self.topics.values_mut() {
+            if let Some(file) = topic_files.file.take() {
+                match file.close() {
+                    Ok(path) => self.files.push(path),
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+        result
+    }
+
+    /// Closes the open files and returns the files written by the sink, in the order they were
+    /// closed.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        self.close_files()?;
+        Ok(std::mem::take(&mut self.files))
+    }
+}
+
+/// Opens the next file of a topic.
+fn open_file(
+    directory: &Path,
+    topic: &str,
+    topic_files: &mut TopicFiles,
+    properties: WriterProperties,
+) -> Result<OpenFile> {
+    let name = topic_file_name(topic);
+    let directory = directory.join(&name);
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Unable to create {}", directory.display()))?;
+    // Continue after the files of earlier runs, complete or not, instead of replacing them.
+    let (path, tmp_path) = loop {
+        let file_name = format!("{}_{:05}.parquet", name, topic_files.next_index);
+        let path = directory.join(&file_name);
+        let tmp_path = directory.join(format!("{}.tmp", file_name));
+        topic_files.next_index += 1;
+        if !path.exists() && !tmp_path.exists() {
+            break (path, tmp_path);
+        }
+    };
+
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("Unable to create {}", tmp_path.display()))?;
+    let writer = ArrowWriter::try_new(file, topic_files.schema.clone(), Some(properties))?;
+    Ok(OpenFile {
+        writer,
+        path,
+        tmp_path,
+        rows: 0,
+        opened_at: Instant::now(),
+    })
+}
+
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.close_files() {
+            log::error!("Unable to close the Parquet files: {}", e);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::{SCHEMA_NAME_METADATA_KEY, TOPIC_METADATA_KEY};
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn headers(count: usize) -> Vec<Header> {
+        (0..count)
+            .map(|i| Header {
+                frame_id: format!("frame_{}", i),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("r2a_parquet_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_roll_by_rows() {
+        let dir = temp_dir("rows");
+        let fields = Header::flat_arrow_fields(false);
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        let mut sink = ParquetSink::new(&dir)
+            .unwrap()
+            .with_max_file_rows(4)
+            .with_row_group_size(3)
+            .with_compression(Compression::ZSTD(ZstdLevel::default()));
+        for header in headers(10) {
+            row_builder.add_row(&header).unwrap();
+        }
+        sink.write_rows("/robot/header", &fields, &mut row_builder)
+            .unwrap();
+        // The builder was cleared.
+        sink.write_rows("/robot/header", &fields, &mut row_builder)
+            .unwrap();
+        let files = sink.finish().unwrap();
+
+        let rows: Vec<i64> = files
+            .iter()
+            .map(|file| {
+                assert!(file.starts_with(dir.join("robot.header")));
+                let reader = SerializedFileReader::new(File::open(file).unwrap()).unwrap();
+                let metadata = reader.metadata().file_metadata();
+                let key_value: Vec<(&str, Option<&str>)> = metadata
+                    .key_value_metadata()
+                    .unwrap()
+                    .iter()
+                    .map(|kv| (kv.key.as_str(), kv.value.as_deref()))
+                    .collect();
+                assert!(
+                    key_value.contains(&(SCHEMA_NAME_METADATA_KEY, Some("std_msgs/msg/Header")))
+                );
+                assert!(key_value.contains(&(TOPIC_METADATA_KEY, Some("/robot/header"))));
+                assert_eq!(
+                    reader.metadata().num_row_groups() as i64,
+                    (metadata.num_rows() + 2) / 3
+                );
+                metadata.num_rows()
+            })
+            .collect();
+        assert_eq!(rows, [4, 4, 2]);
+
+        // A new sink continues after the existing files.
+        let mut sink = ParquetSink::new(&dir).unwrap();
+        for header in headers(1) {
+            row_builder.add_row(&header).unwrap();
+        }
+        sink.write_rows("/robot/header", &fields, &mut row_builder)
+            .unwrap();
+        drop(sink);
+        assert!(dir.join("robot.header/robot.header_00003.parquet").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_roll_by_size_and_duration() {
+        let dir = temp_dir("size");
+        let fields = Header::arrow_fields(false);
+        let mut row_builder = Header::new_row_builder(fields.iter().collect());
+        let mut sink = ParquetSink::new(&dir).unwrap().with_max_file_size(1);
+        for header in headers(3) {
+            row_builder.add_row(&header).unwrap();
+            sink.write_rows("/header", &fields, &mut row_builder)
+                .unwrap();
+        }
+        assert_eq!(sink.finish().unwrap().len(), 3);
+
+        let mut sink = ParquetSink::new(&dir)
+            .unwrap()
+            .with_max_file_duration(Duration::ZERO);
+        for header in headers(2) {
+            row_builder.add_row(&header).unwrap();
+            sink.write_rows("/header", &fields, &mut row_builder)
+                .unwrap();
+        }
+        // The file opened for the last batch is still open.
+        assert!(dir.join("header/header_00004.parquet.tmp").exists());
+        assert_eq!(sink.finish().unwrap().len(), 2);
+        assert!(!dir.join("header/header_00004.parquet.tmp").exists());
+
+        let other_fields = Header::flat_arrow_fields(false);
+        let mut sink = ParquetSink::new(&dir).unwrap();
+        let batch = RecordBatch::new_empty(Arc::new(Schema::new(fields.clone())));
+        sink.write_batch("/header", "std_msgs/msg/Header", &batch)
+            .unwrap();
+        let other = RecordBatch::new_empty(Arc::new(Schema::new(other_fields)));
+        assert!(sink
+            .write_batch("/header", "std_msgs/msg/Header", &other)
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! The column projections of the topic batches of the bag and MCAP readers.
+
+use anyhow::{bail, Result};
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchOptions};
+use arrow_schema::{Field, Schema, SchemaRef};
+use std::sync::Arc;
+
+/// The columns of a topic that are built, and the order in which they are returned.
+pub(crate) struct Projection {
+    /// The indices of the built columns in the fields of the topic, in ascending order.
+    columns: Vec<usize>,
+    /// The position in `columns` of each returned column.
+    order: Vec<usize>,
+    schema: SchemaRef,
+}
+
+impl Projection {
+    /// All fields of the topic, in their order.
+    pub fn all(fields: &[Field]) -> Self {
+        Projection {
+            columns: (0..fields.len()).collect(),
+            order: (0..fields.len()).collect(),
+            schema: Arc::new(Schema::new(fields.to_vec())),
+        }
+    }
+
+    /// The fields at `projection`, in the order of `projection`.
+    pub fn new(fields: &[Field], projection: &[usize]) -> Result<Self> {
+        let mut columns = projection.to_vec();
+        columns.sort_unstable();
+        columns.dedup();
+        if columns.len() != projection.len() {
+            bail!("The projection {:?} has duplicate columns", projection);
+        }
+        if let Some(&index) = columns.last().filter(|&&index| index >= fields.len()) {
+            bail!(
+                "Column {} is out of range, the topic has {} columns",
+                index,
+                fields.len()
+            );
+        }
+        let order = projection
+            .iter()
+            .map(|index| columns.binary_search(index).unwrap())
+            .collect();
+        let schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|&index| fields[index].clone())
+                .collect::<Vec<_>>(),
+        ));
+        Ok(Projection {
+            columns,
+            order,
+            schema,
+        })
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The built fields, in the order of the fields of the topic.
+    pub fn fields<'f>(&self, fields: &'f [Field]) -> Vec<&'f Field> {
+        self.columns.iter().map(|&index| &fields[index]).collect()
+    }
+
+    /// Creates a batch of `rows` rows from the arrays of the built fields, in their order.
+    pub fn batch(&self, arrays: Vec<ArrayRef>, rows: usize) -> Result<RecordBatch> {
+        let arrays = self
+            .order
+            .iter()
+            .map(|&position| arrays[position].clone())
+            .collect();
+        Ok(RecordBatch::try_new_with_options(
+            self.schema.clone(),
+            arrays,
+            &RecordBatchOptions::new().with_row_count(Some(rows)),
+        )?)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{ArrowSupport, RowBuilder, RowContext};
+use anyhow::Result;
+use arrow_array::Array;
+use arrow_schema::Field;
+use std::sync::Arc;
+
+/// A row builder for serialized messages of a ROS type that is only known at run time, by its
+/// schema name, for example when reading recorded data. Created with [`new_raw_row_builder`].
+pub trait RawRowBuilder<'a> {
+    /// The schema name of the messages, for example `sensor_msgs/msg/LaserScan`.
+    fn schema_name(&self) -> &'static str;
+
+    /// Deserializes a message and adds it to the row builder, like [`RowBuilder::add_raw_row`].
+    fn add_raw_row(&mut self, msg: &[u8]) -> Result<()>;
+
+    /// Deserializes a message and adds it to the row builder together with its per-row
+    /// context, like [`RowBuilder::add_row_with_context`].
+    fn add_raw_row_with_context(&mut self, msg: &[u8], ctx: &RowContext) -> Result<()>;
+
+    /// Converts the accumulated rows into Arrow arrays, like [`RowBuilder::to_arc_arrays`].
+    fn to_arc_arrays(&mut self) -> Vec<Arc<dyn Array>>;
+}
+
+struct TypedRawRowBuilder<T, B> {
+    row_builder: B,
+    deserialize: fn(&[u8]) -> Result<T>,
+}
+
+impl<'a, T, B> RawRowBuilder<'a> for TypedRawRowBuilder<T, B>
+where
+    T: ArrowSupport<'a>,
+    B: RowBuilder<'a, T>,
+{
+    fn schema_name(&self) -> &'static str {
+        T::schema_name()
+    }
+
+    fn add_raw_row(&mut self, msg: &[u8]) -> Result<()> {
+        self.row_builder.add_raw_row(msg)
+    }
+
+    fn add_raw_row_with_context(&mut self, msg: &[u8], ctx: &RowContext) -> Result<()> {
+        let msg = (self.deserialize)(msg)?;
+        self.row_builder.add_row_with_context(&msg, ctx)
+    }
+
+    fn to_arc_arrays(&mut self) -> Vec<Arc<dyn Array>> {
+        self.row_builder.to_arc_arrays()
+    }
+}
+
+/// Boxes the row builder of `T` for the generated schema dispatch.
+#[allow(dead_code)]
+pub(crate) fn boxed_raw_row_builder<'a, T>(
+    arrow_fields: Vec<&'a Field>,
+    flat: bool,
+    deserialize: fn(&[u8]) -> Result<T>,
+) -> Box<dyn RawRowBuilder<'a> + 'a>
+where
+    T: ArrowSupport<'a> + 'a,
+    T::RowBuilderType: RowBuilder<'a, T> + 'a,
+    T::FlatRowBuilderType: RowBuilder<'a, T> + 'a,
+{
+    if flat {
+        Box::new(TypedRawRowBuilder {
+            row_builder: T::new_flat_row_builder(arrow_fields),
+            deserialize,
+        })
+    } else {
+        Box::new(TypedRawRowBuilder {
+            row_builder: T::new_row_builder(arrow_fields),
+            deserialize,
+        })
+    }
+}
+
+/// Returns the Arrow fields of a supported schema, like `ArrowSupport::arrow_fields` or, if
+/// `flat` is true, `ArrowSupport::flat_arrow_fields`. Returns `None` for unknown schemas.
+pub fn schema_arrow_fields(
+    schema_name: &str,
+    flat: bool,
+    include_msg_struct: bool,
+) -> Option<Vec<Field>> {
+    if !crate::get_supported_schemas().contains(&schema_name) {
+        return None;
+    }
+    Some(if flat {
+        crate::ros_mapper::map_ros_schema_to_flat_arrow_fields(schema_name, include_msg_struct)
+    } else {
+        crate::ros_mapper::map_ros_schema_to_arrow_fields(schema_name, include_msg_struct)
+    })
+}
+
+/// Creates a row builder for the serialized messages of `schema_name`, or returns `None` if the
+/// schema is not supported.
+///
+/// # Arguments
+///
+/// * `schema_name` - The schema name, for example `sensor_msgs/msg/LaserScan`.
+/// * `arrow_fields` - The requested columns, a subset of [`schema_arrow_fields`] with the same
+///   `flat`, plus optional message struct and context columns.
+/// * `flat` - Selects the flat layout.
+///
+/// # Example
+///
+/// ```
+/// let fields = r2a::schema_arrow_fields("std_msgs/msg/Header", true, false).unwrap();
+/// let mut row_builder =
+///     r2a::new_raw_row_builder("std_msgs/msg/Header", fields.iter().collect(), true).unwrap();
+/// let arrow_arrays = row_builder.to_arc_arrays();
+/// ```
+pub fn new_raw_row_builder<'a>(
+    schema_name: &str,
+    arrow_fields: Vec<&'a Field>,
+    flat: bool,
+) -> Option<Box<dyn RawRowBuilder<'a> + 'a>> {
+    crate::ros_mapper::new_raw_row_builder(schema_name, arrow_fields, flat)
+}
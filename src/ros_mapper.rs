@@ -1,8 +1,11 @@
 use anyhow::Result;
-use arrow_array::builder::ArrayBuilder;
 use arrow_array::Array;
 use std::sync::Arc;
 
+use crate::message_struct::MessageStructEncoding;
+use crate::row_context::RowContext;
+use crate::row_filter::{RowFilter, RowFilterStats};
+
 /// The `RowBuilder` trait is implemented for each ROS 2 message type by a code generator.
 /// It serves as an accumulator that collects records and converts them into a collection
 /// of Arrow arrays. This trait is responsible for managing how records are added and stored
@@ -48,6 +51,24 @@ pub trait RowBuilder<'a, T> {
     /// fails for any reason.
     fn add_row(&mut self, msg: &T) -> Result<()>;
 
+    /// Adds a ROS 2 message of type `T` to the row builder together with its per-row context.
+    ///
+    /// Works like `add_row`, but also fills the reserved context columns (`_recv_time`,
+    /// `_topic`, `_seq` and `_publisher_gid`) if they were part of the fields the builder was
+    /// created with. See [`crate::row_context_fields`]. Rows added with `add_row` get nulls in
+    /// these columns.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - A reference to a message of type `T` that will be added to the builder.
+    /// * `ctx` - The receive time, topic, sequence number and optional publisher GID of the message.
+    ///
+    /// # Errors
+    ///
+    /// This method returns a `Result` that can indicate an error if the addition of the message
+    /// fails for any reason.
+    fn add_row_with_context(&mut self, msg: &T, ctx: &RowContext) -> Result<()>;
+
     /// Deserializes then adds raw binary data (usually in the form of a serialized message) to the row builder.
     ///
     /// # Arguments
@@ -60,6 +81,19 @@ pub trait RowBuilder<'a, T> {
     /// processed or added correctly.
     fn add_raw_row(&mut self, msg: &[u8]) -> Result<()>;
 
+    /// Sets a filter on the row builder. Messages rejected by the filter are skipped by
+    /// `add_row`, `add_row_with_context` and `add_raw_row` without an error. Setting a new
+    /// filter replaces the previous one and its counters.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The predicates, decimation and rate limit to apply. See [`RowFilter`].
+    fn set_filter(&mut self, filter: RowFilter<T>);
+
+    /// Returns how many rows were accepted and dropped by the filter set with `set_filter`.
+    /// If no filter is set, all counters are zero.
+    fn filter_stats(&self) -> RowFilterStats;
+
     /// Converts the accumulated rows into a vector of Arrow arrays and resets the internal state
     /// of the builder.
     ///
@@ -221,16 +255,54 @@ pub trait ArrowSupport<'a> {
     /// An Arrow schema (`arrow_schema::Schema`) that represents the full structure of the ROS 2
     /// message type plus the optional `message_struct` field.
     fn flat_arrow_schema(include_msg_struct: bool) -> arrow_schema::Schema;
+
+    /// Returns the field definition of a message struct column, which holds the whole message
+    /// in a single column.
+    ///
+    /// Both row builders recognize the column by the [`crate::MESSAGE_STRUCT_ENCODING_KEY`]
+    /// metadata of the field, so the column can have any name. To store only the message
+    /// struct column, without the top-level columns, create the row builder with this field
+    /// alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column, for example `message_struct`.
+    /// * `encoding` - Whether the message is stored as a nested struct, a flat struct or JSON text.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use r2a::ArrowSupport;
+    /// use r2a::MessageStructEncoding;
+    /// use r2r::std_msgs::msg::Header;
+    ///
+    /// let fields = vec![Header::message_struct_field("header", MessageStructEncoding::FlatStruct)];
+    /// let row_builder = Header::new_flat_row_builder(fields.iter().collect());
+    /// ```
+    fn message_struct_field(name: &str, encoding: MessageStructEncoding) -> arrow_schema::Field;
+
+    /// Appends this message as one row to a StructBuilder created from `arrow_fields(false)`.
+    /// This is how the `message_struct` column is built, and it can be used to embed messages
+    /// in custom tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `builder` - A StructBuilder with the fields returned by `arrow_fields(false)`.
+    fn append_to_struct_builder(&self, builder: &mut arrow_array::builder::StructBuilder);
 }
 
-#[cfg(feature = "default")]
+#[cfg(any(feature = "default", feature = "offline"))]
 include!(concat!(env!("OUT_DIR"), "/generated_arrow_mappers.rs"));
 
-#[cfg(test)]
+#[cfg(all(test, feature = "default"))]
 mod tests {
 
     use super::ArrowSupport;
     use super::RowBuilder;
+    use crate::row_context::row_context_fields;
+    use crate::row_context::RowContext;
+    use crate::row_filter::RowFilter;
+    use arrow_array::Array;
     use r2r::builtin_interfaces::msg::Time;
     use r2r::std_msgs::msg::Header;
 
@@ -317,4 +389,269 @@ mod tests {
         };
         assert!(is_correct_struct);
     }
+
+    #[test]
+    fn test_add_row_with_context() {
+        let msg = Header {
+            stamp: Time { sec: 0, nanosec: 0 },
+            frame_id: "test_frame".to_string(),
+        };
+
+        for flat in [false, true] {
+            let mut fields = if flat {
+                Header::flat_arrow_fields(false)
+            } else {
+                Header::arrow_fields(false)
+            };
+            fields.extend(row_context_fields(true));
+            let field_count = fields.len();
+
+            let arrays = if flat {
+                let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+                let ctx = RowContext::new("/header", 0).with_publisher_gid(&[1, 2, 3]);
+                assert!(row_builder.add_row_with_context(&msg, &ctx).is_ok());
+                assert!(row_builder.add_row(&msg).is_ok());
+                row_builder.to_arc_arrays()
+            } else {
+                let mut row_builder = Header::new_row_builder(fields.iter().collect());
+                let ctx = RowContext::new("/header", 0).with_publisher_gid(&[1, 2, 3]);
+                assert!(row_builder.add_row_with_context(&msg, &ctx).is_ok());
+                assert!(row_builder.add_row(&msg).is_ok());
+                row_builder.to_arc_arrays()
+            };
+
+            assert_eq!(arrays.len(), field_count);
+            for (array, field) in arrays.iter().zip(fields.iter()) {
+                assert_eq!(array.len(), 2);
+                assert_eq!(array.data_type(), field.data_type());
+            }
+
+            let seq = arrays[field_count - 2]
+                .as_any()
+                .downcast_ref::<arrow_array::UInt64Array>()
+                .unwrap();
+            assert_eq!(seq.value(0), 0);
+            assert!(seq.is_null(1));
+
+            let topic = arrays[field_count - 3]
+                .as_any()
+                .downcast_ref::<arrow_array::StringArray>()
+                .unwrap();
+            assert_eq!(topic.value(0), "/header");
+            assert!(topic.is_null(1));
+        }
+    }
+
+    #[test]
+    fn test_row_filter() {
+        use r2r::geometry_msgs::msg::PoseStamped;
+
+        let fields = PoseStamped::arrow_fields(false);
+        let mut row_builder = PoseStamped::new_row_builder(fields.iter().collect());
+        row_builder.set_filter(
+            RowFilter::new()
+                .with_predicate(|msg: &PoseStamped| msg.header.frame_id == "base_link")
+                .keep_one_in(2)
+                .max_rate_hz(1.0),
+        );
+
+        // 40 messages at 10 Hz, every other one in the wrong frame.
+        for i in 0..40 {
+            let msg = PoseStamped {
+                header: Header {
+                    stamp: Time {
+                        sec: i / 10,
+                        nanosec: (i % 10) as u32 * 100_000_000,
+                    },
+                    frame_id: if i % 2 == 0 { "base_link" } else { "map" }.to_string(),
+                },
+                ..Default::default()
+            };
+            assert!(row_builder.add_row(&msg).is_ok());
+        }
+
+        let stats = row_builder.filter_stats();
+        assert_eq!(stats.dropped_by_predicate, 20);
+        assert_eq!(stats.dropped_by_decimation, 10);
+        assert_eq!(stats.dropped_by_rate, 6);
+        assert_eq!(stats.accepted, 4);
+        assert_eq!(stats.dropped(), 36);
+
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays[0].len(), 4);
+    }
+
+    #[test]
+    fn test_message_struct_json() {
+        let msg = Header {
+            stamp: Time { sec: 1, nanosec: 2 },
+            frame_id: "test \"frame\"".to_string(),
+        };
+
+        let mut fields = Header::flat_arrow_fields(false);
+        fields.push(Header::message_struct_field(
+            "message_struct",
+            crate::MessageStructEncoding::Json,
+        ));
+        let mut row_builder = Header::new_flat_row_builder(fields.iter().collect());
+        assert!(row_builder.add_row(&msg).is_ok());
+
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), 4);
+        let json = arrays[3]
+            .as_any()
+            .downcast_ref::<arrow_array::StringArray>()
+            .unwrap();
+        assert_eq!(
+            json.value(0),
+            r#"{"stamp":{"sec":1,"nanosec":2},"frame_id":"test \"frame\""}"#
+        );
+    }
+
+    #[test]
+    fn test_message_struct_only() {
+        use crate::MessageStructEncoding;
+
+        let msg = Header {
+            stamp: Time { sec: 1, nanosec: 2 },
+            frame_id: "test_frame".to_string(),
+        };
+
+        for encoding in [
+            MessageStructEncoding::Struct,
+            MessageStructEncoding::FlatStruct,
+            MessageStructEncoding::Json,
+        ] {
+            let fields = vec![Header::message_struct_field("header", encoding)];
+            let mut row_builder = Header::new_row_builder(fields.iter().collect());
+            assert!(row_builder.add_row(&msg).is_ok());
+
+            let arrays = row_builder.to_arc_arrays();
+            assert_eq!(arrays.len(), 1);
+            assert_eq!(arrays[0].len(), 1);
+            assert_eq!(arrays[0].data_type(), fields[0].data_type());
+        }
+
+        let flat_field = Header::message_struct_field("header", MessageStructEncoding::FlatStruct);
+        match flat_field.data_type() {
+            arrow_schema::DataType::Struct(fields) => {
+                assert_eq!(fields.len(), 3);
+                assert_eq!(fields[0].name(), "stamp_sec");
+                assert_eq!(fields[1].name(), "stamp_nanosec");
+                assert_eq!(fields[2].name(), "frame_id");
+            }
+            _ => panic!("Expected a struct"),
+        }
+    }
+
+    #[test]
+    fn test_service_request_and_response() {
+        use r2r::example_interfaces::srv::AddTwoInts;
+
+        assert_eq!(
+            AddTwoInts::Request::schema_name(),
+            "example_interfaces/srv/AddTwoInts_Request"
+        );
+        assert_eq!(
+            AddTwoInts::Response::schema_name(),
+            "example_interfaces/srv/AddTwoInts_Response"
+        );
+        assert!(
+            crate::get_supported_schemas().contains(&"example_interfaces/srv/AddTwoInts_Request")
+        );
+        assert!(
+            crate::get_supported_schemas().contains(&"example_interfaces/srv/AddTwoInts_Response")
+        );
+
+        let fields = AddTwoInts::Request::arrow_fields(true);
+        let mut row_builder = AddTwoInts::Request::new_row_builder(fields.iter().collect());
+        assert!(row_builder
+            .add_row(&AddTwoInts::Request { a: 1, b: 2 })
+            .is_ok());
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), 3);
+        assert_eq!(arrays[0].data_type(), &arrow_schema::DataType::Int64);
+
+        let fields = AddTwoInts::Response::flat_arrow_fields(false);
+        let mut row_builder = AddTwoInts::Response::new_flat_row_builder(fields.iter().collect());
+        assert!(row_builder
+            .add_row(&AddTwoInts::Response { sum: 3 })
+            .is_ok());
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), 1);
+        assert_eq!(arrays[0].len(), 1);
+    }
+
+    #[test]
+    fn test_action_types() {
+        use r2r::example_interfaces::action::Fibonacci;
+
+        let expected = [
+            (Fibonacci::Goal::schema_name(), "Goal"),
+            (Fibonacci::Result::schema_name(), "Result"),
+            (Fibonacci::Feedback::schema_name(), "Feedback"),
+            (Fibonacci::FeedbackMessage::schema_name(), "FeedbackMessage"),
+            (
+                Fibonacci::SendGoal::Request::schema_name(),
+                "SendGoal_Request",
+            ),
+            (
+                Fibonacci::SendGoal::Response::schema_name(),
+                "SendGoal_Response",
+            ),
+            (
+                Fibonacci::GetResult::Request::schema_name(),
+                "GetResult_Request",
+            ),
+            (
+                Fibonacci::GetResult::Response::schema_name(),
+                "GetResult_Response",
+            ),
+        ];
+        for (schema_name, suffix) in expected {
+            assert_eq!(
+                schema_name,
+                format!("example_interfaces/action/Fibonacci_{}", suffix)
+            );
+            assert!(crate::get_supported_schemas().contains(&schema_name));
+        }
+
+        let fields = Fibonacci::FeedbackMessage::flat_arrow_fields(true);
+        let mut row_builder =
+            Fibonacci::FeedbackMessage::new_flat_row_builder(fields.iter().collect());
+        let msg = Fibonacci::FeedbackMessage {
+            feedback: Fibonacci::Feedback {
+                sequence: vec![0, 1, 1, 2, 3],
+            },
+            ..Default::default()
+        };
+        assert!(row_builder.add_row(&msg).is_ok());
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), fields.len());
+        let sequence = fields
+            .iter()
+            .position(|f| f.name() == "feedback_sequence")
+            .unwrap();
+        assert_eq!(arrays[sequence].len(), 1);
+    }
+
+    #[test]
+    fn test_package_modules() {
+        use r2r::geometry_msgs::msg::PoseStamped;
+
+        assert_eq!(
+            crate::geometry_msgs::msg::PoseStamped_Schema(false),
+            PoseStamped::arrow_fields(false)
+        );
+        assert_eq!(
+            crate::example_interfaces::action::Fibonacci::SendGoal::Request_FlatSchema(false),
+            r2r::example_interfaces::action::Fibonacci::SendGoal::Request::flat_arrow_fields(false)
+        );
+
+        let fields = crate::geometry_msgs::msg::PoseStamped_FlatSchema(false);
+        let mut row_builder =
+            crate::geometry_msgs::msg::PoseStamped_FlatRowBuilder::new(fields.iter().collect());
+        assert!(row_builder.add_row(&PoseStamped::default()).is_ok());
+        assert_eq!(row_builder.to_arc_arrays().len(), fields.len());
+    }
 }
@@ -0,0 +1,34 @@
+//! Reads ROS 1 bags (rosbag v2.0, `.bag` files) into the Arrow layouts of the equivalent ROS 2
+//! types. Requires the `rosbag1` feature.
+//!
+//! The messages are decoded with the message definition stored in the bag and serialized to
+//! CDR as the ROS 2 type, then added to its row builder, so ROS 1 and ROS 2 recordings share one
+//! table format. A ROS 1 type `pkg/Type` is read as `pkg/msg/Type`, other mappings can be added
+//! with [`Rosbag1Reader::with_type_mapping`]. The fields are matched by name, ignoring case:
+//! the `seq` of `std_msgs/Header` is dropped, and ROS 2 fields that ROS 1 doesn't have are
+//! filled with their default value.
+//!
+//! The batches have the context columns of [`crate::row_context_fields`], where `_recv_time`
+//! holds the receive time the bag recorded. Chunks can be uncompressed or compressed with bz2 or
+//! lz4.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::rosbag1::Rosbag1Reader;
+//!
+//! let reader = Rosbag1Reader::open("recordings/2019-06-03-run_7.bag").unwrap();
+//! for topic in reader.topics() {
+//!     let schema_name = reader.topic_schema_name(topic).unwrap();
+//!     for batch in reader.topic_batches(topic, true).unwrap() {
+//!         println!("{} ({}): {} rows", topic, schema_name, batch.unwrap().num_rows());
+//!     }
+//! }
+//! ```
+
+mod reader;
+mod records;
+mod ros1_msg;
+
+pub use reader::{Ros1Connection, Rosbag1Reader, Rosbag1TopicBatches, DEFAULT_BATCH_SIZE};
+pub use ros1_msg::ros2_schema_name;
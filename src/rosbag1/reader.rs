@@ -0,0 +1,809 @@
+use super::records::{
+    BagHeaderRecord, ByteReader, ChunkInfoRecord, ChunkRecord, ConnectionRecord, MessageDataRecord,
+    RecordHeader, MAGIC, OP_BAG_HEADER, OP_CHUNK, OP_CHUNK_INFO, OP_CONNECTION, OP_MESSAGE_DATA,
+};
+use super::ros1_msg::{ros2_schema_name, Ros1Definition, Ros1Transcoder};
+use crate::projection::Projection;
+use crate::{new_raw_row_builder, row_context_fields, schema_arrow_fields, RowContext};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::RecordBatch;
+use arrow_schema::{Field, Schema, SchemaRef};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The number of rows of the batches returned by [`Rosbag1Reader::topic_batches`], unless set
+/// with [`Rosbag1Reader::with_batch_size`].
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// A connection of a ROS 1 bag, a topic as recorded from one publisher.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ros1Connection {
+    pub id: u32,
+    pub topic: String,
+    /// The ROS 1 type, for example `sensor_msgs/LaserScan`.
+    pub message_type: String,
+    pub md5sum: String,
+    /// The full definition of the type, including its nested types.
+    pub message_definition: String,
+    pub callerid: String,
+    pub latching: bool,
+}
+
+impl From<ConnectionRecord> for Ros1Connection {
+    fn from(record: ConnectionRecord) -> Self {
+        Ros1Connection {
+            id: record.conn,
+            topic: record.topic,
+            message_type: record.message_type,
+            md5sum: record.md5sum,
+            message_definition: record.message_definition,
+            callerid: record.callerid,
+            latching: record.latching,
+        }
+    }
+}
+
+/// A chunk of the bag and the connections of its messages.
+#[derive(Debug, Clone)]
+struct ChunkBlock {
+    offset: u64,
+    start_time: u64,
+    end_time: u64,
+    connections: BTreeSet<u32>,
+}
+
+/// Reads the topics of a ROS 1 bag (rosbag v2.0) into the Arrow layouts of the equivalent ROS 2
+/// types.
+pub struct Rosbag1Reader {
+    path: PathBuf,
+    connections: BTreeMap<u32, Ros1Connection>,
+    chunks: Vec<ChunkBlock>,
+    type_mappings: HashMap<String, String>,
+    batch_size: usize,
+}
+
+impl Rosbag1Reader {
+    /// Opens the bag at `path`. The connections and chunks are read from the index section if
+    /// the bag was closed properly, otherwise from the chunks, which also recovers the complete
+    /// chunks of bags that are still being recorded.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        let mut file = BufReader::new(file);
+
+        let mut magic = [0u8; MAGIC.len()];
+        file.read_exact(&mut magic)
+            .with_context(|| format!("{} is not a ROS 1 bag", path.display()))?;
+        if &magic != MAGIC {
+            bail!("{} is not a rosbag v2.0 bag", path.display());
+        }
+        let (header, _) = read_record(&mut file)?
+            .ok_or_else(|| anyhow!("{} has no bag header", path.display()))?;
+        if header.op()? != OP_BAG_HEADER {
+            bail!("{} doesn't start with a bag header", path.display());
+        }
+        let bag_header = BagHeaderRecord::parse(&header)?;
+        let data_start = file.stream_position()?;
+
+        let (connections, chunks) = if bag_header.index_pos != 0 {
+            file.seek(SeekFrom::Start(bag_header.index_pos))?;
+            read_index(&mut file)
+        } else {
+            file.seek(SeekFrom::Start(data_start))?;
+            scan_chunks(&mut file)
+        }
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+
+        Ok(Rosbag1Reader {
+            path: path.to_path_buf(),
+            connections,
+            chunks,
+            type_mappings: HashMap::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// Sets the maximum number of rows of the batches.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Reads the messages of the ROS 1 type `ros1_type` as `ros2_schema_name`, for types that
+    /// were renamed or moved in ROS 2. Other types `pkg/Type` are read as `pkg/msg/Type`.
+    pub fn with_type_mapping(mut self, ros1_type: &str, ros2_schema_name: &str) -> Self {
+        self.type_mappings
+            .insert(ros1_type.to_string(), ros2_schema_name.to_string());
+        self
+    }
+
+    /// The connections of the bag, ordered by id.
+    pub fn connections(&self) -> Vec<&Ros1Connection> {
+        self.connections.values().collect()
+    }
+
+    /// The topics of the bag, in the order of their first connection.
+    pub fn topics(&self) -> Vec<&str> {
+        let mut topics: Vec<&str> = vec![];
+        for connection in self.connections.values() {
+            if !topics.contains(&connection.topic.as_str()) {
+                topics.push(&connection.topic);
+            }
+        }
+        topics
+    }
+
+    /// The receive time of the first message, in nanoseconds since the UNIX epoch.
+    pub fn message_start_time(&self) -> Option<u64> {
+        self.chunks.iter().map(|chunk| chunk.start_time).min()
+    }
+
+    /// The receive time of the last message, in nanoseconds since the UNIX epoch.
+    pub fn message_end_time(&self) -> Option<u64> {
+        self.chunks.iter().map(|chunk| chunk.end_time).max()
+    }
+
+    /// The ROS 2 schema name the messages of `topic` are read as.
+    pub fn topic_schema_name(&self, topic: &str) -> Result<String> {
+        let (_, schema_name) = self.topic_connections(topic)?;
+        Ok(schema_name)
+    }
+
+    fn ros2_schema_name(&self, ros1_type: &str) -> String {
+        self.type_mappings
+            .get(ros1_type)
+            .cloned()
+            .unwrap_or_else(|| ros2_schema_name(ros1_type))
+    }
+
+    /// The connections of `topic` and the ROS 2 schema name of their type.
+    fn topic_connections(&self, topic: &str) -> Result<(Vec<&Ros1Connection>, String)> {
+        let connections: Vec<&Ros1Connection> = self
+            .connections
+            .values()
+            .filter(|connection| connection.topic == topic)
+            .collect();
+        let Some(first) = connections.first() else {
+            bail!("Topic {} is not in {}", topic, self.path.display());
+        };
+        for connection in &connections {
+            if connection.message_type != first.message_type {
+                bail!(
+                    "Topic {} has messages of both {} and {}",
+                    topic,
+                    first.message_type,
+                    connection.message_type
+                );
+            }
+        }
+        let schema_name = self.ros2_schema_name(&first.message_type);
+        Ok((connections, schema_name))
+    }
+
+    /// The schema of the batches of `topic`: the fields of the ROS 2 equivalent of its type in
+    /// the regular or, if `flat` is true, the flat layout, followed by the context columns.
+    pub fn topic_schema(&self, topic: &str, flat: bool) -> Result<Schema> {
+        let (_, schema_name) = self.topic_connections(topic)?;
+        Ok(Schema::new(topic_fields(topic, &schema_name, flat)?))
+    }
+
+    /// Returns the messages of `topic` as batches of at most `batch_size` rows.
+    pub fn topic_batches(&self, topic: &str, flat: bool) -> Result<Rosbag1TopicBatches<'_>> {
+        self.topic_batches_in_range(topic, flat, ..)
+    }
+
+    /// Returns the messages of `topic` with a receive time in `range`, in nanoseconds since the
+    /// UNIX epoch, as batches of at most `batch_size` rows. The messages of a chunk are sorted by
+    /// receive time, and the chunks are read in the order of their first message.
+    pub fn topic_batches_in_range(
+        &self,
+        topic: &str,
+        flat: bool,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Rosbag1TopicBatches<'_>> {
+        let (connections, schema_name) = self.topic_connections(topic)?;
+        let fields = topic_fields(topic, &schema_name, flat)?;
+        let descriptor = crate::type_descriptor(&schema_name)
+            .ok_or_else(|| anyhow!("Unsupported message type {}", schema_name))?;
+        let transcoders = connections
+            .iter()
+            .map(|connection| {
+                let definition =
+                    Ros1Definition::parse(&connection.message_type, &connection.message_definition)
+                        .with_context(|| {
+                            format!("Invalid message definition of topic {}", topic)
+                        })?;
+                Ok((
+                    connection.id,
+                    Ros1Transcoder::new(definition, descriptor.clone()),
+                ))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        let mut chunks: Vec<ChunkBlock> = self
+            .chunks
+            .iter()
+            .filter(|chunk| {
+                transcoders.keys().any(|id| chunk.connections.contains(id))
+                    && overlaps(&range, chunk.start_time, chunk.end_time)
+            })
+            .cloned()
+            .collect();
+        chunks.sort_by_key(|chunk| chunk.start_time);
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Unable to open {}", self.path.display()))?;
+        Ok(Rosbag1TopicBatches {
+            reader: self,
+            file: BufReader::new(file),
+            topic: topic.to_string(),
+            schema_name,
+            projection: Projection::all(&fields),
+            fields,
+            flat,
+            transcoders,
+            range,
+            chunks: chunks.into(),
+            pending: VecDeque::new(),
+            seq: 0,
+        })
+    }
+}
+
+fn overlaps(range: &(Bound<u64>, Bound<u64>), start: u64, end: u64) -> bool {
+    let after_start = match range.0 {
+        Bound::Included(bound) => end >= bound,
+        Bound::Excluded(bound) => end > bound,
+        Bound::Unbounded => true,
+    };
+    let before_end = match range.1 {
+        Bound::Included(bound) => start <= bound,
+        Bound::Excluded(bound) => start < bound,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+fn topic_fields(topic: &str, schema_name: &str, flat: bool) -> Result<Vec<Field>> {
+    let mut fields = schema_arrow_fields(schema_name, flat, false).ok_or_else(|| {
+        anyhow!(
+            "Unsupported message type {} of topic {}",
+            schema_name,
+            topic
+        )
+    })?;
+    fields.extend(row_context_fields(false));
+    Ok(fields)
+}
+
+/// Reads the next record of the file, or returns `None` at the end of the file or of its last
+/// complete record.
+fn read_record(file: &mut BufReader<File>) -> Result<Option<(RecordHeader, Vec<u8>)>> {
+    let Some(header) = read_byte_array(file)? else {
+        return Ok(None);
+    };
+    let Some(data) = read_byte_array(file)? else {
+        return Ok(None);
+    };
+    Ok(Some((RecordHeader::parse(&header)?, data)))
+}
+
+/// Reads a length-prefixed byte array, or returns `None` if the file ends before it does.
+fn read_byte_array(file: &mut BufReader<File>) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match file.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    // The length comes from the file, the bytes are read through `take` so that a corrupt
+    // length doesn't allocate more than the rest of the file.
+    let len = u32::from_le_bytes(len) as usize;
+    let mut bytes = vec![];
+    file.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(bytes))
+}
+
+type Index = (BTreeMap<u32, Ros1Connection>, Vec<ChunkBlock>);
+
+/// Reads the connection and chunk info records of the index section.
+fn read_index(file: &mut BufReader<File>) -> Result<Index> {
+    let mut connections = BTreeMap::new();
+    let mut chunks = vec![];
+    while let Some((header, data)) = read_record(file)? {
+        match header.op()? {
+            OP_CONNECTION => {
+                let connection = ConnectionRecord::parse(&header, &data)?;
+                connections.insert(connection.conn, connection.into());
+            }
+            OP_CHUNK_INFO => {
+                let info = ChunkInfoRecord::parse(&header, &data)?;
+                chunks.push(ChunkBlock {
+                    offset: info.chunk_pos,
+                    start_time: info.start_time,
+                    end_time: info.end_time,
+                    connections: info.connection_counts.into_keys().collect(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok((connections, chunks))
+}
+
+/// Reads the chunks of the data section, up to the last complete chunk.
+fn scan_chunks(file: &mut BufReader<File>) -> Result<Index> {
+    let mut connections = BTreeMap::new();
+    let mut chunks = vec![];
+    loop {
+        let offset = file.stream_position()?;
+        let Some((header, data)) = read_record(file)? else {
+            break;
+        };
+        match header.op()? {
+            OP_CHUNK => {
+                let records = ChunkRecord::parse(&header, &data)?.decompress()?;
+                let mut records = ByteReader::new(&records);
+                let mut chunk = ChunkBlock {
+                    offset,
+                    start_time: u64::MAX,
+                    end_time: 0,
+                    connections: BTreeSet::new(),
+                };
+                while !records.is_empty() {
+                    let (header, data) = records.record()?;
+                    match header.op()? {
+                        OP_CONNECTION => {
+                            let connection = ConnectionRecord::parse(&header, data)?;
+                            connections.insert(connection.conn, connection.into());
+                        }
+                        OP_MESSAGE_DATA => {
+                            let message = MessageDataRecord::parse(&header, data)?;
+                            chunk.start_time = chunk.start_time.min(message.time);
+                            chunk.end_time = chunk.end_time.max(message.time);
+                            chunk.connections.insert(message.conn);
+                        }
+                        _ => {}
+                    }
+                }
+                if !chunk.connections.is_empty() {
+                    chunks.push(chunk);
+                }
+            }
+            OP_CONNECTION => {
+                let connection = ConnectionRecord::parse(&header, &data)?;
+                connections.insert(connection.conn, connection.into());
+            }
+            _ => {}
+        }
+    }
+    Ok((connections, chunks))
+}
+
+/// The batches of a topic, returned by [`Rosbag1Reader::topic_batches`].
+pub struct Rosbag1TopicBatches<'r> {
+    reader: &'r Rosbag1Reader,
+    file: BufReader<File>,
+    topic: String,
+    schema_name: String,
+    fields: Vec<Field>,
+    projection: Projection,
+    flat: bool,
+    /// The transcoders of the connections of the topic, by connection id.
+    transcoders: HashMap<u32, Ros1Transcoder>,
+    range: (Bound<u64>, Bound<u64>),
+    chunks: VecDeque<ChunkBlock>,
+    /// The receive time, connection and data of the messages read from the current chunk.
+    pending: VecDeque<(u64, u32, Vec<u8>)>,
+    seq: u64,
+}
+
+impl Rosbag1TopicBatches<'_> {
+    /// Only builds the columns at the indices of `projection` in
+    /// [`Rosbag1Reader::topic_schema`], and returns them in the order of `projection`. The
+    /// messages are still transcoded, but the other columns are skipped. An empty projection
+    /// returns batches without columns, which only have a row count.
+    pub fn with_projection(mut self, projection: &[usize]) -> Result<Self> {
+        self.projection = Projection::new(&self.fields, projection)?;
+        Ok(self)
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.projection.schema()
+    }
+
+    /// Reads the messages of the topic from the next chunk into `pending`.
+    fn read_chunk(&mut self, chunk: ChunkBlock) -> Result<()> {
+        self.file.seek(SeekFrom::Start(chunk.offset))?;
+        let (header, data) = read_record(&mut self.file)?
+            .ok_or_else(|| anyhow!("Missing chunk at offset {}", chunk.offset))?;
+        if header.op()? != OP_CHUNK {
+            bail!("Expected a chunk at offset {}", chunk.offset);
+        }
+        let records = ChunkRecord::parse(&header, &data)?.decompress()?;
+        let mut records = ByteReader::new(&records);
+        while !records.is_empty() {
+            let (header, data) = records.record()?;
+            if header.op()? != OP_MESSAGE_DATA {
+                continue;
+            }
+            let message = MessageDataRecord::parse(&header, data)?;
+            if self.transcoders.contains_key(&message.conn) && self.range.contains(&message.time) {
+                self.pending
+                    .push_back((message.time, message.conn, message.data.to_vec()));
+            }
+        }
+        self.pending
+            .make_contiguous()
+            .sort_by_key(|(time, _, _)| *time);
+        Ok(())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let batch_size = self.reader.batch_size;
+        while self.pending.len() < batch_size {
+            let Some(chunk) = self.chunks.pop_front() else {
+                break;
+            };
+            self.read_chunk(chunk)
+                .with_context(|| format!("Unable to read {}", self.reader.path.display()))?;
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+
+        let mut row_builder = new_raw_row_builder(
+            &self.schema_name,
+            self.projection.fields(&self.fields),
+            self.flat,
+        )
+        .ok_or_else(|| anyhow!("Unsupported message type {}", self.schema_name))?;
+        let rows = self.pending.len().min(batch_size);
+        for (time, conn, data) in self.pending.drain(..rows) {
+            let ctx = RowContext::new(&self.topic, self.seq)
+                .with_recv_time(UNIX_EPOCH + Duration::from_nanos(time));
+            let message = self.transcoders[&conn].transcode(&data);
+            message
+                .and_then(|message| row_builder.add_raw_row_with_context(&message, &ctx))
+                .with_context(|| {
+                    format!("Unable to read message {} of {}", self.seq, self.topic)
+                })?;
+            self.seq += 1;
+        }
+        Ok(Some(
+            self.projection.batch(row_builder.to_arc_arrays(), rows)?,
+        ))
+    }
+}
+
+impl Iterator for Rosbag1TopicBatches<'_> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::super::records::writer::{header, record, time};
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, Int32Type, TimestampNanosecondType};
+    use arrow_array::Array;
+    use std::io::Write;
+
+    const LASER_SCAN_DEFINITION: &str = "\
+Header header
+float32 angle_min
+float32 angle_max
+float32 angle_increment
+float32 time_increment
+float32 scan_time
+float32 range_min
+float32 range_max
+float32[] ranges
+float32[] intensities
+================================================================================
+MSG: std_msgs/Header
+uint32 seq
+time stamp
+string frame_id
+";
+
+    fn ros1_scan(sec: u32) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(sec.to_le_bytes());
+        data.extend(sec.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(5u32.to_le_bytes());
+        data.extend(b"laser");
+        for _ in 0..7 {
+            data.extend(0f32.to_le_bytes());
+        }
+        data.extend(1u32.to_le_bytes());
+        data.extend((sec as f32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data
+    }
+
+    fn connection(conn: u32, topic: &str, message_type: &str, definition: &str) -> Vec<u8> {
+        record(
+            &[
+                ("op", &[OP_CONNECTION]),
+                ("conn", &conn.to_le_bytes()),
+                ("topic", topic.as_bytes()),
+            ],
+            &header(&[
+                ("topic", topic.as_bytes()),
+                ("type", message_type.as_bytes()),
+                ("md5sum", b"*"),
+                ("message_definition", definition.as_bytes()),
+            ]),
+        )
+    }
+
+    fn compress(records: &[u8], compression: &str) -> Vec<u8> {
+        match compression {
+            "bz2" => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+                encoder.write_all(records).unwrap();
+                encoder.finish().unwrap()
+            }
+            "lz4" => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+                encoder.write_all(records).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => records.to_vec(),
+        }
+    }
+
+    /// Writes /scan messages at 1..=6 s and a /chatter message at 2.5 s, in chunks of three
+    /// messages.
+    fn write_bag(name: &str, compression: &str, index: bool) -> PathBuf {
+        let mut messages: Vec<(u32, u64, Vec<u8>)> = (1..=6)
+            .map(|sec| (0, sec as u64 * 1_000_000_000, ros1_scan(sec)))
+            .collect();
+        let mut chatter = 5u32.to_le_bytes().to_vec();
+        chatter.extend(b"hello");
+        messages.insert(2, (1, 2_500_000_000, chatter));
+        let connections = [
+            connection(0, "/scan", "sensor_msgs/LaserScan", LASER_SCAN_DEFINITION),
+            connection(1, "/chatter", "std_msgs/String", "string data\n"),
+        ];
+
+        let mut data = vec![];
+        let mut chunk_infos = vec![];
+        for (index, chunk_messages) in messages.chunks(3).enumerate() {
+            let mut records = vec![];
+            if index == 0 {
+                records.extend(connections.concat());
+            }
+            let mut counts = BTreeMap::<u32, u32>::new();
+            for (conn, message_time, message) in chunk_messages {
+                records.extend(record(
+                    &[
+                        ("op", &[OP_MESSAGE_DATA]),
+                        ("conn", &conn.to_le_bytes()),
+                        ("time", &time(*message_time)),
+                    ],
+                    message,
+                ));
+                *counts.entry(*conn).or_default() += 1;
+            }
+            let chunk_pos = (MAGIC.len() + 4096 + data.len()) as u64;
+            data.extend(record(
+                &[
+                    ("op", &[OP_CHUNK]),
+                    ("compression", compression.as_bytes()),
+                    ("size", &(records.len() as u32).to_le_bytes()),
+                ],
+                &compress(&records, compression),
+            ));
+            let times: Vec<u64> = chunk_messages.iter().map(|(_, time, _)| *time).collect();
+            let mut counts_data = vec![];
+            for (conn, count) in &counts {
+                counts_data.extend(conn.to_le_bytes());
+                counts_data.extend(count.to_le_bytes());
+            }
+            chunk_infos.push(record(
+                &[
+                    ("op", &[OP_CHUNK_INFO]),
+                    ("ver", &1u32.to_le_bytes()),
+                    ("chunk_pos", &chunk_pos.to_le_bytes()),
+                    ("start_time", &time(*times.iter().min().unwrap())),
+                    ("end_time", &time(*times.iter().max().unwrap())),
+                    ("count", &(counts.len() as u32).to_le_bytes()),
+                ],
+                &counts_data,
+            ));
+        }
+
+        let index_pos = if index {
+            (MAGIC.len() + 4096 + data.len()) as u64
+        } else {
+            0
+        };
+        let mut bag_header = record(
+            &[
+                ("op", &[OP_BAG_HEADER]),
+                ("index_pos", &index_pos.to_le_bytes()),
+                ("conn_count", &2u32.to_le_bytes()),
+                ("chunk_count", &(chunk_infos.len() as u32).to_le_bytes()),
+            ],
+            &[],
+        );
+        // The bag header is padded to 4096 bytes with its data.
+        let padding = 4096 - bag_header.len();
+        bag_header.truncate(bag_header.len() - 4);
+        bag_header.extend((padding as u32).to_le_bytes());
+        bag_header.resize(4096, b' ');
+
+        let mut bag = MAGIC.to_vec();
+        bag.extend(bag_header);
+        bag.extend(data);
+        if index {
+            bag.extend(connections.concat());
+            bag.extend(chunk_infos.concat());
+        }
+        let path = std::env::temp_dir().join(format!("r2a_{}_{}.bag", name, std::process::id()));
+        std::fs::write(&path, bag).unwrap();
+        path
+    }
+
+    fn read_ranges(batches: &[RecordBatch]) -> Vec<f32> {
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let ranges = batch.column_by_name("ranges").unwrap().as_list::<i64>();
+                (0..ranges.len())
+                    .map(|i| ranges.value(i).as_primitive::<Float32Type>().value(0))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn check_reader(reader: &Rosbag1Reader) {
+        assert_eq!(reader.topics(), ["/scan", "/chatter"]);
+        assert_eq!(
+            reader.topic_schema_name("/scan").unwrap(),
+            "sensor_msgs/msg/LaserScan"
+        );
+        assert_eq!(reader.message_start_time(), Some(1_000_000_000));
+        assert_eq!(reader.message_end_time(), Some(6_000_000_000));
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/scan", true)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(
+            batches[0].schema().as_ref(),
+            &reader.topic_schema("/scan", true).unwrap()
+        );
+        assert_eq!(read_ranges(&batches), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let stamps = batches[1]
+            .column_by_name("header_stamp_sec")
+            .unwrap()
+            .as_primitive::<Int32Type>();
+        assert_eq!(stamps.value(0), 3);
+        assert_eq!(
+            batches[1]
+                .column_by_name("header_frame_id")
+                .unwrap()
+                .as_string::<i32>()
+                .value(0),
+            "laser"
+        );
+        let recv_times = batches[1]
+            .column_by_name(crate::RECV_TIME_FIELD)
+            .unwrap()
+            .as_primitive::<TimestampNanosecondType>();
+        assert_eq!(recv_times.value(0), 3_000_000_000);
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches_in_range("/scan", false, 3_500_000_000..=5_000_000_000)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read_ranges(&batches), [4.0, 5.0]);
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/chatter", false)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let data = batches[0]
+            .column_by_name("data")
+            .unwrap()
+            .as_string::<i32>();
+        assert_eq!(data.value(0), "hello");
+
+        assert!(reader.topic_batches("/missing", false).is_err());
+    }
+
+    #[test]
+    fn test_read_bag() {
+        for compression in ["none", "bz2", "lz4"] {
+            for index in [true, false] {
+                let path = write_bag(&format!("{}_{}", compression, index), compression, index);
+                let reader = Rosbag1Reader::open(&path).unwrap().with_batch_size(2);
+                check_reader(&reader);
+                std::fs::remove_file(&path).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_truncated_bag() {
+        let path = write_bag("truncated", "lz4", false);
+        let bag = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bag[..bag.len() - 10]).unwrap();
+        // The last chunk, with the message at 6 s, is incomplete.
+        let reader = Rosbag1Reader::open(&path).unwrap();
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/scan", false)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read_ranges(&batches), [1.0, 2.0, 3.0, 4.0, 5.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        // A bag without an index that ends with the prefix of a record of 4 GiB.
+        let path = write_bag("corrupt_record", "lz4", false);
+        let mut bag = std::fs::read(&path).unwrap();
+        bag.extend_from_slice(&u32::MAX.to_le_bytes());
+        bag.extend_from_slice(&[0; 16]);
+        std::fs::write(&path, &bag).unwrap();
+        let reader = Rosbag1Reader::open(&path).unwrap();
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/scan", false)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read_ranges(&batches), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        std::fs::remove_file(&path).unwrap();
+
+        // Chunks that decompress to more than their size.
+        for compression in ["bz2", "lz4"] {
+            let data = match compression {
+                "bz2" => {
+                    let mut encoder = bzip2::write::BzEncoder::new(vec![], Default::default());
+                    encoder.write_all(&[0; 1000]).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => {
+                    let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+                    encoder.write_all(&[0; 1000]).unwrap();
+                    encoder.finish().unwrap()
+                }
+            };
+            let chunk = ChunkRecord {
+                compression: compression.to_string(),
+                size: 10,
+                data: &data,
+            };
+            let error = chunk.decompress().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Chunk is 11 bytes long after decompression, expected 10"
+            );
+        }
+    }
+}
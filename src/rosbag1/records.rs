@@ -0,0 +1,326 @@
+//! The records of the rosbag v2.0 format, see <http://wiki.ros.org/Bags/Format/2.0>.
+//!
+//! Every record is a header, a `u32` length followed by `name=value` fields that are each
+//! prefixed by their `u32` length, and data, a `u32` length followed by the data. The `op` field
+//! of the header selects the type of the record. All integers are little endian.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+pub(crate) const MAGIC: &[u8; 13] = b"#ROSBAG V2.0\n";
+
+pub(crate) const OP_MESSAGE_DATA: u8 = 0x02;
+pub(crate) const OP_BAG_HEADER: u8 = 0x03;
+pub(crate) const OP_CHUNK: u8 = 0x05;
+pub(crate) const OP_CHUNK_INFO: u8 = 0x06;
+pub(crate) const OP_CONNECTION: u8 = 0x07;
+
+/// Reads little endian values from a byte slice.
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        ByteReader { data, position: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.position < len {
+            bail!(
+                "Unexpected end of the data at byte {}, {} more bytes needed",
+                self.position,
+                len
+            );
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    /// Reads a `u32` length and that many bytes.
+    pub(crate) fn byte_array(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    /// Reads a record, returning its header and its data.
+    pub(crate) fn record(&mut self) -> Result<(RecordHeader, &'a [u8])> {
+        let header = RecordHeader::parse(self.byte_array()?)?;
+        let data = self.byte_array()?;
+        Ok((header, data))
+    }
+}
+
+/// The fields of a record header, or of a connection header.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RecordHeader {
+    fields: BTreeMap<String, Vec<u8>>,
+}
+
+impl RecordHeader {
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(data);
+        let mut fields = BTreeMap::new();
+        while !reader.is_empty() {
+            let field = reader.byte_array()?;
+            let separator = field
+                .iter()
+                .position(|&byte| byte == b'=')
+                .ok_or_else(|| anyhow!("Header field without '='"))?;
+            let name = std::str::from_utf8(&field[..separator])
+                .context("Invalid header field name")?
+                .to_string();
+            fields.insert(name, field[separator + 1..].to_vec());
+        }
+        Ok(RecordHeader { fields })
+    }
+
+    fn field(&self, name: &str) -> Result<&[u8]> {
+        self.fields
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("Missing header field {}", name))
+    }
+
+    pub(crate) fn op(&self) -> Result<u8> {
+        ByteReader::new(self.field("op")?).u8()
+    }
+
+    pub(crate) fn u32(&self, name: &str) -> Result<u32> {
+        ByteReader::new(self.field(name)?).u32()
+    }
+
+    pub(crate) fn u64(&self, name: &str) -> Result<u64> {
+        ByteReader::new(self.field(name)?).u64()
+    }
+
+    /// Reads a time field, `u32` seconds and `u32` nanoseconds, as nanoseconds since the UNIX
+    /// epoch.
+    pub(crate) fn time(&self, name: &str) -> Result<u64> {
+        let mut reader = ByteReader::new(self.field(name)?);
+        let sec = reader.u32()? as u64;
+        let nsec = reader.u32()? as u64;
+        Ok(sec * 1_000_000_000 + nsec)
+    }
+
+    pub(crate) fn string(&self, name: &str) -> Result<String> {
+        String::from_utf8(self.field(name)?.to_vec())
+            .with_context(|| format!("Invalid UTF-8 in header field {}", name))
+    }
+
+    /// Like [`RecordHeader::string`], but returns an empty string for a missing field.
+    pub(crate) fn optional_string(&self, name: &str) -> Result<String> {
+        if self.fields.contains_key(name) {
+            self.string(name)
+        } else {
+            Ok(String::new())
+        }
+    }
+}
+
+/// The bag header record, which is padded to 4096 bytes so it can be rewritten in place.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BagHeaderRecord {
+    /// The offset of the first record of the index section, 0 if the bag was not closed.
+    pub index_pos: u64,
+    pub conn_count: u32,
+    pub chunk_count: u32,
+}
+
+impl BagHeaderRecord {
+    pub(crate) fn parse(header: &RecordHeader) -> Result<Self> {
+        Ok(BagHeaderRecord {
+            index_pos: header.u64("index_pos")?,
+            conn_count: header.u32("conn_count")?,
+            chunk_count: header.u32("chunk_count")?,
+        })
+    }
+}
+
+/// A connection record. The data of the record is a connection header with the type and the
+/// message definition of the topic.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConnectionRecord {
+    pub conn: u32,
+    pub topic: String,
+    pub message_type: String,
+    pub md5sum: String,
+    pub message_definition: String,
+    pub callerid: String,
+    pub latching: bool,
+}
+
+impl ConnectionRecord {
+    pub(crate) fn parse(header: &RecordHeader, data: &[u8]) -> Result<Self> {
+        let connection_header = RecordHeader::parse(data)?;
+        Ok(ConnectionRecord {
+            conn: header.u32("conn")?,
+            topic: header.string("topic")?,
+            message_type: connection_header.string("type")?,
+            md5sum: connection_header.optional_string("md5sum")?,
+            message_definition: connection_header.optional_string("message_definition")?,
+            callerid: connection_header.optional_string("callerid")?,
+            latching: connection_header.optional_string("latching")? == "1",
+        })
+    }
+}
+
+/// A message data record, with the serialized message as data.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MessageDataRecord<'a> {
+    pub conn: u32,
+    /// The receive time, in nanoseconds since the UNIX epoch.
+    pub time: u64,
+    pub data: &'a [u8],
+}
+
+impl<'a> MessageDataRecord<'a> {
+    pub(crate) fn parse(header: &RecordHeader, data: &'a [u8]) -> Result<Self> {
+        Ok(MessageDataRecord {
+            conn: header.u32("conn")?,
+            time: header.time("time")?,
+            data,
+        })
+    }
+}
+
+/// A chunk record, whose data are compressed connection and message data records.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChunkRecord<'a> {
+    /// `none`, `bz2` or `lz4`.
+    pub compression: String,
+    pub size: u32,
+    pub data: &'a [u8],
+}
+
+/// The largest compression ratio of lz4, which bounds the initial allocation of a decompressed
+/// chunk. bz2 chunks can compress further, their buffer grows while they are decompressed.
+const LZ4_MAX_RATIO: usize = 255;
+
+impl<'a> ChunkRecord<'a> {
+    pub(crate) fn parse(header: &RecordHeader, data: &'a [u8]) -> Result<Self> {
+        Ok(ChunkRecord {
+            compression: header.string("compression")?,
+            size: header.u32("size")?,
+            data,
+        })
+    }
+
+    /// Returns the records of the chunk.
+    pub(crate) fn decompress(&self) -> Result<Vec<u8>> {
+        // The size comes from the file: it only bounds the initial allocation, and the
+        // decompression stops one byte after it.
+        let capacity = (self.size as usize).min(self.data.len().saturating_mul(LZ4_MAX_RATIO));
+        let limit = u64::from(self.size) + 1;
+        let mut records = Vec::with_capacity(capacity);
+        match self.compression.as_str() {
+            "none" => records.extend_from_slice(self.data),
+            "bz2" => {
+                bzip2::read::BzDecoder::new(self.data)
+                    .take(limit)
+                    .read_to_end(&mut records)
+                    .context("Unable to decompress a bz2 chunk")?;
+            }
+            // roslz4 writes LZ4 frames.
+            "lz4" => {
+                lz4_flex::frame::FrameDecoder::new(self.data)
+                    .take(limit)
+                    .read_to_end(&mut records)
+                    .context("Unable to decompress an lz4 chunk")?;
+            }
+            other => bail!("Unsupported chunk compression {}", other),
+        }
+        if records.len() != self.size as usize {
+            bail!(
+                "Chunk is {} bytes long after decompression, expected {}",
+                records.len(),
+                self.size
+            );
+        }
+        Ok(records)
+    }
+}
+
+/// A chunk info record of the index section.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChunkInfoRecord {
+    pub chunk_pos: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    /// The number of messages per connection in the chunk.
+    pub connection_counts: BTreeMap<u32, u32>,
+}
+
+impl ChunkInfoRecord {
+    pub(crate) fn parse(header: &RecordHeader, data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(data);
+        let count = header.u32("count")?;
+        let mut connection_counts = BTreeMap::new();
+        for _ in 0..count {
+            let conn = reader.u32()?;
+            connection_counts.insert(conn, reader.u32()?);
+        }
+        Ok(ChunkInfoRecord {
+            chunk_pos: header.u64("chunk_pos")?,
+            start_time: header.time("start_time")?,
+            end_time: header.time("end_time")?,
+            connection_counts,
+        })
+    }
+}
+
+/// Writes records, for the tests.
+#[cfg(test)]
+pub(crate) mod writer {
+    /// Returns a record with the header `fields` and `data`.
+    pub(crate) fn record(fields: &[(&str, &[u8])], data: &[u8]) -> Vec<u8> {
+        let header = header(fields);
+        let mut record = (header.len() as u32).to_le_bytes().to_vec();
+        record.extend(header);
+        record.extend((data.len() as u32).to_le_bytes());
+        record.extend(data);
+        record
+    }
+
+    /// Returns the fields of a record or connection header, without the length of the header.
+    pub(crate) fn header(fields: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut header = vec![];
+        for (name, value) in fields {
+            header.extend(((name.len() + 1 + value.len()) as u32).to_le_bytes());
+            header.extend(name.as_bytes());
+            header.push(b'=');
+            header.extend(*value);
+        }
+        header
+    }
+
+    /// Encodes a time field.
+    pub(crate) fn time(nanos: u64) -> Vec<u8> {
+        let mut time = ((nanos / 1_000_000_000) as u32).to_le_bytes().to_vec();
+        time.extend(((nanos % 1_000_000_000) as u32).to_le_bytes());
+        time
+    }
+}
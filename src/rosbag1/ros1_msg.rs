@@ -0,0 +1,532 @@
+//! Decodes ROS 1 messages with the message definition of their connection and serializes them
+//! to CDR as the equivalent ROS 2 type.
+//!
+//! ROS 1 serializes the fields in declaration order, little endian and without alignment.
+//! Strings and variable length arrays are prefixed by their `u32` length, fixed size arrays are
+//! not. `time` and `duration` are two 32 bit integers, seconds and nanoseconds.
+//!
+//! The fields of the ROS 2 type are matched to the ROS 1 fields by name, ignoring case, so
+//! `std_msgs/Header` loses its `seq` and the `K` of `sensor_msgs/CameraInfo` becomes `k`. ROS 2
+//! fields without a ROS 1 counterpart are written with their default value. `time` and
+//! `duration` become `builtin_interfaces/msg/Time` and `builtin_interfaces/msg/Duration`.
+
+use super::records::ByteReader;
+use crate::cdr::{CdrValue, CdrWriter};
+use crate::{ArraySize, RosFieldDescriptor, RosTypeDescriptor};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+
+/// Returns the ROS 2 schema name of a ROS 1 type, `pkg/Type` becomes `pkg/msg/Type`.
+pub fn ros2_schema_name(ros1_type: &str) -> String {
+    match ros1_type.split_once('/') {
+        Some((package, name)) => format!("{}/msg/{}", package, name),
+        None => ros1_type.to_string(),
+    }
+}
+
+/// The type of a field of a ROS 1 message, or of its elements if it is an array.
+#[derive(Debug, Clone, PartialEq)]
+enum Ros1Type {
+    Bool,
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Float32,
+    Float64,
+    String,
+    Time,
+    Duration,
+    /// A message, with its full name, for example `std_msgs/Header`.
+    Message(String),
+}
+
+impl Ros1Type {
+    fn parse(name: &str, package: &str) -> Ros1Type {
+        match name {
+            "bool" => Ros1Type::Bool,
+            // `byte` and `char` are the deprecated aliases of `int8` and `uint8`.
+            "int8" | "byte" => Ros1Type::Int8,
+            "uint8" | "char" => Ros1Type::UInt8,
+            "int16" => Ros1Type::Int16,
+            "uint16" => Ros1Type::UInt16,
+            "int32" => Ros1Type::Int32,
+            "uint32" => Ros1Type::UInt32,
+            "int64" => Ros1Type::Int64,
+            "uint64" => Ros1Type::UInt64,
+            "float32" => Ros1Type::Float32,
+            "float64" => Ros1Type::Float64,
+            "string" => Ros1Type::String,
+            "time" => Ros1Type::Time,
+            "duration" => Ros1Type::Duration,
+            "Header" => Ros1Type::Message("std_msgs/Header".to_string()),
+            name if name.contains('/') => Ros1Type::Message(name.to_string()),
+            name => Ros1Type::Message(format!("{}/{}", package, name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Ros1Field {
+    name: String,
+    ros_type: Ros1Type,
+    /// `None` for a single value, `Some(None)` for a variable length array and `Some(Some(N))`
+    /// for an array of N elements.
+    array: Option<Option<usize>>,
+}
+
+/// The message types of a connection, parsed from its `message_definition`: the type itself,
+/// followed by the definitions of the nested types, each after a line of `=` and `MSG: pkg/Type`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Ros1Definition {
+    message_type: String,
+    types: HashMap<String, Vec<Ros1Field>>,
+}
+
+impl Ros1Definition {
+    pub(crate) fn parse(message_type: &str, definition: &str) -> Result<Self> {
+        let mut types = HashMap::new();
+        let mut current_type = message_type.to_string();
+        let mut fields = vec![];
+        for line in definition.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with("==") {
+                types.insert(
+                    std::mem::take(&mut current_type),
+                    std::mem::take(&mut fields),
+                );
+                continue;
+            }
+            if let Some(nested_type) = line.strip_prefix("MSG:") {
+                current_type = nested_type.trim().to_string();
+                continue;
+            }
+            let (field_type, rest) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                anyhow!(
+                    "Invalid line '{}' in the definition of {}",
+                    line,
+                    current_type
+                )
+            })?;
+            // Constants don't take space in the message.
+            if rest.contains('=') {
+                continue;
+            }
+            let package = current_type.split('/').next().unwrap_or_default();
+            let (element_type, array) = match field_type.split_once('[') {
+                Some((element_type, size)) => {
+                    let size = size.trim_end_matches(']');
+                    let size = if size.is_empty() {
+                        None
+                    } else {
+                        Some(size.parse().with_context(|| {
+                            format!("Invalid array size in '{}' of {}", line, current_type)
+                        })?)
+                    };
+                    (element_type, Some(size))
+                }
+                None => (field_type, None),
+            };
+            fields.push(Ros1Field {
+                name: rest.trim().to_string(),
+                ros_type: Ros1Type::parse(element_type, package),
+                array,
+            });
+        }
+        types.insert(current_type, fields);
+        Ok(Ros1Definition {
+            message_type: message_type.to_string(),
+            types,
+        })
+    }
+
+    fn fields(&self, message_type: &str) -> Result<&[Ros1Field]> {
+        self.types
+            .get(message_type)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("Missing definition of {}", message_type))
+    }
+
+    fn decode_value(&self, ros_type: &Ros1Type, reader: &mut ByteReader) -> Result<Ros1Value> {
+        Ok(match ros_type {
+            Ros1Type::Bool => Ros1Value::Bool(reader.u8()? != 0),
+            Ros1Type::Int8 => Ros1Value::Int(reader.u8()? as i8 as i64),
+            Ros1Type::UInt8 => Ros1Value::UInt(reader.u8()? as u64),
+            Ros1Type::Int16 => Ros1Value::Int(reader.u16()? as i16 as i64),
+            Ros1Type::UInt16 => Ros1Value::UInt(reader.u16()? as u64),
+            Ros1Type::Int32 => Ros1Value::Int(reader.u32()? as i32 as i64),
+            Ros1Type::UInt32 => Ros1Value::UInt(reader.u32()? as u64),
+            Ros1Type::Int64 => Ros1Value::Int(reader.u64()? as i64),
+            Ros1Type::UInt64 => Ros1Value::UInt(reader.u64()?),
+            Ros1Type::Float32 => Ros1Value::Float(f32::from_bits(reader.u32()?) as f64),
+            Ros1Type::Float64 => Ros1Value::Float(f64::from_bits(reader.u64()?)),
+            Ros1Type::String => {
+                Ros1Value::String(String::from_utf8_lossy(reader.byte_array()?).into_owned())
+            }
+            Ros1Type::Time => Ros1Value::Time {
+                sec: reader.u32()? as i64,
+                nanosec: reader.u32()? as i64,
+            },
+            Ros1Type::Duration => Ros1Value::Time {
+                sec: reader.u32()? as i32 as i64,
+                nanosec: reader.u32()? as i32 as i64,
+            },
+            Ros1Type::Message(message_type) => self.decode_message(message_type, reader)?,
+        })
+    }
+
+    fn decode_field(&self, field: &Ros1Field, reader: &mut ByteReader) -> Result<Ros1Value> {
+        let Some(size) = field.array else {
+            return self.decode_value(&field.ros_type, reader);
+        };
+        let len = match size {
+            Some(len) => len,
+            None => reader.u32()? as usize,
+        };
+        let fixed = size.is_some();
+        if field.ros_type == Ros1Type::UInt8 {
+            let bytes = reader.bytes(len)?.to_vec();
+            return Ok(Ros1Value::Bytes { bytes, fixed });
+        }
+        let elements = (0..len)
+            .map(|_| self.decode_value(&field.ros_type, reader))
+            .collect::<Result<_>>()?;
+        Ok(Ros1Value::Array { elements, fixed })
+    }
+
+    fn decode_message(&self, message_type: &str, reader: &mut ByteReader) -> Result<Ros1Value> {
+        self.fields(message_type)?
+            .iter()
+            .map(|field| {
+                let value = self
+                    .decode_field(field, reader)
+                    .with_context(|| format!("Unable to read field {}", field.name))?;
+                Ok((field.name.clone(), value))
+            })
+            .collect::<Result<_>>()
+            .map(Ros1Value::Message)
+    }
+
+    /// Decodes a serialized ROS 1 message of the connection's type.
+    fn decode(&self, data: &[u8]) -> Result<Ros1Value> {
+        self.decode_message(&self.message_type, &mut ByteReader::new(data))
+    }
+}
+
+/// A decoded ROS 1 value.
+#[derive(Debug, Clone, PartialEq)]
+enum Ros1Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    /// A `time` or a `duration`.
+    Time {
+        sec: i64,
+        nanosec: i64,
+    },
+    /// A `uint8` or `char` array. `fixed` is set for arrays with a size in the definition.
+    Bytes {
+        bytes: Vec<u8>,
+        fixed: bool,
+    },
+    Array {
+        elements: Vec<Ros1Value>,
+        fixed: bool,
+    },
+    Message(Vec<(String, Ros1Value)>),
+}
+
+impl Ros1Value {
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            Ros1Value::Bool(value) => Some(*value as i64),
+            Ros1Value::Int(value) => Some(*value),
+            Ros1Value::UInt(value) => Some(*value as i64),
+            Ros1Value::Float(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Ros1Value::UInt(value) => Some(*value),
+            value => value.as_i64().map(|value| value as u64),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Ros1Value::Float(value) => Some(*value),
+            Ros1Value::Int(value) => Some(*value as f64),
+            Ros1Value::UInt(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    /// The value of the field of a message whose name matches `name`, ignoring case and the
+    /// trailing `_` of fields named after Rust keywords.
+    fn field(&self, name: &str) -> Option<&Ros1Value> {
+        let Ros1Value::Message(fields) = self else {
+            return None;
+        };
+        let name = crate::introspection::ros_field_name(name);
+        fields
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .or_else(|| {
+                fields
+                    .iter()
+                    .find(|(field_name, _)| field_name.eq_ignore_ascii_case(name))
+            })
+            .map(|(_, value)| value)
+    }
+}
+
+/// Converts the messages of a connection to CDR serialized messages of a ROS 2 type.
+pub(crate) struct Ros1Transcoder {
+    definition: Ros1Definition,
+    descriptor: RosTypeDescriptor,
+}
+
+impl Ros1Transcoder {
+    pub(crate) fn new(definition: Ros1Definition, descriptor: RosTypeDescriptor) -> Self {
+        Ros1Transcoder {
+            definition,
+            descriptor,
+        }
+    }
+
+    /// Converts a serialized ROS 1 message to a CDR serialized ROS 2 message.
+    pub(crate) fn transcode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let message = self.definition.decode(data)?;
+        let mut writer = CdrWriter::default();
+        write_message(Some(&message), &self.descriptor, &mut writer)?;
+        Ok(writer.finish())
+    }
+}
+
+fn write_message(
+    message: Option<&Ros1Value>,
+    descriptor: &RosTypeDescriptor,
+    writer: &mut CdrWriter,
+) -> Result<()> {
+    for field in &descriptor.fields {
+        let value = message.and_then(|message| message.field(field.name));
+        write_field(value, field, writer)
+            .with_context(|| format!("Unable to write field {}", field.name))?;
+    }
+    Ok(())
+}
+
+fn write_field(
+    value: Option<&Ros1Value>,
+    field: &RosFieldDescriptor,
+    writer: &mut CdrWriter,
+) -> Result<()> {
+    let Some(size) = field.array else {
+        return write_value(value, field, writer);
+    };
+    let (elements, fixed) = match value {
+        Some(Ros1Value::Array { elements, fixed }) => (elements.clone(), *fixed),
+        Some(Ros1Value::Bytes { bytes, fixed }) => {
+            if matches!(field.ros_type, "uint8" | "byte" | "char") {
+                return match array_size(size, bytes.len(), *fixed) {
+                    ArraySize::Fixed(len) => {
+                        writer.write_byte_array(&resized(bytes.clone(), len), len)
+                    }
+                    _ => writer.write_byte_sequence(bytes),
+                };
+            }
+            let elements = bytes
+                .iter()
+                .map(|&byte| Ros1Value::UInt(byte as u64))
+                .collect();
+            (elements, *fixed)
+        }
+        Some(other) => bail!("Expected an array, got {:?}", other),
+        None => (vec![], false),
+    };
+    match array_size(size, elements.len(), fixed) {
+        ArraySize::Fixed(len) => {
+            for index in 0..len {
+                write_value(elements.get(index), field, writer)?;
+            }
+        }
+        ArraySize::Unbounded | ArraySize::Bounded(_) => {
+            writer.write_length(elements.len())?;
+            for element in &elements {
+                write_value(Some(element), field, writer)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The size of the ROS 2 array. Descriptors built from the code r2r generated report every array
+/// as unbounded, so a fixed size ROS 1 array stays fixed size then.
+fn array_size(size: ArraySize, ros1_len: usize, ros1_fixed: bool) -> ArraySize {
+    match size {
+        ArraySize::Unbounded if ros1_fixed => ArraySize::Fixed(ros1_len),
+        size => size,
+    }
+}
+
+fn resized(mut bytes: Vec<u8>, len: usize) -> Vec<u8> {
+    bytes.resize(len, 0);
+    bytes
+}
+
+/// Writes a single value of the type of `field`, or the default value if `value` is `None`.
+fn write_value(
+    value: Option<&Ros1Value>,
+    field: &RosFieldDescriptor,
+    writer: &mut CdrWriter,
+) -> Result<()> {
+    if let Some(nested) = &field.nested {
+        return match value {
+            Some(Ros1Value::Time { sec, nanosec }) => {
+                // ROS 2 keeps the nanoseconds positive, also for negative durations.
+                let nanos = sec * 1_000_000_000 + nanosec;
+                (nanos.div_euclid(1_000_000_000) as i32).write_cdr(writer)?;
+                (nanos.rem_euclid(1_000_000_000) as u32).write_cdr(writer)
+            }
+            Some(Ros1Value::Message(_)) | None => write_message(value, nested, writer),
+            Some(other) => bail!("Expected a {}, got {:?}", nested.schema_name, other),
+        };
+    }
+
+    let mismatch = || {
+        anyhow!(
+            "Expected a {} value, got {:?}",
+            field.ros_type,
+            value.cloned()
+        )
+    };
+    match field.ros_type {
+        "string" | "wstring" => match value {
+            Some(Ros1Value::String(value)) => writer.write_string(value),
+            None => writer.write_string(""),
+            Some(_) => Err(mismatch()),
+        },
+        "bool" => {
+            let value = value
+                .map_or(Some(0), Ros1Value::as_i64)
+                .ok_or_else(mismatch)?;
+            (value != 0).write_cdr(writer)
+        }
+        "float32" | "float64" => {
+            let value = value
+                .map_or(Some(0.0), Ros1Value::as_f64)
+                .ok_or_else(mismatch)?;
+            if field.ros_type == "float32" {
+                (value as f32).write_cdr(writer)
+            } else {
+                value.write_cdr(writer)
+            }
+        }
+        "uint8" | "byte" | "char" | "uint16" | "uint32" | "uint64" => {
+            let value = value
+                .map_or(Some(0), Ros1Value::as_u64)
+                .ok_or_else(mismatch)?;
+            match field.ros_type {
+                "uint16" => (value as u16).write_cdr(writer),
+                "uint32" => (value as u32).write_cdr(writer),
+                "uint64" => value.write_cdr(writer),
+                _ => (value as u8).write_cdr(writer),
+            }
+        }
+        "int8" | "int16" | "int32" | "int64" => {
+            let value = value
+                .map_or(Some(0), Ros1Value::as_i64)
+                .ok_or_else(mismatch)?;
+            match field.ros_type {
+                "int8" => (value as i8).write_cdr(writer),
+                "int16" => (value as i16).write_cdr(writer),
+                "int32" => (value as i32).write_cdr(writer),
+                _ => value.write_cdr(writer),
+            }
+        }
+        other => bail!("Unsupported ROS 2 type {}", other),
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::cdr::CdrMessage;
+    use crate::msgs::sensor_msgs::msg::CameraInfo;
+
+    /// A `sensor_msgs/CameraInfo` without most of its fields, with a field ROS 2 doesn't have.
+    const CAMERA_INFO_DEFINITION: &str = "\
+# Comment
+Header header
+uint32 height
+uint32 width
+string distortion_model
+float64[] D
+float64[9] K  # 3x3 row-major matrix
+uint8 ROS1_ONLY=1
+int8 ros1_only
+================================================================================
+MSG: std_msgs/Header
+uint32 seq
+time stamp
+string frame_id
+";
+
+    pub(crate) fn ros1_camera_info() -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(7u32.to_le_bytes());
+        data.extend(10u32.to_le_bytes());
+        data.extend(20u32.to_le_bytes());
+        data.extend(6u32.to_le_bytes());
+        data.extend(b"camera");
+        data.extend(480u32.to_le_bytes());
+        data.extend(640u32.to_le_bytes());
+        data.extend(9u32.to_le_bytes());
+        data.extend(b"plumb_bob");
+        data.extend(1u32.to_le_bytes());
+        data.extend(0.5f64.to_le_bytes());
+        for value in 1..=9 {
+            data.extend((value as f64).to_le_bytes());
+        }
+        data.push(-1i8 as u8);
+        data
+    }
+
+    #[test]
+    fn test_transcode() {
+        assert_eq!(
+            ros2_schema_name("sensor_msgs/CameraInfo"),
+            "sensor_msgs/msg/CameraInfo"
+        );
+        let definition =
+            Ros1Definition::parse("sensor_msgs/CameraInfo", CAMERA_INFO_DEFINITION).unwrap();
+        let descriptor = crate::type_descriptor("sensor_msgs/msg/CameraInfo").unwrap();
+        let transcoder = Ros1Transcoder::new(definition, descriptor);
+        let message = transcoder.transcode(&ros1_camera_info()).unwrap();
+        let camera_info = CameraInfo::from_serialized_bytes(&message).unwrap();
+        assert_eq!(camera_info.header.stamp.sec, 10);
+        assert_eq!(camera_info.header.stamp.nanosec, 20);
+        assert_eq!(camera_info.header.frame_id, "camera");
+        assert_eq!((camera_info.height, camera_info.width), (480, 640));
+        assert_eq!(camera_info.distortion_model, "plumb_bob");
+        assert_eq!(camera_info.d, [0.5]);
+        assert_eq!(camera_info.k, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        // Fields ROS 1 doesn't have get their default value.
+        assert_eq!(camera_info.r, [0.0; 9]);
+        assert_eq!(camera_info.binning_x, 0);
+
+        let truncated = &ros1_camera_info()[..30];
+        assert!(transcoder.transcode(truncated).is_err());
+    }
+}
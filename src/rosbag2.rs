@@ -0,0 +1,668 @@
+//! Reads rosbag2 bags with the SQLite3 storage (`.db3` files) into Arrow. Requires the `rosbag2`
+//! feature.
+//!
+//! The serialized messages of a topic are added to the row builder of its type with
+//! `add_raw_row_with_context`, and returned as a stream of `RecordBatch`es. The batches have
+//! the context columns of [`crate::row_context_fields`]: `_recv_time` holds the bag timestamp of
+//! the message, `_topic` the topic and `_seq` the index of the message in the topic.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use r2a::rosbag2::Rosbag2Reader;
+//!
+//! let reader = Rosbag2Reader::open("recordings/run_42").unwrap();
+//! for topic in reader.topics() {
+//!     for batch in reader.topic_batches(&topic.name, true).unwrap() {
+//!         let batch = batch.unwrap();
+//!         println!("{}: {} rows", topic.name, batch.num_rows());
+//!     }
+//! }
+//! ```
+
+use crate::projection::Projection;
+use crate::{new_raw_row_builder, row_context_fields, schema_arrow_fields, RowContext};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::RecordBatch;
+use arrow_schema::{Field, Schema, SchemaRef};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::Deserialize;
+use std::fs;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The number of rows of the batches returned by [`Rosbag2Reader::topic_batches`], unless set
+/// with [`Rosbag2Reader::with_batch_size`].
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// The content of the `metadata.yaml` of a bag.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BagMetadata {
+    pub version: u32,
+    pub storage_identifier: String,
+    #[serde(default)]
+    pub relative_file_paths: Vec<String>,
+    pub duration: BagDuration,
+    pub starting_time: BagTime,
+    pub message_count: u64,
+    pub topics_with_message_count: Vec<TopicWithMessageCount>,
+    #[serde(default)]
+    pub compression_format: String,
+    #[serde(default)]
+    pub compression_mode: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BagDuration {
+    pub nanoseconds: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BagTime {
+    pub nanoseconds_since_epoch: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicWithMessageCount {
+    pub topic_metadata: TopicMetadata,
+    pub message_count: u64,
+}
+
+/// A topic recorded in a bag.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicMetadata {
+    pub name: String,
+    /// The schema name of the messages, for example `sensor_msgs/msg/LaserScan`.
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub serialization_format: String,
+    /// The QoS profiles of the publishers: a YAML string up to metadata version 8, and a
+    /// sequence of QoS mappings from version 9 (Jazzy).
+    #[serde(default)]
+    pub offered_qos_profiles: serde_yaml::Value,
+}
+
+#[derive(Deserialize)]
+struct MetadataFile {
+    rosbag2_bagfile_information: BagMetadata,
+}
+
+/// Reads the topics of a rosbag2 bag directory with the SQLite3 storage.
+pub struct Rosbag2Reader {
+    metadata: BagMetadata,
+    files: Vec<Connection>,
+    batch_size: usize,
+}
+
+impl Rosbag2Reader {
+    /// Opens the bag directory at `path`, which holds `metadata.yaml` and the `.db3` files.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let metadata_path = path.join("metadata.yaml");
+        let metadata = fs::read_to_string(&metadata_path)
+            .with_context(|| format!("Unable to read {}", metadata_path.display()))?;
+        let metadata: MetadataFile = serde_yaml::from_str(&metadata)
+            .with_context(|| format!("Unable to parse {}", metadata_path.display()))?;
+        let metadata = metadata.rosbag2_bagfile_information;
+
+        if metadata.storage_identifier != "sqlite3" {
+            bail!(
+                "Unsupported storage {} in {}, only sqlite3 is supported",
+                metadata.storage_identifier,
+                path.display()
+            );
+        }
+        if !metadata.compression_mode.is_empty() {
+            bail!(
+                "Compressed bags are not supported, {} is compressed with {}",
+                path.display(),
+                metadata.compression_format
+            );
+        }
+
+        let files = bag_files(path, &metadata)?
+            .iter()
+            .map(|file| {
+                Connection::open_with_flags(file, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                    .with_context(|| format!("Unable to open {}", file.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Rosbag2Reader {
+            metadata,
+            files,
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// Sets the maximum number of rows of the batches.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn metadata(&self) -> &BagMetadata {
+        &self.metadata
+    }
+
+    /// The topics recorded in the bag.
+    pub fn topics(&self) -> Vec<&TopicMetadata> {
+        self.metadata
+            .topics_with_message_count
+            .iter()
+            .map(|topic| &topic.topic_metadata)
+            .collect()
+    }
+
+    fn topic(&self, topic: &str) -> Result<&TopicMetadata> {
+        self.topics()
+            .into_iter()
+            .find(|metadata| metadata.name == topic)
+            .ok_or_else(|| anyhow!("Topic {} is not in the bag", topic))
+    }
+
+    /// The schema of the batches of `topic`: the fields of its type in the regular or, if
+    /// `flat` is true, the flat layout, followed by the context columns.
+    pub fn topic_schema(&self, topic: &str, flat: bool) -> Result<Schema> {
+        Ok(Schema::new(topic_fields(self.topic(topic)?, flat)?))
+    }
+
+    /// Returns the messages of `topic` as batches of at most `batch_size` rows, in the order of
+    /// their bag timestamp.
+    pub fn topic_batches(&self, topic: &str, flat: bool) -> Result<TopicBatches<'_>> {
+        self.topic_batches_in_range(topic, flat, ..)
+    }
+
+    /// Returns the messages of `topic` with a bag timestamp in `range`, in nanoseconds since the
+    /// UNIX epoch, as batches of at most `batch_size` rows. The range is part of the query, so
+    /// the messages outside of it are not read.
+    pub fn topic_batches_in_range(
+        &self,
+        topic: &str,
+        flat: bool,
+        range: impl RangeBounds<u64>,
+    ) -> Result<TopicBatches<'_>> {
+        let topic = self.topic(topic)?.clone();
+        if topic.serialization_format != "cdr" {
+            bail!(
+                "Unsupported serialization format {} of topic {}",
+                topic.serialization_format,
+                topic.name
+            );
+        }
+        let fields = topic_fields(&topic, flat)?;
+        let timestamp = |time: u64| i64::try_from(time).unwrap_or(i64::MAX);
+        let start = match range.start_bound() {
+            Bound::Included(start) => timestamp(*start),
+            Bound::Excluded(start) => timestamp(*start).saturating_add(1),
+            Bound::Unbounded => i64::MIN,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => timestamp(*end),
+            Bound::Excluded(end) => timestamp(*end) - 1,
+            Bound::Unbounded => i64::MAX,
+        };
+        Ok(TopicBatches {
+            reader: self,
+            projection: Projection::all(&fields),
+            fields,
+            topic,
+            flat,
+            start,
+            end,
+            file_index: 0,
+            topic_id: None,
+            cursor: None,
+            seq: 0,
+        })
+    }
+}
+
+/// The `.db3` files of the bag, in order. Bags written before the files were listed in the
+/// metadata have a single file, named after the directory.
+fn bag_files(path: &Path, metadata: &BagMetadata) -> Result<Vec<PathBuf>> {
+    if !metadata.relative_file_paths.is_empty() {
+        return Ok(metadata
+            .relative_file_paths
+            .iter()
+            .map(|file| path.join(file))
+            .collect());
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.extension().is_some_and(|ext| ext == "db3"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn topic_fields(topic: &TopicMetadata, flat: bool) -> Result<Vec<Field>> {
+    let mut fields = schema_arrow_fields(&topic.type_name, flat, false).ok_or_else(|| {
+        anyhow!(
+            "Unsupported message type {} of topic {}",
+            topic.type_name,
+            topic.name
+        )
+    })?;
+    fields.extend(row_context_fields(false));
+    Ok(fields)
+}
+
+/// The batches of a topic, returned by [`Rosbag2Reader::topic_batches`].
+pub struct TopicBatches<'r> {
+    reader: &'r Rosbag2Reader,
+    topic: TopicMetadata,
+    fields: Vec<Field>,
+    projection: Projection,
+    flat: bool,
+    /// The first and last bag timestamp of the messages that are read.
+    start: i64,
+    end: i64,
+    file_index: usize,
+    /// The id of the topic in the current file, `Some(None)` if the file doesn't have it.
+    topic_id: Option<Option<i64>>,
+    /// The bag timestamp and id of the last message read from the current file.
+    cursor: Option<(i64, i64)>,
+    seq: u64,
+}
+
+impl TopicBatches<'_> {
+    /// Only builds the columns at the indices of `projection` in [`Rosbag2Reader::topic_schema`],
+    /// and returns them in the order of `projection`. The messages are still deserialized, but
+    /// the other columns are skipped. An empty projection returns batches without columns, which
+    /// only have a row count.
+    pub fn with_projection(mut self, projection: &[usize]) -> Result<Self> {
+        self.projection = Projection::new(&self.fields, projection)?;
+        Ok(self)
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.projection.schema()
+    }
+
+    /// Reads up to `limit` messages from the current file, after the cursor.
+    fn read_messages(&mut self, limit: usize) -> Result<Vec<(i64, i64, Vec<u8>)>> {
+        let connection = &self.reader.files[self.file_index];
+        let topic_id = match self.topic_id {
+            Some(topic_id) => topic_id,
+            None => {
+                let topic_id = connection
+                    .query_row(
+                        "SELECT id FROM topics WHERE name = ?1",
+                        params![self.topic.name],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                self.topic_id = Some(topic_id);
+                topic_id
+            }
+        };
+        let Some(topic_id) = topic_id else {
+            return Ok(vec![]);
+        };
+
+        let (timestamp, id) = self.cursor.unwrap_or((self.start, i64::MIN));
+        let mut statement = connection.prepare_cached(
+            "SELECT id, timestamp, data FROM messages \
+             WHERE topic_id = ?1 AND (timestamp > ?2 OR (timestamp = ?2 AND id > ?3)) \
+             AND timestamp <= ?4 ORDER BY timestamp, id LIMIT ?5",
+        )?;
+        let messages = statement
+            .query_map(
+                params![topic_id, timestamp, id, self.end, limit as i64],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?
+            .collect::<rusqlite::Result<Vec<(i64, i64, Vec<u8>)>>>()?;
+        if let Some((id, timestamp, _)) = messages.last() {
+            self.cursor = Some((*timestamp, *id));
+        }
+        Ok(messages)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        loop {
+            let mut messages = Vec::with_capacity(self.reader.batch_size);
+            while messages.len() < self.reader.batch_size
+                && self.file_index < self.reader.files.len()
+            {
+                let read = self.read_messages(self.reader.batch_size - messages.len())?;
+                if read.is_empty() {
+                    self.file_index += 1;
+                    self.topic_id = None;
+                    self.cursor = None;
+                }
+                messages.extend(read);
+            }
+            if messages.is_empty() {
+                return Ok(None);
+            }
+
+            let mut row_builder = new_raw_row_builder(
+                &self.topic.type_name,
+                self.projection.fields(&self.fields),
+                self.flat,
+            )
+            .ok_or_else(|| anyhow!("Unsupported message type {}", self.topic.type_name))?;
+            let mut rows = 0;
+            for (_, timestamp, data) in &messages {
+                let seq = self.seq;
+                self.seq += 1;
+                // The timestamp is signed in the database, a negative one is a corrupt row.
+                let Ok(timestamp) = u64::try_from(*timestamp) else {
+                    log::error!(
+                        "Skipping message {} of {} with the negative timestamp {}",
+                        seq,
+                        self.topic.name,
+                        timestamp
+                    );
+                    continue;
+                };
+                let ctx = RowContext::new(&self.topic.name, seq)
+                    .with_recv_time(UNIX_EPOCH + Duration::from_nanos(timestamp));
+                row_builder
+                    .add_raw_row_with_context(data, &ctx)
+                    .with_context(|| {
+                        format!("Unable to read message {} of {}", seq, self.topic.name)
+                    })?;
+                rows += 1;
+            }
+            // Read on if every message of the batch was skipped.
+            if rows > 0 {
+                return Ok(Some(
+                    self.projection.batch(row_builder.to_arc_arrays(), rows)?,
+                ));
+            }
+        }
+    }
+}
+
+impl Iterator for TopicBatches<'_> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::cdr::CdrMessage;
+    use crate::msgs::builtin_interfaces::msg::Time;
+    use crate::msgs::sensor_msgs::msg::LaserScan;
+    use crate::msgs::std_msgs::msg::String as StringMsg;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, TimestampNanosecondType, UInt64Type};
+    use arrow_array::Array;
+
+    const METADATA: &str = r#"
+rosbag2_bagfile_information:
+  version: 5
+  storage_identifier: sqlite3
+  duration:
+    nanoseconds: 2000
+  starting_time:
+    nanoseconds_since_epoch: 1000
+  message_count: 6
+  topics_with_message_count:
+    - topic_metadata:
+        name: /scan
+        type: sensor_msgs/msg/LaserScan
+        serialization_format: cdr
+        offered_qos_profiles: ""
+      message_count: 5
+    - topic_metadata:
+        name: /chatter
+        type: std_msgs/msg/String
+        serialization_format: cdr
+        offered_qos_profiles: ""
+      message_count: 1
+  compression_format: ""
+  compression_mode: ""
+  relative_file_paths:
+    - test_bag_0.db3
+"#;
+
+    /// The metadata of a Jazzy bag, with the QoS profiles as a sequence.
+    const METADATA_V9: &str = r#"
+rosbag2_bagfile_information:
+  version: 9
+  storage_identifier: sqlite3
+  duration:
+    nanoseconds: 2000
+  starting_time:
+    nanoseconds_since_epoch: 1000
+  message_count: 6
+  topics_with_message_count:
+    - topic_metadata:
+        name: /scan
+        type: sensor_msgs/msg/LaserScan
+        serialization_format: cdr
+        offered_qos_profiles:
+          - history: keep_last
+            depth: 10
+            reliability: reliable
+            durability: volatile
+            deadline:
+              sec: 9223372036
+              nsec: 854775807
+            lifespan:
+              sec: 9223372036
+              nsec: 854775807
+            liveliness: automatic
+            liveliness_lease_duration:
+              sec: 9223372036
+              nsec: 854775807
+            avoid_ros_namespace_conventions: false
+        type_description_hash: RIHS01_64a1c1d7a1d2d9e6a5a1f8ba6ba0c57ea1cb43b0a4c4e2a7fa4b6c23cd87c9c1
+      message_count: 5
+    - topic_metadata:
+        name: /chatter
+        type: std_msgs/msg/String
+        serialization_format: cdr
+        offered_qos_profiles: []
+        type_description_hash: RIHS01_df668c740482bbd48fb39d76a70dfd4bd59db1288021743503259e948f6b1a18
+      message_count: 1
+  compression_format: ""
+  compression_mode: ""
+  relative_file_paths:
+    - test_bag_0.db3
+  files:
+    - path: test_bag_0.db3
+      starting_time:
+        nanoseconds_since_epoch: 1000
+      duration:
+        nanoseconds: 2000
+      message_count: 6
+  custom_data: ~
+  ros_distro: jazzy
+"#;
+
+    fn write_bag(name: &str, metadata: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("r2a_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("metadata.yaml"), metadata).unwrap();
+
+        let connection = Connection::open(path.join("test_bag_0.db3")).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE topics(id INTEGER PRIMARY KEY, name TEXT NOT NULL, type TEXT NOT NULL, \
+                 serialization_format TEXT NOT NULL, offered_qos_profiles TEXT NOT NULL);
+                 CREATE TABLE messages(id INTEGER PRIMARY KEY, topic_id INTEGER NOT NULL, \
+                 timestamp INTEGER NOT NULL, data BLOB NOT NULL);
+                 INSERT INTO topics VALUES (1, '/scan', 'sensor_msgs/msg/LaserScan', 'cdr', '');
+                 INSERT INTO topics VALUES (2, '/chatter', 'std_msgs/msg/String', 'cdr', '');",
+            )
+            .unwrap();
+
+        // Inserted out of order, the reader returns them by timestamp.
+        for (id, sec) in [(1, 3), (2, 1), (4, 2), (5, 5), (6, 4)] {
+            let scan = LaserScan {
+                header: crate::msgs::std_msgs::msg::Header {
+                    stamp: Time { sec, nanosec: 0 },
+                    frame_id: "laser".to_string(),
+                },
+                ranges: vec![sec as f32],
+                ..Default::default()
+            };
+            connection
+                .execute(
+                    "INSERT INTO messages VALUES (?1, 1, ?2, ?3)",
+                    params![id, 1000 * sec, scan.to_serialized_bytes().unwrap()],
+                )
+                .unwrap();
+        }
+        let chatter = StringMsg {
+            data: "hello".to_string(),
+        };
+        connection
+            .execute(
+                "INSERT INTO messages VALUES (3, 2, 1500, ?1)",
+                params![chatter.to_serialized_bytes().unwrap()],
+            )
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_topics() {
+        let path = write_bag("read_topics", METADATA);
+        let reader = Rosbag2Reader::open(&path).unwrap().with_batch_size(2);
+        assert_eq!(reader.metadata().message_count, 6);
+        let topics: Vec<&str> = reader
+            .topics()
+            .iter()
+            .map(|topic| topic.name.as_str())
+            .collect();
+        assert_eq!(topics, ["/scan", "/chatter"]);
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/scan", true)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            batches
+                .iter()
+                .map(|batch| batch.num_rows())
+                .collect::<Vec<_>>(),
+            [2, 2, 1]
+        );
+        assert_eq!(
+            batches[0].schema().as_ref(),
+            &reader.topic_schema("/scan", true).unwrap()
+        );
+        let ranges: Vec<f32> = batches
+            .iter()
+            .flat_map(|batch| {
+                let ranges = batch.column_by_name("ranges").unwrap().as_list::<i64>();
+                (0..ranges.len())
+                    .map(|i| ranges.value(i).as_primitive::<Float32Type>().value(0))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(ranges, [1.0, 2.0, 3.0, 4.0, 5.0]);
+        let recv_time = batches[1]
+            .column_by_name("_recv_time")
+            .unwrap()
+            .as_primitive::<TimestampNanosecondType>();
+        assert_eq!(recv_time.value(0), 3000);
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/chatter", false)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            batches[0]
+                .column_by_name("data")
+                .unwrap()
+                .as_string::<i32>()
+                .value(0),
+            "hello"
+        );
+
+        assert!(reader.topic_batches("/unknown", false).is_err());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_range_and_projection() {
+        let path = write_bag("range_and_projection", METADATA);
+        let reader = Rosbag2Reader::open(&path).unwrap().with_batch_size(2);
+        let schema = reader.topic_schema("/scan", true).unwrap();
+        let recv_time = schema.index_of("_recv_time").unwrap();
+        let ranges = schema.index_of("ranges").unwrap();
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches_in_range("/scan", true, 2000..=4000)
+            .unwrap()
+            .with_projection(&[ranges, recv_time])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            batches[0].schema().as_ref(),
+            &schema.project(&[ranges, recv_time]).unwrap()
+        );
+        let recv_times: Vec<i64> = batches
+            .iter()
+            .flat_map(|batch| {
+                let recv_time = batch.column(1).as_primitive::<TimestampNanosecondType>();
+                recv_time.values().to_vec()
+            })
+            .collect();
+        assert_eq!(recv_times, [2000, 3000, 4000]);
+
+        let rows: usize = reader
+            .topic_batches_in_range("/scan", false, 1000..3000)
+            .unwrap()
+            .with_projection(&[])
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum();
+        assert_eq!(rows, 2);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_jazzy_metadata() {
+        let path = write_bag("jazzy_metadata", METADATA_V9);
+        // A corrupt row with a negative timestamp is skipped.
+        let connection = Connection::open(path.join("test_bag_0.db3")).unwrap();
+        let chatter = StringMsg {
+            data: "corrupt".to_string(),
+        };
+        connection
+            .execute(
+                "INSERT INTO messages VALUES (7, 2, -1, ?1)",
+                params![chatter.to_serialized_bytes().unwrap()],
+            )
+            .unwrap();
+        drop(connection);
+
+        let reader = Rosbag2Reader::open(&path).unwrap();
+        assert_eq!(reader.metadata().version, 9);
+        let qos = &reader.topics()[0].offered_qos_profiles;
+        assert_eq!(qos[0]["reliability"].as_str(), Some("reliable"));
+        assert_eq!(qos[0]["depth"].as_u64(), Some(10));
+
+        let batches: Vec<RecordBatch> = reader
+            .topic_batches("/chatter", false)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 1);
+        let seq = batches[0]
+            .column_by_name("_seq")
+            .unwrap()
+            .as_primitive::<UInt64Type>();
+        assert_eq!(seq.value(0), 1);
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use arrow_array::builder::{
+    LargeBinaryBuilder, StringBuilder, TimestampNanosecondBuilder, UInt64Builder,
+};
+use arrow_array::Array;
+use arrow_schema::{DataType, Field, TimeUnit};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the reserved column holding the time the message was received.
+pub const RECV_TIME_FIELD: &str = "_recv_time";
+/// Name of the reserved column holding the topic the message was received on.
+pub const TOPIC_FIELD: &str = "_topic";
+/// Name of the reserved column holding the sequence number of the message.
+pub const SEQ_FIELD: &str = "_seq";
+/// Name of the reserved column holding the GID of the publisher of the message.
+pub const PUBLISHER_GID_FIELD: &str = "_publisher_gid";
+
+/// Per-row metadata that is not part of the message content itself, but is usually needed when
+/// logging messages: when and where the message was received, and in which order.
+///
+/// Pass it to [`crate::RowBuilder::add_row_with_context`] to fill the reserved context columns
+/// returned by [`row_context_fields`].
+///
+/// # Example
+///
+/// ```
+/// use r2a::RowContext;
+///
+/// let ctx = RowContext::new("/laser_scan", 42).with_publisher_gid(&[1, 2, 3]);
+/// assert_eq!(ctx.seq, 42);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowContext {
+    /// The time the message was received. Stored as nanoseconds since the UNIX epoch (UTC).
+    pub recv_time: SystemTime,
+    /// The topic the message was received on.
+    pub topic: String,
+    /// A monotonically increasing sequence number, maintained by the caller.
+    pub seq: u64,
+    /// The GID of the publisher, for example from r2r's message info. Optional.
+    pub publisher_gid: Option<Vec<u8>>,
+}
+
+impl RowContext {
+    /// Creates a new context for `topic` and `seq`, with the receive time set to now.
+    pub fn new(topic: &str, seq: u64) -> Self {
+        RowContext {
+            recv_time: SystemTime::now(),
+            topic: topic.to_string(),
+            seq,
+            publisher_gid: None,
+        }
+    }
+
+    /// Overrides the receive time.
+    pub fn with_recv_time(mut self, recv_time: SystemTime) -> Self {
+        self.recv_time = recv_time;
+        self
+    }
+
+    /// Sets the publisher GID.
+    pub fn with_publisher_gid(mut self, publisher_gid: &[u8]) -> Self {
+        self.publisher_gid = Some(publisher_gid.to_vec());
+        self
+    }
+
+    pub(crate) fn recv_time_nanos(&self) -> i64 {
+        system_time_to_nanos(self.recv_time)
+    }
+}
+
+/// Converts a `SystemTime` to nanoseconds since the UNIX epoch.
+pub(crate) fn system_time_to_nanos(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos() as i64,
+        Err(e) => -(e.duration().as_nanos() as i64),
+    }
+}
+
+/// Returns the reserved context columns that can be appended to the fields of both the regular
+/// and the flat row builders.
+///
+/// # Arguments
+///
+/// * `include_publisher_gid` - If true, the `_publisher_gid` column is included as well.
+///
+/// # Example
+///
+/// ```
+/// use r2a::ArrowSupport;
+///
+/// let mut fields = r2r::std_msgs::msg::Header::arrow_fields(false);
+/// fields.extend(r2a::row_context_fields(false));
+/// let row_builder = r2r::std_msgs::msg::Header::new_row_builder(fields.iter().collect());
+/// ```
+pub fn row_context_fields(include_publisher_gid: bool) -> Vec<Field> {
+    let mut fields = vec![
+        Field::new(
+            RECV_TIME_FIELD,
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new(TOPIC_FIELD, DataType::Utf8, true),
+        Field::new(SEQ_FIELD, DataType::UInt64, true),
+    ];
+    if include_publisher_gid {
+        fields.push(Field::new(PUBLISHER_GID_FIELD, DataType::LargeBinary, true));
+    }
+    fields
+}
+
+pub(crate) fn is_row_context_field(name: &str) -> bool {
+    matches!(
+        name,
+        RECV_TIME_FIELD | TOPIC_FIELD | SEQ_FIELD | PUBLISHER_GID_FIELD
+    )
+}
+
+/// Accumulates the reserved context columns for a generated row builder. Rows added without a
+/// context get nulls in these columns.
+#[derive(Default)]
+pub(crate) struct RowContextBuilder {
+    recv_time: Option<TimestampNanosecondBuilder>,
+    topic: Option<StringBuilder>,
+    seq: Option<UInt64Builder>,
+    publisher_gid: Option<LargeBinaryBuilder>,
+}
+
+impl RowContextBuilder {
+    pub(crate) fn add_field(&mut self, field: &Field) {
+        match field.name().as_str() {
+            RECV_TIME_FIELD => {
+                self.recv_time = Some(TimestampNanosecondBuilder::new().with_timezone("UTC"))
+            }
+            TOPIC_FIELD => self.topic = Some(StringBuilder::new()),
+            SEQ_FIELD => self.seq = Some(UInt64Builder::new()),
+            PUBLISHER_GID_FIELD => self.publisher_gid = Some(LargeBinaryBuilder::new()),
+            other => log::error!("Invalid context field name: {}", other),
+        }
+    }
+
+    pub(crate) fn append(&mut self, name: &str, ctx: Option<&RowContext>) {
+        match name {
+            RECV_TIME_FIELD => self
+                .recv_time
+                .as_mut()
+                .unwrap()
+                .append_option(ctx.map(|ctx| ctx.recv_time_nanos())),
+            TOPIC_FIELD => self
+                .topic
+                .as_mut()
+                .unwrap()
+                .append_option(ctx.map(|ctx| ctx.topic.as_str())),
+            SEQ_FIELD => self
+                .seq
+                .as_mut()
+                .unwrap()
+                .append_option(ctx.map(|ctx| ctx.seq)),
+            PUBLISHER_GID_FIELD => self
+                .publisher_gid
+                .as_mut()
+                .unwrap()
+                .append_option(ctx.and_then(|ctx| ctx.publisher_gid.as_deref())),
+            other => log::error!("Invalid context field name: {}", other),
+        }
+    }
+
+    /// Returns the column of the context field `name`. The callers only pass the names for
+    /// which `is_row_context_field` is true.
+    pub(crate) fn finish(&mut self, name: &str) -> Arc<dyn Array> {
+        match name {
+            RECV_TIME_FIELD => Arc::new(self.recv_time.as_mut().unwrap().finish()),
+            TOPIC_FIELD => Arc::new(self.topic.as_mut().unwrap().finish()),
+            SEQ_FIELD => Arc::new(self.seq.as_mut().unwrap().finish()),
+            PUBLISHER_GID_FIELD => Arc::new(self.publisher_gid.as_mut().unwrap().finish()),
+            other => unreachable!("Invalid context field name: {}", other),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Predicate<T> = Box<dyn Fn(&T) -> bool + Send>;
+
+/// Counters of a [`RowFilter`], as returned by [`crate::RowBuilder::filter_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RowFilterStats {
+    /// Number of rows that passed the filter and were added to the builder.
+    pub accepted: u64,
+    /// Number of rows dropped because one of the predicates returned false.
+    pub dropped_by_predicate: u64,
+    /// Number of rows dropped by the "keep 1 of N" decimation.
+    pub dropped_by_decimation: u64,
+    /// Number of rows dropped by the maximum rate limit.
+    pub dropped_by_rate: u64,
+}
+
+impl RowFilterStats {
+    /// Total number of dropped rows.
+    pub fn dropped(&self) -> u64 {
+        self.dropped_by_predicate + self.dropped_by_decimation + self.dropped_by_rate
+    }
+}
+
+/// A filter that can be set on a row builder with [`crate::RowBuilder::set_filter`], so that
+/// `add_row`, `add_row_with_context` and `add_raw_row` skip messages that don't match.
+///
+/// The rules are applied in this order:
+/// 1. Predicates: all of them have to return true.
+/// 2. Decimation: of the messages that passed the predicates, only 1 of every N is kept.
+/// 3. Rate limit: a message is only kept if at least `1 / hz` seconds have passed since the last
+///    kept message. The time is taken from the `header.stamp` of the message. For messages
+///    without a header, the receive time of the row context is used, or the time the row is
+///    added if there is no context.
+///
+/// # Example
+///
+/// ```
+/// use r2a::ArrowSupport;
+/// use r2a::RowBuilder;
+/// use r2a::RowFilter;
+/// use r2r::std_msgs::msg::Header;
+///
+/// let fields = Header::arrow_fields(false);
+/// let mut row_builder = Header::new_row_builder(fields.iter().collect());
+/// row_builder.set_filter(
+///     RowFilter::new()
+///         .with_predicate(|msg: &Header| msg.frame_id == "base_link")
+///         .keep_one_in(10)
+///         .max_rate_hz(5.0),
+/// );
+/// ```
+pub struct RowFilter<T> {
+    predicates: Vec<Predicate<T>>,
+    keep_one_in: Option<u64>,
+    min_period_nanos: Option<i64>,
+    decimation_counter: u64,
+    last_kept_stamp: Option<i64>,
+    stats: RowFilterStats,
+}
+
+impl<T> Default for RowFilter<T> {
+    fn default() -> Self {
+        RowFilter {
+            predicates: Vec::new(),
+            keep_one_in: None,
+            min_period_nanos: None,
+            decimation_counter: 0,
+            last_kept_stamp: None,
+            stats: RowFilterStats::default(),
+        }
+    }
+}
+
+impl<T> RowFilter<T> {
+    /// Creates a filter that accepts every message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a predicate. Messages for which the predicate returns false are dropped.
+    pub fn with_predicate<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + 'static,
+    {
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// Keeps only 1 of every `n` messages. The first message is always kept. `n` of 0 or 1
+    /// disables decimation.
+    pub fn keep_one_in(mut self, n: u64) -> Self {
+        self.keep_one_in = if n > 1 { Some(n) } else { None };
+        self
+    }
+
+    /// Keeps at most `hz` messages per second, based on the header stamp of the messages.
+    /// A non-positive rate disables the limit.
+    pub fn max_rate_hz(mut self, hz: f64) -> Self {
+        self.min_period_nanos = if hz > 0.0 {
+            Some((1_000_000_000.0 / hz) as i64)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Returns the counters of accepted and dropped rows.
+    pub fn stats(&self) -> RowFilterStats {
+        self.stats
+    }
+
+    /// Decides whether a message should be added, and updates the counters.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message.
+    /// * `stamp_nanos` - The time of the message in nanoseconds, used by the rate limit. If
+    ///   `None`, the current time is used.
+    pub fn accept(&mut self, msg: &T, stamp_nanos: Option<i64>) -> bool {
+        if !self.predicates.iter().all(|predicate| predicate(msg)) {
+            self.stats.dropped_by_predicate += 1;
+            return false;
+        }
+
+        if let Some(n) = self.keep_one_in {
+            let skip = self.decimation_counter > 0;
+            self.decimation_counter = (self.decimation_counter + 1) % n;
+            if skip {
+                self.stats.dropped_by_decimation += 1;
+                return false;
+            }
+        }
+
+        if let Some(min_period_nanos) = self.min_period_nanos {
+            let stamp = stamp_nanos.unwrap_or_else(now_nanos);
+            match self.last_kept_stamp {
+                // A stamp going backwards (e.g. a restarted bag playback) resets the limit.
+                Some(last) if stamp >= last && stamp - last < min_period_nanos => {
+                    self.stats.dropped_by_rate += 1;
+                    return false;
+                }
+                _ => self.last_kept_stamp = Some(stamp),
+            }
+        }
+
+        self.stats.accepted += 1;
+        true
+    }
+}
+
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(all(test, feature = "offline"))]
+mod tests {
+    use super::*;
+    use crate::msgs::builtin_interfaces::msg::Time;
+    use crate::msgs::geometry_msgs::msg::PointStamped;
+    use crate::msgs::std_msgs::msg::Header;
+    use crate::{ArrowSupport, RowBuilder};
+
+    fn header(frame_id: &str, sec: i32, nanosec: u32) -> Header {
+        Header {
+            stamp: Time { sec, nanosec },
+            frame_id: frame_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_keep_one_in() {
+        let mut filter = RowFilter::new().keep_one_in(3);
+        let kept: Vec<bool> = (0..7)
+            .map(|sec| filter.accept(&header("a", sec, 0), None))
+            .collect();
+        assert_eq!(kept, [true, false, false, true, false, false, true]);
+        assert_eq!(filter.stats().dropped_by_decimation, 4);
+
+        // Messages dropped by a predicate don't count for the decimation.
+        let mut filter = RowFilter::new()
+            .with_predicate(|msg: &Header| msg.frame_id == "a")
+            .keep_one_in(2);
+        let kept: Vec<bool> = ["a", "b", "a", "b", "a"]
+            .iter()
+            .map(|frame_id| filter.accept(&header(frame_id, 0, 0), None))
+            .collect();
+        assert_eq!(kept, [true, false, false, false, true]);
+
+        let mut filter = RowFilter::new().keep_one_in(1);
+        assert!((0..3).all(|_| filter.accept(&header("a", 0, 0), None)));
+    }
+
+    #[test]
+    fn test_max_rate_hz() {
+        // At most one message per 100 ms.
+        let mut filter = RowFilter::<Header>::new().max_rate_hz(10.0);
+        let msg = header("a", 0, 0);
+        let kept: Vec<bool> = [
+            0,
+            99_999_999,
+            100_000_000,
+            150_000_000,
+            199_999_999,
+            200_000_000,
+        ]
+        .iter()
+        .map(|&stamp| filter.accept(&msg, Some(stamp)))
+        .collect();
+        assert_eq!(kept, [true, false, true, false, false, true]);
+        assert_eq!(filter.stats().dropped_by_rate, 3);
+
+        // A stamp going backwards restarts the window.
+        assert!(filter.accept(&msg, Some(50_000_000)));
+        assert!(!filter.accept(&msg, Some(100_000_000)));
+        assert!(filter.accept(&msg, Some(150_000_000)));
+
+        let mut filter = RowFilter::<Header>::new().max_rate_hz(0.0);
+        assert!(filter.accept(&msg, Some(0)) && filter.accept(&msg, Some(0)));
+    }
+
+    #[test]
+    fn test_stats() {
+        let fields = PointStamped::arrow_fields(false);
+        let mut row_builder = PointStamped::new_row_builder(fields.iter().collect());
+        row_builder.set_filter(
+            RowFilter::new()
+                .with_predicate(|msg: &PointStamped| msg.header.frame_id != "drop")
+                .keep_one_in(2)
+                .max_rate_hz(1.0),
+        );
+        // The rate limit uses the header stamps.
+        for (frame_id, sec, nanosec) in [
+            ("a", 0, 0),
+            ("drop", 0, 0),
+            ("a", 0, 1),
+            ("a", 0, 500_000_000),
+            ("a", 0, 700_000_000),
+            ("a", 1, 0),
+            ("a", 1, 1),
+        ] {
+            let point = PointStamped {
+                header: header(frame_id, sec, nanosec),
+                ..Default::default()
+            };
+            row_builder.add_row(&point).unwrap();
+        }
+        let stats = row_builder.filter_stats();
+        assert_eq!(
+            stats,
+            RowFilterStats {
+                accepted: 2,
+                dropped_by_predicate: 1,
+                dropped_by_decimation: 3,
+                dropped_by_rate: 1,
+            }
+        );
+        assert_eq!(stats.dropped(), 5);
+        assert_eq!(row_builder.to_arc_arrays()[0].len(), 2);
+    }
+}
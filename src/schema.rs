@@ -1,5 +1,11 @@
-#[cfg(feature = "default")]
+#[cfg(any(feature = "default", feature = "offline"))]
 include!(concat!(env!("OUT_DIR"), "/generated_schema.rs"));
 
-#[cfg(feature = "doc-only")]
+#[cfg(not(any(feature = "default", feature = "offline")))]
 pub static SUPPORTED_SCHEMAS: &'static [&'static str] = &[];
+
+#[cfg(not(any(feature = "default", feature = "offline")))]
+pub(crate) static TYPE_TABLE: &[crate::introspection::TypeEntry] = &[];
+
+#[cfg(not(any(feature = "default", feature = "offline")))]
+pub(crate) static RUST_KEYWORDS: &[&str] = &[];
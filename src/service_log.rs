@@ -0,0 +1,376 @@
+use crate::row_context::system_time_to_nanos;
+use crate::ArrowSupport;
+use anyhow::Result;
+use arrow_array::builder::{
+    Int64Builder, StructBuilder, TimestampNanosecondBuilder, UInt64Builder,
+};
+use arrow_array::{Array, StructArray};
+use arrow_schema::{DataType, Field, Fields, Schema, TimeUnit};
+use r2r::WrappedServiceTypeSupport;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Accumulates an audit table of service calls for the service type `S`, with the request and
+/// the response of each call in the same row.
+///
+/// The table has the following columns:
+///
+/// | column           | type                         | description                                     |
+/// |:-----------------|:-----------------------------|:------------------------------------------------|
+/// | `correlation_id` | `UInt64`                     | Identifies the call.                            |
+/// | `request_time`   | `Timestamp(Nanosecond, UTC)` | When the request was sent or received.          |
+/// | `response_time`  | `Timestamp(Nanosecond, UTC)` | When the response arrived or was sent. Nullable. |
+/// | `latency_ns`     | `Int64`                      | `response_time - request_time`. Nullable.       |
+/// | `request`        | `Struct`                     | The request, in the layout of `arrow_fields`.   |
+/// | `response`       | `Struct`                     | The response, null if there was none.           |
+///
+/// # Example
+///
+/// ```
+/// use r2a::ServiceCallRowBuilder;
+/// use r2r::example_interfaces::srv::AddTwoInts;
+/// use std::time::SystemTime;
+///
+/// let mut row_builder = ServiceCallRowBuilder::<AddTwoInts::Service>::new();
+/// let request_time = SystemTime::now();
+/// let correlation_id = row_builder.next_correlation_id();
+/// row_builder.add_call(
+///     correlation_id,
+///     &AddTwoInts::Request { a: 1, b: 2 },
+///     request_time,
+///     Some((&AddTwoInts::Response { sum: 3 }, SystemTime::now())),
+/// );
+/// let arrow_arrays = row_builder.to_arc_arrays();
+/// ```
+pub struct ServiceCallRowBuilder<S>
+where
+    S: WrappedServiceTypeSupport,
+{
+    correlation_id: UInt64Builder,
+    request_time: TimestampNanosecondBuilder,
+    response_time: TimestampNanosecondBuilder,
+    latency_ns: Int64Builder,
+    request: StructBuilder,
+    response: StructBuilder,
+    response_validity: Vec<bool>,
+    next_correlation_id: u64,
+    _phantom: std::marker::PhantomData<S>,
+}
+
+impl<S> Default for ServiceCallRowBuilder<S>
+where
+    S: WrappedServiceTypeSupport,
+    S::Request: for<'a> ArrowSupport<'a>,
+    S::Response: for<'a> ArrowSupport<'a> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> ServiceCallRowBuilder<S>
+where
+    S: WrappedServiceTypeSupport,
+    S::Request: for<'a> ArrowSupport<'a>,
+    S::Response: for<'a> ArrowSupport<'a> + Default,
+{
+    /// Creates an empty service call row builder.
+    pub fn new() -> Self {
+        ServiceCallRowBuilder {
+            correlation_id: UInt64Builder::new(),
+            request_time: TimestampNanosecondBuilder::new().with_timezone("UTC"),
+            response_time: TimestampNanosecondBuilder::new().with_timezone("UTC"),
+            latency_ns: Int64Builder::new(),
+            request: StructBuilder::from_fields(S::Request::arrow_fields(false), 0),
+            response: StructBuilder::from_fields(S::Response::arrow_fields(false), 0),
+            response_validity: Vec::new(),
+            next_correlation_id: 0,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns the Arrow field definitions of the service call table.
+    pub fn arrow_fields() -> Vec<Field> {
+        let timestamp = DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()));
+        vec![
+            Field::new("correlation_id", DataType::UInt64, false),
+            Field::new("request_time", timestamp.clone(), false),
+            Field::new("response_time", timestamp, true),
+            Field::new("latency_ns", DataType::Int64, true),
+            Field::new(
+                "request",
+                DataType::Struct(Fields::from(S::Request::arrow_fields(false))),
+                false,
+            ),
+            Field::new(
+                "response",
+                DataType::Struct(Fields::from(S::Response::arrow_fields(false))),
+                true,
+            ),
+        ]
+    }
+
+    /// Returns the Arrow schema of the service call table.
+    pub fn arrow_schema() -> Schema {
+        Schema::new(Self::arrow_fields())
+    }
+
+    /// Returns a new correlation id. Ids start at 0 and increase by one with every call.
+    pub fn next_correlation_id(&mut self) -> u64 {
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id += 1;
+        correlation_id
+    }
+
+    /// Adds a service call to the builder.
+    ///
+    /// # Arguments
+    ///
+    /// * `correlation_id` - Identifies the call, for example from `next_correlation_id`.
+    /// * `request` - The request.
+    /// * `request_time` - When the request was sent (client) or received (server).
+    /// * `response` - The response and when it arrived (client) or was sent (server). `None`
+    ///   if the call failed or was never answered.
+    pub fn add_call(
+        &mut self,
+        correlation_id: u64,
+        request: &S::Request,
+        request_time: SystemTime,
+        response: Option<(&S::Response, SystemTime)>,
+    ) {
+        let request_nanos = system_time_to_nanos(request_time);
+        self.correlation_id.append_value(correlation_id);
+        self.request_time.append_value(request_nanos);
+        request.append_to_struct_builder(&mut self.request);
+
+        match response {
+            Some((response, response_time)) => {
+                let response_nanos = system_time_to_nanos(response_time);
+                self.response_time.append_value(response_nanos);
+                self.latency_ns.append_value(response_nanos - request_nanos);
+                response.append_to_struct_builder(&mut self.response);
+                self.response_validity.push(true);
+            }
+            None => {
+                self.response_time.append_null();
+                self.latency_ns.append_null();
+                // The child arrays have to stay aligned, so a default response is appended and
+                // masked out by the validity of the struct.
+                S::Response::default().append_to_struct_builder(&mut self.response);
+                self.response_validity.push(false);
+            }
+        }
+    }
+
+    /// Converts the accumulated calls into a vector of Arrow arrays, in the order of
+    /// `arrow_fields`, and resets the builder. Correlation ids keep increasing.
+    pub fn to_arc_arrays(&mut self) -> Vec<Arc<dyn Array>> {
+        let (fields, columns, _) = self.response.finish().into_parts();
+        let response = StructArray::new(
+            fields,
+            columns,
+            Some(self.response_validity.drain(..).collect()),
+        );
+
+        vec![
+            Arc::new(self.correlation_id.finish()),
+            Arc::new(self.request_time.finish()),
+            Arc::new(self.response_time.finish()),
+            Arc::new(self.latency_ns.finish()),
+            Arc::new(self.request.finish()),
+            Arc::new(response),
+        ]
+    }
+}
+
+/// Records every call that passes through it into a shared [`ServiceCallRowBuilder`]. It can
+/// wrap both sides of a service: `call` sends a request with an r2r client, `respond` answers a
+/// request received by an r2r service.
+///
+/// # Example
+///
+/// ```no_run
+/// use r2a::ServiceCallLogger;
+/// use r2r::example_interfaces::srv::AddTwoInts;
+///
+/// # async fn example(client: r2r::Client<AddTwoInts::Service>) -> anyhow::Result<()> {
+/// let logger = ServiceCallLogger::<AddTwoInts::Service>::new();
+/// let response = logger.call(&client, &AddTwoInts::Request { a: 1, b: 2 }).await?;
+/// let arrow_arrays = logger.row_builder().lock().unwrap().to_arc_arrays();
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServiceCallLogger<S>
+where
+    S: WrappedServiceTypeSupport,
+{
+    row_builder: Arc<Mutex<ServiceCallRowBuilder<S>>>,
+}
+
+impl<S> Clone for ServiceCallLogger<S>
+where
+    S: WrappedServiceTypeSupport,
+{
+    fn clone(&self) -> Self {
+        ServiceCallLogger {
+            row_builder: self.row_builder.clone(),
+        }
+    }
+}
+
+impl<S> Default for ServiceCallLogger<S>
+where
+    S: WrappedServiceTypeSupport + 'static,
+    S::Request: for<'a> ArrowSupport<'a>,
+    S::Response: for<'a> ArrowSupport<'a> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> ServiceCallLogger<S>
+where
+    S: WrappedServiceTypeSupport + 'static,
+    S::Request: for<'a> ArrowSupport<'a>,
+    S::Response: for<'a> ArrowSupport<'a> + Default,
+{
+    /// Creates a logger with an empty row builder.
+    pub fn new() -> Self {
+        ServiceCallLogger {
+            row_builder: Arc::new(Mutex::new(ServiceCallRowBuilder::new())),
+        }
+    }
+
+    /// The shared row builder the calls are recorded into.
+    pub fn row_builder(&self) -> &Arc<Mutex<ServiceCallRowBuilder<S>>> {
+        &self.row_builder
+    }
+
+    /// Sends `request` with `client`, waits for the response and records the call. Failed
+    /// calls are recorded with a null response, and the error is returned.
+    pub async fn call(&self, client: &r2r::Client<S>, request: &S::Request) -> Result<S::Response> {
+        let correlation_id = self.row_builder.lock().unwrap().next_correlation_id();
+        let request_time = SystemTime::now();
+        let result = match client.request(request) {
+            Ok(future) => future.await,
+            Err(e) => Err(e),
+        };
+        let response_time = SystemTime::now();
+
+        let mut row_builder = self.row_builder.lock().unwrap();
+        match result {
+            Ok(response) => {
+                row_builder.add_call(
+                    correlation_id,
+                    request,
+                    request_time,
+                    Some((&response, response_time)),
+                );
+                Ok(response)
+            }
+            Err(e) => {
+                row_builder.add_call(correlation_id, request, request_time, None);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Answers a request received by an r2r service with `response` and records the call. The
+    /// response time is taken after the response is sent. If sending fails, the call is recorded
+    /// with a null response, and the error is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request, as received from the service stream.
+    /// * `request_time` - When the request was received.
+    /// * `response` - The response to send.
+    pub fn respond(
+        &self,
+        request: r2r::ServiceRequest<S>,
+        request_time: SystemTime,
+        response: S::Response,
+    ) -> Result<()> {
+        // `respond` consumes the request and the response, keep copies for the row.
+        let message = request.message.clone();
+        let sent_response = response.clone();
+        let result = request.respond(response);
+        let response_time = SystemTime::now();
+
+        let mut row_builder = self.row_builder.lock().unwrap();
+        let correlation_id = row_builder.next_correlation_id();
+        match result {
+            Ok(()) => {
+                row_builder.add_call(
+                    correlation_id,
+                    &message,
+                    request_time,
+                    Some((&sent_response, response_time)),
+                );
+                Ok(())
+            }
+            Err(e) => {
+                row_builder.add_call(correlation_id, &message, request_time, None);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::ServiceCallRowBuilder;
+    use arrow_array::Array;
+    use r2r::example_interfaces::srv::AddTwoInts;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_add_call() {
+        let mut row_builder = ServiceCallRowBuilder::<AddTwoInts::Service>::new();
+        let request_time = SystemTime::now();
+
+        let correlation_id = row_builder.next_correlation_id();
+        row_builder.add_call(
+            correlation_id,
+            &AddTwoInts::Request { a: 1, b: 2 },
+            request_time,
+            Some((
+                &AddTwoInts::Response { sum: 3 },
+                request_time + Duration::from_millis(5),
+            )),
+        );
+        let correlation_id = row_builder.next_correlation_id();
+        row_builder.add_call(
+            correlation_id,
+            &AddTwoInts::Request { a: 3, b: 4 },
+            request_time,
+            None,
+        );
+
+        let arrays = row_builder.to_arc_arrays();
+        let fields = ServiceCallRowBuilder::<AddTwoInts::Service>::arrow_fields();
+        assert_eq!(arrays.len(), fields.len());
+        for (array, field) in arrays.iter().zip(fields.iter()) {
+            assert_eq!(array.len(), 2);
+            assert_eq!(array.data_type(), field.data_type());
+        }
+
+        let latency = arrays[3]
+            .as_any()
+            .downcast_ref::<arrow_array::Int64Array>()
+            .unwrap();
+        assert_eq!(latency.value(0), 5_000_000);
+        assert!(latency.is_null(1));
+
+        let correlation_ids = arrays[0]
+            .as_any()
+            .downcast_ref::<arrow_array::UInt64Array>()
+            .unwrap();
+        assert_eq!(correlation_ids.values(), &[0, 1]);
+
+        assert!(arrays[5].is_valid(0));
+        assert!(arrays[5].is_null(1));
+        assert_eq!(arrays[4].null_count(), 0);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::flatten::flatten_struct_array;
+use crate::message_struct::{message_struct_encoding, message_struct_to_json};
+use crate::row_context::{is_row_context_field, RowContextBuilder};
+use crate::{
+    ArrowSupport, MessageStructEncoding, RowBuilder, RowContext, RowFilter, RowFilterStats,
+};
+use anyhow::{anyhow, Result};
+use arrow_array::builder::StructBuilder;
+use arrow_array::{new_null_array, Array, ArrayRef, StructArray};
+use arrow_schema::{Field, Fields};
+use std::sync::Arc;
+
+/// Deserializes the messages of `add_raw_row`.
+type Deserializer<T> = fn(&[u8]) -> Result<T>;
+
+/// The row builder of the types that implement [`ArrowSupport`] with `#[derive(ArrowSupport)]`,
+/// and of the ROS types with the `descriptor-builders` feature, used for both the regular and
+/// the flat layout.
+///
+/// Rows are accumulated in the regular, nested layout with
+/// [`ArrowSupport::append_to_struct_builder`]. The flat layout is derived from it when the
+/// arrays are built, so fields that are r2r messages end up in the same columns as in the
+/// flat layout of the message itself.
+pub struct StructRowBuilder<'a, T> {
+    arrow_fields: Vec<&'a Field>,
+    flat: bool,
+    rows: StructBuilder,
+    context: RowContextBuilder,
+    filter: Option<RowFilter<T>>,
+    stamp_nanos: fn(&T) -> Option<i64>,
+    deserialize: Option<Deserializer<T>>,
+}
+
+impl<'a, T> StructRowBuilder<'a, T>
+where
+    T: ArrowSupport<'a>,
+{
+    /// Creates a row builder for the given subset of the fields of the layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `arrow_fields` - The requested columns, from `arrow_fields` if `flat` is false or from
+    ///   `flat_arrow_fields` if it is true, plus optional message struct and context columns.
+    /// * `flat` - Selects the flat layout.
+    /// * `stamp_nanos` - Returns the time of a message for the rate limit of a [`RowFilter`],
+    ///   or `None` to use the receive time.
+    pub fn new(
+        arrow_fields: Vec<&'a Field>,
+        flat: bool,
+        stamp_nanos: fn(&T) -> Option<i64>,
+    ) -> Self {
+        let layout_fields = if flat {
+            T::flat_arrow_fields(false)
+        } else {
+            T::arrow_fields(false)
+        };
+        let mut context = RowContextBuilder::default();
+        for field in &arrow_fields {
+            if message_struct_encoding(field).is_some() {
+                continue;
+            }
+            if is_row_context_field(field.name()) {
+                context.add_field(field);
+            } else if !layout_fields.iter().any(|f| f.name() == field.name()) {
+                log::error!("Invalid field name: {}", field.name());
+            }
+        }
+
+        StructRowBuilder {
+            arrow_fields,
+            flat,
+            rows: StructBuilder::from_fields(T::arrow_fields(false), 0),
+            context,
+            filter: None,
+            stamp_nanos,
+            deserialize: None,
+        }
+    }
+
+    /// Sets the function that deserializes the messages of `add_raw_row`. Without it
+    /// `add_raw_row` fails.
+    pub fn with_deserializer(mut self, deserialize: Deserializer<T>) -> Self {
+        self.deserialize = Some(deserialize);
+        self
+    }
+
+    fn append_row(&mut self, msg: &T, ctx: Option<&RowContext>) -> Result<()> {
+        if let Some(filter) = self.filter.as_mut() {
+            let stamp = (self.stamp_nanos)(msg).or_else(|| ctx.map(|ctx| ctx.recv_time_nanos()));
+            if !filter.accept(msg, stamp) {
+                return Ok(());
+            }
+        }
+
+        msg.append_to_struct_builder(&mut self.rows);
+        for field in &self.arrow_fields {
+            if message_struct_encoding(field).is_none() && is_row_context_field(field.name()) {
+                self.context.append(field.name(), ctx);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T> RowBuilder<'a, T> for StructRowBuilder<'a, T>
+where
+    T: ArrowSupport<'a>,
+{
+    fn add_row(&mut self, msg: &T) -> Result<()> {
+        self.append_row(msg, None)
+    }
+
+    fn add_row_with_context(&mut self, msg: &T, ctx: &RowContext) -> Result<()> {
+        self.append_row(msg, Some(ctx))
+    }
+
+    fn add_raw_row(&mut self, msg: &[u8]) -> Result<()> {
+        match self.deserialize {
+            Some(deserialize) => self.add_row(&deserialize(msg)?),
+            None => Err(anyhow!(
+                "{} has no serialized form, add rows with add_row",
+                T::schema_name()
+            )),
+        }
+    }
+
+    fn set_filter(&mut self, filter: RowFilter<T>) {
+        self.filter = Some(filter);
+    }
+
+    fn filter_stats(&self) -> RowFilterStats {
+        self.filter
+            .as_ref()
+            .map(|filter| filter.stats())
+            .unwrap_or_default()
+    }
+
+    fn to_arc_arrays(&mut self) -> Vec<Arc<dyn Array>> {
+        let rows = self.rows.finish();
+        let flat_columns = flatten_struct_array(&rows);
+        let columns: Vec<(Field, ArrayRef)> = if self.flat {
+            flat_columns.clone()
+        } else {
+            rows.fields()
+                .iter()
+                .map(|field| field.as_ref().clone())
+                .zip(rows.columns().iter().cloned())
+                .collect()
+        };
+
+        let mut res: Vec<Arc<dyn Array>> = vec![];
+        for field in &self.arrow_fields {
+            if let Some(encoding) = message_struct_encoding(field) {
+                match encoding {
+                    MessageStructEncoding::Struct => res.push(Arc::new(rows.clone())),
+                    MessageStructEncoding::FlatStruct => {
+                        let (fields, arrays): (Vec<Field>, Vec<ArrayRef>) =
+                            flat_columns.iter().cloned().unzip();
+                        res.push(Arc::new(StructArray::new(
+                            Fields::from(fields),
+                            arrays,
+                            None,
+                        )))
+                    }
+                    MessageStructEncoding::Json => {
+                        res.push(Arc::new(message_struct_to_json(&rows)))
+                    }
+                }
+            } else if is_row_context_field(field.name()) {
+                res.push(self.context.finish(field.name()));
+            } else {
+                match columns.iter().find(|(f, _)| f.name() == field.name()) {
+                    Some((_, array)) => res.push(array.clone()),
+                    None => res.push(new_null_array(field.data_type(), rows.len())),
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(all(test, feature = "default", feature = "derive"))]
+mod tests {
+
+    use crate::{ArrowSupport, MessageStructEncoding, RowBuilder, RowContext, RowFilter};
+    use arrow_array::cast::AsArray;
+    use arrow_array::Array;
+    use arrow_schema::DataType;
+    use r2r::geometry_msgs::msg::{Point, Pose};
+    use r2r::std_msgs::msg::Header;
+
+    #[derive(ArrowSupport, Default)]
+    struct Waypoint {
+        name: String,
+        position: Point,
+    }
+
+    #[derive(ArrowSupport, Default)]
+    #[r2a(schema_name = "planner/msg/Diagnostics")]
+    struct Diagnostics {
+        header: Header,
+        planning_time: f64,
+        iterations: u32,
+        costs: Vec<f32>,
+        pose: Pose,
+        waypoints: Vec<Waypoint>,
+        #[r2a(skip)]
+        #[allow(dead_code)]
+        cache: std::collections::HashMap<String, f64>,
+    }
+
+    fn diagnostics(sec: i32) -> Diagnostics {
+        Diagnostics {
+            header: Header {
+                stamp: r2r::builtin_interfaces::msg::Time { sec, nanosec: 0 },
+                frame_id: "map".to_string(),
+            },
+            planning_time: 0.25,
+            iterations: 12,
+            costs: vec![1.0, 2.0],
+            waypoints: vec![Waypoint {
+                name: "start".to_string(),
+                position: Point {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 0.0,
+                },
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_derived_fields() {
+        assert_eq!(Diagnostics::schema_name(), "planner/msg/Diagnostics");
+        assert_eq!(Waypoint::schema_name(), "Waypoint");
+
+        let names: Vec<String> = Diagnostics::arrow_fields(true)
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "header",
+                "planning_time",
+                "iterations",
+                "costs",
+                "pose",
+                "waypoints",
+                "message_struct"
+            ]
+        );
+
+        let flat_fields = Diagnostics::flat_arrow_fields(false);
+        let pose_fields: Vec<String> = Pose::flat_arrow_fields(false)
+            .iter()
+            .map(|field| format!("pose_{}", field.name()))
+            .collect();
+        for name in ["header_stamp_sec", "header_frame_id", "planning_time"]
+            .into_iter()
+            .map(String::from)
+            .chain(pose_fields)
+        {
+            assert!(flat_fields.iter().any(|field| field.name() == &name));
+        }
+        let waypoints = flat_fields
+            .iter()
+            .find(|field| field.name() == "waypoints")
+            .unwrap();
+        match waypoints.data_type() {
+            DataType::LargeList(item) => match item.data_type() {
+                DataType::Struct(fields) => {
+                    assert_eq!(fields[0].name(), "name");
+                    assert_eq!(fields[1].name(), "position_x");
+                }
+                _ => panic!("Expected a struct item"),
+            },
+            _ => panic!("Expected a list"),
+        }
+    }
+
+    #[test]
+    fn test_derived_row_builders() {
+        let fields = Diagnostics::arrow_fields(true);
+        let mut row_builder = Diagnostics::new_row_builder(fields.iter().collect());
+        row_builder.add_row(&diagnostics(1)).unwrap();
+        row_builder.add_row(&diagnostics(2)).unwrap();
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), fields.len());
+        for (array, field) in arrays.iter().zip(fields.iter()) {
+            assert_eq!(array.len(), 2);
+            assert_eq!(array.data_type(), field.data_type());
+        }
+        assert!(row_builder.add_raw_row(&[]).is_err());
+
+        let mut fields = Diagnostics::flat_arrow_fields(false);
+        fields.push(Diagnostics::message_struct_field(
+            "diagnostics",
+            MessageStructEncoding::Json,
+        ));
+        fields.extend(crate::row_context_fields(false));
+        let mut row_builder = Diagnostics::new_flat_row_builder(fields.iter().collect());
+        row_builder.set_filter(RowFilter::new().max_rate_hz(1.0));
+        for sec in [1, 1, 2] {
+            row_builder
+                .add_row_with_context(&diagnostics(sec), &RowContext::new("/diagnostics", 0))
+                .unwrap();
+        }
+        assert_eq!(row_builder.filter_stats().dropped_by_rate, 1);
+
+        let arrays = row_builder.to_arc_arrays();
+        assert_eq!(arrays.len(), fields.len());
+        for (array, field) in arrays.iter().zip(fields.iter()) {
+            assert_eq!(array.len(), 2);
+            assert_eq!(array.data_type(), field.data_type());
+        }
+        let iterations = fields
+            .iter()
+            .position(|field| field.name() == "iterations")
+            .unwrap();
+        assert_eq!(
+            arrays[iterations]
+                .as_primitive::<arrow_array::types::UInt32Type>()
+                .value(0),
+            12
+        );
+        let json = fields
+            .iter()
+            .position(|field| field.name() == "diagnostics")
+            .unwrap();
+        assert!(arrays[json]
+            .as_string::<i32>()
+            .value(0)
+            .contains(r#""planning_time":0.25"#));
+    }
+}
@@ -0,0 +1,87 @@
+//! Converts timestamps to UTC calendar times.
+
+/// The UTC calendar time of a timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub nanosecond: u32,
+}
+
+impl UtcTime {
+    /// The time of `nanos` nanoseconds since the UNIX epoch.
+    pub fn from_nanos(nanos: i64) -> Self {
+        let seconds = nanos.div_euclid(1_000_000_000);
+        let days = seconds.div_euclid(86_400);
+        let second_of_day = seconds.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        UtcTime {
+            year,
+            month,
+            day,
+            hour: (second_of_day / 3600) as u32,
+            minute: (second_of_day % 3600 / 60) as u32,
+            second: (second_of_day % 60) as u32,
+            nanosecond: nanos.rem_euclid(1_000_000_000) as u32,
+        }
+    }
+
+    /// The time in the RFC 3339 format with nanoseconds, for example
+    /// `2024-10-13T14:34:56.000000001Z`.
+    #[cfg_attr(not(feature = "export"), allow(dead_code))]
+    pub fn to_rfc3339(self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.nanosecond
+        )
+    }
+}
+
+/// The proleptic Gregorian date of a number of days since 1970-01-01, see
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_time() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20_009), (2024, 10, 13));
+        let time = UtcTime::from_nanos(1_728_830_096 * 1_000_000_000 + 1);
+        assert_eq!((time.hour, time.minute, time.second), (14, 34, 56));
+        assert_eq!(time.nanosecond, 1);
+        let time = UtcTime::from_nanos(-1);
+        assert_eq!((time.year, time.month, time.day), (1969, 12, 31));
+        assert_eq!(time.nanosecond, 999_999_999);
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let time = UtcTime::from_nanos(1_728_830_096 * 1_000_000_000 + 1);
+        assert_eq!(time.to_rfc3339(), "2024-10-13T14:34:56.000000001Z");
+        assert_eq!(
+            UtcTime::from_nanos(-1).to_rfc3339(),
+            "1969-12-31T23:59:59.999999999Z"
+        );
+    }
+}